@@ -1,4 +1,18 @@
+#[macro_use]
+extern crate log;
+
+use igloo_base::*;
+
+use std::path::PathBuf;
 
 fn main() {
-    println!("Hello, world!");
+	// The agent has no terminal to log to, $IGLOO_LOG_FILE keeps its logs
+	let level = log_level(false, 0, std::env::var("IGLOO_LOG").ok().as_deref());
+	let file = std::env::var_os("IGLOO_LOG_FILE").map(PathBuf::from);
+	if let Err(e) = init_logging(level, file.as_deref())
+	{
+		eprintln!("Failed to open the log file: {}", e);
+	}
+	info!("igloo agent started");
+	println!("Hello, world!");
 }
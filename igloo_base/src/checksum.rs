@@ -0,0 +1,22 @@
+/// CRC-32 (IEEE 802.3, reflected, polynomial 0xEDB88320) as used by zlib,
+/// Ethernet and most bootloaders
+pub fn crc32(data: &[u8]) -> u32
+{
+	crc32_update(0, data)
+}
+
+/// Continues a CRC-32 over more data. crc is the result of a previous call
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32
+{
+	let mut crc = !crc;
+	for byte in data
+	{
+		crc ^= *byte as u32;
+		for _bit in 0..8
+		{
+			let mask = (crc & 1).wrapping_neg();
+			crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+		}
+	}
+	!crc
+}
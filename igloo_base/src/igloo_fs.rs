@@ -0,0 +1,855 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use crate::{say, say_raw};
+use crate::igloo_message::*;
+
+use serde_json::json;
+
+/// Filesystem used by igloo for everything it reads from ESF and writes to a project.
+/// RealFs goes to disk, MemFs keeps everything in memory (used by the tests) and
+/// DryRunFs records changes without making them.
+pub trait IglooFs
+{
+	fn exists(&self, path: &Path) -> bool;
+	fn is_dir(&self, path: &Path) -> bool;
+	/// true if path exists, without following a symlink at path itself
+	fn symlink_exists(&self, path: &Path) -> bool;
+	fn read(&self, path: &Path) -> Result<Vec<u8>>;
+	/// Returns the paths of the entries in a directory, sorted
+	fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>>;
+
+	fn create_dir(&self, path: &Path) -> Result<()>;
+	fn create_dir_all(&self, path: &Path) -> Result<()>;
+	fn remove_file(&self, path: &Path) -> Result<()>;
+	fn remove_dir_all(&self, path: &Path) -> Result<()>;
+	/// Creates a symlink at dst pointing to src
+	fn symlink(&self, src: &Path, dst: &Path) -> Result<()>;
+	fn copy(&self, from: &Path, to: &Path) -> Result<()>;
+	/// Writes contents to path, replacing whatever was there
+	fn write(&self, path: &Path, contents: &[u8]) -> Result<()>;
+	/// Marks a file as executable. Only the disk has permissions
+	fn set_executable(&self, _path: &Path) -> Result<()>
+	{
+		Ok(())
+	}
+
+	/// The operations recorded instead of performed. None unless this is a dry run
+	fn plan(&self) -> Option<Vec<IglooFsOp>>
+	{
+		None
+	}
+
+	fn read_to_string(&self, path: &Path) -> Result<String>
+	{
+		let contents = self.read(path)?;
+		String::from_utf8(contents).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+	}
+}
+
+/// A filesystem operation igloo would perform in a dry run
+#[derive(Debug)]
+#[derive(PartialEq)]
+#[derive(Clone)]
+pub enum IglooFsOp
+{
+	CreateDir(PathBuf),
+	RemoveFile(PathBuf),
+	RemoveDir(PathBuf),
+	Symlink { src: PathBuf, dst: PathBuf },
+	Copy { from: PathBuf, to: PathBuf },
+	/// old is the content the file had before the write, if it existed
+	Write { path: PathBuf, old: Option<String>, new: String },
+}
+
+/// The disk
+pub struct RealFs;
+
+impl IglooFs for RealFs
+{
+	fn exists(&self, path: &Path) -> bool
+	{
+		path.exists()
+	}
+
+	fn is_dir(&self, path: &Path) -> bool
+	{
+		path.is_dir()
+	}
+
+	fn symlink_exists(&self, path: &Path) -> bool
+	{
+		path.symlink_metadata().is_ok()
+	}
+
+	fn read(&self, path: &Path) -> Result<Vec<u8>>
+	{
+		std::fs::read(path)
+	}
+
+	fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>>
+	{
+		let mut ret: Vec<PathBuf> = Vec::new();
+		for entry in std::fs::read_dir(path)?
+		{
+			ret.push(entry?.path());
+		}
+		ret.sort();
+		Ok(ret)
+	}
+
+	fn create_dir(&self, path: &Path) -> Result<()>
+	{
+		std::fs::create_dir(path)
+	}
+
+	fn create_dir_all(&self, path: &Path) -> Result<()>
+	{
+		std::fs::create_dir_all(path)
+	}
+
+	fn remove_file(&self, path: &Path) -> Result<()>
+	{
+		std::fs::remove_file(path)
+	}
+
+	fn remove_dir_all(&self, path: &Path) -> Result<()>
+	{
+		std::fs::remove_dir_all(path)
+	}
+
+	fn symlink(&self, src: &Path, dst: &Path) -> Result<()>
+	{
+		std::os::unix::fs::symlink(src, dst)
+	}
+
+	fn copy(&self, from: &Path, to: &Path) -> Result<()>
+	{
+		std::fs::copy(from, to).map(|_v| ())
+	}
+
+	fn write(&self, path: &Path, contents: &[u8]) -> Result<()>
+	{
+		std::fs::write(path, contents)
+	}
+
+	fn set_executable(&self, path: &Path) -> Result<()>
+	{
+		use std::os::unix::fs::PermissionsExt;
+		let mut perms = std::fs::metadata(path)?.permissions();
+		perms.set_mode(perms.mode() | 0o111);
+		std::fs::set_permissions(path, perms)
+	}
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+enum MemEntry
+{
+	Dir,
+	File(Vec<u8>),
+	Symlink(PathBuf),
+}
+
+/// An in-memory filesystem. Paths should be absolute.
+/// Symlinks are followed when reading through them.
+pub struct MemFs
+{
+	entries: RefCell<BTreeMap<PathBuf, MemEntry>>,
+}
+
+impl Default for MemFs
+{
+	fn default() -> Self
+	{
+		MemFs::new()
+	}
+}
+
+impl MemFs
+{
+	pub fn new() -> MemFs
+	{
+		let mut entries = BTreeMap::new();
+		entries.insert(PathBuf::from("/"), MemEntry::Dir);
+		MemFs
+		{
+			entries: RefCell::new(entries),
+		}
+	}
+
+	/// Writes a file, creating its parent directories. Used to set up test trees.
+	pub fn add_file(&self, path: &Path, contents: &str)
+	{
+		if let Some(parent) = path.parent()
+		{
+			self.create_dir_all(parent).unwrap();
+		}
+		self.write(path, contents.as_bytes()).unwrap();
+	}
+
+	/// Returns every path in the filesystem, sorted
+	pub fn paths(&self) -> Vec<PathBuf>
+	{
+		self.entries.borrow().keys().cloned().collect()
+	}
+
+	/// Removes . and .. components
+	fn normalize(path: &Path) -> PathBuf
+	{
+		let mut ret = PathBuf::new();
+		for component in path.components()
+		{
+			match component
+			{
+				Component::CurDir => (),
+				Component::ParentDir =>
+				{
+					ret.pop();
+				}
+				c => ret.push(c.as_os_str()),
+			}
+		}
+		ret
+	}
+
+	/// Follows symlinks in every component of path
+	fn resolve(&self, path: &Path) -> PathBuf
+	{
+		let entries = self.entries.borrow();
+		let mut ret = PathBuf::new();
+		for component in MemFs::normalize(path).components()
+		{
+			ret.push(component.as_os_str());
+			let mut hops = 0;
+			while let Some(MemEntry::Symlink(target)) = entries.get(&ret)
+			{
+				ret = MemFs::normalize(&ret.parent().unwrap_or(Path::new("/")).join(target));
+				hops += 1;
+				if hops > 32
+				{
+					break
+				}
+			}
+		}
+		ret
+	}
+
+	/// Resolves the parent of path but not path itself
+	fn resolve_parent(&self, path: &Path) -> PathBuf
+	{
+		let path = MemFs::normalize(path);
+		match (path.parent(), path.file_name())
+		{
+			(Some(parent), Some(name)) => self.resolve(parent).join(name),
+			_ => path,
+		}
+	}
+
+	fn not_found(path: &Path) -> Error
+	{
+		Error::new(ErrorKind::NotFound, format!("{} not found", path.display()))
+	}
+
+	fn parent_is_dir(&self, path: &Path) -> bool
+	{
+		match path.parent()
+		{
+			None => true,
+			Some(parent) => self.is_dir(parent),
+		}
+	}
+}
+
+impl IglooFs for MemFs
+{
+	fn exists(&self, path: &Path) -> bool
+	{
+		let path = self.resolve(path);
+		self.entries.borrow().contains_key(&path)
+	}
+
+	fn is_dir(&self, path: &Path) -> bool
+	{
+		let path = self.resolve(path);
+		matches!(self.entries.borrow().get(&path), Some(MemEntry::Dir))
+	}
+
+	fn symlink_exists(&self, path: &Path) -> bool
+	{
+		let path = self.resolve_parent(path);
+		self.entries.borrow().contains_key(&path)
+	}
+
+	fn read(&self, path: &Path) -> Result<Vec<u8>>
+	{
+		let resolved = self.resolve(path);
+		match self.entries.borrow().get(&resolved)
+		{
+			Some(MemEntry::File(v)) => Ok(v.clone()),
+			Some(_v) => Err(Error::other(format!("{} is not a file", path.display()))),
+			None => Err(MemFs::not_found(path)),
+		}
+	}
+
+	fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>>
+	{
+		if !self.is_dir(path)
+		{
+			return Err(MemFs::not_found(path))
+		}
+		let resolved = self.resolve(path);
+		let entries = self.entries.borrow();
+		let mut ret: Vec<PathBuf> = Vec::new();
+		for key in entries.keys()
+		{
+			if key.parent() == Some(resolved.as_path())
+			{
+				ret.push(MemFs::normalize(path).join(key.file_name().unwrap()));
+			}
+		}
+		Ok(ret)
+	}
+
+	fn create_dir(&self, path: &Path) -> Result<()>
+	{
+		let path = self.resolve_parent(path);
+		if self.entries.borrow().contains_key(&path)
+		{
+			return Err(Error::new(ErrorKind::AlreadyExists,
+								  format!("{} already exists", path.display())))
+		}
+		if !self.parent_is_dir(&path)
+		{
+			return Err(MemFs::not_found(path.parent().unwrap()))
+		}
+		self.entries.borrow_mut().insert(path, MemEntry::Dir);
+		Ok(())
+	}
+
+	fn create_dir_all(&self, path: &Path) -> Result<()>
+	{
+		let path = MemFs::normalize(path);
+		let mut partial = PathBuf::new();
+		for component in path.components()
+		{
+			partial.push(component.as_os_str());
+			if !self.is_dir(&partial)
+			{
+				self.create_dir(&partial)?;
+			}
+		}
+		Ok(())
+	}
+
+	fn remove_file(&self, path: &Path) -> Result<()>
+	{
+		let path = self.resolve_parent(path);
+		let mut entries = self.entries.borrow_mut();
+		match entries.get(&path)
+		{
+			Some(MemEntry::Dir) => Err(Error::other(format!("{} is a directory", path.display()))),
+			Some(_v) =>
+			{
+				entries.remove(&path);
+				Ok(())
+			}
+			None => Err(MemFs::not_found(&path)),
+		}
+	}
+
+	fn remove_dir_all(&self, path: &Path) -> Result<()>
+	{
+		let path = self.resolve_parent(path);
+		let mut entries = self.entries.borrow_mut();
+		if !entries.contains_key(&path)
+		{
+			return Err(MemFs::not_found(&path))
+		}
+		let doomed: Vec<PathBuf> = entries.keys()
+			.filter(|k| k.starts_with(&path))
+			.cloned()
+			.collect();
+		for key in doomed
+		{
+			entries.remove(&key);
+		}
+		Ok(())
+	}
+
+	fn symlink(&self, src: &Path, dst: &Path) -> Result<()>
+	{
+		let dst = self.resolve_parent(dst);
+		if self.entries.borrow().contains_key(&dst)
+		{
+			return Err(Error::new(ErrorKind::AlreadyExists,
+								  format!("{} already exists", dst.display())))
+		}
+		if !self.parent_is_dir(&dst)
+		{
+			return Err(MemFs::not_found(dst.parent().unwrap()))
+		}
+		self.entries.borrow_mut().insert(dst, MemEntry::Symlink(src.to_path_buf()));
+		Ok(())
+	}
+
+	fn copy(&self, from: &Path, to: &Path) -> Result<()>
+	{
+		let contents = self.read(from)?;
+		self.write(to, &contents)
+	}
+
+	fn write(&self, path: &Path, contents: &[u8]) -> Result<()>
+	{
+		let path = self.resolve(path);
+		if self.is_dir(&path)
+		{
+			return Err(Error::other(format!("{} is a directory", path.display())))
+		}
+		if !self.parent_is_dir(&path)
+		{
+			return Err(MemFs::not_found(path.parent().unwrap()))
+		}
+		self.entries.borrow_mut().insert(path, MemEntry::File(contents.to_vec()));
+		Ok(())
+	}
+}
+
+/// Reads go to the wrapped filesystem. Every change is recorded in a plan
+/// instead of being made so it can be reviewed with print_plan. Reads see the
+/// planned changes, so later steps of a command work on what earlier ones wrote.
+pub struct DryRunFs
+{
+	inner: Rc<dyn IglooFs>,
+	plan: RefCell<Vec<IglooFsOp>>,
+	/// path -> planned contents, None if the file is planned to be removed
+	files: RefCell<BTreeMap<PathBuf, Option<Vec<u8>>>>,
+	/// directories planned to be removed
+	removed_dirs: RefCell<Vec<PathBuf>>,
+}
+
+impl DryRunFs
+{
+	pub fn new(inner: Rc<dyn IglooFs>) -> DryRunFs
+	{
+		DryRunFs
+		{
+			inner,
+			plan: RefCell::new(Vec::new()),
+			files: RefCell::new(BTreeMap::new()),
+			removed_dirs: RefCell::new(Vec::new()),
+		}
+	}
+
+	/// Planned contents of path. None if the plan does not touch it,
+	/// Some(None) if it is planned to be removed
+	fn planned_file(&self, path: &Path) -> Option<Option<Vec<u8>>>
+	{
+		if let Some(v) = self.files.borrow().get(path)
+		{
+			return Some(v.clone())
+		}
+		if self.removed_dirs.borrow().iter().any(|d| path.starts_with(d))
+		{
+			return Some(None)
+		}
+		None
+	}
+
+	/// Whether the last planned operation on directory path creates it
+	fn planned_dir(&self, path: &Path) -> bool
+	{
+		self.plan.borrow().iter().rev()
+			.find_map(|op| match op
+			{
+				IglooFsOp::CreateDir(v) if v == path => Some(true),
+				IglooFsOp::RemoveDir(v) if path.starts_with(v) => Some(false),
+				_ => None,
+			})
+			.unwrap_or(false)
+	}
+
+	fn record(&self, op: IglooFsOp)
+	{
+		self.plan.borrow_mut().push(op);
+	}
+
+	fn planned(&self, op: &IglooFsOp) -> bool
+	{
+		self.plan.borrow().contains(op)
+	}
+}
+
+impl IglooFs for DryRunFs
+{
+	fn exists(&self, path: &Path) -> bool
+	{
+		match self.planned_file(path)
+		{
+			Some(v) => v.is_some() || self.planned_dir(path),
+			None => self.planned_dir(path) || self.inner.exists(path),
+		}
+	}
+
+	fn is_dir(&self, path: &Path) -> bool
+	{
+		match self.planned_file(path)
+		{
+			Some(_v) => self.planned_dir(path),
+			None => self.planned_dir(path) || self.inner.is_dir(path),
+		}
+	}
+
+	fn symlink_exists(&self, path: &Path) -> bool
+	{
+		match self.planned_file(path)
+		{
+			Some(v) => v.is_some(),
+			None => self.inner.symlink_exists(path),
+		}
+	}
+
+	fn read(&self, path: &Path) -> Result<Vec<u8>>
+	{
+		match self.planned_file(path)
+		{
+			Some(Some(v)) => Ok(v),
+			Some(None) => Err(Error::new(ErrorKind::NotFound,
+										 format!("{} is planned to be removed", path.display()))),
+			None => self.inner.read(path),
+		}
+	}
+
+	fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>>
+	{
+		self.inner.read_dir(path)
+	}
+
+	fn create_dir(&self, path: &Path) -> Result<()>
+	{
+		self.record(IglooFsOp::CreateDir(path.to_path_buf()));
+		Ok(())
+	}
+
+	fn create_dir_all(&self, path: &Path) -> Result<()>
+	{
+		let op = IglooFsOp::CreateDir(path.to_path_buf());
+		if !self.inner.is_dir(path) && !self.planned(&op)
+		{
+			self.record(op);
+		}
+		Ok(())
+	}
+
+	fn remove_file(&self, path: &Path) -> Result<()>
+	{
+		self.record(IglooFsOp::RemoveFile(path.to_path_buf()));
+		self.files.borrow_mut().insert(path.to_path_buf(), None);
+		Ok(())
+	}
+
+	fn remove_dir_all(&self, path: &Path) -> Result<()>
+	{
+		self.record(IglooFsOp::RemoveDir(path.to_path_buf()));
+		self.files.borrow_mut().retain(|p, _contents| !p.starts_with(path));
+		self.removed_dirs.borrow_mut().push(path.to_path_buf());
+		Ok(())
+	}
+
+	fn symlink(&self, src: &Path, dst: &Path) -> Result<()>
+	{
+		self.record(IglooFsOp::Symlink
+		{
+			src: src.to_path_buf(),
+			dst: dst.to_path_buf(),
+		});
+		Ok(())
+	}
+
+	fn copy(&self, from: &Path, to: &Path) -> Result<()>
+	{
+		let contents = self.read(from)?;
+		self.record(IglooFsOp::Copy
+		{
+			from: from.to_path_buf(),
+			to: to.to_path_buf(),
+		});
+		self.files.borrow_mut().insert(to.to_path_buf(), Some(contents));
+		Ok(())
+	}
+
+	fn write(&self, path: &Path, contents: &[u8]) -> Result<()>
+	{
+		self.record(IglooFsOp::Write
+		{
+			path: path.to_path_buf(),
+			old: self.read_to_string(path).ok(),
+			new: String::from_utf8_lossy(contents).into_owned(),
+		});
+		self.files.borrow_mut().insert(path.to_path_buf(), Some(contents.to_vec()));
+		Ok(())
+	}
+
+	fn plan(&self) -> Option<Vec<IglooFsOp>>
+	{
+		Some(self.plan.borrow().clone())
+	}
+}
+
+/// Passes everything to the wrapped filesystem and emits a JSON message for
+/// every path it creates, changes or removes. Used with --message-format json
+pub struct ReportingFs
+{
+	inner: Rc<dyn IglooFs>,
+}
+
+impl ReportingFs
+{
+	pub fn new(inner: Rc<dyn IglooFs>) -> ReportingFs
+	{
+		ReportingFs { inner }
+	}
+
+	fn report(reason: &str, kind: &str, path: &Path)
+	{
+		emit(json!({ "reason": reason, "kind": kind, "path": path.display().to_string() }));
+	}
+
+	/// What writing contents to path does: path-created, path-updated, or
+	/// None when the file already holds contents
+	fn file_change(&self, path: &Path, contents: &[u8]) -> Option<&'static str>
+	{
+		match self.inner.read(path)
+		{
+			Ok(ref old) if old.as_slice() == contents => None,
+			Ok(_) => Some("path-updated"),
+			Err(_e) => Some("path-created"),
+		}
+	}
+}
+
+impl IglooFs for ReportingFs
+{
+	fn exists(&self, path: &Path) -> bool
+	{
+		self.inner.exists(path)
+	}
+
+	fn is_dir(&self, path: &Path) -> bool
+	{
+		self.inner.is_dir(path)
+	}
+
+	fn symlink_exists(&self, path: &Path) -> bool
+	{
+		self.inner.symlink_exists(path)
+	}
+
+	fn read(&self, path: &Path) -> Result<Vec<u8>>
+	{
+		self.inner.read(path)
+	}
+
+	fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>>
+	{
+		self.inner.read_dir(path)
+	}
+
+	fn create_dir(&self, path: &Path) -> Result<()>
+	{
+		self.inner.create_dir(path)?;
+		ReportingFs::report("path-created", "dir", path);
+		Ok(())
+	}
+
+	fn create_dir_all(&self, path: &Path) -> Result<()>
+	{
+		let existed = self.inner.is_dir(path);
+		self.inner.create_dir_all(path)?;
+		if !existed
+		{
+			ReportingFs::report("path-created", "dir", path);
+		}
+		Ok(())
+	}
+
+	fn remove_file(&self, path: &Path) -> Result<()>
+	{
+		self.inner.remove_file(path)?;
+		ReportingFs::report("path-removed", "file", path);
+		Ok(())
+	}
+
+	fn remove_dir_all(&self, path: &Path) -> Result<()>
+	{
+		self.inner.remove_dir_all(path)?;
+		ReportingFs::report("path-removed", "dir", path);
+		Ok(())
+	}
+
+	fn symlink(&self, src: &Path, dst: &Path) -> Result<()>
+	{
+		self.inner.symlink(src, dst)?;
+		ReportingFs::report("path-created", "symlink", dst);
+		Ok(())
+	}
+
+	fn copy(&self, from: &Path, to: &Path) -> Result<()>
+	{
+		let change = self.file_change(to, &self.inner.read(from)?);
+		self.inner.copy(from, to)?;
+		if let Some(reason) = change
+		{
+			ReportingFs::report(reason, "file", to);
+		}
+		Ok(())
+	}
+
+	fn write(&self, path: &Path, contents: &[u8]) -> Result<()>
+	{
+		let change = self.file_change(path, contents);
+		self.inner.write(path, contents)?;
+		if let Some(reason) = change
+		{
+			ReportingFs::report(reason, "file", path);
+		}
+		Ok(())
+	}
+
+	fn set_executable(&self, path: &Path) -> Result<()>
+	{
+		self.inner.set_executable(path)
+	}
+}
+
+/// Prints every planned operation. Writes to files that already
+/// exist are shown as a diff against the current content.
+pub fn print_plan(plan: &[IglooFsOp])
+{
+	if message_format() == MessageFormat::Json
+	{
+		for op in plan
+		{
+			emit(plan_message(op));
+		}
+		return
+	}
+	say!("Dry run, no changes were made. Planned operations:");
+	for op in plan
+	{
+		match op
+		{
+			IglooFsOp::CreateDir(v) => say!("  create dir  {}", v.display()),
+			IglooFsOp::RemoveFile(v) => say!("  remove      {}", v.display()),
+			IglooFsOp::RemoveDir(v) => say!("  remove dir  {}", v.display()),
+			IglooFsOp::Symlink { src, dst } =>
+			{
+				say!("  symlink     {} -> {}", dst.display(), src.display());
+			}
+			IglooFsOp::Copy { from, to } =>
+			{
+				say!("  copy        {} -> {}", from.display(), to.display());
+			}
+			IglooFsOp::Write { path, old, new } =>
+			{
+				match old
+				{
+					None => say!("  write       {} ({} bytes)",
+									 path.display(), new.len()),
+					Some(old) =>
+					{
+						if old == new
+						{
+							say!("  unchanged   {}", path.display());
+						}
+						else
+						{
+							say!("  modify      {}", path.display());
+							say_raw!("{}", diff_lines(old, new));
+						}
+					}
+				}
+			}
+		}
+	}
+}
+
+/// A planned operation as a JSON message
+fn plan_message(op: &IglooFsOp) -> serde_json::Value
+{
+	let path = |p: &Path| p.display().to_string();
+	match op
+	{
+		IglooFsOp::CreateDir(v) => json!({ "reason": "planned", "op": "create-dir", "path": path(v) }),
+		IglooFsOp::RemoveFile(v) => json!({ "reason": "planned", "op": "remove", "path": path(v) }),
+		IglooFsOp::RemoveDir(v) => json!({ "reason": "planned", "op": "remove-dir", "path": path(v) }),
+		IglooFsOp::Symlink { src, dst } =>
+		{
+			json!({ "reason": "planned", "op": "symlink", "path": path(dst), "src": path(src) })
+		}
+		IglooFsOp::Copy { from, to } =>
+		{
+			json!({ "reason": "planned", "op": "copy", "path": path(to), "src": path(from) })
+		}
+		IglooFsOp::Write { path: p, old, new } =>
+		{
+			let op = match old
+			{
+				None => "write",
+				Some(old) if old == new => "unchanged",
+				Some(_old) => "modify",
+			};
+			json!({ "reason": "planned", "op": op, "path": path(p) })
+		}
+	}
+}
+
+/// Returns a line based diff of old and new. Removed lines are
+/// prefixed with '-', added lines with '+'. Unchanged lines are omitted.
+pub fn diff_lines(old: &str, new: &str) -> String
+{
+	let old_lines: Vec<&str> = old.lines().collect();
+	let new_lines: Vec<&str> = new.lines().collect();
+
+	// Longest common subsequence table
+	let mut lcs = vec![vec![0usize; new_lines.len() + 1]; old_lines.len() + 1];
+	for i in (0..old_lines.len()).rev()
+	{
+		for j in (0..new_lines.len()).rev()
+		{
+			lcs[i][j] = if old_lines[i] == new_lines[j]
+			{
+				lcs[i + 1][j + 1] + 1
+			}
+			else
+			{
+				std::cmp::max(lcs[i + 1][j], lcs[i][j + 1])
+			};
+		}
+	}
+
+	let mut ret = String::new();
+	let (mut i, mut j) = (0, 0);
+	while i < old_lines.len() || j < new_lines.len()
+	{
+		if i < old_lines.len() && j < new_lines.len() && old_lines[i] == new_lines[j]
+		{
+			i += 1;
+			j += 1;
+		}
+		else if j < new_lines.len()
+			&& (i == old_lines.len() || lcs[i][j + 1] >= lcs[i + 1][j])
+		{
+			ret.push_str(&format!("    +{}\n", new_lines[j]));
+			j += 1;
+		}
+		else
+		{
+			ret.push_str(&format!("    -{}\n", old_lines[i]));
+			i += 1;
+		}
+	}
+	ret
+}
@@ -0,0 +1,317 @@
+use crate::igloo_message::*;
+
+use serde_json::json;
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Output of a program igloo ran
+#[derive(Debug)]
+#[derive(PartialEq)]
+#[derive(Clone)]
+pub struct IglooCmdOutput
+{
+	pub success: bool,
+	pub stdout: String,
+	pub stderr: String,
+}
+
+/// A program run as one of a batch of jobs
+#[derive(Debug, Clone, PartialEq)]
+pub struct IglooJob
+{
+	/// prefixed to every line of output when jobs run in parallel
+	pub name: String,
+	pub program: String,
+	pub args: Vec<String>,
+	pub dir: PathBuf,
+}
+
+/// How a job went: whether it succeeded, or why it could not be started
+#[derive(Debug)]
+pub struct IglooJobResult
+{
+	pub result: Result<bool>,
+	pub duration: Duration,
+}
+
+/// Runs jobs one after the other with IglooHost::run
+fn run_jobs_in_order<H: IglooHost + ?Sized>(host: &H, jobs: &[IglooJob]) -> Vec<IglooJobResult>
+{
+	jobs.iter().map(|job|
+	{
+		let start = Instant::now();
+		let args: Vec<&str> = job.args.iter().map(|a| a.as_str()).collect();
+		let result = host.run(&job.program, &args, &job.dir);
+		IglooJobResult { result, duration: start.elapsed() }
+	}).collect()
+}
+
+/// What igloo asks of the host system besides the filesystem: running programs
+/// and creating symlinks. RealHost asks the OS, MemHost answers from a table of
+/// known programs (used by the tests).
+pub trait IglooHost
+{
+	/// Runs program with args and waits for it. Fails if it could not be started,
+	/// with ErrorKind::NotFound if it is not on PATH
+	fn output(&self, program: &str, args: &[&str]) -> Result<IglooCmdOutput>;
+	/// Runs program with args in dir, printing its output as it goes.
+	/// Returns whether it succeeded
+	fn run(&self, program: &str, args: &[&str], dir: &Path) -> Result<bool>;
+	/// Runs jobs, up to parallel at a time. Results are in the order of jobs.
+	/// Runs them one after the other by default
+	fn run_jobs(&self, jobs: &[IglooJob], _parallel: usize) -> Vec<IglooJobResult>
+	{
+		run_jobs_in_order(self, jobs)
+	}
+	/// Checks that the user may create symlinks. Fails on Windows without
+	/// developer mode or administrator rights
+	fn symlinks_supported(&self) -> Result<()>;
+}
+
+/// The system igloo runs on
+pub struct RealHost;
+
+impl IglooHost for RealHost
+{
+	fn output(&self, program: &str, args: &[&str]) -> Result<IglooCmdOutput>
+	{
+		let output = Command::new(program).args(args).output()?;
+		Ok(IglooCmdOutput
+		{
+			success: output.status.success(),
+			stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+			stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+		})
+	}
+
+	fn run(&self, program: &str, args: &[&str], dir: &Path) -> Result<bool>
+	{
+		// the output of the program would mix with the JSON messages on stdout
+		if message_format() == MessageFormat::Json
+		{
+			let job = IglooJob
+			{
+				name: String::from(program),
+				program: String::from(program),
+				args: args.iter().map(|a| String::from(*a)).collect(),
+				dir: dir.to_path_buf(),
+			};
+			return run_prefixed(&job, MessageFormat::Json)
+		}
+		Ok(Command::new(program).args(args).current_dir(dir).status()?.success())
+	}
+
+	fn run_jobs(&self, jobs: &[IglooJob], parallel: usize) -> Vec<IglooJobResult>
+	{
+		if parallel <= 1 || jobs.len() <= 1
+		{
+			return run_jobs_in_order(self, jobs)
+		}
+		// workers do not see the message format of this thread
+		let format = message_format();
+		let next = AtomicUsize::new(0);
+		let results: Mutex<Vec<Option<IglooJobResult>>> =
+			Mutex::new(jobs.iter().map(|_j| None).collect());
+		std::thread::scope(|s|
+		{
+			for _worker in 0..parallel.min(jobs.len())
+			{
+				s.spawn(||
+				{
+					loop
+					{
+						let i = next.fetch_add(1, Ordering::SeqCst);
+						let job = match jobs.get(i)
+						{
+							Some(v) => v,
+							None => break,
+						};
+						let start = Instant::now();
+						let result = run_prefixed(job, format);
+						results.lock().unwrap()[i] = Some(IglooJobResult
+						{
+							result,
+							duration: start.elapsed(),
+						});
+					}
+				});
+			}
+		});
+		results.into_inner().unwrap().into_iter().map(|r| r.unwrap()).collect()
+	}
+
+	fn symlinks_supported(&self) -> Result<()>
+	{
+		let dir = std::env::temp_dir().join(format!("igloo-symlink-{}", std::process::id()));
+		std::fs::create_dir_all(&dir)?;
+		std::fs::write(dir.join("src"), b"")?;
+		#[cfg(unix)]
+		let res = std::os::unix::fs::symlink(dir.join("src"), dir.join("dst"));
+		#[cfg(windows)]
+		let res = std::os::windows::fs::symlink_file(dir.join("src"), dir.join("dst"));
+		let _ = std::fs::remove_dir_all(&dir);
+		res
+	}
+}
+
+/// Prints every line of output, whole lines at a time so parallel jobs do not
+/// interleave within a line. With the Json format every line is an output message
+fn print_prefixed<R: Read>(name: &str, output: R, stderr: bool, format: MessageFormat)
+{
+	for line in BufReader::new(output).lines()
+	{
+		let line = match line
+		{
+			Ok(v) => v,
+			Err(_e) => break,
+		};
+		if format == MessageFormat::Json
+		{
+			let stream = if stderr { "stderr" } else { "stdout" };
+			emit_line(&json!({ "reason": "output", "job": name, "stream": stream, "line": line }));
+		}
+		else if stderr
+		{
+			eprintln!("[{}] {}", name, line);
+		}
+		else
+		{
+			println!("[{}] {}", name, line);
+		}
+	}
+}
+
+/// Runs a job, prefixing its output with the name of the job
+fn run_prefixed(job: &IglooJob, format: MessageFormat) -> Result<bool>
+{
+	let mut child = Command::new(&job.program)
+		.args(&job.args)
+		.current_dir(&job.dir)
+		.stdin(Stdio::null())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::piped())
+		.spawn()?;
+	let stdout = child.stdout.take();
+	let stderr = child.stderr.take();
+	std::thread::scope(|s|
+	{
+		if let Some(v) = stdout
+		{
+			s.spawn(|| print_prefixed(&job.name, v, false, format));
+		}
+		if let Some(v) = stderr
+		{
+			s.spawn(|| print_prefixed(&job.name, v, true, format));
+		}
+	});
+	Ok(child.wait()?.success())
+}
+
+/// A host with a fixed set of programs. Programs print their output regardless
+/// of the arguments they are given. Programs started with run are recorded.
+pub struct MemHost
+{
+	programs: RefCell<BTreeMap<String, IglooCmdOutput>>,
+	runs: RefCell<Vec<(String, Vec<String>, PathBuf)>>,
+	symlinks: RefCell<bool>,
+}
+
+impl Default for MemHost
+{
+	fn default() -> Self
+	{
+		MemHost::new()
+	}
+}
+
+impl MemHost
+{
+	/// A host without programs that supports symlinks
+	pub fn new() -> MemHost
+	{
+		MemHost
+		{
+			programs: RefCell::new(BTreeMap::new()),
+			runs: RefCell::new(Vec::new()),
+			symlinks: RefCell::new(true),
+		}
+	}
+
+	/// Installs program, printing stdout when run
+	pub fn add_program(&self, program: &str, stdout: &str)
+	{
+		self.programs.borrow_mut().insert(String::from(program), IglooCmdOutput
+		{
+			success: true,
+			stdout: String::from(stdout),
+			stderr: String::new(),
+		});
+	}
+
+	/// Installs program, failing with stderr when run
+	pub fn add_failing_program(&self, program: &str, stderr: &str)
+	{
+		self.programs.borrow_mut().insert(String::from(program), IglooCmdOutput
+		{
+			success: false,
+			stdout: String::new(),
+			stderr: String::from(stderr),
+		});
+	}
+
+	/// Programs started with run: (program, args, directory)
+	pub fn runs(&self) -> Vec<(String, Vec<String>, PathBuf)>
+	{
+		self.runs.borrow().clone()
+	}
+
+	pub fn remove_program(&self, program: &str)
+	{
+		self.programs.borrow_mut().remove(program);
+	}
+
+	pub fn set_symlinks_supported(&self, supported: bool)
+	{
+		*self.symlinks.borrow_mut() = supported;
+	}
+}
+
+impl IglooHost for MemHost
+{
+	fn output(&self, program: &str, _args: &[&str]) -> Result<IglooCmdOutput>
+	{
+		match self.programs.borrow().get(program)
+		{
+			Some(v) => Ok(v.clone()),
+			None => Err(Error::new(ErrorKind::NotFound, format!("{} not found", program))),
+		}
+	}
+
+	fn run(&self, program: &str, args: &[&str], dir: &Path) -> Result<bool>
+	{
+		let output = self.output(program, args)?;
+		self.runs.borrow_mut().push((String::from(program),
+									 args.iter().map(|a| String::from(*a)).collect(),
+									 dir.to_path_buf()));
+		Ok(output.success)
+	}
+
+	fn symlinks_supported(&self) -> Result<()>
+	{
+		if *self.symlinks.borrow()
+		{
+			Ok(())
+		}
+		else
+		{
+			Err(Error::new(ErrorKind::PermissionDenied, "symlinks are not permitted"))
+		}
+	}
+}
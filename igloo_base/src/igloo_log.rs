@@ -0,0 +1,116 @@
+// Logging
+// igloo logs through the log crate. Everything igloo says to the user goes through
+// say!, diagnostics go through error!, warn!, info!, debug! and trace! and are
+// printed on stderr as "level: message" when their level is enabled. The level
+// only filters these records, command output is printed whatever it is:
+//
+// -q           errors only
+// (default)    warnings and errors
+// -v           info
+// -vv          debug, e.g. manifest merges
+// -vvv         trace
+//
+// Without -q or -v the level comes from $IGLOO_LOG (off, error, warn, info, debug
+// or trace). Logs can also be appended to a file, which the agent uses since it
+// has no terminal. $IGLOO_LOG_FILE names that file.
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+struct IglooLogger
+{
+	file: Mutex<Option<File>>,
+}
+
+static LOGGER: IglooLogger = IglooLogger { file: Mutex::new(None) };
+
+impl Log for IglooLogger
+{
+	fn enabled(&self, metadata: &Metadata) -> bool
+	{
+		metadata.level() <= log::max_level()
+	}
+
+	fn log(&self, record: &Record)
+	{
+		if !self.enabled(record.metadata())
+		{
+			return
+		}
+		let level = match record.level()
+		{
+			Level::Error => "error",
+			Level::Warn => "warning",
+			Level::Info => "info",
+			Level::Debug => "debug",
+			Level::Trace => "trace",
+		};
+		let mut file = self.file.lock().unwrap();
+		match file.as_mut()
+		{
+			Some(f) =>
+			{
+				let _ = writeln!(f, "{} [{}] {}", level, record.target(), record.args());
+			}
+			None => eprintln!("{}: {}", level, record.args()),
+		}
+	}
+
+	fn flush(&self)
+	{
+		if let Some(f) = self.file.lock().unwrap().as_mut()
+		{
+			let _ = f.flush();
+		}
+	}
+}
+
+/// Parses a level name of $IGLOO_LOG
+pub fn parse_log_level(name: &str) -> Option<LevelFilter>
+{
+	match name.trim().to_lowercase().as_str()
+	{
+		"off" => Some(LevelFilter::Off),
+		"error" => Some(LevelFilter::Error),
+		"warn" | "warning" => Some(LevelFilter::Warn),
+		"info" => Some(LevelFilter::Info),
+		"debug" => Some(LevelFilter::Debug),
+		"trace" => Some(LevelFilter::Trace),
+		_ => None,
+	}
+}
+
+/// The level for -q and the number of -v given. env is $IGLOO_LOG, used when
+/// neither is given
+pub fn log_level(quiet: bool, verbose: u64, env: Option<&str>) -> LevelFilter
+{
+	if quiet
+	{
+		return LevelFilter::Error
+	}
+	match verbose
+	{
+		0 => env.and_then(parse_log_level).unwrap_or(LevelFilter::Warn),
+		1 => LevelFilter::Info,
+		2 => LevelFilter::Debug,
+		_ => LevelFilter::Trace,
+	}
+}
+
+/// Installs the igloo logger at level. With file, logs are appended to it
+/// instead of printed. May be called again to change the level or file
+pub fn init_logging(level: LevelFilter, file: Option<&Path>) -> std::io::Result<()>
+{
+	let _ = log::set_logger(&LOGGER);
+	log::set_max_level(level);
+	let file = match file
+	{
+		Some(path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+		None => None,
+	};
+	*LOGGER.file.lock().unwrap() = file;
+	Ok(())
+}
@@ -0,0 +1,132 @@
+// Message format
+// With --message-format json igloo prints one JSON object per line on stdout, each
+// with a "reason" saying what it reports:
+//
+// {"reason":"path-created","kind":"file","path":"/work/blinky/src/main.c"}
+// {"reason":"build-finished","target":"samd21j18a","success":true,...}
+// {"reason":"finished","command":"build","success":true}
+// {"reason":"error","command":"build","code":39,"error":"BuildFailed"}
+//
+// Everything igloo prints for people goes through say! and moves to stderr, so
+// stdout only holds JSON lines. The format is kept per thread: a command runs on
+// one thread, and tests running in parallel do not see each other's messages.
+use serde_json::Value;
+
+use std::cell::Cell;
+#[cfg(any(test, feature = "record-messages"))]
+use std::cell::RefCell;
+use std::io::Write;
+
+/// How igloo reports what it does
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessageFormat
+{
+	/// text for people on stdout
+	Human,
+	/// JSON lines on stdout, text for people on stderr
+	Json,
+}
+
+thread_local!
+{
+	static FORMAT: Cell<MessageFormat> = const { Cell::new(MessageFormat::Human) };
+}
+
+#[cfg(any(test, feature = "record-messages"))]
+thread_local!
+{
+	/// JSON messages emitted on this thread, taken by take_messages
+	static EMITTED: RefCell<Vec<Value>> = const { RefCell::new(Vec::new()) };
+}
+
+pub fn set_message_format(format: MessageFormat)
+{
+	FORMAT.with(|f| f.set(format));
+}
+
+pub fn message_format() -> MessageFormat
+{
+	FORMAT.with(|f| f.get())
+}
+
+/// Prints a JSON message on stdout. Used directly by threads that do not
+/// share the message format of the command, emit should be used otherwise
+pub fn emit_line(message: &Value)
+{
+	let stdout = std::io::stdout();
+	let mut out = stdout.lock();
+	let _ = writeln!(out, "{}", message);
+}
+
+/// Emits a JSON message. Does nothing unless the message format is Json
+pub fn emit(message: Value)
+{
+	if message_format() != MessageFormat::Json
+	{
+		return
+	}
+	emit_line(&message);
+	#[cfg(any(test, feature = "record-messages"))]
+	EMITTED.with(|m| m.borrow_mut().push(message));
+}
+
+/// Emits value, a JSON object, as a message with the given reason
+pub fn emit_as(reason: &str, value: Value)
+{
+	let mut message = match value
+	{
+		Value::Object(v) => v,
+		v =>
+		{
+			let mut m = serde_json::Map::new();
+			m.insert(String::from("value"), v);
+			m
+		}
+	};
+	message.insert(String::from("reason"), Value::from(reason));
+	emit(Value::Object(message));
+}
+
+/// Removes and returns the messages emitted on this thread so far. Only
+/// tests record messages, with the record-messages feature
+#[cfg(any(test, feature = "record-messages"))]
+pub fn take_messages() -> Vec<Value>
+{
+	EMITTED.with(|m| m.borrow_mut().drain(..).collect())
+}
+
+/// println! for text meant for people: stdout normally, stderr when stdout
+/// holds JSON messages
+#[macro_export]
+macro_rules! say
+{
+	() => { $crate::say!("") };
+	($($arg:tt)*) =>
+	{
+		if $crate::message_format() == $crate::MessageFormat::Json
+		{
+			eprintln!($($arg)*);
+		}
+		else
+		{
+			println!($($arg)*);
+		}
+	};
+}
+
+/// print! counterpart of say!
+#[macro_export]
+macro_rules! say_raw
+{
+	($($arg:tt)*) =>
+	{
+		if $crate::message_format() == $crate::MessageFormat::Json
+		{
+			eprint!($($arg)*);
+		}
+		else
+		{
+			print!($($arg)*);
+		}
+	};
+}
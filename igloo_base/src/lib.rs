@@ -1,4 +1,15 @@
+mod checksum;
 mod env_info;
+mod igloo_fs;
+mod igloo_host;
+mod igloo_log;
+mod igloo_message;
+
+pub use checksum::*;
+pub use igloo_fs::*;
+pub use igloo_host::*;
+pub use igloo_log::*;
+pub use igloo_message::*;
 
 use std::path::PathBuf;
 use std::env;
@@ -15,20 +26,7 @@ mod tests {
 
 #[derive(Debug)]
 #[derive(PartialEq)]
-pub enum IglooInstType
-{
-	Null = -1,
-	New = 0,
-	Run = 1,
-	Flash = 2,
-	Debug = 3,
-	Clean = 4,
-	Erase = 5,
-	GenDoc = 6,
-}
-
-#[derive(Debug)]
-#[derive(PartialEq)]
+#[derive(Clone, Copy)]
 pub enum IglooErrType
 {
 	ErrNone =		 				0,
@@ -55,9 +53,86 @@ pub enum IglooErrType
 	/// inside a project without also generating a scripts directory.
 	/// The likely culprit of this failure is a user has messed with the folder
 	FailedToFindTargetScriptsDir = 12,
+	/// A project command (clean, gendoc, etc.) was called
+	/// from a directory that does not contain an .igloo folder
+	NotInsideProject = 13,
+	/// This means igloo couldn't find or parse the project file
+	/// located at .igloo/<PrjName>.toml
+	FailedToLoadProjectManifest = 14,
+	/// Doxygen could not be started or exited with a failure
+	FailedToGenerateDocs = 15,
+	/// igloo init was called in a directory that already contains
+	/// an .igloo folder
+	ProjectAlreadyInitialized = 16,
+	/// The requested project template could not be found in the
+	/// user template directory or ESF
+	InvalidTemplate = 17,
+	/// A template file could not be read or written to the project
+	FailedToApplyTemplate = 18,
+	/// igloo make regen --check found generated build files that differ
+	/// from what igloo would generate now
+	BuildFilesOutOfDate = 19,
+	/// The esf.memory table of a target manifest or a project carve-out
+	/// is malformed (missing origin/length, overlapping regions, bad sizes)
+	InvalidMemoryMap = 20,
+	/// The ELF of a target could not be read or parsed.
+	/// Usually the target has not been built yet
+	FailedToReadElf = 21,
+	/// The firmware of a target does not fit its memory regions
+	MemoryRegionOverflow = 22,
+	/// The linker .map file of a target could not be read or holds no memory map
+	FailedToReadMap = 23,
+	/// A firmware image could not be read, parsed or rendered
+	InvalidImage = 24,
+	/// A written firmware image did not read back as the image igloo wrote
+	ImageVerifyFailed = 25,
+	/// Images being merged set the same address to different values
+	ImageOverlap = 26,
+	/// The image header, crc or sign table of a target is malformed or
+	/// refers to symbols the ELF does not have
+	InvalidPostBuild = 27,
+	/// A signing or verifying key could not be read or parsed
+	FailedToReadKey = 28,
+	/// igloo image verify found a wrong header, CRC or signature
+	ImageCheckFailed = 29,
+	/// igloo doctor found a problem that keeps igloo from building or flashing
+	DoctorFoundProblems = 30,
+	/// A toolchain requirement, toolchains.toml index or lockfile is malformed
+	InvalidToolchain = 31,
+	/// No installed or installable toolchain satisfies a requirement
+	ToolchainNotFound = 32,
+	/// A toolchain archive could not be read, verified or unpacked
+	FailedToInstallToolchain = 33,
+	/// A module.toml or a module given to igloo add is malformed
+	InvalidModule = 34,
+	/// A module is in no registry, or not part of the project
+	ModuleNotFound = 35,
+	/// No version of a module satisfies every requirement on it
+	ModuleVersionConflict = 36,
+	/// A module supports none of the targets of the project
+	ModuleNotCompatible = 37,
+	/// igloo build --locked found the environment no longer matches igloo.lock
+	LockfileMismatch = 38,
+	/// make could not be started or failed to build a target
+	BuildFailed = 39,
+	/// igloo search or igloo info found no matching target or module
+	NotFoundInRegistry = 40,
+	/// igloo retarget was asked for a target the project already has
+	TargetAlreadyInProject = 41,
+	/// make push failed to flash a target
+	FlashFailed = 42,
+	/// openocd failed to erase a target
+	EraseFailed = 43,
+	/// make debug failed to start a debug session
+	DebugFailed = 44,
+	/// a command working on a single target was given several
+	MultipleTargetsSelected = 45,
+	/// The template has no board code for the target of the project
+	TemplateNotCompatible = 46,
+	/// an option was given a value it does not accept
+	InvalidArgument = 47,
 }
 
-
 #[derive(Debug)]
 #[derive(PartialEq)]
 #[derive(Clone)]
@@ -67,7 +142,7 @@ pub struct IglooEnvInfo
 	pub cwd: PathBuf,
 	// Home Directory
 	pub hd: PathBuf,
-	// ESF Directory
+	// ESF Directory, empty if $ESF_DIR is not set
 	pub esfd: PathBuf,
 }
 
@@ -89,20 +164,11 @@ impl IglooEnvInfo
 					std::process::exit(1);
 				}
 			},
+			// Checked in Igloo::start so igloo doctor can report it
 			esfd: match std::env::var("ESF_DIR")
 			{
-				Ok(v) =>
-				{
-					std::path::PathBuf::from(&v.to_owned())
-				}
-				Err(e) =>
-				{
-					// Note: Need to change new to return errors
-					// instead of exiting early
-					println!("Error: $ESF_DIR not defined as an environment\
-							  variable -- {:?}", e);
-					std::process::exit(1);
-				}
+				Ok(v) => PathBuf::from(v),
+				Err(_e) => PathBuf::new(),
 			}
 		}
 	}
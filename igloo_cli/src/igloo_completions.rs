@@ -0,0 +1,140 @@
+// Shell completions
+// igloo completions <shell> prints a completion script generated from the clap app.
+// Target names are not known when the script is generated, so the values of
+// -t/--target are completed by calling back into igloo:
+//
+// igloo __complete targets
+//
+// which prints the targets of the current project and of the master target
+// manifest, one per line. The hook is left out of help and of the scripts. igloo
+// prints errors and logs on stderr, which the scripts discard, so a project or
+// ESF that fails to load completes nothing.
+use crate::igloo_user_command;
+
+use clap::{App, ArgSettings};
+use clap_generate::generators::{Bash, Fish, Zsh};
+
+/// Shells igloo completions can generate a script for
+pub const COMPLETION_SHELLS: [&str; 3] = ["bash", "zsh", "fish"];
+
+/// The command completing target names
+const TARGETS_HOOK: &str = "igloo __complete targets 2>/dev/null";
+
+/// Names of the subcommands with a -t/--target option taking a value
+fn target_subcommands(app: &App, ret: &mut Vec<String>)
+{
+	for sub in app.get_subcommands()
+	{
+		let has_target = sub.get_arguments()
+			.any(|a| a.get_long() == Some("target") && a.is_set(ArgSettings::TakesValue));
+		if has_target
+		{
+			ret.push(String::from(sub.get_name()));
+		}
+		target_subcommands(sub, ret);
+	}
+}
+
+/// bash completes option values from the previous word, -t and --target
+/// are completed with the hook wherever they appear
+fn bash_targets(script: &str) -> String
+{
+	let files = "COMPREPLY=($(compgen -f \"${cur}\"))";
+	let hook = format!("COMPREPLY=($(compgen -W \"$({})\" -- \"${{cur}}\"))", TARGETS_HOOK);
+	let mut ret = String::new();
+	let mut target_case = false;
+	for line in script.lines()
+	{
+		let trimmed = line.trim();
+		if trimmed.ends_with(')') && !trimmed.contains(' ')
+		{
+			target_case = trimmed == "--target)" || trimmed == "-t)";
+		}
+		if target_case && trimmed == files
+		{
+			ret.push_str(&line.replace(files, &hook));
+		}
+		else
+		{
+			ret.push_str(line);
+		}
+		ret.push('\n');
+	}
+	ret
+}
+
+/// zsh takes the completion of an option value after its description,
+/// e.g. '-t+[MCU Target]:target:_igloo_targets'
+fn zsh_targets(script: &str) -> String
+{
+	let mut ret = String::new();
+	let mut target_spec = false;
+	for line in script.lines()
+	{
+		let spec = line.trim_start_matches('\'').trim_start_matches('*');
+		if line.starts_with('\'')
+		{
+			target_spec = spec.starts_with("-t+[") || spec.starts_with("--target=[");
+		}
+		if target_spec && line.ends_with("]' \\")
+		{
+			ret.push_str(&line.replacen("]' \\", "]:target:_igloo_targets' \\", 1));
+			target_spec = false;
+		}
+		else if line == "_igloo \"$@\""
+		{
+			ret.push_str("_igloo_targets() {\n");
+			ret.push_str("    local -a targets\n");
+			ret.push_str(&format!("    targets=(${{(f)\"$({})\"}})\n", TARGETS_HOOK));
+			ret.push_str("    _describe 'target' targets\n");
+			ret.push_str("}\n\n");
+			ret.push_str(line);
+		}
+		else
+		{
+			ret.push_str(line);
+		}
+		ret.push('\n');
+	}
+	ret
+}
+
+/// fish merges completions, the hook is added for every subcommand with a target
+fn fish_targets(script: &str, app: &App) -> String
+{
+	let mut subcommands: Vec<String> = Vec::new();
+	target_subcommands(app, &mut subcommands);
+	let mut ret = String::from(script);
+	if !ret.ends_with('\n')
+	{
+		ret.push('\n');
+	}
+	for sub in subcommands
+	{
+		ret.push_str(&format!(
+			"complete -c igloo -n \"__fish_seen_subcommand_from {}\" -s t -l target -f -a \"({})\"\n",
+			sub, TARGETS_HOOK));
+	}
+	ret
+}
+
+/// The completion script of shell, None if igloo has none for it
+pub fn completion_script(shell: &str) -> Option<String>
+{
+	let mut app = igloo_user_command();
+	let mut buf: Vec<u8> = Vec::new();
+	match shell
+	{
+		"bash" => clap_generate::generate::<Bash, _>(&mut app, "igloo", &mut buf),
+		"zsh" => clap_generate::generate::<Zsh, _>(&mut app, "igloo", &mut buf),
+		"fish" => clap_generate::generate::<Fish, _>(&mut app, "igloo", &mut buf),
+		_ => return None,
+	}
+	let script = String::from_utf8_lossy(&buf).into_owned();
+	Some(match shell
+	{
+		"bash" => bash_targets(&script),
+		"zsh" => zsh_targets(&script),
+		_ => fish_targets(&script, &app),
+	})
+}
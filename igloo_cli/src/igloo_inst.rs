@@ -0,0 +1,479 @@
+// Typed commands
+// Every subcommand parses into a struct holding its arguments, carried by the
+// IglooInstType variant of the subcommand:
+//
+// igloo new blinky -t samd21j18a   =>   New(NewArgs { name: "blinky", target: "samd21j18a", ... })
+//
+// Values clap cannot check, like the number given to -j, are parsed here so a
+// command never starts with arguments it cannot use.
+use igloo_base::IglooErrType;
+use igloo_base::IglooErrType::*;
+
+use clap::ArgMatches;
+
+/// The command igloo was asked to run
+#[derive(Debug, Clone, PartialEq)]
+pub enum IglooInstType
+{
+	New(NewArgs),
+	Init(InitArgs),
+	Template(TemplateCmd),
+	Make(MakeCmd),
+	Size(SizeArgs),
+	Analyze(AnalyzeArgs),
+	Image(ImageCmd),
+	Completions(CompletionsArgs),
+	/// the hidden __complete hook of the completion scripts
+	Complete(CompleteArgs),
+	Doctor(DoctorArgs),
+	Toolchain(ToolchainCmd),
+	Retarget(RetargetArgs),
+	Targets(TargetsArgs),
+	Search(SearchArgs),
+	Info(InfoArgs),
+	Add(ModuleArgs),
+	Remove(ModuleArgs),
+	Build(BuildArgs),
+	Run(TargetArgs),
+	Flash(TargetArgs),
+	Erase(TargetArgs),
+	Debug(TargetArgs),
+	Target(TargetCmd),
+	Clean(CleanArgs),
+	GenDoc(GenDocArgs),
+}
+
+/// -t/--target and --all-targets of the commands working on project targets,
+/// with the positional targets of the commands that took them before -t
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TargetArgs
+{
+	pub targets: Vec<String>,
+	pub all_targets: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewArgs
+{
+	pub name: String,
+	pub target: String,
+	pub template: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InitArgs
+{
+	/// defaults to the name of the directory
+	pub name: Option<String>,
+	pub target: String,
+	pub template: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemplateCmd
+{
+	List,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MakeCmd
+{
+	Regen(MakeRegenArgs),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MakeRegenArgs
+{
+	pub targets: TargetArgs,
+	pub check: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SizeArgs
+{
+	pub targets: TargetArgs,
+	pub top: usize,
+	pub json: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnalyzeArgs
+{
+	pub targets: TargetArgs,
+	pub by: String,
+	pub tree: bool,
+	pub diff: bool,
+	pub base: Option<String>,
+	pub json: bool,
+}
+
+/// Layout options shared by image convert and image merge
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ImageOptionArgs
+{
+	pub fill: Option<String>,
+	pub pad_to: Option<String>,
+	pub uf2_family: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImageCmd
+{
+	Convert(ImageConvertArgs),
+	Verify(ImageVerifyArgs),
+	Merge(ImageMergeArgs),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageConvertArgs
+{
+	pub targets: TargetArgs,
+	pub formats: Vec<String>,
+	pub output: Option<String>,
+	pub key: Option<String>,
+	pub options: ImageOptionArgs,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageVerifyArgs
+{
+	pub targets: TargetArgs,
+	pub image: Option<String>,
+	pub key: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageMergeArgs
+{
+	pub inputs: Vec<String>,
+	pub output: String,
+	pub options: ImageOptionArgs,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompletionsArgs
+{
+	pub shell: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompleteArgs
+{
+	/// what to print, only "targets" for now
+	pub what: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DoctorArgs
+{
+	pub json: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ToolchainCmd
+{
+	List,
+	/// toolchain specs, the ones the project needs when empty
+	Install(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetargetArgs
+{
+	pub old: String,
+	pub new: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TargetsArgs
+{
+	pub vendor: Option<String>,
+	pub core: Option<String>,
+	pub json: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchArgs
+{
+	/// the words of the query, joined with spaces when searching
+	pub query: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InfoArgs
+{
+	pub name: String,
+}
+
+/// Modules given to add and remove
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModuleArgs
+{
+	pub modules: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildArgs
+{
+	pub targets: TargetArgs,
+	pub locked: bool,
+	/// targets built at once, at least 1
+	pub jobs: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TargetCmd
+{
+	Default(TargetDefaultArgs),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TargetDefaultArgs
+{
+	pub name: Option<String>,
+	pub unset: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CleanArgs
+{
+	pub targets: TargetArgs,
+	pub all: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenDocArgs
+{
+	pub targets: TargetArgs,
+}
+
+fn value(args: &ArgMatches, name: &str) -> Option<String>
+{
+	args.value_of(name).map(String::from)
+}
+
+/// The value of an argument clap requires or gives a default to
+fn required(args: &ArgMatches, name: &str) -> String
+{
+	String::from(args.value_of(name).unwrap_or_default())
+}
+
+/// The number given to an option, at least min
+fn number(args: &ArgMatches, name: &str, min: usize) -> Result<usize, IglooErrType>
+{
+	let text = required(args, name);
+	match text.parse()
+	{
+		Ok(v) if v >= min => Ok(v),
+		_ =>
+		{
+			error!("--{} needs a number of at least {}, got \"{}\"", name, min, text);
+			Err(InvalidArgument)
+		}
+	}
+}
+
+fn values(args: &ArgMatches, name: &str) -> Vec<String>
+{
+	match args.values_of(name)
+	{
+		Some(v) => v.map(String::from).collect(),
+		None => Vec::new(),
+	}
+}
+
+impl TargetArgs
+{
+	fn from_matches(args: &ArgMatches) -> TargetArgs
+	{
+		let mut targets = values(args, "target");
+		targets.extend(values(args, "target_name"));
+		TargetArgs
+		{
+			targets,
+			all_targets: args.is_present("all_targets"),
+		}
+	}
+
+	/// The target names as the actions take them
+	pub fn names(&self) -> Vec<&str>
+	{
+		self.targets.iter().map(String::as_str).collect()
+	}
+}
+
+impl ImageOptionArgs
+{
+	fn from_matches(args: &ArgMatches) -> ImageOptionArgs
+	{
+		ImageOptionArgs
+		{
+			fill: value(args, "fill"),
+			pad_to: value(args, "pad_to"),
+			uf2_family: value(args, "uf2_family"),
+		}
+	}
+}
+
+impl IglooInstType
+{
+	/// Parses the matches of a subcommand called name
+	pub fn from_matches(name: &str, args: &ArgMatches) -> Result<IglooInstType, IglooErrType>
+	{
+		use IglooInstType::*;
+		let inst = match name
+		{
+			"new" => New(NewArgs
+			{
+				name: required(args, "project_name"),
+				target: required(args, "target"),
+				template: required(args, "template"),
+			}),
+			"init" => Init(InitArgs
+			{
+				name: value(args, "project_name"),
+				target: required(args, "target"),
+				template: required(args, "template"),
+			}),
+			"template" => match args.subcommand_name()
+			{
+				Some("list") => Template(TemplateCmd::List),
+				_ => return Err(UnknownInstType),
+			},
+			"make" => match args.subcommand()
+			{
+				Some(("regen", regen_args)) => Make(MakeCmd::Regen(MakeRegenArgs
+				{
+					targets: TargetArgs::from_matches(regen_args),
+					check: regen_args.is_present("check"),
+				})),
+				_ => return Err(UnknownInstType),
+			},
+			"size" => Size(SizeArgs
+			{
+				targets: TargetArgs::from_matches(args),
+				top: number(args, "top", 0)?,
+				json: args.is_present("json"),
+			}),
+			"analyze" => Analyze(AnalyzeArgs
+			{
+				targets: TargetArgs::from_matches(args),
+				by: required(args, "by"),
+				tree: args.is_present("tree"),
+				diff: args.is_present("diff"),
+				base: value(args, "base"),
+				json: args.is_present("json"),
+			}),
+			"image" => match args.subcommand()
+			{
+				Some(("convert", convert_args)) => Image(ImageCmd::Convert(ImageConvertArgs
+				{
+					targets: TargetArgs::from_matches(convert_args),
+					formats: values(convert_args, "format"),
+					output: value(convert_args, "output"),
+					key: value(convert_args, "key"),
+					options: ImageOptionArgs::from_matches(convert_args),
+				})),
+				Some(("verify", verify_args)) => Image(ImageCmd::Verify(ImageVerifyArgs
+				{
+					targets: TargetArgs::from_matches(verify_args),
+					image: value(verify_args, "image"),
+					key: value(verify_args, "key"),
+				})),
+				Some(("merge", merge_args)) => Image(ImageCmd::Merge(ImageMergeArgs
+				{
+					inputs: values(merge_args, "input"),
+					output: required(merge_args, "output"),
+					options: ImageOptionArgs::from_matches(merge_args),
+				})),
+				_ => return Err(UnknownInstType),
+			},
+			"completions" => Completions(CompletionsArgs { shell: required(args, "shell") }),
+			"__complete" => Complete(CompleteArgs { what: required(args, "what") }),
+			"doctor" => Doctor(DoctorArgs { json: args.is_present("json") }),
+			"toolchain" => match args.subcommand()
+			{
+				Some(("list", _list_args)) => Toolchain(ToolchainCmd::List),
+				Some(("install", install_args)) =>
+					Toolchain(ToolchainCmd::Install(values(install_args, "toolchain"))),
+				_ => return Err(UnknownInstType),
+			},
+			"retarget" => Retarget(RetargetArgs
+			{
+				old: required(args, "old"),
+				new: required(args, "new"),
+			}),
+			"targets" => Targets(TargetsArgs
+			{
+				vendor: value(args, "vendor"),
+				core: value(args, "core"),
+				json: args.is_present("json"),
+			}),
+			"search" => Search(SearchArgs { query: values(args, "query") }),
+			"info" => Info(InfoArgs { name: required(args, "name") }),
+			"add" => Add(ModuleArgs { modules: values(args, "module") }),
+			"remove" => Remove(ModuleArgs { modules: values(args, "module") }),
+			"build" => Build(BuildArgs
+			{
+				targets: TargetArgs::from_matches(args),
+				locked: args.is_present("locked"),
+				jobs: number(args, "jobs", 1)?,
+			}),
+			"run" => Run(TargetArgs::from_matches(args)),
+			"flash" => Flash(TargetArgs::from_matches(args)),
+			"erase" => Erase(TargetArgs::from_matches(args)),
+			"debug" => Debug(TargetArgs::from_matches(args)),
+			"target" => match args.subcommand()
+			{
+				Some(("default", default_args)) => Target(TargetCmd::Default(TargetDefaultArgs
+				{
+					name: value(default_args, "name"),
+					unset: default_args.is_present("unset"),
+				})),
+				_ => return Err(UnknownInstType),
+			},
+			"clean" => Clean(CleanArgs
+			{
+				targets: TargetArgs::from_matches(args),
+				all: args.is_present("all"),
+			}),
+			"gendoc" => GenDoc(GenDocArgs { targets: TargetArgs::from_matches(args) }),
+			_ => return Err(UnknownInstType),
+		};
+		Ok(inst)
+	}
+
+	/// The name of the subcommand, as typed on the command line
+	pub fn name(&self) -> &'static str
+	{
+		use IglooInstType::*;
+		match self
+		{
+			New(_) => "new",
+			Init(_) => "init",
+			Template(_) => "template",
+			Make(_) => "make",
+			Size(_) => "size",
+			Analyze(_) => "analyze",
+			Image(_) => "image",
+			Completions(_) => "completions",
+			Complete(_) => "__complete",
+			Doctor(_) => "doctor",
+			Toolchain(_) => "toolchain",
+			Retarget(_) => "retarget",
+			Targets(_) => "targets",
+			Search(_) => "search",
+			Info(_) => "info",
+			Add(_) => "add",
+			Remove(_) => "remove",
+			Build(_) => "build",
+			Run(_) => "run",
+			Flash(_) => "flash",
+			Erase(_) => "erase",
+			Debug(_) => "debug",
+			Target(_) => "target",
+			Clean(_) => "clean",
+			GenDoc(_) => "gendoc",
+		}
+	}
+}
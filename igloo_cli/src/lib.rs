@@ -1,8 +1,9 @@
 extern crate clap;
 extern crate config;
+#[macro_use]
+extern crate log;
 
 use igloo_base::*;
-use igloo_base::IglooInstType::*;
 use igloo_base::IglooErrType::*;
 
 use clap::{App, Arg, ArgMatches};
@@ -16,13 +17,68 @@ mod tests {
 }
 
 
+mod igloo_completions;
+mod igloo_inst;
+
+pub use igloo_completions::*;
+pub use igloo_inst::*;
+
 /// runs the clap initializer to get command line arguments
 pub fn igloo_app() -> ArgMatches
 {
-	let ret_app = App::new("igloo")
+	igloo_command().get_matches()
+}
+
+/// parses args instead of the process arguments. The first arg is the binary name.
+pub fn igloo_app_from(args: &[&str]) -> ArgMatches
+{
+	igloo_command().get_matches_from(args)
+}
+
+/// builds the clap app describing every igloo command
+pub fn igloo_command() -> App<'static>
+{
+	igloo_user_command()
+		.subcommand(App::new("__complete")
+					.about("Prints the values the completion scripts offer")
+					.setting(clap::AppSettings::Hidden)
+					.arg(Arg::new("what")
+						 .required(true)
+						 .possible_values(&["targets"])),)
+}
+
+/// the clap app without the hidden commands, which completion scripts leave out
+pub fn igloo_user_command() -> App<'static>
+{
+	App::new("igloo")
 		.about(clap::crate_description!())
 		.version(clap::crate_version!())
 		.setting(clap::AppSettings::SubcommandRequiredElseHelp)
+		.arg(Arg::new("dry-run")
+			 .about("Prints the filesystem changes a command would make \
+					 without making them")
+			 .long("dry-run")
+			 .global(true))
+		.arg(Arg::new("message-format")
+			 .about("Prints results as JSON lines on stdout instead of text")
+			 .long("message-format")
+			 .takes_value(true)
+			 .possible_values(&["human", "json"])
+			 .default_value("human")
+			 .global(true))
+		.arg(Arg::new("quiet")
+			 .about("Prints errors only")
+			 .short('q')
+			 .long("quiet")
+			 .global(true))
+		.arg(Arg::new("verbose")
+			 .about("Prints more about what igloo does, -vv for debug output. \
+					 $IGLOO_LOG sets the level otherwise")
+			 .short('v')
+			 .long("verbose")
+			 .multiple_occurrences(true)
+			 .conflicts_with("quiet")
+			 .global(true))
 		.subcommand(App::new("new")
 					.about("Creates a new igloo project")
 					.arg(Arg::new("project_name")
@@ -33,61 +89,351 @@ pub fn igloo_app() -> ArgMatches
 						.about("MCU Target")
 						.short('t')
 						.long("target")
-						.takes_value(true)),)
+						.takes_value(true))
+					.arg(Arg::new("template")
+						 .required(false)
+						 .about("Project template used for the starter code")
+						 .long("template")
+						 .takes_value(true)
+						 .default_value("empty")),)
+		.subcommand(App::new("init")
+					.about("Creates an igloo project in the current directory. \
+							Existing files are left untouched.")
+					.arg(Arg::new("target")
+						.required(true)
+						.about("MCU Target")
+						.short('t')
+						.long("target")
+						.takes_value(true))
+					.arg(Arg::new("project_name")
+						 .required(false)
+						 .about("The name of the project\n\
+								 Defaults to the name of the current directory")
+						 .short('n')
+						 .long("name")
+						 .takes_value(true))
+					.arg(Arg::new("template")
+						 .required(false)
+						 .about("Project template used for the starter code")
+						 .long("template")
+						 .takes_value(true)
+						 .default_value("empty")),)
+		.subcommand(App::new("template")
+					.about("Manages project templates")
+					.setting(clap::AppSettings::SubcommandRequiredElseHelp)
+					.subcommand(App::new("list")
+								.about("Lists available project templates")))
+		.subcommand(App::new("make")
+					.about("Manages the generated build files")
+					.setting(clap::AppSettings::SubcommandRequiredElseHelp)
+					.subcommand(App::new("regen")
+								.about("Regenerates Makefiles and igloo.h from the manifests")
+								.args(target_args())
+								.arg(Arg::new("check")
+									 .required(false)
+									 .about("Fails if the build files on disk differ from \
+											 the regenerated ones instead of rewriting them")
+									 .long("check"))))
+		.subcommand(App::new("size")
+					.about("Reports flash and RAM usage of the built firmware")
+					.args(target_args())
+					.arg(positional_target_arg())
+					.arg(Arg::new("top")
+						 .required(false)
+						 .about("Number of largest symbols to list")
+						 .long("top")
+						 .takes_value(true)
+						 .default_value("10"))
+					.arg(Arg::new("json")
+						 .required(false)
+						 .about("Prints the report as JSON")
+						 .long("json")),)
+		.subcommand(App::new("analyze")
+					.about("Attributes code and data size to libraries, files and \
+							sections using the linker map")
+					.args(target_args())
+					.arg(positional_target_arg())
+					.arg(Arg::new("by")
+						 .required(false)
+						 .about("Groups sizes by library (user, ESF, libc...), \
+								 file or output section")
+						 .long("by")
+						 .takes_value(true)
+						 .possible_values(&["group", "file", "section"])
+						 .default_value("group"))
+					.arg(Arg::new("tree")
+						 .required(false)
+						 .about("Prints libraries, files and symbols as a tree")
+						 .long("tree"))
+					.arg(Arg::new("diff")
+						 .required(false)
+						 .about("Compares with the previous build")
+						 .long("diff"))
+					.arg(Arg::new("base")
+						 .required(false)
+						 .about("Compares with another build, given by its .map file")
+						 .long("base")
+						 .takes_value(true))
+					.arg(Arg::new("json")
+						 .required(false)
+						 .about("Prints the analysis as JSON")
+						 .long("json")),)
+		.subcommand(App::new("image")
+					.about("Converts and merges firmware images without objcopy")
+					.setting(clap::AppSettings::SubcommandRequiredElseHelp)
+					.subcommand(App::new("convert")
+								.about("Converts the built ELF of target(s) to other formats")
+								.args(target_args())
+								.arg(positional_target_arg())
+								.arg(Arg::new("format")
+									 .required(true)
+									 .about("Output format: hex, srec, bin or uf2")
+									 .short('f')
+									 .long("format")
+									 .takes_value(true)
+									 .multiple_occurrences(true))
+								.arg(Arg::new("output")
+									 .required(false)
+									 .about("Output file\n\
+											 Defaults to <PrjName>.<format> next to the ELF")
+									 .short('o')
+									 .long("output")
+									 .takes_value(true))
+								.arg(Arg::new("key")
+									 .required(false)
+									 .about("Signing key, overrides image.sign.key \
+											 of the project file")
+									 .short('k')
+									 .long("key")
+									 .takes_value(true))
+								.args(image_option_args()))
+					.subcommand(App::new("verify")
+								.about("Checks the header, CRC and signature of converted images")
+								.args(target_args())
+								.arg(positional_target_arg())
+								.arg(Arg::new("image")
+									 .required(false)
+									 .about("Image to verify (bin images need @address)\n\
+											 Defaults to every converted image next to the ELF")
+									 .short('i')
+									 .long("image")
+									 .takes_value(true))
+								.arg(Arg::new("key")
+									 .required(false)
+									 .about("Public or private key, overrides image.sign \
+											 of the project file")
+									 .short('k')
+									 .long("key")
+									 .takes_value(true)))
+					.subcommand(App::new("merge")
+								.about("Merges images, e.g. a bootloader and an application")
+								.arg(Arg::new("input")
+									 .required(true)
+									 .about("Images to merge (elf, hex, srec, uf2, bin@address)")
+									 .multiple(true))
+								.arg(Arg::new("output")
+									 .required(true)
+									 .about("Merged image, the format follows the extension")
+									 .short('o')
+									 .long("output")
+									 .takes_value(true))
+								.args(image_option_args())))
+		.subcommand(App::new("completions")
+					.about("Prints a shell completion script, e.g. \
+							igloo completions bash > /etc/bash_completion.d/igloo")
+					.arg(Arg::new("shell")
+						 .required(true)
+						 .about("Shell to complete in")
+						 .possible_values(&COMPLETION_SHELLS)),)
+		.subcommand(App::new("doctor")
+					.about("Checks ESF, the manifests, toolchains and tools igloo needs")
+					.arg(Arg::new("json")
+						 .required(false)
+						 .about("Prints the results as JSON")
+						 .long("json")),)
+		.subcommand(App::new("toolchain")
+					.about("Installs pinned toolchains from local archives and mirrors")
+					.setting(clap::AppSettings::SubcommandRequiredElseHelp)
+					.subcommand(App::new("list")
+								.about("Lists installed and available toolchains"))
+					.subcommand(App::new("install")
+								.about("Installs toolchains into ~/.igloo/toolchains")
+								.arg(Arg::new("toolchain")
+									 .required(false)
+									 .about("Toolchain(s) as name or name@version\n\
+											 Defaults to the toolchains the project needs")
+									 .multiple(true))),)
+		.subcommand(App::new("retarget")
+					.about("Replaces a target of the project with another MCU and reports \
+							what the old one provided that the new one does not")
+					.arg(Arg::new("old")
+						 .required(true)
+						 .about("Target of the project to replace"))
+					.arg(Arg::new("new")
+						 .required(true)
+						 .about("Target to build for instead")),)
+		.subcommand(App::new("targets")
+					.about("Lists the targets of ESF and whether the files they reference exist")
+					.arg(Arg::new("vendor")
+						 .required(false)
+						 .about("Only lists targets whose vendor contains this")
+						 .long("vendor")
+						 .takes_value(true))
+					.arg(Arg::new("core")
+						 .required(false)
+						 .about("Only lists targets whose core contains this, e.g. cortex-m4")
+						 .long("core")
+						 .takes_value(true))
+					.arg(Arg::new("json")
+						 .required(false)
+						 .about("Prints the targets as JSON")
+						 .long("json")),)
+		.subcommand(App::new("search")
+					.about("Searches the targets and modules of ESF and the registries")
+					.arg(Arg::new("query")
+						 .required(false)
+						 .about("Words to look for in names, descriptions, vendors, \
+								 cores and tags\n\
+								 Lists everything if not given")
+						 .multiple(true)),)
+		.subcommand(App::new("info")
+					.about("Describes a target or module")
+					.arg(Arg::new("name")
+						 .required(true)
+						 .about("Name of the target or module")),)
+		.subcommand(App::new("add")
+					.about("Adds software modules to the project")
+					.arg(Arg::new("module")
+						 .required(true)
+						 .about("Module(s) as name or name@version\n\
+								 Defaults to the newest version in the registries")
+						 .multiple(true)),)
+		.subcommand(App::new("remove")
+					.about("Removes software modules from the project")
+					.arg(Arg::new("module")
+						 .required(true)
+						 .about("Module(s) to remove")
+						 .multiple(true)),)
+		.subcommand(App::new("build")
+					.about("Builds target(s) with their generated Makefiles")
+					.args(target_args())
+					.arg(Arg::new("locked")
+						 .required(false)
+						 .about("Fails if ESF, the target manifests, modules or \
+								 toolchains no longer match igloo.lock")
+						 .long("locked"))
+					.arg(Arg::new("jobs")
+						 .required(false)
+						 .about("Number of targets to build at a time")
+						 .short('j')
+						 .long("jobs")
+						 .takes_value(true)
+						 .default_value("1")),)
 		.subcommand(App::new("run")
 					.about("Compiles if needed. Flashes MCU and runs \
 							current project on default target.")
-					.arg(Arg::new("build_type")
-						 .required(false)
-						 .about("Release or Debug build type\n\
-								 Defaults to Debug")),)
+					.args(target_args()))
 		.subcommand(App::new("flash")
 					.about("Flashes target mcu or multiple mcus")
-					.arg(Arg::new("build_type")
-						 .required(false)
-						 .about("Release or Debug build type\n\
-								 Defaults to Debug")),)
+					.args(target_args()))
 		.subcommand(App::new("erase")
-					.about("Erases flash from target mcu or target mcus"))
-		.get_matches();
+					.about("Erases flash from target mcu or target mcus")
+					.args(target_args()))
+		.subcommand(App::new("debug")
+					.about("Starts a gdb session on the target mcu")
+					.args(target_args()))
+		.subcommand(App::new("clean")
+					.about("Removes build outputs from target(s)")
+					.args(target_args())
+					.arg(Arg::new("all")
+						 .required(false)
+						 .about("Also removes generated build files (Makefile)")
+						 .long("all")),)
+		.subcommand(App::new("target")
+					.about("Manages the targets of the project")
+					.setting(clap::AppSettings::SubcommandRequiredElseHelp)
+					.subcommand(App::new("default")
+								.about("Shows or sets the target commands run on \
+										when none is given")
+								.arg(Arg::new("name")
+									 .required(false)
+									 .about("Target of the project to make the default"))
+								.arg(Arg::new("unset")
+									 .required(false)
+									 .about("Removes the default target, commands run on \
+											 every target")
+									 .long("unset")
+									 .conflicts_with("name"))))
+		.subcommand(App::new("gendoc")
+					.about("Generates doxygen documentation for the project \
+							and its linked ESF sources")
+					.args(target_args()),)
+}
+
+/// target selection shared by the commands working on targets of the project
+fn target_args() -> Vec<Arg<'static>>
+{
+	vec![
+		Arg::new("target")
+			.required(false)
+			.about("Target(s) to work on\n\
+					Defaults to the default target of the project, \
+					or every target if it has none")
+			.short('t')
+			.long("target")
+			.takes_value(true)
+			.multiple_occurrences(true),
+		Arg::new("all_targets")
+			.required(false)
+			.about("Works on every target of the project")
+			.long("all-targets")
+			.conflicts_with("target"),
+	]
+}
 
-	ret_app
+/// igloo size <target> and the like, which predate -t. Same as -t
+fn positional_target_arg() -> Arg<'static>
+{
+	Arg::new("target_name")
+		.required(false)
+		.about("Target(s) to work on, same as -t")
+		.multiple(true)
+		.conflicts_with("all_targets")
 }
 
+/// options shared by the image subcommands
+fn image_option_args() -> Vec<Arg<'static>>
+{
+	vec![
+		Arg::new("fill")
+			.required(false)
+			.about("Byte used for gaps in bin images\n\
+					Defaults to 0xFF")
+			.long("fill")
+			.takes_value(true),
+		Arg::new("pad_to")
+			.required(false)
+			.about("Pads bin images with the fill byte up to this size")
+			.long("pad-to")
+			.takes_value(true),
+		Arg::new("uf2_family")
+			.required(false)
+			.about("UF2 family id, e.g. 0x68ed2b88 for SAMD21")
+			.long("uf2-family")
+			.takes_value(true),
+	]
+}
 
+/// parses the subcommand of args into the command igloo runs
 pub fn igloo_subcommand(args: &ArgMatches) -> Result<IglooInstType, IglooErrType>
 {
-	let mut _res_type: IglooInstType = Null;
-	match args.subcommand_name()
+	match args.subcommand()
 	{
-		Some("new") =>
-		{
-			println!("Igloo new was called!");
-			_res_type = New;
-		}
-		Some("run") =>
+		Some((name, sub_args)) =>
 		{
-			println!("Igloo run was called!");
-			_res_type = Run;
+			debug!("Igloo {} was called!", name);
+			IglooInstType::from_matches(name, sub_args)
 		}
-		Some("flash") =>
-		{
-			println!("Igloo flash was called!");
-			_res_type = Flash;
-		}
-		Some("erase") =>
-		{
-			println!("Igloo erase was called!");
-			_res_type = Erase;
-		}
-		None => unreachable!(),
-		_ => unreachable!(),
+		None => Err(UnknownInstType),
 	}
-
-	if _res_type == Null
-	{
-		return Err(ErrUnknown)
-	}
-
-	Ok(_res_type)
 }
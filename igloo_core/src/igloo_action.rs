@@ -2,50 +2,1787 @@ use igloo_base::*;
 use igloo_base::IglooErrType::*;
 
 use crate::Igloo;
+use crate::igloo_analyze::*;
+use crate::igloo_doctor::*;
+use crate::igloo_elf::IglooElf;
+use crate::igloo_image::*;
+use crate::igloo_linker::parse_size;
+use crate::igloo_lock::*;
+use crate::igloo_module::*;
+use crate::igloo_postbuild::*;
 use crate::igloo_project::IglooPrj;
+use crate::igloo_registry::*;
+use crate::igloo_retarget::*;
+use crate::igloo_size::*;
+use crate::igloo_target::IglooTarget;
+use crate::igloo_template::IglooTemplate;
+use crate::igloo_toolchain::*;
+use semver::{Version, VersionReq};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::path::Path;
 
-pub fn run(prj_name: &str, target: &str) -> IglooErrType
+/// Builds the given targets if needed and flashes them
+pub fn run(inst: &Igloo, targets: &[&str], all_targets: bool) -> IglooErrType
 {
-	let res_err: IglooErrType = ErrNone;
-	res_err
+	let res_err = build(inst, targets, all_targets, false, 1);
+	if res_err != ErrNone
+	{
+		return res_err
+	}
+	flash(inst, targets, all_targets)
+}
+
+/// Runs program in the directory of every target, failing with err on the first
+/// target it fails for. Dry runs only print what would run
+fn run_in_targets(inst: &Igloo, targets: &[&IglooTarget], program: &str,
+				  args: &[String], err: IglooErrType) -> IglooErrType
+{
+	let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+	for target in targets
+	{
+		if inst.fs.plan().is_some()
+		{
+			say!("Dry run, skipping {} {} in {}", program, args.join(" "),
+					 target.root.display());
+			continue
+		}
+		let res = inst.host.run(program, &args, &target.root);
+		emit(json!({
+			"reason": "program-finished",
+			"program": program,
+			"args": args,
+			"target": target.name,
+			"success": matches!(res, Ok(true)),
+		}));
+		match res
+		{
+			Ok(true) => (),
+			Ok(false) =>
+			{
+				say!("{} failed for target {}", program, target.name);
+				return err
+			}
+			Err(e) =>
+			{
+				say!("Failed to run {}: {:?}", program, e);
+				return err
+			}
+		}
+	}
+	ErrNone
+}
+
+/// Flashes the given targets with make push
+pub fn flash(inst: &Igloo, targets: &[&str], all_targets: bool) -> IglooErrType
+{
+	let project = match IglooPrj::open(inst)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			error!("Failed to open project: {:?}", e);
+			return e
+		}
+	};
+	let targets = match project.select_targets(targets, all_targets)
+	{
+		Ok(v) => v,
+		Err(e) => return e,
+	};
+	for target in &targets
+	{
+		say!("Flashing target {}", target.name);
+	}
+	run_in_targets(inst, &targets, "make", &[String::from("push")], FlashFailed)
+}
+
+/// Erases the flash of the given targets with openocd and their openocd config
+pub fn erase(inst: &Igloo, targets: &[&str], all_targets: bool) -> IglooErrType
+{
+	let project = match IglooPrj::open(inst)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			error!("Failed to open project: {:?}", e);
+			return e
+		}
+	};
+	let targets = match project.select_targets(targets, all_targets)
+	{
+		Ok(v) => v,
+		Err(e) => return e,
+	};
+	for target in targets
+	{
+		say!("Erasing target {}", target.name);
+		let args: Vec<String> = vec![
+			String::from("-f"),
+			format!("scripts/{}.cfg", target.name),
+			String::from("-c"),
+			String::from("init; reset halt; flash erase_sector 0 0 last; shutdown"),
+		];
+		let res_err = run_in_targets(inst, &[target], "openocd", &args, EraseFailed);
+		if res_err != ErrNone
+		{
+			return res_err
+		}
+	}
+	ErrNone
+}
+
+/// Starts a gdb session on a single target with make debug
+pub fn debug(inst: &Igloo, targets: &[&str], all_targets: bool) -> IglooErrType
+{
+	let project = match IglooPrj::open(inst)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			error!("Failed to open project: {:?}", e);
+			return e
+		}
+	};
+	let targets = match project.select_targets(targets, all_targets)
+	{
+		Ok(v) => v,
+		Err(e) => return e,
+	};
+	if targets.len() != 1
+	{
+		say!("igloo debug works on a single target, pick one with -t or \
+				  igloo target default");
+		return MultipleTargetsSelected
+	}
+	say!("Debugging target {}", targets[0].name);
+	run_in_targets(inst, &targets, "make", &[String::from("debug")], DebugFailed)
+}
+
+/// Shows the default target of the project, or sets it to name
+/// unset -- removes the default target instead
+pub fn target_default(inst: &Igloo, name: Option<&str>, unset: bool) -> IglooErrType
+{
+	let project = match IglooPrj::open(inst)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			error!("Failed to open project: {:?}", e);
+			return e
+		}
+	};
+	if name.is_none() && !unset
+	{
+		match project.default_target()
+		{
+			Some(v) => say!("{}", v),
+			None => say!("No default target, commands work on every target"),
+		}
+		return ErrNone
+	}
+	project.set_default_target(name)
 }
 
-pub fn new(inst: &Igloo, prj_name: &str, target: &str)
+pub fn new(inst: &Igloo, prj_name: &str, target: &str, template: &str)
 		   -> IglooErrType
 {
 	let mut res_err: IglooErrType = ErrNone;
 	// Check if we are already inside of an igloo project
 	// Creating an igloo project inside an igloo project
 	// is a no no
-	if std::path::Path::new(".igloo").exists()
+	if inst.fs.exists(&inst.env.cwd.join(".igloo"))
 	{
 		res_err = NewCalledInsideProject;
 		return res_err
 	}
 	// Check if the project folder already exists
 	// Don't want to accidentally overwrite anything
-	if std::path::Path::new(prj_name).exists()
+	if inst.fs.exists(&inst.env.cwd.join(prj_name))
 	{
 		res_err = FolderAlreadyExists;
 		return res_err
 	}
 
+	// Find the template before creating anything
+	let template = match IglooTemplate::find(inst, template)
+	{
+		Ok(v) => v,
+		Err(e) => return e,
+	};
+
 	let project = IglooPrj::new(inst, prj_name, target);
 	match project
 	{
 		Err(e) =>
 		{
-			println!("Error spawning project: {:?}", e);
+			error!("Failed to create project: {:?}", e);
 			res_err = e;
 			return res_err
 		}
 		_ => (),
 	}
 
-	let res_err = project.unwrap().populate();
+	let project = project.unwrap();
+	let res_err = project.check_template(&template);
+	if res_err != ErrNone
+	{
+		return res_err
+	}
+	let res_err = project.populate();
 	if res_err  != ErrNone
 	{
 		return res_err
 	}
+	project.gen_from_template(&template)
+}
+
+/// Adopts the current working directory as an igloo project
+/// Existing source files are not overwritten. Every file igloo would add is
+/// reported, along with the files it skipped because they are already present.
+pub fn init(inst: &Igloo, prj_name: Option<&str>, target: &str, template: &str)
+			-> IglooErrType
+{
+	if inst.fs.exists(&inst.env.cwd.join(".igloo"))
+	{
+		return ProjectAlreadyInitialized
+	}
+
+	let template = match IglooTemplate::find(inst, template)
+	{
+		Ok(v) => v,
+		Err(e) => return e,
+	};
+
+	let cwd = inst.env.cwd.clone();
+	let prj_name = match prj_name
+	{
+		Some(v) => String::from(v),
+		None => match cwd.file_name()
+		{
+			Some(v) => v.to_string_lossy().into_owned(),
+			None => return InvalidProjectName,
+		},
+	};
+
+	let project = match IglooPrj::new_at(inst, &prj_name, target, cwd)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			error!("Failed to create project: {:?}", e);
+			return e
+		}
+	};
+
+	let res_err = project.check_template(&template);
+	if res_err != ErrNone
+	{
+		return res_err
+	}
+	let res_err = project.populate();
+	if res_err != ErrNone
+	{
+		return res_err
+	}
+	project.gen_from_template(&template)
+}
+
+/// Prints every available project template
+pub fn template_list(inst: &Igloo) -> IglooErrType
+{
+	for template in IglooTemplate::list(inst)
+	{
+		say!("{:<16} {:<8} {}", template.name, template.source, template.description);
+		emit(json!({ "reason": "template", "name": template.name,
+					 "source": template.source, "description": template.description }));
+	}
+	ErrNone
+}
+
+/// Builds the given targets of the current project with make. Build files that
+/// are out of date are regenerated first, igloo.lock included
+/// targets -- names of the targets to build, the default target if empty
+/// all_targets -- builds every target
+/// locked -- fails instead if the project no longer resolves to igloo.lock
+/// parallel -- number of targets built at a time
+/// Every target is built even if one fails, a summary of them is printed at the end
+pub fn build(inst: &Igloo, targets: &[&str], all_targets: bool, locked: bool,
+			 parallel: usize) -> IglooErrType
+{
+	let project = match IglooPrj::open(inst)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			error!("Failed to open project: {:?}", e);
+			return e
+		}
+	};
+	let build_targets = match project.select_targets(targets, all_targets)
+	{
+		Ok(v) => v,
+		Err(e) => return e,
+	};
+	let names: Vec<&str> = build_targets.iter().map(|t| t.name.as_str()).collect();
+
+	if locked
+	{
+		let lock = match IglooLock::read(inst.fs.as_ref(), &project.project_dir)
+		{
+			Some(v) => v,
+			None =>
+			{
+				error!("the project has no {}, run igloo make regen to create it",
+						 LOCK_FILE);
+				return LockfileMismatch
+			}
+		};
+		let differences = lock.differences(&project.lock());
+		if !differences.is_empty()
+		{
+			error!("the project no longer matches {}:", LOCK_FILE);
+			for difference in differences
+			{
+				say!("  {}", difference);
+			}
+			say!("Run igloo build without --locked to update it");
+			return LockfileMismatch
+		}
+	}
+
+	let res_err = make_regen(inst, &names, false, false);
+	if res_err != ErrNone
+	{
+		return res_err
+	}
+
+	let mut jobs: Vec<IglooJob> = Vec::new();
+	for target in &build_targets
+	{
+		if inst.fs.plan().is_some()
+		{
+			say!("Dry run, skipping make in {}", target.root.display());
+			continue
+		}
+		say!("Building target {}", target.name);
+		jobs.push(IglooJob
+		{
+			name: target.name.clone(),
+			program: String::from("make"),
+			args: vec![String::from("all")],
+			dir: target.root.clone(),
+		});
+	}
+	if jobs.is_empty()
+	{
+		return ErrNone
+	}
+	let results = inst.host.run_jobs(&jobs, parallel);
+
+	let mut res_err = ErrNone;
+	say!("\n{:<16} {:<8} {:>8}  {}", "Target", "Status", "Time", "Memory");
+	for (target, result) in build_targets.iter().zip(results.iter())
+	{
+		let status = match &result.result
+		{
+			Ok(true) => "ok",
+			Ok(false) => "failed",
+			Err(_e) => "error",
+		};
+		let report = match &result.result
+		{
+			Ok(true) => build_report(inst, &project, target),
+			_ => None,
+		};
+		let usage = match (&result.result, &report)
+		{
+			(Ok(true), Some(v)) => v.usage(),
+			(Ok(true), None) => String::from("-"),
+			(Ok(false), _) => String::new(),
+			(Err(e), _) => format!("could not run make: {}", e),
+		};
+		say!("{:<16} {:<8} {:>7.1}s  {}", target.name, status,
+				 result.duration.as_secs_f64(), usage);
+		emit(json!({
+			"reason": "build-finished",
+			"target": target.name,
+			"success": matches!(result.result, Ok(true)),
+			"duration": result.duration.as_secs_f64(),
+			"artifacts": build_artifacts(inst, &project, target),
+			"regions": report.map(|r| r.to_json()["regions"].clone()),
+		}));
+		if !matches!(result.result, Ok(true))
+		{
+			res_err = BuildFailed;
+		}
+	}
+	res_err
+}
+
+/// Size report of a freshly built target for the build summary
+fn build_report(inst: &Igloo, project: &IglooPrj, target: &IglooTarget) -> Option<IglooSizeReport>
+{
+	let elf_path = project.elf_path(target);
+	let elf = match inst.fs.read(&elf_path).map(IglooElf::parse)
+	{
+		Ok(Ok(v)) => v,
+		_ => return None,
+	};
+	Some(IglooSizeReport::from_elf(target, &elf_path, &elf, 0))
+}
+
+/// Paths of the ELF, map, hex and bin files a build left for a target
+fn build_artifacts(inst: &Igloo, project: &IglooPrj, target: &IglooTarget) -> Vec<String>
+{
+	let elf_path = project.elf_path(target);
+	["elf", "map", "hex", "bin"].iter()
+		.map(|ext| elf_path.with_extension(ext))
+		.filter(|path| inst.fs.exists(path))
+		.map(|path| path.display().to_string())
+		.collect()
+}
+
+/// Removes build outputs of the given targets in the current project
+/// targets -- names of the targets to clean, the default target if empty
+/// all_targets -- cleans every target
+/// all -- also removes the generated build files
+pub fn clean(inst: &Igloo, targets: &[&str], all_targets: bool, all: bool) -> IglooErrType
+{
+	let project = match IglooPrj::open(inst)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			error!("Failed to open project: {:?}", e);
+			return e
+		}
+	};
+
+	let targets = match project.select_targets(targets, all_targets)
+	{
+		Ok(v) => v,
+		Err(e) => return e,
+	};
+
+	for target in targets
+	{
+		say!("Cleaning target {}", target.name);
+		let res_err = target.clean(all);
+		if res_err != ErrNone
+		{
+			return res_err
+		}
+	}
+	ErrNone
+}
+
+/// Generates doxygen documentation of the current project for the given targets
+/// Output is placed in doc/<target>
+/// targets -- names of the targets, the default target if empty
+/// all_targets -- documents every target
+pub fn gendoc(inst: &Igloo, targets: &[&str], all_targets: bool) -> IglooErrType
+{
+	let project = match IglooPrj::open(inst)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			error!("Failed to open project: {:?}", e);
+			return e
+		}
+	};
+
+	let targets = match project.select_targets(targets, all_targets)
+	{
+		Ok(v) => v,
+		Err(e) => return e,
+	};
+
+	for target in targets
+	{
+		let res_err = project.gen_doxyfile(target);
+		if res_err != ErrNone
+		{
+			return res_err
+		}
+
+		match inst.fs.create_dir_all(&project.project_dir.join("doc").join(&target.name))
+		{
+			Err(e) => error!("{:?}", e),
+			_ => (),
+		}
+
+		say!("Generating documentation for target {}", target.name);
+		if inst.fs.plan().is_some()
+		{
+			say!("Dry run, skipping doxygen {}",
+					 target.root.join("Doxyfile").display());
+			continue
+		}
+		let doxyfile = target.root.join("Doxyfile");
+		match inst.host.run("doxygen", &[&doxyfile.to_string_lossy()], &project.project_dir)
+		{
+			Ok(true) => (),
+			Ok(false) =>
+			{
+				say!("doxygen failed for target {}", target.name);
+				return FailedToGenerateDocs
+			}
+			Err(e) =>
+			{
+				say!("Failed to run doxygen: {:?}", e);
+				return FailedToGenerateDocs
+			}
+		}
+	}
+	ErrNone
+}
+
+/// Regenerates the build files (Makefiles, igloo.h) of the current project
+/// targets -- names of the targets to regenerate, the default target if empty
+/// all_targets -- regenerates every target
+/// check -- only compares the regenerated files with the ones on disk and fails
+/// with BuildFilesOutOfDate if they differ. Nothing is written.
+pub fn make_regen(inst: &Igloo, targets: &[&str], all_targets: bool, check: bool)
+				  -> IglooErrType
+{
+	let project = match IglooPrj::open(inst)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			error!("Failed to open project: {:?}", e);
+			return e
+		}
+	};
+
+	let targets = match project.select_targets(targets, all_targets)
+	{
+		Ok(v) => v,
+		Err(e) => return e,
+	};
+
+	let build_files = match project.render_build_files(&targets)
+	{
+		Ok(v) => v,
+		Err(e) => return e,
+	};
+
+	// Module versions may have changed with the lock
+	if !check
+	{
+		let res_err = project.link_modules(&[]);
+		if res_err != ErrNone
+		{
+			return res_err
+		}
+	}
+
+	let mut res_err = ErrNone;
+	for (path, contents) in build_files
+	{
+		let on_disk = inst.fs.read_to_string(&path).ok();
+		if on_disk.as_deref() == Some(contents.as_str())
+		{
+			say!("Up to date {}", path.display());
+			emit(json!({ "reason": "build-file", "path": path.display().to_string(),
+						 "status": "up-to-date" }));
+			continue
+		}
+
+		if check
+		{
+			say!("Out of date {}", path.display());
+			emit(json!({ "reason": "build-file", "path": path.display().to_string(),
+						 "status": "out-of-date" }));
+			say_raw!("{}", diff_lines(on_disk.as_deref().unwrap_or(""), &contents));
+			res_err = BuildFilesOutOfDate;
+			continue
+		}
+
+		match inst.fs.write(&path, contents.as_bytes())
+		{
+			Ok(_v) =>
+			{
+				say!("Regenerated {}", path.display());
+				emit(json!({ "reason": "build-file", "path": path.display().to_string(),
+							 "status": "regenerated" }));
+			}
+			Err(e) =>
+			{
+				error!("{:?}", e);
+				return ErrUnknown
+			}
+		}
+	}
+	res_err
+}
+
+/// Reports the memory usage of the built firmware of the given targets
+/// targets -- names of the targets, the default target if empty
+/// all_targets -- reports every target
+/// top -- number of largest symbols to list
+/// json -- print the reports as a JSON array instead of tables
+/// Fails with MemoryRegionOverflow if a region is over capacity.
+pub fn size(inst: &Igloo, targets: &[&str], all_targets: bool, top: usize, json: bool)
+			-> IglooErrType
+{
+	let project = match IglooPrj::open(inst)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			error!("Failed to open project: {:?}", e);
+			return e
+		}
+	};
+
+	let targets = match project.select_targets(targets, all_targets)
+	{
+		Ok(v) => v,
+		Err(e) => return e,
+	};
+
+	let mut reports: Vec<IglooSizeReport> = Vec::new();
+	for target in targets
+	{
+		match size_report(inst.fs.as_ref(), target, &project.elf_path(target), top)
+		{
+			Ok(v) => reports.push(v),
+			Err(e) => return e,
+		}
+	}
+
+	for report in &reports
+	{
+		emit_as("size-report", report.to_json());
+	}
+	if json
+	{
+		let reports: Vec<serde_json::Value> = reports.iter().map(|r| r.to_json()).collect();
+		say!("{}", serde_json::to_string_pretty(&reports).unwrap());
+	}
+	else
+	{
+		for report in &reports
+		{
+			report.print();
+			say!();
+		}
+	}
+
+	let mut res_err = ErrNone;
+	for report in &reports
+	{
+		for region in report.regions.iter().filter(|r| r.overflows())
+		{
+			error!("target {} overflows {} by {} bytes", report.target,
+					 region.name, region.used - region.capacity.unwrap());
+			res_err = MemoryRegionOverflow;
+		}
+	}
 	res_err
 }
+
+/// Attributes the code and data size of the given targets to groups (user, ESF,
+/// libraries), files or output sections using the linker map and ELF
+/// targets -- names of the targets, the default target if empty
+/// all_targets -- analyzes every target
+/// by -- view to print: group, file or section
+/// tree -- prints group -> file -> symbol as a tree instead of a table
+/// diff -- compares with the previous build
+/// base -- compares with another build, given by its .map file (the ELF next to it is used)
+/// json -- prints the analysis as JSON
+pub fn analyze(inst: &Igloo, targets: &[&str], all_targets: bool, by: &str, tree: bool,
+			   diff: bool, base: Option<&str>, json: bool) -> IglooErrType
+{
+	let project = match IglooPrj::open(inst)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			error!("Failed to open project: {:?}", e);
+			return e
+		}
+	};
+
+	let targets = match project.select_targets(targets, all_targets)
+	{
+		Ok(v) => v,
+		Err(e) => return e,
+	};
+
+	let mut json_out: Vec<serde_json::Value> = Vec::new();
+	for target in targets
+	{
+		let analysis = match analyze_build(inst.fs.as_ref(), &target.name,
+										   &project.map_path(target),
+										   &project.elf_path(target))
+		{
+			Ok(v) => v,
+			Err(e) => return e,
+		};
+		let prev = store_analysis(inst.fs.as_ref(), &target.root, &analysis);
+
+		let old = match base
+		{
+			Some(v) =>
+			{
+				let base_map = inst.env.cwd.join(v);
+				match analyze_build(inst.fs.as_ref(), &target.name, &base_map,
+									&base_map.with_extension("elf"))
+				{
+					Ok(v) => Some(v),
+					Err(e) => return e,
+				}
+			}
+			None if diff =>
+			{
+				if prev.is_none()
+				{
+					say!("No previous build of {} to compare with", target.name);
+				}
+				prev
+			}
+			None => None,
+		};
+
+		let mut val = analysis.to_json();
+		if let Some(old) = &old
+		{
+			val["diff"] = analysis.diff(old, by).iter()
+				.map(|(name, o, n)| serde_json::json!({ "name": name, "old": o, "new": n }))
+				.collect();
+		}
+		emit_as("analysis", val.clone());
+		if json
+		{
+			json_out.push(val);
+		}
+		else if let Some(old) = &old
+		{
+			analysis.print_diff(old, by);
+		}
+		else if tree
+		{
+			analysis.print_tree();
+		}
+		else
+		{
+			analysis.print_table(by);
+		}
+	}
+
+	if json
+	{
+		say!("{}", serde_json::to_string_pretty(&json_out).unwrap());
+	}
+	ErrNone
+}
+
+/// Builds image options from the target's esf.image table and the command line.
+/// Command line values win.
+fn image_options(target_image: Option<&std::collections::BTreeMap<String, config::Value>>,
+				 fill: Option<&str>, pad_to: Option<&str>, uf2_family: Option<&str>)
+				 -> Result<ImageOptions, IglooErrType>
+{
+	let mut options = ImageOptions::default();
+	let lookup = |name: &str, cli: Option<&str>, max: u64| -> Result<Option<u64>, IglooErrType>
+	{
+		let val = match cli
+		{
+			Some(v) => config::Value::from(v),
+			None => match target_image.and_then(|t| t.get(name))
+			{
+				Some(v) => v.clone(),
+				None => return Ok(None),
+			},
+		};
+		match parse_size(&val)
+		{
+			Ok(v) if v <= max => Ok(Some(v)),
+			Ok(v) =>
+			{
+				error!("{}: {:#x} is larger than {:#x}", name, v, max);
+				Err(InvalidImage)
+			}
+			Err(e) =>
+			{
+				error!("{}: {}", name, e);
+				Err(InvalidImage)
+			}
+		}
+	};
+	if let Some(v) = lookup("fill", fill, u8::MAX as u64)?
+	{
+		options.fill = v as u8;
+	}
+	options.pad_to = lookup("pad_to", pad_to, u64::MAX)?;
+	options.uf2_family = lookup("uf2_family", uf2_family, u32::MAX as u64)?.map(|v| v as u32);
+	Ok(options)
+}
+
+fn image_formats(formats: &[&str]) -> Result<Vec<ImageFormat>, IglooErrType>
+{
+	let mut ret: Vec<ImageFormat> = Vec::new();
+	for format in formats
+	{
+		match ImageFormat::from_name(format)
+		{
+			Some(ImageFormat::Elf) | None =>
+			{
+				error!("unsupported output format {}", format);
+				return Err(InvalidImage)
+			}
+			Some(v) => ret.push(v),
+		}
+	}
+	Ok(ret)
+}
+
+/// Reads the post-build steps of a target and the key they sign or verify with
+/// key -- key file from the command line, overrides the project file
+/// signing -- the key must be a private key
+fn post_build(inst: &Igloo, project: &IglooPrj, target: &IglooTarget, key: Option<&str>,
+			  signing: bool)
+			  -> Result<(IglooPostBuild, Option<SigningKey>, Option<VerifyingKey>), IglooErrType>
+{
+	let post = IglooPostBuild::from_table(&target.image, &project.project_dir)?;
+	let sign = match &post.sign
+	{
+		Some(v) => v,
+		None => return Ok((post, None, None)),
+	};
+	let path = match key
+	{
+		Some(v) => Some(inst.env.cwd.join(v)),
+		None if signing => sign.key.clone(),
+		None => sign.public_key.clone().or_else(|| sign.key.clone()),
+	};
+	let path = match path
+	{
+		Some(v) => v,
+		None =>
+		{
+			error!("target {} signs its images but has no key. \
+					  Set image.sign.key or pass --key", target.name);
+			return Err(FailedToReadKey)
+		}
+	};
+	let (signer, verifier) = read_key(inst.fs.as_ref(), &path, sign.algorithm)?;
+	if signing && signer.is_none()
+	{
+		error!("{}: signing needs a private key", path.display());
+		return Err(FailedToReadKey)
+	}
+	Ok((post, signer, Some(verifier)))
+}
+
+/// Converts the built ELF of the given targets into other image formats
+/// The post-build steps of the target (header, crc, sign) are applied first.
+/// Images are written next to the ELF (<PrjName>.<format>) unless output is given.
+/// targets -- names of the targets, the default target if empty
+/// all_targets -- converts every target
+/// formats -- hex, srec, bin or uf2
+/// key -- signing key, overrides the project file
+pub fn image_convert(inst: &Igloo, targets: &[&str], all_targets: bool, formats: &[&str],
+					 fill: Option<&str>, pad_to: Option<&str>, uf2_family: Option<&str>,
+					 output: Option<&str>, key: Option<&str>) -> IglooErrType
+{
+	let project = match IglooPrj::open(inst)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			error!("Failed to open project: {:?}", e);
+			return e
+		}
+	};
+
+	let targets = match project.select_targets(targets, all_targets)
+	{
+		Ok(v) => v,
+		Err(e) => return e,
+	};
+	let formats = match image_formats(formats)
+	{
+		Ok(v) => v,
+		Err(e) => return e,
+	};
+	if output.is_some() && (targets.len() != 1 || formats.len() != 1)
+	{
+		error!("--output needs exactly one target and one format");
+		return InvalidImage
+	}
+
+	for target in targets
+	{
+		let options = match image_options(Some(&target.image), fill, pad_to, uf2_family)
+		{
+			Ok(v) => v,
+			Err(e) => return e,
+		};
+		let elf_path = project.elf_path(target);
+		let (image, elf) = match read_elf_image(inst, &elf_path)
+		{
+			Ok(v) => v,
+			Err(e) =>
+			{
+				say!("Build target {} first", target.name);
+				return e
+			}
+		};
+		let (post, signer, _verifier) = match post_build(inst, &project, target, key, true)
+		{
+			Ok(v) => v,
+			Err(e) => return e,
+		};
+		let image = if post.is_empty()
+		{
+			image
+		}
+		else
+		{
+			match post.apply(&elf, &image, options.fill, signer.as_ref())
+			{
+				Ok(v) => v,
+				Err(e) => return e,
+			}
+		};
+		for format in &formats
+		{
+			let out_path = match output
+			{
+				Some(v) => inst.env.cwd.join(v),
+				None => elf_path.with_extension(format.extension()),
+			};
+			let res_err = write_image(inst.fs.as_ref(), &image, *format, &options, &out_path);
+			if res_err != ErrNone
+			{
+				return res_err
+			}
+		}
+	}
+	ErrNone
+}
+
+/// Fails with InvalidImage like the other image inputs, missing builds included
+fn read_elf_image(inst: &Igloo, elf_path: &Path) -> Result<(IglooImage, IglooElf), IglooErrType>
+{
+	let elf = read_elf(inst.fs.as_ref(), elf_path).map_err(|_e| InvalidImage)?;
+	match IglooImage::from_elf(&elf)
+	{
+		Ok(v) => Ok((v, elf)),
+		Err(e) =>
+		{
+			error!("{}: {}", elf_path.display(), e);
+			Err(InvalidImage)
+		}
+	}
+}
+
+/// Checks the header, CRC and signature of the converted images of targets
+/// targets -- names of the targets, the default target if empty
+/// all_targets -- verifies every target
+/// image -- image to check instead of the converted ones, needs a single target
+/// key -- public or private key, overrides the project file
+pub fn image_verify(inst: &Igloo, targets: &[&str], all_targets: bool, image: Option<&str>,
+					key: Option<&str>) -> IglooErrType
+{
+	let project = match IglooPrj::open(inst)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			error!("Failed to open project: {:?}", e);
+			return e
+		}
+	};
+
+	let targets = match project.select_targets(targets, all_targets)
+	{
+		Ok(v) => v,
+		Err(e) => return e,
+	};
+	if image.is_some() && targets.len() != 1
+	{
+		error!("--image needs exactly one target");
+		return InvalidImage
+	}
+
+	let mut res_err = ErrNone;
+	for target in targets
+	{
+		let options = match image_options(Some(&target.image), None, None, None)
+		{
+			Ok(v) => v,
+			Err(e) => return e,
+		};
+		let (post, _signer, verifier) = match post_build(inst, &project, target, key, false)
+		{
+			Ok(v) => v,
+			Err(e) => return e,
+		};
+		if post.is_empty()
+		{
+			say!("Target {} has no post-build steps to verify", target.name);
+			continue
+		}
+		let elf_path = project.elf_path(target);
+		let (elf_image, elf) = match read_elf_image(inst, &elf_path)
+		{
+			Ok(v) => v,
+			Err(e) =>
+			{
+				say!("Build target {} first", target.name);
+				return e
+			}
+		};
+
+		let mut specs: Vec<std::path::PathBuf> = Vec::new();
+		match image
+		{
+			Some(v) => specs.push(inst.env.cwd.join(v)),
+			None => for format in &[ImageFormat::Hex, ImageFormat::Srec,
+									ImageFormat::Bin, ImageFormat::Uf2]
+			{
+				let path = elf_path.with_extension(format.extension());
+				if !inst.fs.exists(&path)
+				{
+					continue
+				}
+				if *format == ImageFormat::Bin
+				{
+					// Raw binaries start where the firmware does
+					let base = elf_image.start().unwrap_or(0);
+					specs.push(format!("{}@0x{:x}", path.display(), base).into());
+				}
+				else
+				{
+					specs.push(path);
+				}
+			},
+		}
+		if specs.is_empty()
+		{
+			error!("target {} has no converted images. Run igloo image convert first",
+					 target.name);
+			return InvalidImage
+		}
+
+		for spec in specs
+		{
+			let image = match read_image(inst.fs.as_ref(), &spec)
+			{
+				Ok(v) => v,
+				Err(e) => return e,
+			};
+			let checks = match post.verify(&elf, &image, options.fill, verifier.as_ref())
+			{
+				Ok(v) => v,
+				Err(e) => return e,
+			};
+			say!("{}", spec.display());
+			for (name, res) in checks
+			{
+				match res
+				{
+					Ok(()) => say!("  ok    {}", name),
+					Err(e) =>
+					{
+						say!("  FAIL  {}: {}", name, e);
+						res_err = ImageCheckFailed;
+					}
+				}
+			}
+		}
+	}
+	res_err
+}
+
+/// Merges images (e.g. bootloader and application) into one
+/// inputs -- image files, the format is taken from the extension.
+/// Raw binaries take their load address after an @ (boot.bin@0x0)
+/// output -- merged image, the format is taken from the extension
+pub fn image_merge(inst: &Igloo, inputs: &[&str], output: &str, fill: Option<&str>,
+				   pad_to: Option<&str>, uf2_family: Option<&str>) -> IglooErrType
+{
+	let options = match image_options(None, fill, pad_to, uf2_family)
+	{
+		Ok(v) => v,
+		Err(e) => return e,
+	};
+	let out_path = inst.env.cwd.join(output);
+	let format = match ImageFormat::from_path(&out_path)
+	{
+		Some(ImageFormat::Elf) | None =>
+		{
+			error!("{}: unsupported output format", out_path.display());
+			return InvalidImage
+		}
+		Some(v) => v,
+	};
+
+	let mut merged = IglooImage::new();
+	for input in inputs
+	{
+		let image = match read_image(inst.fs.as_ref(), &inst.env.cwd.join(input))
+		{
+			Ok(v) => v,
+			Err(e) => return e,
+		};
+		match merged.merge(&image)
+		{
+			Ok(()) => (),
+			Err(e) =>
+			{
+				error!("{}: {}", input, e);
+				return ImageOverlap
+			}
+		}
+	}
+	write_image(inst.fs.as_ref(), &merged, format, &options, &out_path)
+}
+
+/// Prints the completion script of shell
+pub fn completions(shell: &str) -> IglooErrType
+{
+	match igloo_cli::completion_script(shell)
+	{
+		Some(v) =>
+		{
+			print!("{}", v);
+			ErrNone
+		}
+		None => ErrUnknown,
+	}
+}
+
+/// Prints the target names -t/--target completes to: the targets of the project
+/// in the current directory, then the ones of the master target manifest
+pub fn complete_targets(inst: &Igloo) -> IglooErrType
+{
+	let mut names: Vec<String> = match IglooPrj::open(inst)
+	{
+		Ok(v) => v.get_targets(&[]).unwrap_or_default().iter().map(|t| t.name.clone()).collect(),
+		Err(_e) => Vec::new(),
+	};
+	for listing in target_listings(inst)
+	{
+		if !names.contains(&listing.name)
+		{
+			names.push(listing.name);
+		}
+	}
+	for name in names
+	{
+		println!("{}", name);
+	}
+	ErrNone
+}
+
+/// Checks the environment igloo runs in and prints what to fix
+/// json -- prints the checks and a summary as JSON instead of a table
+pub fn doctor(inst: &Igloo, json: bool) -> IglooErrType
+{
+	let checks = run_checks(inst);
+	let checks_json = checks_to_json(&checks);
+	for check in checks_json["checks"].as_array().unwrap()
+	{
+		emit_as("doctor-check", check.clone());
+	}
+	emit_as("doctor-summary", checks_json["summary"].clone());
+	if json
+	{
+		say!("{}", serde_json::to_string_pretty(&checks_json).unwrap());
+	}
+	else
+	{
+		print_checks(&checks);
+	}
+
+	if checks.iter().any(|c| c.status == CheckStatus::Error)
+	{
+		return DoctorFoundProblems
+	}
+	ErrNone
+}
+
+/// Lists the installed toolchains and the ones the indexes offer
+pub fn toolchain_list(inst: &Igloo) -> IglooErrType
+{
+	let installed = installed_toolchains(inst.fs.as_ref(), &inst.env.hd);
+	say!("Installed toolchains:");
+	if installed.is_empty()
+	{
+		say!("  none");
+	}
+	for pin in &installed
+	{
+		let dir = pin.dir.as_ref().map(|d| d.display().to_string());
+		say!("  {:<24} {:<12} {}", pin.name, pin.version.to_string(),
+				 dir.clone().unwrap_or_default());
+		emit(json!({ "reason": "toolchain", "installed": true, "name": pin.name,
+					 "version": pin.version.to_string(), "path": dir }));
+	}
+
+	let available = match available_toolchains(inst.fs.as_ref(), &inst.env)
+	{
+		Ok(v) => v,
+		Err(e) => return e,
+	};
+	say!("Available toolchains for {}:", host_triple());
+	if available.is_empty()
+	{
+		say!("  none, list archives in ~/.igloo/{}", TOOLCHAIN_INDEX);
+	}
+	for archive in &available
+	{
+		say!("  {:<24} {:<12} {}", archive.name, archive.version.to_string(),
+				 archive.archive.display());
+		emit(json!({ "reason": "toolchain", "installed": false, "name": archive.name,
+					 "version": archive.version.to_string(),
+					 "path": archive.archive.display().to_string() }));
+	}
+	ErrNone
+}
+
+/// Installs toolchains from the archives listed in the toolchain indexes
+/// specs -- name or name@version of the toolchains to install. If empty, the
+/// toolchains the project in the current directory needs are installed, the
+/// locked versions if the project has an igloo.lock
+pub fn toolchain_install(inst: &Igloo, specs: &[&str]) -> IglooErrType
+{
+	let available = match available_toolchains(inst.fs.as_ref(), &inst.env)
+	{
+		Ok(v) => v,
+		Err(e) => return e,
+	};
+
+	// requirement, locked toolchain
+	let mut wanted: Vec<(ToolchainReq, Option<ToolchainPin>)> = Vec::new();
+	let in_project = specs.is_empty();
+	if in_project
+	{
+		let project = match IglooPrj::open(inst)
+		{
+			Ok(v) => v,
+			Err(e) =>
+			{
+				error!("Failed to open project: {:?}\n\
+						  Name the toolchain to install outside of a project", e);
+				return e
+			}
+		};
+		for target in project.get_targets(&[]).unwrap_or_default()
+		{
+			match (&target.toolchain_req, &target.toolchain)
+			{
+				(_, Some(pin)) if pin.dir.is_some() =>
+					say!("{} {} is installed", pin.name, pin.version),
+				(Some(req), locked) => wanted.push((req.clone(), locked.clone())),
+				(None, _) => (),
+			}
+		}
+	}
+	for spec in specs
+	{
+		match ToolchainReq::from_spec(spec)
+		{
+			Ok(v) => wanted.push((v, None)),
+			Err(e) =>
+			{
+				error!("{}", e);
+				return InvalidToolchain
+			}
+		}
+	}
+
+	let installed = installed_toolchains(inst.fs.as_ref(), &inst.env.hd);
+	for (req, locked) in wanted
+	{
+		let archive = available.iter()
+			.filter(|a| req.matches(&a.name, &a.version))
+			.filter(|a| match &locked
+			{
+				Some(l) => l.version == a.version
+					&& a.sha256.as_ref().map_or(true, |s| *s == l.sha256),
+				None => true,
+			})
+			.max_by(|a, b| a.version.cmp(&b.version));
+		let archive = match archive
+		{
+			Some(v) => v,
+			None =>
+			{
+				match &locked
+				{
+					Some(l) => error!("no archive of {} {} (locked) is available",
+										l.name, l.version),
+					None => error!("no archive of {} is available", req),
+				}
+				say!("List toolchain archives in ~/.igloo/{}", TOOLCHAIN_INDEX);
+				return ToolchainNotFound
+			}
+		};
+		if locked.is_none() && installed.iter().any(|t| t.name == archive.name
+													&& t.version == archive.version)
+		{
+			say!("{} {} is installed", archive.name, archive.version);
+			continue
+		}
+		let pin = match install_toolchain(inst.fs.as_ref(), &inst.env.hd, archive)
+		{
+			Ok(v) => v,
+			Err(e) => return e,
+		};
+		if let Some(l) = &locked
+		{
+			if l.sha256 != pin.sha256
+			{
+				error!("{} differs from the archive locked in {}",
+						 archive.archive.display(), LOCK_FILE);
+				return FailedToInstallToolchain
+			}
+		}
+		say!("Installed {} {}", pin.name, pin.version);
+	}
+
+	if in_project
+	{
+		// Point the build files at the new toolchains and lock them
+		return make_regen(inst, &[], true, false)
+	}
+	ErrNone
+}
+
+/// Writes the [modules] table of the project file, then links the modules and
+/// regenerates the build files. The project file is restored if the modules do
+/// not resolve.
+fn set_modules(inst: &Igloo, project: &IglooPrj, wanted: &BTreeMap<String, VersionReq>)
+			   -> IglooErrType
+{
+	let prj_file = project.project_file();
+	let old_text = match inst.fs.read_to_string(&prj_file)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			error!("{:?}", e);
+			return FailedToLoadProjectManifest
+		}
+	};
+	if let Err(e) = inst.fs.write(&prj_file, set_project_modules(&old_text, wanted).as_bytes())
+	{
+		error!("{:?}", e);
+		return ErrUnknown
+	}
+	let updated = match IglooPrj::open(inst)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			let _ = inst.fs.write(&prj_file, old_text.as_bytes());
+			return e
+		}
+	};
+
+	let stale: Vec<String> = project.modules().iter()
+		.filter(|m| !updated.modules().iter().any(|n| n.name == m.name))
+		.map(|m| m.name.clone())
+		.collect();
+	let res_err = updated.link_modules(&stale);
+	if res_err != ErrNone
+	{
+		return res_err
+	}
+	make_regen(inst, &[], true, false)
+}
+
+/// Adds modules to the project
+/// specs -- name or name@version requirement of each module. Without a
+/// requirement the newest version in the registries is required (^version)
+pub fn add(inst: &Igloo, specs: &[&str]) -> IglooErrType
+{
+	let project = match IglooPrj::open(inst)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			error!("Failed to open project: {:?}", e);
+			return e
+		}
+	};
+	let mut prj_man = config::Config::new();
+	let res_err = igloo_manifest::get_project_manifest(inst.fs.as_ref(), &mut prj_man,
+													   &inst.env.cwd);
+	if res_err != ErrNone
+	{
+		return res_err
+	}
+	let mut wanted = match project_modules(&prj_man)
+	{
+		Ok(v) => v,
+		Err(e) => return e,
+	};
+
+	let available = available_modules(inst.fs.as_ref(), &inst.env);
+	let mut added: Vec<String> = Vec::new();
+	for spec in specs
+	{
+		let (name, req) = match spec.split_once('@')
+		{
+			Some((n, v)) => match VersionReq::parse(v)
+			{
+				Ok(req) => (n, req),
+				Err(e) =>
+				{
+					error!("{}: {}", spec, e);
+					return InvalidModule
+				}
+			},
+			None => match available.iter().filter(|m| m.name == *spec).last()
+			{
+				Some(v) => (*spec, VersionReq::parse(&format!("^{}", v.version)).unwrap()),
+				None =>
+				{
+					error!("module {} is in no registry", spec);
+					return ModuleNotFound
+				}
+			},
+		};
+		say!("Adding {} {}", name, req);
+		wanted.insert(String::from(name), req);
+		added.push(String::from(name));
+	}
+
+	// Refuse modules none of the targets can build before touching the project.
+	// The modules added are resolved again, the others keep their locked version
+	let locked: BTreeMap<String, Version> = project.modules().iter()
+		.filter(|m| !added.contains(&m.name))
+		.map(|m| (m.name.clone(), m.version.clone()))
+		.collect();
+	let resolved = match resolve_modules(&available, &wanted, &locked)
+	{
+		Ok(v) => v,
+		Err(e) => return e,
+	};
+	let targets = project.get_targets(&[]).unwrap_or_default();
+	for name in &added
+	{
+		let built = targets.iter()
+			.any(|t| modules_for_target(&resolved, t).iter().any(|m| m.name == *name));
+		if !built
+		{
+			error!("module {} (or a module it depends on) supports none of the \
+					  project targets ({})", name,
+					 targets.iter().map(|t| t.name.as_str()).collect::<Vec<_>>().join(", "));
+			return ModuleNotCompatible
+		}
+	}
+	let lock_path = project.project_dir.join(LOCK_FILE);
+	let old_lock = inst.fs.read_to_string(&lock_path).ok();
+	if let Some(mut lock) = IglooLock::read(inst.fs.as_ref(), &project.project_dir)
+	{
+		lock.modules.retain(|name, _module| !added.contains(name));
+		if let Err(e) = inst.fs.write(&lock_path, lock.render().as_bytes())
+		{
+			error!("{}: {:?}", lock_path.display(), e);
+			return ErrUnknown
+		}
+	}
+	let res_err = set_modules(inst, &project, &wanted);
+	if res_err != ErrNone
+	{
+		if let Some(v) = old_lock
+		{
+			let _ = inst.fs.write(&lock_path, v.as_bytes());
+		}
+	}
+	else
+	{
+		for name in &added
+		{
+			emit(json!({ "reason": "module-added", "name": name,
+						 "requirement": wanted[name].to_string() }));
+		}
+	}
+	res_err
+}
+
+/// Removes modules from the project
+pub fn remove(inst: &Igloo, names: &[&str]) -> IglooErrType
+{
+	let project = match IglooPrj::open(inst)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			error!("Failed to open project: {:?}", e);
+			return e
+		}
+	};
+	let mut prj_man = config::Config::new();
+	let res_err = igloo_manifest::get_project_manifest(inst.fs.as_ref(), &mut prj_man,
+													   &inst.env.cwd);
+	if res_err != ErrNone
+	{
+		return res_err
+	}
+	let mut wanted = match project_modules(&prj_man)
+	{
+		Ok(v) => v,
+		Err(e) => return e,
+	};
+	for name in names
+	{
+		if wanted.remove(*name).is_none()
+		{
+			error!("{} is not a module of the project", name);
+			return ModuleNotFound
+		}
+		say!("Removing {}", name);
+	}
+	let res_err = set_modules(inst, &project, &wanted);
+	if res_err == ErrNone
+	{
+		for name in names
+		{
+			emit(json!({ "reason": "module-removed", "name": name }));
+		}
+	}
+	res_err
+}
+
+/// Replaces the target old of the project with new. The project file is updated,
+/// the target directory of old and the ESF links only it used are removed, new is
+/// populated and the build files are regenerated. Prints what old provided that
+/// new does not and where the project still uses it.
+pub fn retarget(inst: &Igloo, old: &str, new: &str) -> IglooErrType
+{
+	let project = match IglooPrj::open(inst)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			error!("Failed to open project: {:?}", e);
+			return e
+		}
+	};
+	let old_target = match project.get_targets(&[old])
+	{
+		Ok(v) => v[0],
+		Err(e) => return e,
+	};
+	if project.get_targets(&[]).unwrap_or_default().iter().any(|t| t.name == new)
+	{
+		say!("Target \"{}\" is already part of this project", new);
+		return TargetAlreadyInProject
+	}
+	let new_target = match IglooPrj::load_target(inst, project.project_dir.clone(), new)
+	{
+		Ok(v) => v,
+		Err(e) => return e,
+	};
+	let mut report = compare_targets(old_target, &new_target);
+
+	let prj_file = project.project_file();
+	let old_text = match inst.fs.read_to_string(&prj_file)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			error!("{:?}", e);
+			return FailedToLoadProjectManifest
+		}
+	};
+	let regions: Vec<String> = new_target.memory.iter().map(|r| r.name.clone()).collect();
+	let (text, dropped) = retarget_project_file(&old_text, old, new, &regions);
+	report.carve_outs = dropped;
+	if let Err(e) = inst.fs.write(&prj_file, text.as_bytes())
+	{
+		error!("{:?}", e);
+		return ErrUnknown
+	}
+	let updated = match IglooPrj::open(inst)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			let _ = inst.fs.write(&prj_file, old_text.as_bytes());
+			return e
+		}
+	};
+	let targets = updated.get_targets(&[]).unwrap_or_default();
+	let target = match targets.iter().find(|t| t.name == new)
+	{
+		Some(v) => v,
+		None =>
+		{
+			error!("{} is not a target of the project after retargeting", new);
+			let _ = inst.fs.write(&prj_file, old_text.as_bytes());
+			return InvalidTarget
+		}
+	};
+	report.modules = old_target.modules.iter()
+		.filter(|m| !target.modules.iter().any(|n| n.name == m.name))
+		.map(|m| m.name.clone())
+		.collect();
+
+	if inst.fs.is_dir(&old_target.root)
+	{
+		match inst.fs.remove_dir_all(&old_target.root)
+		{
+			Err(e) => error!("{}: {:?}", old_target.root.display(), e),
+			_ => say!("Removed {}", old_target.root.display()),
+		}
+	}
+	for key in old_target.links.keys().filter(|k| !targets.iter().any(|t| t.links.contains_key(*k)))
+	{
+		let link = project.project_dir.join("ESF").join(key);
+		if inst.fs.symlink_exists(&link)
+		{
+			match inst.fs.remove_file(&link)
+			{
+				Err(e) => error!("{}: {:?}", link.display(), e),
+				_ => say!("Removed {}", link.display()),
+			}
+		}
+	}
+	for step in [IglooTarget::generate, IglooTarget::populate, IglooTarget::generate_openocd_config]
+	{
+		let res_err = step(target);
+		if res_err != ErrNone
+		{
+			return res_err
+		}
+	}
+	let res_err = make_regen(inst, &[], true, false);
+
+	let mut names = report.includes.clone();
+	names.extend(report.defines.iter().cloned());
+	report.references = find_references(inst.fs.as_ref(), &project.project_dir,
+										&[project.project_dir.join("src"),
+										  project.project_dir.join("inc")], &names);
+	say!("Retargeted {} from {} to {}", project.name(), old, new);
+	report.print(old, new);
+	emit_as("retarget-report", report.to_json(old, new));
+	res_err
+}
+
+/// Lists the targets of the master target manifest. vendor and core filter on
+/// case insensitive substrings. Fails if no target is left
+pub fn targets(inst: &Igloo, vendor: Option<&str>, core: Option<&str>, json: bool) -> IglooErrType
+{
+	let listings = target_listings(inst);
+	let entries = index_entries_of(inst, &listings);
+	let vendor_of = |name: &str| -> String
+	{
+		entries.iter()
+			.find(|e| e.kind == EntryKind::Target && e.name == name)
+			.map(|e| e.vendor.clone())
+			.unwrap_or_default()
+	};
+	let contains = |text: &str, filter: Option<&str>| -> bool
+	{
+		filter.map(|f| text.to_lowercase().contains(&f.to_lowercase())).unwrap_or(true)
+	};
+	let listings: Vec<(&IglooTargetListing, String)> = listings.iter()
+		.map(|l| (l, vendor_of(&l.name)))
+		.filter(|(l, v)| contains(v, vendor) && contains(&l.core, core))
+		.collect();
+
+	let listings_json: Vec<serde_json::Value> = listings.iter().map(|(l, v)| json!({
+		"name": l.name,
+		"make_table": l.make_table,
+		"manifest": l.manifest,
+		"mcu": l.mcu,
+		"core": l.core,
+		"vendor": v,
+		"ok": l.problems.is_empty(),
+		"problems": l.problems,
+	})).collect();
+	for listing in &listings_json
+	{
+		emit_as("target", listing.clone());
+	}
+	if json
+	{
+		say!("{}", serde_json::to_string_pretty(&json!({ "targets": listings_json })).unwrap());
+	}
+	else
+	{
+		for (listing, _vendor) in &listings
+		{
+			say!("{:<16} {:<12} {:<14} {:<36} {}", listing.name, listing.mcu, listing.core,
+					 listing.make_table, listing.manifest);
+			for problem in &listing.problems
+			{
+				say!("  problem: {}", problem);
+			}
+		}
+	}
+
+	if listings.is_empty()
+	{
+		say!("No target matches");
+		return NotFoundInRegistry
+	}
+	ErrNone
+}
+
+/// Lists the targets and modules matching query, everything if it is empty.
+/// Fails if nothing matches, so scripts can test for a target or module
+pub fn search(inst: &Igloo, query: &str) -> IglooErrType
+{
+	let entries: Vec<IglooIndexEntry> = index_entries(inst).into_iter()
+		.filter(|e| e.matches(query))
+		.collect();
+	if entries.is_empty()
+	{
+		say!("Nothing matches \"{}\"", query);
+		return NotFoundInRegistry
+	}
+	for entry in &entries
+	{
+		let version = entry.versions.last().map(|v| v.to_string()).unwrap_or_default();
+		say!("{:<7} {:<20} {:<10} {}", entry.kind.name(), entry.name, version,
+				 entry.description);
+		emit_as("registry-entry", entry.to_json());
+	}
+	ErrNone
+}
+
+/// Describes the targets and modules named name
+pub fn info(inst: &Igloo, name: &str) -> IglooErrType
+{
+	let entries: Vec<IglooIndexEntry> = index_entries(inst).into_iter()
+		.filter(|e| e.name == name)
+		.collect();
+	if entries.is_empty()
+	{
+		say!("No target or module is named {}, try igloo search {}", name, name);
+		return NotFoundInRegistry
+	}
+	for entry in &entries
+	{
+		say!("{} {}", entry.kind.name(), entry.name);
+		emit_as("registry-entry", entry.to_json());
+		let mut fields: Vec<(String, String)> = vec![
+			(String::from("description"), entry.description.clone()),
+			(String::from("vendor"), entry.vendor.clone()),
+			(String::from("cores"), entry.cores.join(", ")),
+			(String::from("tags"), entry.tags.join(", ")),
+			(String::from("versions"), entry.versions.iter()
+			 .map(|v| v.to_string()).collect::<Vec<_>>().join(", ")),
+			(String::from("registry"), entry.registry.clone()),
+		];
+		fields.extend(entry.details.iter().cloned());
+		for (key, val) in fields.iter().filter(|f| !f.1.is_empty())
+		{
+			say!("  {:<16} {}", key, val);
+		}
+	}
+	ErrNone
+}
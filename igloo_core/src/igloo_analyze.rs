@@ -0,0 +1,557 @@
+use igloo_base::*;
+use igloo_base::IglooErrType::*;
+
+use crate::igloo_elf::*;
+
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Analysis of the last analyze run, kept in the target directory
+pub const ANALYZE_REPORT: &str = "analyze.json";
+/// Analysis of the build before the last one, used by --diff
+pub const ANALYZE_REPORT_PREV: &str = "analyze.prev.json";
+
+/// An input section placed by the linker, read from the .map file
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapEntry
+{
+	/// output section, e.g. .text
+	pub output: String,
+	/// input section, e.g. .text.main or *fill*
+	pub input: String,
+	pub addr: u64,
+	pub size: u64,
+	/// object file or archive(member). Empty for linker generated entries
+	pub file: String,
+}
+
+/// Parses the memory map of a GNU ld .map file
+pub fn parse_map(text: &str) -> Vec<MapEntry>
+{
+	let mut ret: Vec<MapEntry> = Vec::new();
+	let mut in_memory_map = false;
+	let mut output: Option<String> = None;
+	// output section name on its own line, addr and size follow on the next line
+	let mut pending_output = false;
+	// input section name on its own line
+	let mut pending_input: Option<String> = None;
+
+	for line in text.lines()
+	{
+		if !in_memory_map
+		{
+			in_memory_map = line.starts_with("Linker script and memory map");
+			continue
+		}
+
+		let tokens: Vec<&str> = line.split_whitespace().collect();
+		if tokens.is_empty()
+		{
+			continue
+		}
+		let is_hex = |t: &str| t.starts_with("0x");
+
+		if !line.starts_with(' ')
+		{
+			pending_input = None;
+			if tokens[0].starts_with('.')
+			{
+				output = Some(String::from(tokens[0]));
+				pending_output = tokens.len() == 1;
+			}
+			else
+			{
+				output = None;
+				pending_output = false;
+			}
+			continue
+		}
+
+		if pending_output
+		{
+			// continuation of the output section line
+			pending_output = false;
+			if is_hex(tokens[0])
+			{
+				continue
+			}
+		}
+
+		let output_name = match &output
+		{
+			Some(v) => v.clone(),
+			None => continue,
+		};
+
+		if let Some(input) = pending_input.take()
+		{
+			if tokens.len() >= 2 && is_hex(tokens[0]) && is_hex(tokens[1])
+			{
+				push_entry(&mut ret, &output_name, &input, &tokens);
+				continue
+			}
+		}
+
+		let name = tokens[0];
+		let is_input = name.starts_with('.') || name == "COMMON" || name == "*fill*";
+		if !is_input
+		{
+			// symbol assignments and input section patterns
+			continue
+		}
+		if tokens.len() == 1
+		{
+			pending_input = Some(String::from(name));
+			continue
+		}
+		if tokens.len() >= 3 && is_hex(tokens[1]) && is_hex(tokens[2])
+		{
+			push_entry(&mut ret, &output_name, name, &tokens[1..]);
+		}
+	}
+	ret
+}
+
+/// tokens -- addr, size and the optional file
+fn push_entry(entries: &mut Vec<MapEntry>, output: &str, input: &str, tokens: &[&str])
+{
+	let parse_hex = |t: &str| u64::from_str_radix(t.trim_start_matches("0x"), 16).unwrap_or(0);
+	let size = parse_hex(tokens[1]);
+	if size == 0
+	{
+		return
+	}
+	entries.push(MapEntry
+	{
+		output: String::from(output),
+		input: String::from(input),
+		addr: parse_hex(tokens[0]),
+		size,
+		file: tokens[2..].join(" "),
+	});
+}
+
+/// Where the bytes of a file go, berkeley size style
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Footprint
+{
+	/// code and read only data, flash
+	pub code: u64,
+	/// initialized data, flash and RAM
+	pub data: u64,
+	/// zero initialized data, RAM
+	pub bss: u64,
+}
+
+impl Footprint
+{
+	pub fn total(&self) -> u64
+	{
+		self.code + self.data + self.bss
+	}
+
+	fn add(&mut self, other: &Footprint)
+	{
+		self.code += other.code;
+		self.data += other.data;
+		self.bss += other.bss;
+	}
+
+	fn to_json(self) -> serde_json::Value
+	{
+		json!({ "code": self.code, "data": self.data, "bss": self.bss })
+	}
+
+	fn from_json(val: &serde_json::Value) -> Footprint
+	{
+		Footprint
+		{
+			code: val["code"].as_u64().unwrap_or(0),
+			data: val["data"].as_u64().unwrap_or(0),
+			bss: val["bss"].as_u64().unwrap_or(0),
+		}
+	}
+}
+
+/// Footprint of one object file
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileFootprint
+{
+	/// user, ESF, the library name (libc_nano, libgcc...) or (linker)
+	pub group: String,
+	pub file: String,
+	pub sizes: Footprint,
+	/// footprint per output section
+	pub sections: BTreeMap<String, Footprint>,
+	/// (symbol, size) defined in the file, largest first
+	pub symbols: Vec<(String, u64)>,
+}
+
+/// Returns the group a file of the map belongs to
+/// Archive members belong to their library, objects below an ESF folder to ESF
+/// and every other object to the user.
+pub fn file_group(file: &str) -> String
+{
+	if file.is_empty()
+	{
+		return String::from("(linker)")
+	}
+	if let Some(archive) = file.split('(').next().filter(|_a| file.ends_with(')'))
+	{
+		let archive = Path::new(archive);
+		return archive.file_stem()
+			.map(|v| v.to_string_lossy().into_owned())
+			.unwrap_or_else(|| String::from("(library)"))
+	}
+	if file.replace('\\', "/").split('/').any(|c| c == "ESF")
+	{
+		return String::from("ESF")
+	}
+	String::from("user")
+}
+
+pub struct IglooAnalysis
+{
+	pub target: String,
+	pub files: Vec<FileFootprint>,
+}
+
+impl IglooAnalysis
+{
+	/// Attributes every allocated input section of the map to its file.
+	/// The ELF tells which output sections are loaded and whether they hold
+	/// code, data or bss. Its symbols are attributed to the input section
+	/// containing their address.
+	pub fn from_map(target: &str, map: &[MapEntry], elf: &IglooElf) -> IglooAnalysis
+	{
+		let mut files: BTreeMap<String, FileFootprint> = BTreeMap::new();
+		let mut placed: Vec<(&MapEntry, String)> = Vec::new();
+		for entry in map
+		{
+			let section = match elf.section(&entry.output)
+			{
+				Some(v) if v.is_alloc() => v,
+				_ => continue,
+			};
+			let mut sizes = Footprint::default();
+			if section.is_nobits()
+			{
+				sizes.bss = entry.size;
+			}
+			else if section.is_write()
+			{
+				sizes.data = entry.size;
+			}
+			else
+			{
+				sizes.code = entry.size;
+			}
+
+			let file_name = if entry.file.is_empty() { entry.input.clone() } else { entry.file.clone() };
+			let file = files.entry(file_name.clone()).or_insert_with(|| FileFootprint
+			{
+				group: file_group(&entry.file),
+				file: file_name.clone(),
+				sizes: Footprint::default(),
+				sections: BTreeMap::new(),
+				symbols: Vec::new(),
+			});
+			file.sizes.add(&sizes);
+			file.sections.entry(entry.output.clone()).or_default().add(&sizes);
+			placed.push((entry, file_name));
+		}
+
+		for symbol in &elf.symbols
+		{
+			if symbol.size == 0 || (symbol.kind != STT_FUNC && symbol.kind != STT_OBJECT)
+			{
+				continue
+			}
+			let owner = placed.iter().find(|(e, _f)|
+				symbol.value >= e.addr && symbol.value < e.addr + e.size);
+			if let Some((_e, file_name)) = owner
+			{
+				let file = files.get_mut(file_name).unwrap();
+				file.symbols.push((symbol.name.clone(), symbol.size));
+			}
+		}
+
+		let mut files: Vec<FileFootprint> = files.into_iter().map(|(_k, v)| v).collect();
+		for file in &mut files
+		{
+			file.symbols.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+			file.symbols.dedup();
+		}
+		files.sort_by(|a, b| b.sizes.total().cmp(&a.sizes.total()).then(a.file.cmp(&b.file)));
+		IglooAnalysis
+		{
+			target: String::from(target),
+			files,
+		}
+	}
+
+	pub fn total(&self) -> Footprint
+	{
+		let mut ret = Footprint::default();
+		for file in &self.files
+		{
+			ret.add(&file.sizes);
+		}
+		ret
+	}
+
+	/// Footprint per group (user, ESF, libraries), largest first
+	pub fn by_group(&self) -> Vec<(String, Footprint)>
+	{
+		let mut groups: BTreeMap<String, Footprint> = BTreeMap::new();
+		for file in &self.files
+		{
+			groups.entry(file.group.clone()).or_default().add(&file.sizes);
+		}
+		sorted(groups)
+	}
+
+	/// Footprint per output section, largest first
+	pub fn by_section(&self) -> Vec<(String, Footprint)>
+	{
+		let mut sections: BTreeMap<String, Footprint> = BTreeMap::new();
+		for file in &self.files
+		{
+			for (name, sizes) in &file.sections
+			{
+				sections.entry(name.clone()).or_default().add(sizes);
+			}
+		}
+		sorted(sections)
+	}
+
+	/// Footprint per file, largest first
+	pub fn by_file(&self) -> Vec<(String, Footprint)>
+	{
+		self.files.iter().map(|f| (f.file.clone(), f.sizes)).collect()
+	}
+
+	/// Rows for one of the analyze views: group, file or section
+	pub fn rows(&self, by: &str) -> Vec<(String, Footprint)>
+	{
+		match by
+		{
+			"file" => self.by_file(),
+			"section" => self.by_section(),
+			_ => self.by_group(),
+		}
+	}
+
+	pub fn to_json(&self) -> serde_json::Value
+	{
+		json!({
+			"target": self.target,
+			"total": self.total().to_json(),
+			"files": self.files.iter().map(|f| json!({
+				"group": f.group,
+				"file": f.file,
+				"sizes": f.sizes.to_json(),
+				"sections": f.sections.iter()
+					.map(|(k, v)| (k.clone(), v.to_json()))
+					.collect::<serde_json::Map<String, serde_json::Value>>(),
+				"symbols": f.symbols.iter()
+					.map(|(name, size)| json!({ "name": name, "size": size }))
+					.collect::<Vec<_>>(),
+			})).collect::<Vec<_>>(),
+		})
+	}
+
+	pub fn from_json(val: &serde_json::Value) -> IglooAnalysis
+	{
+		let mut files: Vec<FileFootprint> = Vec::new();
+		for file in val["files"].as_array().cloned().unwrap_or_default()
+		{
+			files.push(FileFootprint
+			{
+				group: file["group"].as_str().unwrap_or_default().to_string(),
+				file: file["file"].as_str().unwrap_or_default().to_string(),
+				sizes: Footprint::from_json(&file["sizes"]),
+				sections: file["sections"].as_object()
+					.map(|m| m.iter().map(|(k, v)| (k.clone(), Footprint::from_json(v))).collect())
+					.unwrap_or_default(),
+				symbols: file["symbols"].as_array()
+					.map(|a| a.iter().map(|s| (
+						s["name"].as_str().unwrap_or_default().to_string(),
+						s["size"].as_u64().unwrap_or(0))).collect())
+					.unwrap_or_default(),
+			});
+		}
+		IglooAnalysis
+		{
+			target: val["target"].as_str().unwrap_or_default().to_string(),
+			files,
+		}
+	}
+
+	/// Prints one of the views as a table
+	pub fn print_table(&self, by: &str)
+	{
+		say!("Target {}", self.target);
+		say!("{:<48} {:>9} {:>9} {:>9} {:>9}", by, "code", "data", "bss", "total");
+		for (name, sizes) in self.rows(by)
+		{
+			say!("{:<48} {:>9} {:>9} {:>9} {:>9}",
+					 name, sizes.code, sizes.data, sizes.bss, sizes.total());
+		}
+		let total = self.total();
+		say!("{:<48} {:>9} {:>9} {:>9} {:>9}",
+				 "total", total.code, total.data, total.bss, total.total());
+	}
+
+	/// Prints group -> file -> symbol as a tree
+	pub fn print_tree(&self)
+	{
+		let total = self.total();
+		say!("{} (code {}, data {}, bss {})",
+				 self.target, total.code, total.data, total.bss);
+		let groups = self.by_group();
+		for (gi, (group, sizes)) in groups.iter().enumerate()
+		{
+			let last_group = gi + 1 == groups.len();
+			say!("{}{} {}", if last_group { "└── " } else { "├── " },
+					 group, sizes.total());
+			let group_prefix = if last_group { "    " } else { "│   " };
+			let files: Vec<&FileFootprint> = self.files.iter()
+				.filter(|f| &f.group == group)
+				.collect();
+			for (fi, file) in files.iter().enumerate()
+			{
+				let last_file = fi + 1 == files.len();
+				say!("{}{}{} {}", group_prefix, if last_file { "└── " } else { "├── " },
+						 file.file, file.sizes.total());
+				let file_prefix = if last_file { "    " } else { "│   " };
+				for (si, (symbol, size)) in file.symbols.iter().enumerate()
+				{
+					let last_symbol = si + 1 == file.symbols.len();
+					say!("{}{}{}{} {}", group_prefix, file_prefix,
+							 if last_symbol { "└── " } else { "├── " }, symbol, size);
+				}
+			}
+		}
+	}
+
+	/// Changes versus an older analysis for one of the views
+	/// Returns (name, old total, new total), largest change first. Unchanged rows are left out.
+	pub fn diff(&self, old: &IglooAnalysis, by: &str) -> Vec<(String, u64, u64)>
+	{
+		let mut rows: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+		for (name, sizes) in old.rows(by)
+		{
+			rows.entry(name).or_default().0 = sizes.total();
+		}
+		for (name, sizes) in self.rows(by)
+		{
+			rows.entry(name).or_default().1 = sizes.total();
+		}
+		let mut ret: Vec<(String, u64, u64)> = rows.into_iter()
+			.filter(|(_n, (o, n))| o != n)
+			.map(|(name, (o, n))| (name, o, n))
+			.collect();
+		ret.sort_by(|a, b| delta(b.1, b.2).abs().cmp(&delta(a.1, a.2).abs()).then(a.0.cmp(&b.0)));
+		ret
+	}
+
+	pub fn print_diff(&self, old: &IglooAnalysis, by: &str)
+	{
+		say!("Target {}", self.target);
+		say!("{:<48} {:>9} {:>9} {:>9}", by, "old", "new", "delta");
+		for (name, o, n) in self.diff(old, by)
+		{
+			say!("{:<48} {:>9} {:>9} {:>+9}", name, o, n, delta(o, n));
+		}
+		let (o, n) = (old.total().total(), self.total().total());
+		say!("{:<48} {:>9} {:>9} {:>+9}", "total", o, n, delta(o, n));
+	}
+}
+
+fn delta(old: u64, new: u64) -> i64
+{
+	new as i64 - old as i64
+}
+
+fn sorted(rows: BTreeMap<String, Footprint>) -> Vec<(String, Footprint)>
+{
+	let mut ret: Vec<(String, Footprint)> = rows.into_iter().collect();
+	ret.sort_by(|a, b| b.1.total().cmp(&a.1.total()).then(a.0.cmp(&b.0)));
+	ret
+}
+
+/// Reads the .map file and ELF of a build and analyzes them
+pub fn analyze_build(fs: &dyn IglooFs, target: &str, map_path: &Path, elf_path: &Path)
+					 -> Result<IglooAnalysis, IglooErrType>
+{
+	let map = match fs.read_to_string(map_path)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			error!("{}: {}\nBuild the target first", map_path.display(), e);
+			return Err(FailedToReadMap)
+		}
+	};
+	let map = parse_map(&map);
+	if map.is_empty()
+	{
+		error!("{}: no memory map found", map_path.display());
+		return Err(FailedToReadMap)
+	}
+
+	let elf = match fs.read(elf_path)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			error!("{}: {}\nBuild the target first", elf_path.display(), e);
+			return Err(FailedToReadElf)
+		}
+	};
+	match IglooElf::parse(elf)
+	{
+		Ok(v) => Ok(IglooAnalysis::from_map(target, &map, &v)),
+		Err(e) =>
+		{
+			error!("{}: {}", elf_path.display(), e);
+			Err(FailedToReadElf)
+		}
+	}
+}
+
+/// Stores analysis as the analysis of the last build. The stored analysis
+/// is rotated to analyze.prev.json if it belongs to a different build.
+/// Returns the analysis of the previous build, if any.
+pub fn store_analysis(fs: &dyn IglooFs, target_root: &Path, analysis: &IglooAnalysis)
+					  -> Option<IglooAnalysis>
+{
+	let report_path = target_root.join(ANALYZE_REPORT);
+	let prev_path = target_root.join(ANALYZE_REPORT_PREV);
+	let current = analysis.to_json();
+
+	let last = fs.read_to_string(&report_path).ok()
+		.and_then(|v| serde_json::from_str::<serde_json::Value>(&v).ok());
+	if let Some(last) = last
+	{
+		if last != current
+		{
+			match fs.write(&prev_path, last.to_string().as_bytes())
+			{
+				Err(e) => error!("{:?}", e),
+				_ => (),
+			}
+		}
+	}
+	match fs.write(&report_path, current.to_string().as_bytes())
+	{
+		Err(e) => error!("{:?}", e),
+		_ => (),
+	}
+
+	fs.read_to_string(&prev_path).ok()
+		.and_then(|v| serde_json::from_str::<serde_json::Value>(&v).ok())
+		.map(|v| IglooAnalysis::from_json(&v))
+}
@@ -0,0 +1,242 @@
+// Minimal archive reader
+// Unpacks the .tar and .tar.gz archives toolchains are distributed in. Reads ustar,
+// GNU long names and pax path records, which covers what GNU tar and bsdtar write.
+
+/// What an archive entry is
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArchiveKind
+{
+	File { data: Vec<u8>, executable: bool },
+	Dir,
+	/// symlink to the given path, relative to the link
+	Symlink(String),
+	/// hard link to another entry of the archive
+	Hardlink(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveEntry
+{
+	/// path inside the archive, without leading ./
+	pub path: String,
+	pub kind: ArchiveKind,
+}
+
+/// Unpacks an archive, the format is taken from name
+pub fn unpack(name: &str, data: &[u8]) -> Result<Vec<ArchiveEntry>, String>
+{
+	if name.ends_with(".tar.gz") || name.ends_with(".tgz")
+	{
+		untar(&gunzip(data)?)
+	}
+	else if name.ends_with(".tar")
+	{
+		untar(data)
+	}
+	else
+	{
+		Err(format!("{}: unsupported archive, use .tar or .tar.gz", name))
+	}
+}
+
+/// Decompresses a gzip file (RFC 1952)
+pub fn gunzip(data: &[u8]) -> Result<Vec<u8>, String>
+{
+	const FHCRC: u8 = 0x02;
+	const FEXTRA: u8 = 0x04;
+	const FNAME: u8 = 0x08;
+	const FCOMMENT: u8 = 0x10;
+
+	if data.len() < 18 || data[0..3] != [0x1f, 0x8b, 8]
+	{
+		return Err(String::from("not a gzip file"))
+	}
+	let flags = data[3];
+	let mut off = 10;
+	if flags & FEXTRA != 0
+	{
+		let len = *data.get(off).ok_or("truncated gzip header")? as usize
+			| (*data.get(off + 1).ok_or("truncated gzip header")? as usize) << 8;
+		off += 2 + len;
+	}
+	for flag in &[FNAME, FCOMMENT]
+	{
+		if flags & flag != 0
+		{
+			// zero terminated
+			match data.get(off..).and_then(|d| d.iter().position(|b| *b == 0))
+			{
+				Some(v) => off += v + 1,
+				None => return Err(String::from("truncated gzip header")),
+			}
+		}
+	}
+	if flags & FHCRC != 0
+	{
+		off += 2;
+	}
+	let body = match data.get(off..data.len() - 8)
+	{
+		Some(v) => v,
+		None => return Err(String::from("truncated gzip file")),
+	};
+	let out = miniz_oxide::inflate::decompress_to_vec(body)
+		.map_err(|e| format!("corrupt gzip data: {:?}", e))?;
+
+	let trailer = &data[data.len() - 8..];
+	let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+	if igloo_base::crc32(&out) != crc
+	{
+		return Err(String::from("gzip CRC mismatch"))
+	}
+	Ok(out)
+}
+
+fn field(header: &[u8], start: usize, len: usize) -> String
+{
+	let bytes = &header[start..start + len];
+	let end = bytes.iter().position(|b| *b == 0).unwrap_or(len);
+	String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// Octal number field, or base-256 if the high bit of the first byte is set
+fn number(header: &[u8], start: usize, len: usize) -> Result<u64, String>
+{
+	let bytes = &header[start..start + len];
+	if bytes[0] & 0x80 != 0
+	{
+		return Ok(bytes[1..].iter().fold(0u64, |a, b| (a << 8) | *b as u64))
+	}
+	let text = field(header, start, len);
+	let text = text.trim_matches(|c: char| c == ' ' || c == '\0');
+	if text.is_empty()
+	{
+		return Ok(0)
+	}
+	u64::from_str_radix(text, 8).map_err(|_e| format!("bad tar number \"{}\"", text))
+}
+
+/// Reads the path and linkpath records of a pax extended header
+fn pax_records(data: &[u8]) -> (Option<String>, Option<String>)
+{
+	let mut path = None;
+	let mut link = None;
+	let text = String::from_utf8_lossy(data);
+	let mut rest: &str = &text;
+	// each record is "<len> <key>=<value>\n", len counting the whole record
+	while let Some((len, _)) = rest.split_once(' ')
+	{
+		let len: usize = match len.parse()
+		{
+			Ok(v) if v > 0 && v <= rest.len() => v,
+			_ => break,
+		};
+		let record = &rest[..len];
+		rest = &rest[len..];
+		if let Some((key, value)) = record.split_once(' ')
+			.and_then(|(_l, r)| r.trim_end_matches('\n').split_once('='))
+		{
+			match key
+			{
+				"path" => path = Some(String::from(value)),
+				"linkpath" => link = Some(String::from(value)),
+				_ => (),
+			}
+		}
+	}
+	(path, link)
+}
+
+/// Reads a tar archive
+pub fn untar(data: &[u8]) -> Result<Vec<ArchiveEntry>, String>
+{
+	let mut entries: Vec<ArchiveEntry> = Vec::new();
+	let mut off = 0;
+	let mut long_name: Option<String> = None;
+	let mut long_link: Option<String> = None;
+	while off + 512 <= data.len()
+	{
+		let header = &data[off..off + 512];
+		if header.iter().all(|b| *b == 0)
+		{
+			break
+		}
+		let sum: u64 = header.iter().enumerate()
+			.map(|(i, b)| if (148..156).contains(&i) { b' ' as u64 } else { *b as u64 })
+			.sum();
+		if number(header, 148, 8)? != sum
+		{
+			return Err(format!("bad tar header checksum at offset {}", off))
+		}
+
+		// base-256 sizes can be far larger than the archive
+		let size = number(header, 124, 12)? as usize;
+		let body_start = off + 512;
+		let body = match body_start.checked_add(size).and_then(|end| data.get(body_start..end))
+		{
+			Some(v) => v,
+			None => return Err(String::from("truncated tar archive")),
+		};
+		off = match size.checked_add(511).and_then(|v| body_start.checked_add(v / 512 * 512))
+		{
+			Some(v) => v,
+			None => return Err(String::from("truncated tar archive")),
+		};
+
+		let kind = header[156];
+		match kind
+		{
+			// GNU long name and long link name of the next entry
+			b'L' => { long_name = Some(field(body, 0, body.len())); continue }
+			b'K' => { long_link = Some(field(body, 0, body.len())); continue }
+			b'x' =>
+			{
+				let (path, link) = pax_records(body);
+				long_name = path.or(long_name);
+				long_link = link.or(long_link);
+				continue
+			}
+			// pax global header
+			b'g' => continue,
+			_ => (),
+		}
+
+		let path = match long_name.take()
+		{
+			Some(v) => v,
+			None =>
+			{
+				let name = field(header, 0, 100);
+				let prefix = if &header[257..262] == b"ustar" { field(header, 345, 155) }
+							 else { String::new() };
+				if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) }
+			}
+		};
+		let link = long_link.take().unwrap_or_else(|| field(header, 157, 100));
+		let path = path.trim_start_matches("./").trim_end_matches('/').to_string();
+		if path.is_empty()
+		{
+			continue
+		}
+		if path.split('/').any(|c| c == "..") || path.starts_with('/')
+		{
+			return Err(format!("{}: entry escapes the archive", path))
+		}
+
+		let kind = match kind
+		{
+			b'0' | b'\0' | b'7' => ArchiveKind::File
+			{
+				data: body.to_vec(),
+				executable: number(header, 100, 8)? & 0o111 != 0,
+			},
+			b'5' => ArchiveKind::Dir,
+			b'2' => ArchiveKind::Symlink(link),
+			b'1' => ArchiveKind::Hardlink(link.trim_start_matches("./").to_string()),
+			// devices and fifos have no place in a toolchain
+			_ => continue,
+		};
+		entries.push(ArchiveEntry { path, kind });
+	}
+	Ok(entries)
+}
@@ -0,0 +1,337 @@
+// igloo doctor
+// Checks everything igloo needs from the machine it runs on: ESF, the master
+// manifests, the toolchain of every target, openocd, make and symlink support.
+// Every failed check comes with a fix the user can act on.
+use igloo_base::*;
+use igloo_base::IglooErrType::*;
+use igloo_manifest::*;
+
+use crate::Igloo;
+use crate::igloo_project::IglooPrj;
+use crate::igloo_target::IglooTarget;
+
+use config::Config;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CheckStatus
+{
+	Ok,
+	/// igloo works, but some commands will not
+	Warn,
+	/// igloo cannot build or flash
+	Error,
+}
+
+impl CheckStatus
+{
+	pub fn name(&self) -> &'static str
+	{
+		match self
+		{
+			CheckStatus::Ok => "ok",
+			CheckStatus::Warn => "warn",
+			CheckStatus::Error => "error",
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IglooCheck
+{
+	pub name: String,
+	pub status: CheckStatus,
+	/// what was found (a path, a version) or what is wrong
+	pub detail: String,
+	/// how to fix a failed check
+	pub fix: Option<String>,
+}
+
+impl IglooCheck
+{
+	fn ok(name: &str, detail: &str) -> IglooCheck
+	{
+		IglooCheck
+		{
+			name: String::from(name),
+			status: CheckStatus::Ok,
+			detail: String::from(detail),
+			fix: None,
+		}
+	}
+
+	fn failed(name: &str, status: CheckStatus, detail: &str, fix: &str) -> IglooCheck
+	{
+		IglooCheck
+		{
+			name: String::from(name),
+			status,
+			detail: String::from(detail),
+			fix: Some(String::from(fix)),
+		}
+	}
+}
+
+/// Make manifest variables naming toolchain programs, the status to report when
+/// one is missing and what it is needed for
+const TOOLCHAIN_PROGRAMS: [(&str, CheckStatus, &str); 7] =
+[
+	("CC", CheckStatus::Error, "compiling"),
+	("AS", CheckStatus::Error, "assembling"),
+	("CXX", CheckStatus::Warn, "C++ sources"),
+	("OBJCOPY", CheckStatus::Warn, "the bin/hex make targets"),
+	("OBJDUMP", CheckStatus::Warn, "the lss make target"),
+	("SIZE", CheckStatus::Warn, "the size make target"),
+	("GDB", CheckStatus::Warn, "flashing and debugging"),
+];
+
+/// Pulls a version number out of the first line of --version output,
+/// e.g. 10.3.1 out of "arm-none-eabi-gcc (GNU Arm Embedded Toolchain 10.3-2021.10) 10.3.1"
+pub fn parse_tool_version(output: &str) -> Option<String>
+{
+	let line = output.lines().find(|l| !l.trim().is_empty())?.trim();
+	let is_version = |t: &str| t.contains('.')
+		&& t.split('.').all(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit()));
+	let tokens: Vec<&str> = line.split_whitespace()
+		.map(|t| t.trim_matches(|c: char| c == '(' || c == ')' || c == ',' || c == 'v'))
+		.collect();
+	// 10.3.1, then 10.2.90 out of 10.2.90.20210621-git
+	let version = tokens.iter().find(|t| is_version(t))
+		.or_else(|| tokens.iter().rev().find(|t| is_version(t.split('-').next().unwrap_or(""))))
+		.map(|t| t.split('-').next().unwrap_or(t));
+	Some(String::from(version.unwrap_or(line)))
+}
+
+/// Runs program --version. openocd prints its version to stderr
+fn probe(inst: &Igloo, program: &str) -> Result<String, String>
+{
+	match inst.host.output(program, &["--version"])
+	{
+		Ok(v) =>
+		{
+			let text = if v.stdout.trim().is_empty() { &v.stderr } else { &v.stdout };
+			Ok(parse_tool_version(text).unwrap_or_else(|| String::from("unknown version")))
+		}
+		Err(e) if e.kind() == ErrorKind::NotFound => Err(String::from("not found on PATH")),
+		Err(e) => Err(format!("could not be run: {}", e)),
+	}
+}
+
+/// Checks a program that is not part of a toolchain
+fn check_program(inst: &Igloo, program: &str, missing: CheckStatus, fix: &str) -> IglooCheck
+{
+	match probe(inst, program)
+	{
+		Ok(v) => IglooCheck::ok(program, &v),
+		Err(e) => IglooCheck::failed(program, missing, &e, fix),
+	}
+}
+
+/// Targets whose toolchains are checked: the project's targets inside a project,
+/// every target in the master target manifest otherwise
+fn doctor_targets(inst: &Igloo, checks: &mut Vec<IglooCheck>) -> Vec<IglooTarget>
+{
+	let names: Vec<String> = if inst.fs.exists(&inst.env.cwd.join(".igloo"))
+	{
+		let project = match IglooPrj::open(inst)
+		{
+			Ok(v) => v,
+			Err(e) =>
+			{
+				checks.push(IglooCheck::failed(
+					"project", CheckStatus::Error, &format!("{:?}", e),
+					"Fix the project file in .igloo or run igloo doctor outside of the project"));
+				return Vec::new()
+			}
+		};
+		match project.get_targets(&[])
+		{
+			Ok(v) => v.iter().map(|t| t.name.clone()).collect(),
+			Err(_e) => Vec::new(),
+		}
+	}
+	else
+	{
+		let mut names: Vec<String> = match inst.master_target_man.get_table("target.make")
+		{
+			Ok(v) => v.into_iter().map(|(k, _v)| k).collect(),
+			Err(_e) => Vec::new(),
+		};
+		names.sort();
+		names
+	};
+
+	let mut ret: Vec<IglooTarget> = Vec::new();
+	for name in names
+	{
+		let make_loc = inst.master_target_man.get_str(&format!("target.make.{}", name));
+		let man_loc = inst.master_target_man.get_str(&format!("target.manifest.{}", name));
+		let target = match (make_loc, man_loc)
+		{
+			(Ok(make_loc), Ok(man_loc)) =>
+				IglooTarget::from(PathBuf::new(), inst, name.clone(), &make_loc, &man_loc),
+			_ => Err(InvalidTarget),
+		};
+		match target
+		{
+			Ok(v) => ret.push(v),
+			Err(e) => checks.push(IglooCheck::failed(
+				&format!("target {}", name), CheckStatus::Error, &format!("{:?}", e),
+				&format!("Check the entries of {} in ESF_DIR/manifest/target-manifest.toml \
+						  and its target manifest", name))),
+		}
+	}
+	ret
+}
+
+/// Runs every check
+pub fn run_checks(inst: &Igloo) -> Vec<IglooCheck>
+{
+	let mut checks: Vec<IglooCheck> = Vec::new();
+
+	let esfd = &inst.env.esfd;
+	if esfd.as_os_str().is_empty()
+	{
+		checks.push(IglooCheck::failed(
+			"ESF_DIR", CheckStatus::Error, "not set",
+			"export ESF_DIR=/path/to/ESF, e.g. in your shell profile"));
+		return checks
+	}
+	if !inst.fs.is_dir(esfd)
+	{
+		checks.push(IglooCheck::failed(
+			"ESF_DIR", CheckStatus::Error,
+			&format!("{} is not a directory", esfd.display()),
+			"Point ESF_DIR at a checkout of ESF"));
+		return checks
+	}
+	checks.push(IglooCheck::ok("ESF_DIR", &esfd.display().to_string()));
+
+	let mut make_man = Config::new();
+	let mut manifests_ok = true;
+	for (name, file, res_err) in &[
+		("make manifest", "make-manifest.toml",
+		 get_master_make_manifest(inst.fs.as_ref(), esfd, &mut make_man)),
+		("target manifest", "target-manifest.toml",
+		 get_master_target_manifest(inst.fs.as_ref(), esfd, &mut Config::new())),
+	]
+	{
+		let path = esfd.join("manifest").join(file);
+		if *res_err == ErrNone
+		{
+			checks.push(IglooCheck::ok(name, &path.display().to_string()));
+		}
+		else
+		{
+			manifests_ok = false;
+			checks.push(IglooCheck::failed(
+				name, CheckStatus::Error, &format!("{}: {:?}", path.display(), res_err),
+				"Update ESF or fix the TOML syntax of the manifest"));
+		}
+	}
+
+	if manifests_ok
+	{
+		// program -> (status if missing, what it is for, toolchain prefix, targets)
+		let mut programs: BTreeMap<String, (CheckStatus, &str, String, Vec<String>)> =
+			BTreeMap::new();
+		for target in doctor_targets(inst, &mut checks)
+		{
+			let prefix = target.make_manifest.get("TOOLCHAIN")
+				.and_then(|v| v.clone().into_str().ok())
+				.unwrap_or_default();
+			for (var, missing, use_for) in TOOLCHAIN_PROGRAMS.iter()
+			{
+				let program = match target.make_manifest.get(*var)
+					.and_then(|v| v.clone().into_str().ok())
+				{
+					Some(v) if !v.is_empty() => v,
+					_ => continue,
+				};
+				programs.entry(program)
+					.or_insert((*missing, use_for, prefix.clone(), Vec::new()))
+					.3.push(target.name.clone());
+			}
+		}
+		for (program, (missing, use_for, prefix, targets)) in programs
+		{
+			let toolchain = prefix.trim_end_matches('-');
+			let fix = if toolchain.is_empty()
+			{
+				format!("Install the toolchain providing {} and add it to PATH", program)
+			}
+			else
+			{
+				format!("Install the {} toolchain (e.g. the Arm GNU Toolchain) \
+						 and add its bin directory to PATH", toolchain)
+			};
+			checks.push(match probe(inst, &program)
+			{
+				Ok(v) => IglooCheck::ok(&program, &v),
+				Err(e) => IglooCheck::failed(
+					&program, missing,
+					&format!("{}, needed for {} by {}", e, use_for, targets.join(", ")),
+					&fix),
+			});
+		}
+	}
+
+	checks.push(check_program(inst, "openocd", CheckStatus::Warn,
+							  "Install OpenOCD (0.10 or newer) to flash and debug targets"));
+	checks.push(check_program(inst, "make", CheckStatus::Error,
+							  "Install GNU make, igloo builds with generated Makefiles"));
+	checks.push(check_program(inst, "ninja", CheckStatus::Warn,
+							  "Optional: install ninja"));
+
+	checks.push(match inst.host.symlinks_supported()
+	{
+		Ok(()) => IglooCheck::ok("symlinks", "supported"),
+		Err(e) => IglooCheck::failed(
+			"symlinks", CheckStatus::Error, &e.to_string(),
+			"igloo links ESF into projects. On Windows enable Developer Mode \
+			 or run igloo as administrator"),
+	});
+	checks
+}
+
+pub fn checks_to_json(checks: &[IglooCheck]) -> serde_json::Value
+{
+	let count = |status: CheckStatus| checks.iter().filter(|c| c.status == status).count();
+	json!({
+		"checks": checks.iter().map(|c| json!({
+			"name": c.name,
+			"status": c.status.name(),
+			"detail": c.detail,
+			"fix": c.fix,
+		})).collect::<Vec<_>>(),
+		"summary": {
+			"ok": count(CheckStatus::Ok),
+			"warn": count(CheckStatus::Warn),
+			"error": count(CheckStatus::Error),
+		},
+	})
+}
+
+pub fn print_checks(checks: &[IglooCheck])
+{
+	for check in checks
+	{
+		let status = match check.status
+		{
+			CheckStatus::Ok => "[ ok ]",
+			CheckStatus::Warn => "[warn]",
+			CheckStatus::Error => "[FAIL]",
+		};
+		say!("{} {:<24} {}", status, check.name, check.detail);
+		if let Some(fix) = &check.fix
+		{
+			say!("       fix: {}", fix);
+		}
+	}
+	let count = |status: CheckStatus| checks.iter().filter(|c| c.status == status).count();
+	say!("\n{} ok, {} warnings, {} errors",
+			 count(CheckStatus::Ok), count(CheckStatus::Warn), count(CheckStatus::Error));
+}
@@ -0,0 +1,360 @@
+// Minimal ELF reader
+// Reads the section headers, program headers and symbol table of 32 and 64 bit,
+// little and big endian ELF files. This is all igloo needs to report sizes and
+// convert images, so no external tool (arm-none-eabi-size, objcopy) is required.
+
+/// Section holds no data in the file (.bss)
+pub const SHT_NOBITS: u32 = 8;
+pub const SHT_SYMTAB: u32 = 2;
+pub const SHF_WRITE: u64 = 0x1;
+pub const SHF_ALLOC: u64 = 0x2;
+pub const SHF_EXECINSTR: u64 = 0x4;
+pub const PT_LOAD: u32 = 1;
+pub const STT_OBJECT: u8 = 1;
+pub const STT_FUNC: u8 = 2;
+const EM_ARM: u16 = 40;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElfSection
+{
+	pub name: String,
+	pub kind: u32,
+	pub flags: u64,
+	pub addr: u64,
+	pub offset: u64,
+	pub size: u64,
+}
+
+impl ElfSection
+{
+	/// Section occupies memory on the target
+	pub fn is_alloc(&self) -> bool
+	{
+		self.flags & SHF_ALLOC != 0
+	}
+
+	pub fn is_write(&self) -> bool
+	{
+		self.flags & SHF_WRITE != 0
+	}
+
+	pub fn is_exec(&self) -> bool
+	{
+		self.flags & SHF_EXECINSTR != 0
+	}
+
+	/// Section takes no space in the image (.bss, .stack)
+	pub fn is_nobits(&self) -> bool
+	{
+		self.kind == SHT_NOBITS
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElfSegment
+{
+	pub kind: u32,
+	pub offset: u64,
+	pub vaddr: u64,
+	/// load address, where the segment is stored in flash
+	pub paddr: u64,
+	pub filesz: u64,
+	pub memsz: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElfSymbol
+{
+	pub name: String,
+	/// address, with the thumb bit cleared for ARM functions
+	pub value: u64,
+	pub size: u64,
+	pub kind: u8,
+	/// name of the section the symbol is defined in
+	pub section: Option<String>,
+}
+
+pub struct IglooElf
+{
+	pub machine: u16,
+	pub entry: u64,
+	pub sections: Vec<ElfSection>,
+	pub segments: Vec<ElfSegment>,
+	pub symbols: Vec<ElfSymbol>,
+	pub data: Vec<u8>,
+}
+
+/// Reads integers of the file's class and byte order
+struct ElfReader<'a>
+{
+	data: &'a [u8],
+	is_64: bool,
+	is_le: bool,
+}
+
+impl<'a> ElfReader<'a>
+{
+	fn bytes(&self, off: u64, len: usize) -> Result<&'a [u8], String>
+	{
+		let off = off as usize;
+		match off.checked_add(len)
+		{
+			Some(end) if end <= self.data.len() => Ok(&self.data[off..end]),
+			_ => Err(format!("truncated ELF, read of {} bytes at 0x{:x}", len, off)),
+		}
+	}
+
+	fn uint(&self, off: u64, len: usize) -> Result<u64, String>
+	{
+		let bytes = self.bytes(off, len)?;
+		let mut ret: u64 = 0;
+		for i in 0..len
+		{
+			let b = if self.is_le { bytes[len - 1 - i] } else { bytes[i] };
+			ret = (ret << 8) | b as u64;
+		}
+		Ok(ret)
+	}
+
+	fn u8(&self, off: u64) -> Result<u8, String>
+	{
+		Ok(self.uint(off, 1)? as u8)
+	}
+
+	fn u16(&self, off: u64) -> Result<u16, String>
+	{
+		Ok(self.uint(off, 2)? as u16)
+	}
+
+	fn u32(&self, off: u64) -> Result<u32, String>
+	{
+		Ok(self.uint(off, 4)? as u32)
+	}
+
+	/// Address sized field (Elf32_Addr/Elf64_Addr, Off, Xword)
+	fn word(&self, off: u64) -> Result<u64, String>
+	{
+		self.uint(off, if self.is_64 { 8 } else { 4 })
+	}
+
+	/// Offset of entry index of the table at base, checked to lie in the file.
+	/// Fields of the entry can then be read at offset + field without overflow
+	fn entry(&self, base: u64, index: u64, entsize: u64, len: usize) -> Result<u64, String>
+	{
+		match index.checked_mul(entsize).and_then(|v| v.checked_add(base))
+		{
+			Some(off) => self.bytes(off, len).map(|_| off),
+			None => Err(format!("entry {} of the table at 0x{:x} overflows", index, base)),
+		}
+	}
+
+	/// String at off in the string table at table
+	fn str(&self, table: u64, off: u64) -> Result<String, String>
+	{
+		let off = match table.checked_add(off)
+		{
+			Some(v) => v,
+			None => return Err(format!("string offset 0x{:x} overflows", off)),
+		};
+		let start = off as usize;
+		if start > self.data.len()
+		{
+			return Err(format!("string offset 0x{:x} outside of the file", off))
+		}
+		let end = match self.data[start..].iter().position(|b| *b == 0)
+		{
+			Some(v) => start + v,
+			None => return Err(String::from("unterminated string")),
+		};
+		Ok(String::from_utf8_lossy(&self.data[start..end]).into_owned())
+	}
+}
+
+impl IglooElf
+{
+	pub fn parse(data: Vec<u8>) -> Result<IglooElf, String>
+	{
+		if data.len() < 16 || &data[0..4] != b"\x7fELF"
+		{
+			return Err(String::from("not an ELF file"))
+		}
+		let rd = ElfReader
+		{
+			data: &data,
+			is_64: data[4] == 2,
+			is_le: data[5] == 1,
+		};
+
+		let machine = rd.u16(18)?;
+		let entry = rd.word(24)?;
+		let (phoff, shoff, hdr) = if rd.is_64
+		{
+			(rd.word(32)?, rd.word(40)?, 52)
+		}
+		else
+		{
+			(rd.word(28)?, rd.word(32)?, 40)
+		};
+		let phentsize = rd.u16(hdr + 2)? as u64;
+		let phnum = rd.u16(hdr + 4)? as u64;
+		let shentsize = rd.u16(hdr + 6)? as u64;
+		let shnum = rd.u16(hdr + 8)? as u64;
+		let shstrndx = rd.u16(hdr + 10)? as u64;
+
+		// Section headers. Names are filled in once the string table is known
+		let mut sections: Vec<ElfSection> = Vec::new();
+		let mut name_offs: Vec<u64> = Vec::new();
+		let mut links: Vec<u64> = Vec::new();
+		for i in 0..shnum
+		{
+			let sh = rd.entry(shoff, i, shentsize, if rd.is_64 { 64 } else { 40 })?;
+			name_offs.push(rd.u32(sh)? as u64);
+			let kind = rd.u32(sh + 4)?;
+			let (flags, addr, offset, size, link) = if rd.is_64
+			{
+				(rd.word(sh + 8)?, rd.word(sh + 16)?, rd.word(sh + 24)?,
+				 rd.word(sh + 32)?, rd.u32(sh + 40)? as u64)
+			}
+			else
+			{
+				(rd.word(sh + 8)?, rd.word(sh + 12)?, rd.word(sh + 16)?,
+				 rd.word(sh + 20)?, rd.u32(sh + 24)? as u64)
+			};
+			if kind != SHT_NOBITS && offset.checked_add(size).is_none()
+			{
+				return Err(format!("section at 0x{:x} overflows", offset))
+			}
+			links.push(link);
+			sections.push(ElfSection
+			{
+				name: String::new(),
+				kind,
+				flags,
+				addr,
+				offset,
+				size,
+			});
+		}
+		if let Some(shstrtab) = sections.get(shstrndx as usize).map(|s| s.offset)
+		{
+			for (section, name_off) in sections.iter_mut().zip(&name_offs)
+			{
+				section.name = rd.str(shstrtab, *name_off)?;
+			}
+		}
+
+		let mut segments: Vec<ElfSegment> = Vec::new();
+		for i in 0..phnum
+		{
+			let ph = rd.entry(phoff, i, phentsize, if rd.is_64 { 56 } else { 32 })?;
+			let segment = if rd.is_64
+			{
+				ElfSegment
+				{
+					kind: rd.u32(ph)?,
+					offset: rd.word(ph + 8)?,
+					vaddr: rd.word(ph + 16)?,
+					paddr: rd.word(ph + 24)?,
+					filesz: rd.word(ph + 32)?,
+					memsz: rd.word(ph + 40)?,
+				}
+			}
+			else
+			{
+				ElfSegment
+				{
+					kind: rd.u32(ph)?,
+					offset: rd.word(ph + 4)?,
+					vaddr: rd.word(ph + 8)?,
+					paddr: rd.word(ph + 12)?,
+					filesz: rd.word(ph + 16)?,
+					memsz: rd.word(ph + 20)?,
+				}
+			};
+			segments.push(segment);
+		}
+
+		let mut symbols: Vec<ElfSymbol> = Vec::new();
+		for (i, symtab) in sections.iter().enumerate()
+		{
+			if symtab.kind != SHT_SYMTAB
+			{
+				continue
+			}
+			let strtab = match sections.get(links[i] as usize)
+			{
+				Some(v) => v.offset,
+				None => return Err(String::from("symbol table without string table")),
+			};
+			let entsize = if rd.is_64 { 24 } else { 16 };
+			// entry 0 is the undefined symbol
+			for j in 1..(symtab.size / entsize)
+			{
+				let st = rd.entry(symtab.offset, j, entsize, entsize as usize)?;
+				let (name_off, value, size, info, shndx) = if rd.is_64
+				{
+					(rd.u32(st)? as u64, rd.word(st + 8)?, rd.word(st + 16)?,
+					 rd.u8(st + 4)?, rd.u16(st + 6)?)
+				}
+				else
+				{
+					(rd.u32(st)? as u64, rd.word(st + 4)?, rd.word(st + 8)?,
+					 rd.u8(st + 12)?, rd.u16(st + 14)?)
+				};
+				let kind = info & 0xf;
+				let value = if machine == EM_ARM && kind == STT_FUNC { value & !1 } else { value };
+				symbols.push(ElfSymbol
+				{
+					name: rd.str(strtab, name_off)?,
+					value,
+					size,
+					kind,
+					// SHN_UNDEF and the reserved indices (SHN_ABS, SHN_COMMON) have no section
+					section: if shndx == 0 || shndx >= 0xff00
+					{
+						None
+					}
+					else
+					{
+						sections.get(shndx as usize).map(|s| s.name.clone())
+					},
+				});
+			}
+		}
+
+		Ok(IglooElf
+		{
+			machine,
+			entry,
+			sections,
+			segments,
+			symbols,
+			data,
+		})
+	}
+
+	/// Contents of a section. Empty for NOBITS sections
+	pub fn section_data(&self, section: &ElfSection) -> &[u8]
+	{
+		if section.is_nobits()
+		{
+			return &[]
+		}
+		let start = section.offset as usize;
+		match section.offset.checked_add(section.size)
+		{
+			Some(end) => self.data.get(start..end as usize).unwrap_or(&[]),
+			None => &[],
+		}
+	}
+
+	pub fn section(&self, name: &str) -> Option<&ElfSection>
+	{
+		self.sections.iter().find(|s| s.name == name)
+	}
+
+	pub fn symbol(&self, name: &str) -> Option<&ElfSymbol>
+	{
+		self.symbols.iter().find(|s| s.name == name)
+	}
+}
@@ -0,0 +1,729 @@
+use igloo_base::*;
+use igloo_base::IglooErrType::*;
+
+use crate::igloo_elf::*;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+/// Byte used for gaps in binary images unless told otherwise
+pub const DEFAULT_FILL: u8 = 0xFF;
+
+const UF2_MAGIC_START0: u32 = 0x0A32_4655;
+const UF2_MAGIC_START1: u32 = 0x9E5D_5157;
+const UF2_MAGIC_END: u32 = 0x0AB1_6F30;
+const UF2_FLAG_FAMILY_ID: u32 = 0x0000_2000;
+const UF2_PAYLOAD: usize = 256;
+
+/// Firmware image formats igloo reads and writes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat
+{
+	Elf,
+	Hex,
+	Srec,
+	Bin,
+	Uf2,
+}
+
+impl ImageFormat
+{
+	pub fn from_name(name: &str) -> Option<ImageFormat>
+	{
+		match name.to_ascii_lowercase().as_str()
+		{
+			"elf" => Some(ImageFormat::Elf),
+			"hex" | "ihex" => Some(ImageFormat::Hex),
+			"srec" | "s19" | "s28" | "s37" | "mot" => Some(ImageFormat::Srec),
+			"bin" => Some(ImageFormat::Bin),
+			"uf2" => Some(ImageFormat::Uf2),
+			_ => None,
+		}
+	}
+
+	/// Guesses the format from the file extension
+	pub fn from_path(path: &Path) -> Option<ImageFormat>
+	{
+		ImageFormat::from_name(&path.extension()?.to_string_lossy())
+	}
+
+	pub fn extension(&self) -> &'static str
+	{
+		match self
+		{
+			ImageFormat::Elf => "elf",
+			ImageFormat::Hex => "hex",
+			ImageFormat::Srec => "srec",
+			ImageFormat::Bin => "bin",
+			ImageFormat::Uf2 => "uf2",
+		}
+	}
+}
+
+/// Options for writing images
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageOptions
+{
+	/// gap fill of binary images
+	pub fill: u8,
+	/// binary images are padded with fill up to this length
+	pub pad_to: Option<u64>,
+	/// UF2 family id, e.g. 0x68ed2b88 for SAMD21
+	pub uf2_family: Option<u32>,
+}
+
+impl Default for ImageOptions
+{
+	fn default() -> ImageOptions
+	{
+		ImageOptions
+		{
+			fill: DEFAULT_FILL,
+			pad_to: None,
+			uf2_family: None,
+		}
+	}
+}
+
+/// A sparse memory image: the bytes to program and where they go
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IglooImage
+{
+	/// start address -> contiguous bytes. Chunks never touch or overlap
+	chunks: BTreeMap<u64, Vec<u8>>,
+}
+
+impl IglooImage
+{
+	pub fn new() -> IglooImage
+	{
+		IglooImage::default()
+	}
+
+	pub fn chunks(&self) -> &BTreeMap<u64, Vec<u8>>
+	{
+		&self.chunks
+	}
+
+	pub fn is_empty(&self) -> bool
+	{
+		self.chunks.is_empty()
+	}
+
+	/// Lowest address in the image
+	pub fn start(&self) -> Option<u64>
+	{
+		self.chunks.keys().next().copied()
+	}
+
+	/// One past the highest address in the image
+	pub fn end(&self) -> Option<u64>
+	{
+		self.chunks.iter().next_back().map(|(a, d)| a + d.len() as u64)
+	}
+
+	/// Writes data at addr. Fails if any byte is already set to a different value
+	pub fn write(&mut self, addr: u64, data: &[u8]) -> Result<(), String>
+	{
+		if data.is_empty()
+		{
+			return Ok(())
+		}
+		let end = match addr.checked_add(data.len() as u64)
+		{
+			Some(v) => v,
+			None => return Err(format!("data at 0x{:08x} ends past the address space", addr)),
+		};
+
+		// Collect every chunk touching [addr, end) and merge them into one
+		let touching: Vec<u64> = self.chunks.iter()
+			.filter(|(a, d)| **a <= end && addr <= **a + d.len() as u64)
+			.map(|(a, _d)| *a)
+			.collect();
+		let mut start = addr;
+		let mut merged_end = end;
+		for a in &touching
+		{
+			start = start.min(*a);
+			merged_end = merged_end.max(a + self.chunks[a].len() as u64);
+		}
+		let mut merged: Vec<Option<u8>> = vec![None; (merged_end - start) as usize];
+		for a in &touching
+		{
+			let chunk = self.chunks.remove(a).unwrap();
+			for (i, b) in chunk.iter().enumerate()
+			{
+				merged[(a - start) as usize + i] = Some(*b);
+			}
+		}
+		let conflict = data.iter().enumerate().find(|(i, b)|
+			matches!(merged[(addr - start) as usize + i], Some(old) if old != **b));
+		if let Some((i, _b)) = conflict
+		{
+			// Put the old contents back before failing
+			self.insert_merged(start, &merged);
+			return Err(format!("overlapping data at 0x{:08x}", addr + i as u64))
+		}
+		for (i, b) in data.iter().enumerate()
+		{
+			merged[(addr - start) as usize + i] = Some(*b);
+		}
+		self.insert_merged(start, &merged);
+		Ok(())
+	}
+
+	fn insert_merged(&mut self, start: u64, merged: &[Option<u8>])
+	{
+		let mut i = 0;
+		while i < merged.len()
+		{
+			if merged[i].is_none()
+			{
+				i += 1;
+				continue
+			}
+			let chunk_start = i;
+			let mut chunk: Vec<u8> = Vec::new();
+			while i < merged.len() && merged[i].is_some()
+			{
+				chunk.push(merged[i].unwrap());
+				i += 1;
+			}
+			self.chunks.insert(start + chunk_start as u64, chunk);
+		}
+	}
+
+	/// Reads len bytes at addr, None if any byte is not set
+	pub fn read(&self, addr: u64, len: usize) -> Option<Vec<u8>>
+	{
+		let (start, chunk) = self.chunks.range(..=addr).next_back()?;
+		let off = (addr - start) as usize;
+		chunk.get(off..off + len).map(|v| v.to_vec())
+	}
+
+	/// Adds all bytes of other. Fails on conflicting bytes
+	pub fn merge(&mut self, other: &IglooImage) -> Result<(), String>
+	{
+		for (addr, data) in &other.chunks
+		{
+			self.write(*addr, data)?;
+		}
+		Ok(())
+	}
+
+	/// Bytes of [start, end), missing bytes set to fill
+	pub fn slice(&self, start: u64, end: u64, fill: u8) -> Vec<u8>
+	{
+		let mut ret = vec![fill; end.saturating_sub(start) as usize];
+		for (addr, data) in self.chunks.range(..end)
+		{
+			for (i, b) in data.iter().enumerate()
+			{
+				let cur = addr + i as u64;
+				if cur >= start && cur < end
+				{
+					ret[(cur - start) as usize] = *b;
+				}
+			}
+		}
+		ret
+	}
+
+	/// Overwrites bytes already in the image. Fails if any of them is not set
+	pub fn patch(&mut self, addr: u64, data: &[u8]) -> Result<(), String>
+	{
+		let (start, chunk) = match self.chunks.range_mut(..=addr).next_back()
+		{
+			Some(v) => v,
+			None => return Err(format!("0x{:08x} is outside of the image", addr)),
+		};
+		let off = (addr - start) as usize;
+		match chunk.get_mut(off..off + data.len())
+		{
+			Some(v) => v.copy_from_slice(data),
+			None => return Err(format!("0x{:08x}..0x{:08x} is outside of the image",
+									   addr, addr + data.len() as u64)),
+		}
+		Ok(())
+	}
+
+	/// Builds the image from the loadable segments of an ELF, placed at their
+	/// load addresses. Initialized data therefore ends up in flash.
+	pub fn from_elf(elf: &IglooElf) -> Result<IglooImage, String>
+	{
+		let mut image = IglooImage::new();
+		let loads: Vec<&ElfSegment> = elf.segments.iter()
+			.filter(|s| s.kind == PT_LOAD && s.filesz > 0)
+			.collect();
+		if loads.is_empty()
+		{
+			// Relocatable or hand made files without program headers
+			for section in elf.sections.iter().filter(|s| s.is_alloc() && !s.is_nobits())
+			{
+				image.write(section.addr, elf.section_data(section))?;
+			}
+			return Ok(image)
+		}
+		for segment in loads
+		{
+			let start = segment.offset as usize;
+			match start.checked_add(segment.filesz as usize).and_then(|end| elf.data.get(start..end))
+			{
+				Some(v) => image.write(segment.paddr, v)?,
+				None => return Err(String::from("segment outside of the file")),
+			}
+		}
+		Ok(image)
+	}
+
+	/// Intel HEX with extended linear address records
+	pub fn to_ihex(&self) -> String
+	{
+		let mut ret = String::new();
+		let mut upper: Option<u64> = None;
+		for (addr, data) in &self.chunks
+		{
+			let mut off = 0;
+			while off < data.len()
+			{
+				let cur = addr + off as u64;
+				if upper != Some(cur >> 16)
+				{
+					upper = Some(cur >> 16);
+					let ext = ((cur >> 16) as u16).to_be_bytes();
+					ihex_record(&mut ret, 0, 0x04, &ext);
+				}
+				// Records stay inside a 64K segment
+				let seg_left = 0x10000 - (cur & 0xFFFF) as usize;
+				let len = 16.min(data.len() - off).min(seg_left);
+				ihex_record(&mut ret, (cur & 0xFFFF) as u16, 0x00, &data[off..off + len]);
+				off += len;
+			}
+		}
+		ihex_record(&mut ret, 0, 0x01, &[]);
+		ret
+	}
+
+	pub fn from_ihex(text: &str) -> Result<IglooImage, String>
+	{
+		let mut image = IglooImage::new();
+		let mut base: u64 = 0;
+		for (n, line) in text.lines().enumerate()
+		{
+			let line = line.trim();
+			if line.is_empty()
+			{
+				continue
+			}
+			let bytes = match line.strip_prefix(':').and_then(decode_hex)
+			{
+				Some(v) if v.len() >= 5 => v,
+				_ => return Err(format!("line {}: not an Intel HEX record", n + 1)),
+			};
+			let len = bytes[0] as usize;
+			if bytes.len() != len + 5
+			{
+				return Err(format!("line {}: bad record length", n + 1))
+			}
+			if bytes.iter().fold(0u8, |a, b| a.wrapping_add(*b)) != 0
+			{
+				return Err(format!("line {}: bad checksum", n + 1))
+			}
+			let offset = u16::from_be_bytes([bytes[1], bytes[2]]) as u64;
+			let data = &bytes[4..4 + len];
+			match bytes[3]
+			{
+				0x00 => image.write(base + offset, data)
+					.map_err(|e| format!("line {}: {}", n + 1, e))?,
+				0x01 => break,
+				0x02 if len == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u64) << 4,
+				0x04 if len == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u64) << 16,
+				0x03 | 0x05 => (),
+				_ => return Err(format!("line {}: unsupported record type", n + 1)),
+			}
+		}
+		Ok(image)
+	}
+
+	/// Motorola S-records. The address width (S1, S2 or S3) fits the highest address
+	pub fn to_srec(&self, header: &str) -> String
+	{
+		let end = self.end().unwrap_or(0);
+		let (data_type, addr_len) = if end <= 0x1_0000
+		{
+			(1, 2)
+		}
+		else if end <= 0x100_0000
+		{
+			(2, 3)
+		}
+		else
+		{
+			(3, 4)
+		};
+
+		let mut ret = String::new();
+		srec_record(&mut ret, 0, 0, 2, header.as_bytes());
+		let mut count: u64 = 0;
+		for (addr, data) in &self.chunks
+		{
+			for (i, line) in data.chunks(16).enumerate()
+			{
+				srec_record(&mut ret, data_type, addr + i as u64 * 16, addr_len, line);
+				count += 1;
+			}
+		}
+		if count <= 0xFFFF
+		{
+			srec_record(&mut ret, 5, count, 2, &[]);
+		}
+		else
+		{
+			srec_record(&mut ret, 6, count, 3, &[]);
+		}
+		srec_record(&mut ret, 10 - data_type, 0, addr_len, &[]);
+		ret
+	}
+
+	pub fn from_srec(text: &str) -> Result<IglooImage, String>
+	{
+		let mut image = IglooImage::new();
+		for (n, line) in text.lines().enumerate()
+		{
+			let line = line.trim();
+			if line.is_empty()
+			{
+				continue
+			}
+			let kind = match line.strip_prefix('S').and_then(|v| v.chars().next())
+			{
+				Some(v) => v,
+				None => return Err(format!("line {}: not an S-record", n + 1)),
+			};
+			let bytes = match line.get(2..).and_then(decode_hex)
+			{
+				Some(v) if !v.is_empty() && v.len() == v[0] as usize + 1 => v,
+				_ => return Err(format!("line {}: bad record length", n + 1)),
+			};
+			if bytes.iter().fold(0u8, |a, b| a.wrapping_add(*b)) != 0xFF
+			{
+				return Err(format!("line {}: bad checksum", n + 1))
+			}
+			let addr_len = match kind
+			{
+				'1' => 2,
+				'2' => 3,
+				'3' => 4,
+				'0' | '5' | '6' | '7' | '8' | '9' => continue,
+				_ => return Err(format!("line {}: unsupported record type", n + 1)),
+			};
+			if bytes.len() < addr_len + 2
+			{
+				return Err(format!("line {}: bad record length", n + 1))
+			}
+			let addr = bytes[1..1 + addr_len].iter().fold(0u64, |a, b| (a << 8) | *b as u64);
+			image.write(addr, &bytes[1 + addr_len..bytes.len() - 1])
+				.map_err(|e| format!("line {}: {}", n + 1, e))?;
+		}
+		Ok(image)
+	}
+
+	/// Raw binary from the lowest address, gaps filled with fill and padded
+	/// with fill up to pad_to bytes
+	pub fn to_bin(&self, fill: u8, pad_to: Option<u64>) -> Vec<u8>
+	{
+		let start = self.start().unwrap_or(0);
+		let len = self.end().unwrap_or(0) - start;
+		let len = pad_to.map_or(len, |p| len.max(p));
+		let mut ret = vec![fill; len as usize];
+		for (addr, data) in &self.chunks
+		{
+			let off = (addr - start) as usize;
+			ret[off..off + data.len()].copy_from_slice(data);
+		}
+		ret
+	}
+
+	pub fn from_bin(data: &[u8], base: u64) -> IglooImage
+	{
+		let mut image = IglooImage::new();
+		if !data.is_empty()
+		{
+			image.chunks.insert(base, data.to_vec());
+		}
+		image
+	}
+
+	/// Returns the image with every page it touches fully set, missing bytes
+	/// set to fill. Pages start at multiples of page.
+	pub fn paged(&self, page: u64, fill: u8) -> IglooImage
+	{
+		let mut pages: BTreeMap<u64, Vec<u8>> = BTreeMap::new();
+		for (addr, data) in &self.chunks
+		{
+			for (i, b) in data.iter().enumerate()
+			{
+				let cur = addr + i as u64;
+				let bytes = pages.entry(cur - cur % page).or_insert_with(|| vec![fill; page as usize]);
+				bytes[(cur % page) as usize] = *b;
+			}
+		}
+		let mut ret = IglooImage::new();
+		for (addr, bytes) in &pages
+		{
+			// Pages are disjoint, this never conflicts
+			let _ = ret.write(*addr, bytes);
+		}
+		ret
+	}
+
+	/// UF2 blocks of 256 payload bytes, as used by USB mass storage bootloaders.
+	/// Blocks start on 256 byte boundaries, bytes not in the image are set to fill.
+	pub fn to_uf2(&self, family: Option<u32>, fill: u8) -> Vec<u8>
+	{
+		let paged = self.paged(UF2_PAYLOAD as u64, fill);
+		let mut blocks: Vec<(u64, &[u8])> = Vec::new();
+		for (addr, data) in &paged.chunks
+		{
+			for (i, block) in data.chunks(UF2_PAYLOAD).enumerate()
+			{
+				blocks.push((addr + (i * UF2_PAYLOAD) as u64, block));
+			}
+		}
+
+		let mut ret: Vec<u8> = Vec::new();
+		for (n, (addr, block)) in blocks.iter().enumerate()
+		{
+			let words = [
+				UF2_MAGIC_START0,
+				UF2_MAGIC_START1,
+				if family.is_some() { UF2_FLAG_FAMILY_ID } else { 0 },
+				*addr as u32,
+				UF2_PAYLOAD as u32,
+				n as u32,
+				blocks.len() as u32,
+				family.unwrap_or(0),
+			];
+			for word in &words
+			{
+				ret.extend_from_slice(&word.to_le_bytes());
+			}
+			let mut payload = [0u8; 476];
+			payload[..block.len()].copy_from_slice(block);
+			ret.extend_from_slice(&payload);
+			ret.extend_from_slice(&UF2_MAGIC_END.to_le_bytes());
+		}
+		ret
+	}
+
+	pub fn from_uf2(data: &[u8]) -> Result<IglooImage, String>
+	{
+		if data.len() % 512 != 0
+		{
+			return Err(String::from("UF2 file is not made of 512 byte blocks"))
+		}
+		let mut image = IglooImage::new();
+		for (n, block) in data.chunks(512).enumerate()
+		{
+			let word = |i: usize| u32::from_le_bytes([
+				block[i * 4], block[i * 4 + 1], block[i * 4 + 2], block[i * 4 + 3]]);
+			if word(0) != UF2_MAGIC_START0 || word(1) != UF2_MAGIC_START1 || word(127) != UF2_MAGIC_END
+			{
+				return Err(format!("block {}: bad UF2 magic", n))
+			}
+			// flag 0x1: not main flash, skipped by bootloaders
+			if word(2) & 1 != 0
+			{
+				continue
+			}
+			let len = word(4) as usize;
+			if len > 476
+			{
+				return Err(format!("block {}: bad payload size", n))
+			}
+			image.write(word(3) as u64, &block[32..32 + len])
+				.map_err(|e| format!("block {}: {}", n, e))?;
+		}
+		Ok(image)
+	}
+
+	/// Reads an image in any supported format
+	/// base -- load address of raw binaries
+	pub fn parse(data: Vec<u8>, format: ImageFormat, base: u64) -> Result<IglooImage, String>
+	{
+		let text = || String::from_utf8(data.clone()).map_err(|_e| String::from("not a text file"));
+		match format
+		{
+			ImageFormat::Elf => IglooImage::from_elf(&IglooElf::parse(data.clone())?),
+			ImageFormat::Hex => IglooImage::from_ihex(&text()?),
+			ImageFormat::Srec => IglooImage::from_srec(&text()?),
+			ImageFormat::Bin => Ok(IglooImage::from_bin(&data, base)),
+			ImageFormat::Uf2 => IglooImage::from_uf2(&data),
+		}
+	}
+
+	/// Renders the image in format. ELF output is not supported
+	pub fn render(&self, format: ImageFormat, options: &ImageOptions) -> Result<Vec<u8>, String>
+	{
+		match format
+		{
+			ImageFormat::Elf => Err(String::from("igloo does not write ELF files")),
+			ImageFormat::Hex => Ok(self.to_ihex().into_bytes()),
+			ImageFormat::Srec => Ok(self.to_srec("igloo").into_bytes()),
+			ImageFormat::Bin => Ok(self.to_bin(options.fill, options.pad_to)),
+			ImageFormat::Uf2 => Ok(self.to_uf2(options.uf2_family, options.fill)),
+		}
+	}
+
+	/// Checks that rendered reads back as this image. Binary and UF2 images are
+	/// compared against this image with its gaps filled.
+	pub fn verify(&self, format: ImageFormat, options: &ImageOptions, rendered: &[u8])
+				  -> Result<(), String>
+	{
+		let base = self.start().unwrap_or(0);
+		let read_back = IglooImage::parse(rendered.to_vec(), format, base)?;
+		let expected = match format
+		{
+			ImageFormat::Bin => IglooImage::from_bin(&self.to_bin(options.fill, options.pad_to), base),
+			ImageFormat::Uf2 => self.paged(UF2_PAYLOAD as u64, options.fill),
+			_ => self.clone(),
+		};
+		if read_back != expected
+		{
+			return Err(format!("{} output does not read back as the original image",
+							   format.extension()))
+		}
+		Ok(())
+	}
+}
+
+fn ihex_record(out: &mut String, addr: u16, kind: u8, data: &[u8])
+{
+	let mut bytes: Vec<u8> = vec![data.len() as u8];
+	bytes.extend_from_slice(&addr.to_be_bytes());
+	bytes.push(kind);
+	bytes.extend_from_slice(data);
+	let checksum = bytes.iter().fold(0u8, |a, b| a.wrapping_add(*b)).wrapping_neg();
+	bytes.push(checksum);
+	write!(out, ":").unwrap();
+	for b in bytes
+	{
+		write!(out, "{:02X}", b).unwrap();
+	}
+	writeln!(out).unwrap();
+}
+
+fn srec_record(out: &mut String, kind: u64, addr: u64, addr_len: usize, data: &[u8])
+{
+	let mut bytes: Vec<u8> = vec![(addr_len + data.len() + 1) as u8];
+	for i in (0..addr_len).rev()
+	{
+		bytes.push((addr >> (i * 8)) as u8);
+	}
+	bytes.extend_from_slice(data);
+	let checksum = !bytes.iter().fold(0u8, |a, b| a.wrapping_add(*b));
+	bytes.push(checksum);
+	write!(out, "S{}", kind).unwrap();
+	for b in bytes
+	{
+		write!(out, "{:02X}", b).unwrap();
+	}
+	writeln!(out).unwrap();
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>>
+{
+	if text.len() % 2 != 0
+	{
+		return None
+	}
+	(0..text.len()).step_by(2)
+		.map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+		.collect()
+}
+
+/// Reads an image file, the format is taken from the extension.
+/// Raw binaries take their load address after an @, e.g. boot.bin@0x0
+pub fn read_image(fs: &dyn IglooFs, spec: &Path) -> Result<IglooImage, IglooErrType>
+{
+	let spec_str = spec.to_string_lossy();
+	let (path, base) = match spec_str.rsplit_once('@')
+	{
+		Some((p, b)) => match crate::igloo_linker::parse_size(&config::Value::from(b))
+		{
+			Ok(v) => (Path::new(p).to_path_buf(), v),
+			Err(e) =>
+			{
+				error!("{}: {}", spec.display(), e);
+				return Err(InvalidImage)
+			}
+		},
+		None => (spec.to_path_buf(), 0),
+	};
+
+	let format = match ImageFormat::from_path(&path)
+	{
+		Some(v) => v,
+		None =>
+		{
+			error!("{}: unknown image format", path.display());
+			return Err(InvalidImage)
+		}
+	};
+	let data = match fs.read(&path)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			error!("{}: {}", path.display(), e);
+			return Err(InvalidImage)
+		}
+	};
+	match IglooImage::parse(data, format, base)
+	{
+		Ok(v) => Ok(v),
+		Err(e) =>
+		{
+			error!("{}: {}", path.display(), e);
+			Err(InvalidImage)
+		}
+	}
+}
+
+/// Renders image in format, verifies the round-trip and writes it to path
+pub fn write_image(fs: &dyn IglooFs, image: &IglooImage, format: ImageFormat,
+				   options: &ImageOptions, path: &Path) -> IglooErrType
+{
+	let rendered = match image.render(format, options)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			error!("{}: {}", path.display(), e);
+			return InvalidImage
+		}
+	};
+	match image.verify(format, options, &rendered)
+	{
+		Ok(()) => (),
+		Err(e) =>
+		{
+			error!("{}: {}", path.display(), e);
+			return ImageVerifyFailed
+		}
+	}
+	match fs.write(path, &rendered)
+	{
+		Ok(_v) =>
+		{
+			say!("Wrote {}", path.display());
+			ErrNone
+		}
+		Err(e) =>
+		{
+			error!("{}: {}", path.display(), e);
+			ErrUnknown
+		}
+	}
+}
@@ -0,0 +1,363 @@
+use igloo_base::*;
+use igloo_base::IglooErrType::*;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Stack size used when the target manifest does not give one
+const DEFAULT_STACK_SIZE: u64 = 0x2000;
+
+/// A memory region of a target, e.g. flash or RAM
+/// Regions are described in the target manifest:
+///
+/// [esf.memory.flash]
+/// origin = 0x00000000
+/// length = "256K"
+/// bootloader = "8K"
+///
+/// [esf.memory.ram]
+/// origin = 0x20000000
+/// length = "32K"
+///
+/// Sizes are integers or strings with an optional K or M suffix.
+/// bootloader reserves the start of the region for a vendor bootloader.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IglooMemRegion
+{
+	pub name: String,
+	pub origin: u64,
+	pub length: u64,
+	/// GNU ld region attributes. Defaults to rx for flash, rwx otherwise
+	pub attributes: String,
+	/// Bytes at the start of the region the firmware may not use
+	pub offset: u64,
+	/// Bytes at the end of the region the firmware may not use
+	pub reserve: u64,
+}
+
+impl IglooMemRegion
+{
+	/// First address available to the firmware
+	pub fn start(&self) -> u64
+	{
+		self.origin + self.offset
+	}
+
+	/// Number of bytes available to the firmware
+	pub fn usable_length(&self) -> u64
+	{
+		self.length - self.offset - self.reserve
+	}
+
+	/// Name of the region in the MEMORY block, e.g. FLASH
+	pub fn ld_name(&self) -> String
+	{
+		crate::igloo_project::c_ident(&self.name)
+	}
+
+	/// Whether the region holds code and initialized data (not writable at runtime)
+	pub fn is_rom(&self) -> bool
+	{
+		!self.attributes.contains('w')
+	}
+
+	/// Applies a project carve-out to the region
+	/// [target.<name>.memory.<region>]
+	/// offset = "16K"   # counts from origin, replaces the bootloader reservation
+	/// reserve = "4K"   # removed from the end of the region
+	pub fn carve_out(&mut self, table: &BTreeMap<String, config::Value>) -> IglooErrType
+	{
+		for (key, val) in table
+		{
+			let size = match parse_size(val)
+			{
+				Ok(v) => v,
+				Err(e) =>
+				{
+					error!("memory.{}.{}: {}", self.name, key, e);
+					return InvalidMemoryMap
+				}
+			};
+			match key.as_str()
+			{
+				"offset" => self.offset = size,
+				"reserve" => self.reserve = size,
+				_ =>
+				{
+					error!("memory.{}: unknown carve-out \"{}\"", self.name, key);
+					return InvalidMemoryMap
+				}
+			}
+		}
+
+		if self.offset.checked_add(self.reserve).map_or(true, |v| v > self.length)
+		{
+			error!("memory.{}: carve-outs are larger than the region", self.name);
+			return InvalidMemoryMap
+		}
+		ErrNone
+	}
+}
+
+/// Parses a size or address. Accepts integers, hex strings (0x2000)
+/// and decimal strings with a K or M suffix (8K, 1M)
+pub fn parse_size(val: &config::Value) -> Result<u64, String>
+{
+	if let Ok(v) = val.clone().into_int()
+	{
+		if v < 0
+		{
+			return Err(format!("{} is negative", v))
+		}
+		return Ok(v as u64)
+	}
+
+	let text = val.to_string();
+	let text = text.trim().trim_end_matches('B').trim_end_matches('b');
+	let (digits, scale) = if let Some(v) = text.strip_suffix(|c| c == 'K' || c == 'k')
+	{
+		(v, 1024)
+	}
+	else if let Some(v) = text.strip_suffix(|c| c == 'M' || c == 'm')
+	{
+		(v, 1024 * 1024)
+	}
+	else
+	{
+		(text, 1)
+	};
+
+	let res = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X"))
+	{
+		Some(hex) => u64::from_str_radix(hex, 16),
+		None => digits.parse::<u64>(),
+	};
+	match res.map(|v| v.checked_mul(scale))
+	{
+		Ok(Some(v)) => Ok(v),
+		Ok(None) => Err(format!("\"{}\" is too large", val)),
+		Err(_e) => Err(format!("\"{}\" is not a valid size", val)),
+	}
+}
+
+/// Reads the esf.memory table of a target manifest
+/// Regions are returned sorted by origin. Overlapping regions are an error.
+pub fn memory_map_from(table: &BTreeMap<String, config::Value>)
+					   -> Result<Vec<IglooMemRegion>, IglooErrType>
+{
+	let mut ret: Vec<IglooMemRegion> = Vec::new();
+	for (name, val) in table
+	{
+		let region = match val.clone().into_table()
+		{
+			Ok(v) => v,
+			Err(e) =>
+			{
+				error!("esf.memory.{}: {}", name, e);
+				return Err(InvalidMemoryMap)
+			}
+		};
+
+		let mut sizes: BTreeMap<&str, u64> = BTreeMap::new();
+		for key in &["origin", "length", "bootloader"]
+		{
+			match region.get(*key)
+			{
+				None => (),
+				Some(v) => match parse_size(v)
+				{
+					Ok(v) => { sizes.insert(key, v); }
+					Err(e) =>
+					{
+						error!("esf.memory.{}.{}: {}", name, key, e);
+						return Err(InvalidMemoryMap)
+					}
+				},
+			}
+		}
+
+		let (origin, length) = match (sizes.get("origin"), sizes.get("length"))
+		{
+			(Some(o), Some(l)) => (*o, *l),
+			_ =>
+			{
+				error!("esf.memory.{} needs an origin and a length", name);
+				return Err(InvalidMemoryMap)
+			}
+		};
+
+		let default_attributes = if name == "flash" { "rx" } else { "rwx" };
+		let attributes = match region.get("attributes")
+		{
+			Some(v) => v.to_string(),
+			None => String::from(default_attributes),
+		};
+
+		if origin.checked_add(length).is_none()
+		{
+			error!("esf.memory.{} ends past the address space", name);
+			return Err(InvalidMemoryMap)
+		}
+
+		let offset = sizes.get("bootloader").copied().unwrap_or(0);
+		if offset > length
+		{
+			error!("esf.memory.{}: bootloader is larger than the region", name);
+			return Err(InvalidMemoryMap)
+		}
+
+		ret.push(IglooMemRegion
+		{
+			name: name.clone(),
+			origin,
+			length,
+			attributes,
+			offset,
+			reserve: 0,
+		});
+	}
+
+	ret.sort_by_key(|r| r.origin);
+	for pair in ret.windows(2)
+	{
+		if pair[0].origin + pair[0].length > pair[1].origin
+		{
+			error!("esf.memory.{} overlaps esf.memory.{}", pair[0].name, pair[1].name);
+			return Err(InvalidMemoryMap)
+		}
+	}
+	Ok(ret)
+}
+
+/// Renders a GNU ld script for a memory map
+/// sections -- esf.sections of the target manifest, mapping section names to regions.
+/// text, data, bss and stack are placed in flash and ram unless given. Any other
+/// entry places .<name> input sections into the named region, e.g. ccmram = "ccm".
+/// The symbols match the ESF startup code (_sfixed, _etext, _srelocate, _szero, _estack...).
+pub fn render_linker_script(target_name: &str, memory: &[IglooMemRegion],
+							sections: &BTreeMap<String, String>, stack_size: Option<u64>)
+							-> Result<String, IglooErrType>
+{
+	let region_for = |section: &str, default: &str| -> Result<&IglooMemRegion, IglooErrType>
+	{
+		let name = sections.get(section).map(|v| v.as_str()).unwrap_or(default);
+		match memory.iter().find(|r| r.name == name)
+		{
+			Some(v) => Ok(v),
+			None =>
+			{
+				error!("section {} is placed in unknown memory region \"{}\"",
+						 section, name);
+				Err(InvalidMemoryMap)
+			}
+		}
+	};
+	let text = region_for("text", "flash")?.ld_name();
+	let data = region_for("data", "ram")?.ld_name();
+	let bss = region_for("bss", "ram")?.ld_name();
+	let stack = region_for("stack", "ram")?.ld_name();
+
+	let mut ld = String::new();
+	writeln!(ld, "/* {}.ld -- Generated by igloo from the {} memory map. Do not edit */",
+			 target_name, target_name).unwrap();
+	writeln!(ld, "OUTPUT_FORMAT(\"elf32-littlearm\", \"elf32-littlearm\", \"elf32-littlearm\")").unwrap();
+	writeln!(ld, "OUTPUT_ARCH(arm)").unwrap();
+	writeln!(ld, "SEARCH_DIR(.)\n").unwrap();
+
+	writeln!(ld, "MEMORY\n{{").unwrap();
+	for region in memory
+	{
+		writeln!(ld, "\t{} ({}) : ORIGIN = 0x{:08X}, LENGTH = 0x{:08X}",
+				 region.ld_name(), region.attributes,
+				 region.start(), region.usable_length()).unwrap();
+	}
+	writeln!(ld, "}}\n").unwrap();
+
+	writeln!(ld, "STACK_SIZE = DEFINED(STACK_SIZE) ? STACK_SIZE : 0x{:X};\n",
+			 stack_size.unwrap_or(DEFAULT_STACK_SIZE)).unwrap();
+	writeln!(ld, "ENTRY(Reset_Handler)\n").unwrap();
+
+	writeln!(ld, "SECTIONS\n{{").unwrap();
+	writeln!(ld, "\t.text :\n\t{{").unwrap();
+	writeln!(ld, "\t\t. = ALIGN(4);").unwrap();
+	writeln!(ld, "\t\t_sfixed = .;").unwrap();
+	writeln!(ld, "\t\tKEEP(*(.vectors .vectors.*))").unwrap();
+	writeln!(ld, "\t\t*(.text .text.* .gnu.linkonce.t.*)").unwrap();
+	writeln!(ld, "\t\t*(.glue_7t) *(.glue_7)").unwrap();
+	writeln!(ld, "\t\t*(.rodata .rodata* .gnu.linkonce.r.*)").unwrap();
+	writeln!(ld, "\t\t*(.ARM.extab* .gnu.linkonce.armextab.*)\n").unwrap();
+	writeln!(ld, "\t\t. = ALIGN(4);").unwrap();
+	writeln!(ld, "\t\tKEEP(*(.init))").unwrap();
+	writeln!(ld, "\t\t. = ALIGN(4);").unwrap();
+	writeln!(ld, "\t\t__preinit_array_start = .;").unwrap();
+	writeln!(ld, "\t\tKEEP(*(.preinit_array))").unwrap();
+	writeln!(ld, "\t\t__preinit_array_end = .;\n").unwrap();
+	writeln!(ld, "\t\t. = ALIGN(4);").unwrap();
+	writeln!(ld, "\t\t__init_array_start = .;").unwrap();
+	writeln!(ld, "\t\tKEEP(*(SORT(.init_array.*)))").unwrap();
+	writeln!(ld, "\t\tKEEP(*(.init_array))").unwrap();
+	writeln!(ld, "\t\t__init_array_end = .;\n").unwrap();
+	writeln!(ld, "\t\t. = ALIGN(4);").unwrap();
+	writeln!(ld, "\t\tKEEP(*(.fini))").unwrap();
+	writeln!(ld, "\t\t. = ALIGN(4);").unwrap();
+	writeln!(ld, "\t\t_efixed = .;").unwrap();
+	writeln!(ld, "\t}} > {}\n", text).unwrap();
+
+	writeln!(ld, "\tPROVIDE_HIDDEN(__exidx_start = .);").unwrap();
+	writeln!(ld, "\t.ARM.exidx :\n\t{{").unwrap();
+	writeln!(ld, "\t\t*(.ARM.exidx* .gnu.linkonce.armexidx.*)").unwrap();
+	writeln!(ld, "\t}} > {}", text).unwrap();
+	writeln!(ld, "\tPROVIDE_HIDDEN(__exidx_end = .);\n").unwrap();
+
+	writeln!(ld, "\t. = ALIGN(4);").unwrap();
+	writeln!(ld, "\t_etext = .;\n").unwrap();
+
+	writeln!(ld, "\t.relocate : AT (_etext)\n\t{{").unwrap();
+	writeln!(ld, "\t\t. = ALIGN(4);").unwrap();
+	writeln!(ld, "\t\t_srelocate = .;").unwrap();
+	writeln!(ld, "\t\t*(.ramfunc .ramfunc.*);").unwrap();
+	writeln!(ld, "\t\t*(.data .data.*);").unwrap();
+	writeln!(ld, "\t\t. = ALIGN(4);").unwrap();
+	writeln!(ld, "\t\t_erelocate = .;").unwrap();
+	writeln!(ld, "\t}} > {}\n", data).unwrap();
+
+	writeln!(ld, "\t.bss (NOLOAD) :\n\t{{").unwrap();
+	writeln!(ld, "\t\t. = ALIGN(4);").unwrap();
+	writeln!(ld, "\t\t_sbss = . ;").unwrap();
+	writeln!(ld, "\t\t_szero = .;").unwrap();
+	writeln!(ld, "\t\t*(.bss .bss.*)").unwrap();
+	writeln!(ld, "\t\t*(COMMON)").unwrap();
+	writeln!(ld, "\t\t. = ALIGN(4);").unwrap();
+	writeln!(ld, "\t\t_ebss = . ;").unwrap();
+	writeln!(ld, "\t\t_ezero = .;").unwrap();
+	writeln!(ld, "\t}} > {}\n", bss).unwrap();
+
+	// Sections placed in other regions, e.g. CCM or backup SRAM
+	for (section, region_name) in sections
+	{
+		if ["text", "data", "bss", "stack"].contains(&section.as_str())
+		{
+			continue
+		}
+		let region = region_for(section, region_name)?;
+		let noload = if region.is_rom() { "" } else { " (NOLOAD)" };
+		writeln!(ld, "\t.{}{} :\n\t{{", section, noload).unwrap();
+		writeln!(ld, "\t\t. = ALIGN(4);").unwrap();
+		writeln!(ld, "\t\tKEEP(*(.{} .{}.*))", section, section).unwrap();
+		writeln!(ld, "\t\t. = ALIGN(4);").unwrap();
+		writeln!(ld, "\t}} > {}\n", region.ld_name()).unwrap();
+	}
+
+	writeln!(ld, "\t.stack (NOLOAD) :\n\t{{").unwrap();
+	writeln!(ld, "\t\t. = ALIGN(8);").unwrap();
+	writeln!(ld, "\t\t_sstack = .;").unwrap();
+	writeln!(ld, "\t\t. = . + STACK_SIZE;").unwrap();
+	writeln!(ld, "\t\t. = ALIGN(8);").unwrap();
+	writeln!(ld, "\t\t_estack = .;").unwrap();
+	writeln!(ld, "\t}} > {}\n", stack).unwrap();
+
+	writeln!(ld, "\t. = ALIGN(4);").unwrap();
+	writeln!(ld, "\t_end = . ;").unwrap();
+	writeln!(ld, "}}").unwrap();
+	Ok(ld)
+}
@@ -0,0 +1,248 @@
+// igloo.lock
+// Records everything a project resolved from the machine it was generated on, so
+// the same build files come out everywhere:
+//
+// [esf]
+// revision = "<git commit of ESF_DIR, if it is a checkout>"
+// manifests = "<SHA-256 of the master manifests>"
+//
+// [target.<t>]
+// manifest = "<SHA-256 of the resolved make and target manifests>"
+//
+// [module.<name>]
+// version = "1.2.0"
+// hash = "<SHA-256 of the module files>"
+//
+// [toolchain.<t>]
+// name = "arm-none-eabi"
+// version = "10.3.1"
+// sha256 = "<SHA-256 of the archive it was installed from>"
+//
+// Locked module and toolchain versions are kept as long as they satisfy the
+// project. igloo build --locked fails if anything else would be picked now.
+use igloo_base::*;
+use igloo_manifest::merge_manifest;
+
+use crate::igloo_toolchain::{parse_version, ToolchainPin};
+
+use config::Config;
+use semver::Version;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+/// Lockfile at the root of a project
+pub const LOCK_FILE: &str = "igloo.lock";
+const LOCK_BANNER: &str = "# igloo.lock -- Generated by igloo. Do not edit";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LockedModule
+{
+	pub version: Version,
+	pub hash: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct IglooLock
+{
+	/// git commit ESF was checked out at, None if ESF is not a git checkout
+	pub esf_revision: Option<String>,
+	/// SHA-256 of the master make and target manifests
+	pub esf_manifests: String,
+	/// target -> SHA-256 of its resolved manifests
+	pub targets: BTreeMap<String, String>,
+	pub modules: BTreeMap<String, LockedModule>,
+	/// target -> toolchain it builds with
+	pub toolchains: BTreeMap<String, ToolchainPin>,
+}
+
+/// Reads the commit HEAD of a git checkout points to, without running git
+pub fn git_revision(fs: &dyn IglooFs, dir: &Path) -> Option<String>
+{
+	let mut git_dir = dir.join(".git");
+	// worktrees and submodules have a .git file pointing at the git directory
+	if !fs.is_dir(&git_dir)
+	{
+		let text = fs.read_to_string(&git_dir).ok()?;
+		git_dir = dir.join(text.trim().strip_prefix("gitdir:")?.trim());
+	}
+	let head = fs.read_to_string(&git_dir.join("HEAD")).ok()?;
+	let head = head.trim();
+	let reference = match head.strip_prefix("ref:")
+	{
+		Some(v) => v.trim(),
+		None => return Some(String::from(head)),
+	};
+	if let Ok(v) = fs.read_to_string(&git_dir.join(reference))
+	{
+		return Some(String::from(v.trim()))
+	}
+	// refs git gc packed away
+	let packed = fs.read_to_string(&git_dir.join("packed-refs")).ok()?;
+	packed.lines()
+		.filter_map(|l| l.split_once(' '))
+		.find(|(_sha, name)| *name == reference)
+		.map(|(sha, _name)| String::from(sha))
+}
+
+impl IglooLock
+{
+	/// Reads the igloo.lock of a project. None if the project has none
+	pub fn read(fs: &dyn IglooFs, prj_root: &Path) -> Option<IglooLock>
+	{
+		let path = prj_root.join(LOCK_FILE);
+		if !fs.exists(&path)
+		{
+			return None
+		}
+		let mut lock = Config::new();
+		if let Err(e) = merge_manifest(fs, &mut lock, &path)
+		{
+			warn!("ignoring {}: {}", path.display(), e);
+			return None
+		}
+		let mut ret = IglooLock
+		{
+			esf_revision: lock.get_str("esf.revision").ok(),
+			esf_manifests: lock.get_str("esf.manifests").unwrap_or_default(),
+			..IglooLock::default()
+		};
+		let tables = |name: &str| -> Vec<(String, BTreeMap<String, String>)>
+		{
+			lock.get_table(name).unwrap_or_default().into_iter()
+				.filter_map(|(k, v)| v.into_table().ok().map(|t| (k, t)))
+				.map(|(k, t)| (k, t.into_iter()
+							   .filter_map(|(k, v)| v.into_str().ok().map(|v| (k, v)))
+							   .collect()))
+				.collect()
+		};
+		for (target, table) in tables("target")
+		{
+			ret.targets.insert(target, table.get("manifest").cloned().unwrap_or_default());
+		}
+		for (module, table) in tables("module")
+		{
+			let version = table.get("version").and_then(|v| parse_version(v).ok());
+			if let Some(version) = version
+			{
+				let hash = table.get("hash").cloned().unwrap_or_default();
+				ret.modules.insert(module, LockedModule { version, hash });
+			}
+		}
+		for (target, table) in tables("toolchain")
+		{
+			let version = table.get("version").and_then(|v| parse_version(v).ok());
+			if let (Some(name), Some(version), Some(sha256)) =
+				(table.get("name"), version, table.get("sha256"))
+			{
+				ret.toolchains.insert(target, ToolchainPin
+				{
+					name: name.clone(),
+					version,
+					sha256: sha256.clone(),
+					dir: None,
+				});
+			}
+		}
+		Some(ret)
+	}
+
+	pub fn render(&self) -> String
+	{
+		let mut ret = String::new();
+		writeln!(ret, "{}", LOCK_BANNER).unwrap();
+		writeln!(ret, "\n[esf]").unwrap();
+		if let Some(v) = &self.esf_revision
+		{
+			writeln!(ret, "revision = \"{}\"", v).unwrap();
+		}
+		writeln!(ret, "manifests = \"{}\"", self.esf_manifests).unwrap();
+		for (target, hash) in &self.targets
+		{
+			writeln!(ret, "\n[target.{}]", target).unwrap();
+			writeln!(ret, "manifest = \"{}\"", hash).unwrap();
+		}
+		for (name, module) in &self.modules
+		{
+			writeln!(ret, "\n[module.{}]", name).unwrap();
+			writeln!(ret, "version = \"{}\"", module.version).unwrap();
+			writeln!(ret, "hash = \"{}\"", module.hash).unwrap();
+		}
+		for (target, pin) in &self.toolchains
+		{
+			writeln!(ret, "\n[toolchain.{}]", target).unwrap();
+			writeln!(ret, "name = \"{}\"", pin.name).unwrap();
+			writeln!(ret, "version = \"{}\"", pin.version).unwrap();
+			writeln!(ret, "sha256 = \"{}\"", pin.sha256).unwrap();
+		}
+		ret
+	}
+
+	/// Describes how now differs from the locked state, empty if they match
+	pub fn differences(&self, now: &IglooLock) -> Vec<String>
+	{
+		let mut ret: Vec<String> = Vec::new();
+		if self.esf_revision != now.esf_revision
+		{
+			ret.push(format!("ESF revision is {}, locked {}",
+							 now.esf_revision.as_deref().unwrap_or("unknown"),
+							 self.esf_revision.as_deref().unwrap_or("unknown")));
+		}
+		if self.esf_manifests != now.esf_manifests
+		{
+			ret.push(String::from("ESF master manifests changed"));
+		}
+		for (target, hash) in &now.targets
+		{
+			match self.targets.get(target)
+			{
+				None => ret.push(format!("target {} is not locked", target)),
+				Some(v) if v != hash => ret.push(format!("manifests of target {} changed", target)),
+				_ => (),
+			}
+		}
+		for target in self.targets.keys().filter(|t| !now.targets.contains_key(*t))
+		{
+			ret.push(format!("target {} is locked but not part of the project", target));
+		}
+		for (name, module) in &now.modules
+		{
+			match self.modules.get(name)
+			{
+				None => ret.push(format!("module {} {} is not locked", name, module.version)),
+				Some(v) if v.version != module.version =>
+					ret.push(format!("module {} resolves to {}, locked {}",
+									 name, module.version, v.version)),
+				Some(v) if v.hash != module.hash =>
+					ret.push(format!("files of module {} {} changed", name, module.version)),
+				_ => (),
+			}
+		}
+		for name in self.modules.keys().filter(|m| !now.modules.contains_key(*m))
+		{
+			ret.push(format!("module {} is locked but no longer used", name));
+		}
+		for (target, pin) in &now.toolchains
+		{
+			match self.toolchains.get(target)
+			{
+				Some(v) if v.name == pin.name && v.version == pin.version
+					&& v.sha256 == pin.sha256 => (),
+				Some(v) => ret.push(format!("target {} builds with {} {}, locked {} {}",
+											target, pin.name, pin.version, v.name, v.version)),
+				None => ret.push(format!("toolchain {} {} of target {} is not locked",
+										 pin.name, pin.version, target)),
+			}
+			if pin.dir.is_none()
+			{
+				ret.push(format!("toolchain {} {} of target {} is not installed",
+								 pin.name, pin.version, target));
+			}
+		}
+		for target in self.toolchains.keys().filter(|t| !now.toolchains.contains_key(*t))
+		{
+			ret.push(format!("toolchain of target {} is locked but not used", target));
+		}
+		ret
+	}
+}
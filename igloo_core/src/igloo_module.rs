@@ -0,0 +1,405 @@
+// Software modules
+// A module is a directory holding a module.toml and the sources it describes:
+//
+// [module]
+// name = "ring_buffer"
+// version = "1.2.0"
+// description = "Interrupt safe ring buffer"
+// vendor = "ePenguin"
+// tags = ["data structures"]
+// sources = ["src/ring_buffer.c"]
+// includes = ["inc"]
+// headers = ["ring_buffer.h"]
+// defines = ["RING_BUFFER_SIZE=64"]
+// mcus = ["samd21*"]
+// cores = ["cortex-m0"]
+//
+// [dependencies]
+// crc = "^1.0"
+//
+// mcus and cores restrict the targets a module builds for, a module without them
+// builds for every target. Modules are found in registries laid out as
+// <registry>/<name>/<version>/module.toml. The project file lists the modules it
+// uses in its [modules] table (name = "version requirement").
+use igloo_base::*;
+use igloo_base::IglooErrType::*;
+use igloo_manifest::merge_manifest;
+
+use crate::igloo_target::IglooTarget;
+use crate::igloo_toolchain::{parse_version, sha256_hex};
+
+use config::Config;
+use semver::{Version, VersionReq};
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+
+/// Manifest of a module, inside its directory
+pub const MODULE_MANIFEST: &str = "module.toml";
+/// Lists registries besides the default ones, in ~/.igloo
+pub const REGISTRIES_FILE: &str = "registries.toml";
+/// Directory of a project modules are linked into
+pub const MODULES_DIR: &str = "modules";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IglooModule
+{
+	pub name: String,
+	pub version: Version,
+	pub description: String,
+	pub vendor: String,
+	/// keywords igloo search matches
+	pub tags: Vec<String>,
+	/// C and assembly sources, relative to the module directory
+	pub sources: Vec<String>,
+	/// include directories, relative to the module directory
+	pub includes: Vec<String>,
+	/// headers included by igloo.h
+	pub headers: Vec<String>,
+	/// preprocessor definitions passed to the compiler (NAME or NAME=VALUE)
+	pub defines: Vec<String>,
+	pub mcus: Vec<String>,
+	pub cores: Vec<String>,
+	pub dependencies: BTreeMap<String, VersionReq>,
+	/// module directory
+	pub root: PathBuf,
+	/// registry the module was found in (user, esf or the registry path)
+	pub registry: String,
+}
+
+/// Matches text against a pattern where * matches any run of characters
+pub fn glob_match(pattern: &str, text: &str) -> bool
+{
+	match pattern.split_once('*')
+	{
+		None => pattern == text,
+		Some((head, tail)) =>
+		{
+			text.starts_with(head) && (head.len()..=text.len())
+				.any(|i| text.is_char_boundary(i) && glob_match(tail, &text[i..]))
+		}
+	}
+}
+
+/// A path inside a module. Absolute paths and .. are refused
+fn relative(path: &str) -> bool
+{
+	Path::new(path).components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+impl IglooModule
+{
+	/// Reads the module.toml of the module in root
+	pub fn from_dir(fs: &dyn IglooFs, root: &Path, registry: &str)
+					-> Result<IglooModule, String>
+	{
+		let mut man = Config::new();
+		merge_manifest(fs, &mut man, &root.join(MODULE_MANIFEST))?;
+		let strings = |key: &str| -> Result<Vec<String>, String>
+		{
+			match man.get_array(&format!("module.{}", key))
+			{
+				Ok(v) => v.into_iter()
+					.map(|v| v.into_str().map_err(|e| format!("module.{}: {}", key, e)))
+					.collect(),
+				Err(_e) => Ok(Vec::new()),
+			}
+		};
+
+		let name = man.get_str("module.name").map_err(|_e| "module.name is required")?;
+		if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+		{
+			return Err(format!("module.name \"{}\" may only hold letters, digits, _ and -", name))
+		}
+		let version = man.get_str("module.version").map_err(|_e| "module.version is required")?;
+		let mut dependencies: BTreeMap<String, VersionReq> = BTreeMap::new();
+		for (dep, req) in man.get_table("dependencies").unwrap_or_default()
+		{
+			let req = req.into_str().map_err(|e| format!("dependencies.{}: {}", dep, e))?;
+			let req = VersionReq::parse(&req).map_err(|e| format!("dependencies.{}: {}", dep, e))?;
+			dependencies.insert(dep, req);
+		}
+		let module = IglooModule
+		{
+			name,
+			version: parse_version(&version)?,
+			description: man.get_str("module.description").unwrap_or_default(),
+			vendor: man.get_str("module.vendor").unwrap_or_default(),
+			tags: strings("tags")?,
+			sources: strings("sources")?,
+			includes: strings("includes")?,
+			headers: strings("headers")?,
+			defines: strings("defines")?,
+			mcus: strings("mcus")?,
+			cores: strings("cores")?,
+			dependencies,
+			root: root.to_path_buf(),
+			registry: String::from(registry),
+		};
+		if let Some(v) = module.sources.iter().chain(&module.includes).find(|p| !relative(p))
+		{
+			return Err(format!("{} is not inside the module", v))
+		}
+		if let Some(v) = module.sources.iter().find(|s| module.object(s).is_none())
+		{
+			return Err(format!("{} is not a .c, .s or .S source", v))
+		}
+		Ok(module)
+	}
+
+	/// Checks the mcus and cores of the module against a target. Patterns are
+	/// matched against the target name, the MCPU of the target and the tables
+	/// it comes from in the make manifest (arm, cortex-m0, samd21, ...)
+	pub fn supports(&self, target: &IglooTarget) -> bool
+	{
+		let mut names: Vec<String> = target.make_table.split('.').map(String::from).collect();
+		names.push(target.name.clone());
+		if let Some(v) = target.make_manifest.get("MCPU")
+		{
+			names.push(v.to_string());
+		}
+		let matches = |patterns: &[String]| patterns.is_empty() || patterns.iter()
+			.any(|p| names.iter().any(|n| glob_match(&p.to_lowercase(), &n.to_lowercase())));
+		matches(&self.mcus) && matches(&self.cores)
+	}
+
+	/// The define telling sources the module is built, e.g. IGLOO_MODULE_RING_BUFFER
+	pub fn define_name(&self) -> String
+	{
+		format!("IGLOO_MODULE_{}", crate::igloo_project::c_ident(&self.name))
+	}
+
+	/// SHA-256 over the paths and contents of every file of the module
+	pub fn hash(&self, fs: &dyn IglooFs) -> String
+	{
+		let mut data: Vec<u8> = Vec::new();
+		let mut dirs: Vec<PathBuf> = vec![self.root.clone()];
+		let mut files: Vec<PathBuf> = Vec::new();
+		while let Some(dir) = dirs.pop()
+		{
+			for path in fs.read_dir(&dir).unwrap_or_default()
+			{
+				if fs.is_dir(&path) { dirs.push(path) } else { files.push(path) }
+			}
+		}
+		files.sort();
+		for file in files
+		{
+			let rel = file.strip_prefix(&self.root).unwrap_or(&file);
+			data.extend(rel.to_string_lossy().as_bytes());
+			data.push(0);
+			data.extend(fs.read(&file).unwrap_or_default());
+			data.push(0);
+		}
+		sha256_hex(&data)
+	}
+
+	/// Where the module is linked into a project, relative to the project root
+	pub fn link_path(&self) -> PathBuf
+	{
+		Path::new(MODULES_DIR).join(&self.name)
+	}
+
+	/// Object make builds from a source, relative to the target directory
+	pub fn object(&self, source: &str) -> Option<String>
+	{
+		let stem = source.strip_suffix(".c")
+			.or_else(|| source.strip_suffix(".s"))
+			.or_else(|| source.strip_suffix(".S"))?;
+		Some(format!("{}/{}.o", self.link_path().display(), stem.trim_start_matches("./")))
+	}
+}
+
+/// Registries in lookup order: ~/.igloo/modules, the ones listed in
+/// ~/.igloo/registries.toml (registries = ["/path"]) and ESF's modules directory.
+/// A listed registry may also be a single index file (see igloo_registry)
+pub fn registries(fs: &dyn IglooFs, env: &IglooEnvInfo) -> Vec<(String, PathBuf)>
+{
+	let user_dir = env.hd.join(".igloo");
+	let mut ret: Vec<(String, PathBuf)> = vec![(String::from("user"), user_dir.join("modules"))];
+	let mut listed = Config::new();
+	if fs.exists(&user_dir.join(REGISTRIES_FILE))
+	{
+		match merge_manifest(fs, &mut listed, &user_dir.join(REGISTRIES_FILE))
+		{
+			Ok(_v) => (),
+			Err(e) => warn!("{}", e),
+		}
+	}
+	for registry in listed.get_array("registries").unwrap_or_default()
+	{
+		if let Ok(v) = registry.into_str()
+		{
+			let path = user_dir.join(&v);
+			ret.push((path.display().to_string(), path));
+		}
+	}
+	ret.push((String::from("esf"), env.esfd.join("modules")));
+	ret
+}
+
+/// Every module version in the registries. A version found in several registries
+/// is taken from the first one. Broken modules are reported and skipped.
+pub fn available_modules(fs: &dyn IglooFs, env: &IglooEnvInfo) -> Vec<IglooModule>
+{
+	let mut ret: Vec<IglooModule> = Vec::new();
+	for (registry, dir) in registries(fs, env)
+	{
+		for module_dir in fs.read_dir(&dir).unwrap_or_default()
+		{
+			for version_dir in fs.read_dir(&module_dir).unwrap_or_default()
+			{
+				if !fs.exists(&version_dir.join(MODULE_MANIFEST))
+				{
+					continue
+				}
+				match IglooModule::from_dir(fs, &version_dir, &registry)
+				{
+					Ok(v) =>
+					{
+						if !ret.iter().any(|m| m.name == v.name && m.version == v.version)
+						{
+							ret.push(v);
+						}
+					}
+					Err(e) => warn!("skipping {}: {}", version_dir.display(), e),
+				}
+			}
+		}
+	}
+	ret.sort_by(|a, b| a.name.cmp(&b.name).then(a.version.cmp(&b.version)));
+	ret
+}
+
+/// Picks a version of every wanted module and of their dependencies. Each module
+/// keeps its locked version if it satisfies every requirement on it, else it gets
+/// the newest version that does. Sorted by name.
+pub fn resolve_modules(available: &[IglooModule], wanted: &BTreeMap<String, VersionReq>,
+					   locked: &BTreeMap<String, Version>)
+					   -> Result<Vec<IglooModule>, IglooErrType>
+{
+	let mut selected: BTreeMap<String, &IglooModule> = BTreeMap::new();
+	// The requirements change with the versions picked, stop once nothing changes
+	for _ in 0..64
+	{
+		// module -> (requirement, who requires it)
+		let mut reqs: BTreeMap<&str, Vec<(&VersionReq, String)>> = BTreeMap::new();
+		for (name, req) in wanted
+		{
+			reqs.entry(name).or_default().push((req, String::from("the project")));
+		}
+		for module in selected.values()
+		{
+			for (name, req) in &module.dependencies
+			{
+				reqs.entry(name).or_default()
+					.push((req, format!("{} {}", module.name, module.version)));
+			}
+		}
+
+		let mut next: BTreeMap<String, &IglooModule> = BTreeMap::new();
+		for (name, reqs) in &reqs
+		{
+			let versions: Vec<&IglooModule> = available.iter().filter(|m| m.name == *name).collect();
+			if versions.is_empty()
+			{
+				error!("module {} (required by {}) is in no registry",
+						 name, reqs.iter().map(|r| r.1.as_str()).collect::<Vec<_>>().join(", "));
+				return Err(ModuleNotFound)
+			}
+			let matching: Vec<&&IglooModule> = versions.iter()
+				.filter(|m| reqs.iter().all(|r| r.0.matches(&m.version)))
+				.collect();
+			let locked_version = matching.iter()
+				.find(|m| locked.get(*name) == Some(&m.version));
+			match locked_version.or_else(|| matching.last())
+			{
+				Some(v) => { next.insert(String::from(*name), **v); }
+				None =>
+				{
+					error!("no version of module {} satisfies every requirement:", name);
+					for (req, by) in reqs
+					{
+						say!("  {} requires {}", by, req);
+					}
+					say!("  available: {}", versions.iter()
+							 .map(|m| m.version.to_string()).collect::<Vec<_>>().join(", "));
+					return Err(ModuleVersionConflict)
+				}
+			}
+		}
+		if next == selected
+		{
+			return Ok(selected.into_values().cloned().collect())
+		}
+		selected = next;
+	}
+	error!("module versions keep changing, check the module dependencies");
+	Err(ModuleVersionConflict)
+}
+
+/// The modules built for a target: resolved modules supporting the target whose
+/// dependencies are built for it as well
+pub fn modules_for_target(resolved: &[IglooModule], target: &IglooTarget) -> Vec<IglooModule>
+{
+	let mut ret: Vec<IglooModule> = resolved.iter().filter(|m| m.supports(target)).cloned().collect();
+	loop
+	{
+		let names: Vec<String> = ret.iter().map(|m| m.name.clone()).collect();
+		let before = ret.len();
+		ret.retain(|m| m.dependencies.keys().all(|d| names.contains(d)));
+		if ret.len() == before
+		{
+			return ret
+		}
+	}
+}
+
+/// Reads the [modules] table of a project file
+pub fn project_modules(prj_man: &Config) -> Result<BTreeMap<String, VersionReq>, IglooErrType>
+{
+	let mut ret: BTreeMap<String, VersionReq> = BTreeMap::new();
+	for (name, req) in prj_man.get_table("modules").unwrap_or_default()
+	{
+		let req = req.into_str().ok().and_then(|v| VersionReq::parse(&v).ok());
+		match req
+		{
+			Some(v) => { ret.insert(name, v); }
+			None =>
+			{
+				error!("modules.{} of the project file is not a version requirement", name);
+				return Err(FailedToLoadProjectManifest)
+			}
+		}
+	}
+	Ok(ret)
+}
+
+/// Replaces the [modules] table of a project file, keeping everything else
+pub fn set_project_modules(prj_file: &str, modules: &BTreeMap<String, VersionReq>) -> String
+{
+	let mut ret = String::new();
+	let mut in_modules = false;
+	for line in prj_file.lines()
+	{
+		let trimmed = line.trim();
+		if trimmed.starts_with('[')
+		{
+			in_modules = trimmed == "[modules]";
+		}
+		if !in_modules
+		{
+			ret.push_str(line);
+			ret.push('\n');
+		}
+	}
+	let mut ret = format!("{}\n", ret.trim_end());
+	if !modules.is_empty()
+	{
+		ret.push_str("\n[modules]\n");
+		for (name, req) in modules
+		{
+			ret.push_str(&format!("{} = \"{}\"\n", name, req));
+		}
+	}
+	ret
+}
@@ -0,0 +1,574 @@
+// Post-build image processing
+// Patches the image header a bootloader expects, inserts or appends a CRC-32 and
+// signs the image. Everything is configured per target in the project file:
+//
+// [target.<t>.image.header]   header struct at an ELF symbol, 32 bit LE fields
+// symbol = "image_header"
+// fields = ["magic", "version", "length", "crc"]
+// magic = 0x49474c4f
+// version = "1.2.3"
+//
+// [target.<t>.image.crc]      CRC-32 at symbol, appended when symbol is absent
+//
+// [target.<t>.image.sign]     signature appended to the image
+// algorithm = "ed25519"       or "ecdsa-p256"
+// key = "keys/release.pem"    PKCS#8 or SEC1 PEM, relative to the project
+// public_key = "keys/release.pub.pem"
+use igloo_base::*;
+use igloo_base::IglooErrType::*;
+
+use crate::igloo_elf::*;
+use crate::igloo_image::*;
+use crate::igloo_linker::parse_size;
+
+use p256::ecdsa::signature::{Signer, Verifier};
+use p256::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Size of Ed25519 and raw (r || s) ECDSA P-256 signatures
+pub const SIGNATURE_LEN: u64 = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeaderField
+{
+	Magic,
+	Version,
+	/// bytes from the start of the image to the end of the firmware,
+	/// appended CRC and signature excluded
+	Length,
+	/// CRC-32 of the same bytes, computed with this field set to 0
+	Crc,
+	Reserved,
+}
+
+impl HeaderField
+{
+	fn from_name(name: &str) -> Option<HeaderField>
+	{
+		match name
+		{
+			"magic" => Some(HeaderField::Magic),
+			"version" => Some(HeaderField::Version),
+			"length" => Some(HeaderField::Length),
+			"crc" => Some(HeaderField::Crc),
+			"reserved" => Some(HeaderField::Reserved),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageHeader
+{
+	pub symbol: String,
+	pub fields: Vec<HeaderField>,
+	pub magic: u32,
+	pub version: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SignAlgorithm
+{
+	Ed25519,
+	EcdsaP256,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageSign
+{
+	pub algorithm: SignAlgorithm,
+	pub key: Option<PathBuf>,
+	pub public_key: Option<PathBuf>,
+}
+
+/// Where the post-build steps put their data, computed from the unprocessed image
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageLayout
+{
+	pub start: u64,
+	/// end of the firmware, before any appended data
+	pub end: u64,
+	pub header: Option<u64>,
+	pub crc: Option<u64>,
+	pub signature: Option<u64>,
+}
+
+/// Post-build steps of a target. Applied in order: header, crc, sign
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IglooPostBuild
+{
+	pub header: Option<ImageHeader>,
+	/// None appends the CRC, otherwise it is written at this symbol
+	pub crc: Option<Option<String>>,
+	pub sign: Option<ImageSign>,
+}
+
+pub enum SigningKey
+{
+	Ed25519(ed25519_dalek::SigningKey),
+	EcdsaP256(p256::ecdsa::SigningKey),
+}
+
+pub enum VerifyingKey
+{
+	Ed25519(ed25519_dalek::VerifyingKey),
+	EcdsaP256(p256::ecdsa::VerifyingKey),
+}
+
+impl SigningKey
+{
+	/// Ed25519 signs the message itself, ECDSA its SHA-256
+	pub fn sign(&self, msg: &[u8]) -> Vec<u8>
+	{
+		match self
+		{
+			SigningKey::Ed25519(k) => k.sign(msg).to_bytes().to_vec(),
+			SigningKey::EcdsaP256(k) =>
+			{
+				let sig: p256::ecdsa::Signature = k.sign(msg);
+				sig.to_bytes().to_vec()
+			}
+		}
+	}
+
+	pub fn verifying_key(&self) -> VerifyingKey
+	{
+		match self
+		{
+			SigningKey::Ed25519(k) => VerifyingKey::Ed25519(k.verifying_key()),
+			SigningKey::EcdsaP256(k) => VerifyingKey::EcdsaP256(*k.verifying_key()),
+		}
+	}
+}
+
+impl VerifyingKey
+{
+	pub fn verify(&self, msg: &[u8], sig: &[u8]) -> Result<(), String>
+	{
+		let res = match self
+		{
+			VerifyingKey::Ed25519(k) =>
+			{
+				let sig = ed25519_dalek::Signature::from_slice(sig).map_err(|e| e.to_string())?;
+				k.verify(msg, &sig)
+			}
+			VerifyingKey::EcdsaP256(k) =>
+			{
+				let sig = p256::ecdsa::Signature::from_slice(sig).map_err(|e| e.to_string())?;
+				k.verify(msg, &sig)
+			}
+		};
+		res.map_err(|_e| String::from("signature does not match"))
+	}
+}
+
+/// Reads a key of algorithm from a PEM file. Private keys are PKCS#8 (or SEC1
+/// for ECDSA), public keys SubjectPublicKeyInfo as written by openssl.
+/// Returns the signing key if the file holds one.
+pub fn read_key(fs: &dyn IglooFs, path: &Path, algorithm: SignAlgorithm)
+				-> Result<(Option<SigningKey>, VerifyingKey), IglooErrType>
+{
+	let pem = match fs.read_to_string(path)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			error!("{}: {}", path.display(), e);
+			return Err(FailedToReadKey)
+		}
+	};
+	let res = if pem.contains("PUBLIC KEY")
+	{
+		match algorithm
+		{
+			SignAlgorithm::Ed25519 => ed25519_dalek::VerifyingKey::from_public_key_pem(&pem)
+				.map(|k| (None, VerifyingKey::Ed25519(k)))
+				.map_err(|e| e.to_string()),
+			SignAlgorithm::EcdsaP256 => p256::ecdsa::VerifyingKey::from_public_key_pem(&pem)
+				.map(|k| (None, VerifyingKey::EcdsaP256(k)))
+				.map_err(|e| e.to_string()),
+		}
+	}
+	else
+	{
+		let key = match algorithm
+		{
+			SignAlgorithm::Ed25519 => ed25519_dalek::SigningKey::from_pkcs8_pem(&pem)
+				.map(SigningKey::Ed25519)
+				.map_err(|e| e.to_string()),
+			SignAlgorithm::EcdsaP256 if pem.contains("EC PRIVATE KEY") =>
+				p256::SecretKey::from_sec1_pem(&pem)
+				.map(|k| SigningKey::EcdsaP256(k.into()))
+				.map_err(|e| e.to_string()),
+			SignAlgorithm::EcdsaP256 => p256::ecdsa::SigningKey::from_pkcs8_pem(&pem)
+				.map(SigningKey::EcdsaP256)
+				.map_err(|e| e.to_string()),
+		};
+		key.map(|k| { let v = k.verifying_key(); (Some(k), v) })
+	};
+	res.map_err(|e|
+	{
+		error!("{}: not a {:?} key: {}", path.display(), algorithm, e);
+		FailedToReadKey
+	})
+}
+
+fn invalid(table: &str, msg: &str) -> IglooErrType
+{
+	error!("image.{}: {}", table, msg);
+	InvalidPostBuild
+}
+
+fn get_u32(table: &BTreeMap<String, config::Value>, name: &str, default: u32)
+		   -> Result<u32, String>
+{
+	match table.get(name)
+	{
+		Some(v) => parse_u32(v),
+		None => Ok(default),
+	}
+}
+
+/// Parses a size that has to fit in 32 bits, like a header magic
+fn parse_u32(val: &config::Value) -> Result<u32, String>
+{
+	match parse_size(val)?
+	{
+		v if v > u32::MAX as u64 => Err(format!("{} does not fit in 32 bits", val)),
+		v => Ok(v as u32),
+	}
+}
+
+/// Parses a version as an integer or major.minor.patch, packed as
+/// major << 24 | minor << 16 | patch
+pub fn parse_version(val: &config::Value) -> Result<u32, String>
+{
+	let text = val.clone().into_str().map_err(|e| e.to_string())?;
+	let parts: Vec<&str> = text.split('.').collect();
+	if parts.len() == 1
+	{
+		return parse_u32(val)
+	}
+	let nums: Vec<u32> = match parts.iter().map(|p| p.parse::<u32>()).collect()
+	{
+		Ok(v) => v,
+		Err(_e) => return Err(format!("invalid version \"{}\"", text)),
+	};
+	match nums.as_slice()
+	{
+		[major, minor, patch] if *major < 256 && *minor < 256 && *patch < 65536 =>
+			Ok(major << 24 | minor << 16 | patch),
+		_ => Err(format!("version \"{}\" is not major.minor.patch", text)),
+	}
+}
+
+fn to_table(val: &config::Value, name: &str)
+			-> Result<BTreeMap<String, config::Value>, IglooErrType>
+{
+	match val.clone().into_table()
+	{
+		Ok(v) => Ok(v.into_iter().collect()),
+		Err(e) => Err(invalid(name, &e.to_string())),
+	}
+}
+
+impl IglooPostBuild
+{
+	/// Reads the header, crc and sign tables of a target's image table
+	/// Key paths are relative to prj_root
+	pub fn from_table(table: &BTreeMap<String, config::Value>, prj_root: &Path)
+					  -> Result<IglooPostBuild, IglooErrType>
+	{
+		let mut ret = IglooPostBuild::default();
+		if let Some(v) = table.get("header")
+		{
+			let header = to_table(v, "header")?;
+			let symbol = match header.get("symbol").map(|v| v.clone().into_str())
+			{
+				Some(Ok(v)) => v,
+				_ => return Err(invalid("header", "symbol is required")),
+			};
+			let mut fields: Vec<HeaderField> = Vec::new();
+			match header.get("fields").map(|v| v.clone().into_array())
+			{
+				None => fields = vec![HeaderField::Magic, HeaderField::Version,
+									  HeaderField::Length, HeaderField::Crc],
+				Some(Ok(v)) => for name in v
+				{
+					let name = name.into_str().unwrap_or_default();
+					match HeaderField::from_name(&name)
+					{
+						Some(f) => fields.push(f),
+						None => return Err(invalid("header",
+												   &format!("unknown field \"{}\"", name))),
+					}
+				},
+				Some(Err(e)) => return Err(invalid("header.fields", &e.to_string())),
+			}
+			let magic = get_u32(&header, "magic", 0).map_err(|e| invalid("header.magic", &e))?;
+			let version = match header.get("version")
+			{
+				Some(v) => parse_version(v).map_err(|e| invalid("header.version", &e))?,
+				None => 0,
+			};
+			ret.header = Some(ImageHeader { symbol, fields, magic, version });
+		}
+
+		if let Some(v) = table.get("crc")
+		{
+			let crc = to_table(v, "crc")?;
+			ret.crc = Some(crc.get("symbol").and_then(|v| v.clone().into_str().ok()));
+			let header_crc = ret.header.as_ref()
+				.map_or(false, |h| h.fields.contains(&HeaderField::Crc));
+			if header_crc && ret.crc != Some(None)
+			{
+				// Both would cover the other's field
+				return Err(invalid("crc", "a header crc field only works with an appended crc"))
+			}
+		}
+
+		if let Some(v) = table.get("sign")
+		{
+			let sign = to_table(v, "sign")?;
+			let algorithm = match sign.get("algorithm").map(|v| v.clone().into_str())
+			{
+				None => SignAlgorithm::Ed25519,
+				Some(Ok(v)) if v == "ed25519" => SignAlgorithm::Ed25519,
+				Some(Ok(v)) if v == "ecdsa-p256" => SignAlgorithm::EcdsaP256,
+				_ => return Err(invalid("sign", "algorithm must be ed25519 or ecdsa-p256")),
+			};
+			let path = |name: &str| sign.get(name)
+				.and_then(|v| v.clone().into_str().ok())
+				.map(|v| prj_root.join(v));
+			ret.sign = Some(ImageSign
+			{
+				algorithm,
+				key: path("key"),
+				public_key: path("public_key"),
+			});
+		}
+		Ok(ret)
+	}
+
+	pub fn is_empty(&self) -> bool
+	{
+		self.header.is_none() && self.crc.is_none() && self.sign.is_none()
+	}
+
+	/// Works out where everything goes for the firmware image of elf
+	pub fn layout(&self, elf: &IglooElf, image: &IglooImage) -> Result<ImageLayout, IglooErrType>
+	{
+		let (start, end) = match (image.start(), image.end())
+		{
+			(Some(s), Some(e)) => (s, e),
+			_ => return Err(invalid("header", "the image is empty")),
+		};
+		let symbol = |table: &str, name: &str, len: u64| -> Result<u64, IglooErrType>
+		{
+			let addr = match elf.symbol(name)
+			{
+				Some(v) => v.value,
+				None => return Err(invalid(table, &format!("no symbol \"{}\" in the ELF", name))),
+			};
+			if addr < start || addr + len > end
+			{
+				return Err(invalid(table, &format!("{} at 0x{:08x} is not in the image",
+												   name, addr)))
+			}
+			Ok(addr)
+		};
+
+		let mut layout = ImageLayout { start, end, header: None, crc: None, signature: None };
+		let mut cur = end;
+		if let Some(header) = &self.header
+		{
+			layout.header = Some(symbol("header", &header.symbol, header.fields.len() as u64 * 4)?);
+		}
+		match &self.crc
+		{
+			Some(Some(name)) => layout.crc = Some(symbol("crc", name, 4)?),
+			Some(None) =>
+			{
+				cur = align4(cur);
+				layout.crc = Some(cur);
+				cur += 4;
+			}
+			None => (),
+		}
+		if self.sign.is_some()
+		{
+			layout.signature = Some(align4(cur));
+		}
+		Ok(layout)
+	}
+
+	/// Runs the post-build steps on the firmware image of elf
+	/// signer is required if the target signs its images
+	pub fn apply(&self, elf: &IglooElf, image: &IglooImage, fill: u8,
+				 signer: Option<&SigningKey>) -> Result<IglooImage, IglooErrType>
+	{
+		let layout = self.layout(elf, image)?;
+		let mut out = image.clone();
+		let patch = |out: &mut IglooImage, addr: u64, data: &[u8]| -> Result<(), IglooErrType>
+		{
+			out.patch(addr, data).map_err(|e| invalid("header", &e))
+		};
+
+		if let (Some(header), Some(addr)) = (&self.header, layout.header)
+		{
+			for (i, field) in header.fields.iter().enumerate()
+			{
+				let val = match field
+				{
+					HeaderField::Magic => header.magic,
+					HeaderField::Version => header.version,
+					HeaderField::Length => (layout.end - layout.start) as u32,
+					HeaderField::Crc | HeaderField::Reserved => 0,
+				};
+				patch(&mut out, addr + i as u64 * 4, &val.to_le_bytes())?;
+			}
+			if let Some(i) = header.fields.iter().position(|f| *f == HeaderField::Crc)
+			{
+				let crc = crc32(&out.slice(layout.start, layout.end, fill));
+				patch(&mut out, addr + i as u64 * 4, &crc.to_le_bytes())?;
+			}
+		}
+
+		match (&self.crc, layout.crc)
+		{
+			(Some(Some(_name)), Some(addr)) =>
+			{
+				patch(&mut out, addr, &[0; 4])?;
+				let crc = crc32(&out.slice(layout.start, layout.end, fill));
+				patch(&mut out, addr, &crc.to_le_bytes())?;
+			}
+			(Some(None), Some(addr)) =>
+			{
+				let crc = crc32(&out.slice(layout.start, addr, fill));
+				append(&mut out, addr, &crc.to_le_bytes(), fill);
+			}
+			_ => (),
+		}
+
+		if let Some(addr) = layout.signature
+		{
+			let signer = match signer
+			{
+				Some(v) => v,
+				None => return Err(invalid("sign", "no signing key")),
+			};
+			let sig = signer.sign(&out.slice(layout.start, addr, fill));
+			append(&mut out, addr, &sig, fill);
+		}
+		Ok(out)
+	}
+
+	/// Checks the header, CRC and signature of a processed image
+	/// Returns the name and outcome of every check.
+	pub fn verify(&self, elf: &IglooElf, image: &IglooImage, fill: u8,
+				  verifier: Option<&VerifyingKey>) -> Result<Vec<(String, Result<(), String>)>, IglooErrType>
+	{
+		let layout = self.layout(elf, &IglooImage::from_elf(elf).map_err(|e| invalid("header", &e))?)?;
+		let word = |addr: u64| -> Result<u32, String>
+		{
+			match image.read(addr, 4)
+			{
+				Some(v) => Ok(u32::from_le_bytes([v[0], v[1], v[2], v[3]])),
+				None => Err(format!("nothing at 0x{:08x}", addr)),
+			}
+		};
+		let expect = |what: &str, got: Result<u32, String>, want: u32| -> Result<(), String>
+		{
+			match got
+			{
+				Ok(v) if v == want => Ok(()),
+				Ok(v) => Err(format!("{} is 0x{:08x}, expected 0x{:08x}", what, v, want)),
+				Err(e) => Err(e),
+			}
+		};
+		// CRC of [start, end) with the 4 bytes at field read as 0
+		let crc_without = |field: u64, end: u64| -> u32
+		{
+			let mut bytes = image.slice(layout.start, end, fill);
+			let off = (field - layout.start) as usize;
+			bytes[off..off + 4].copy_from_slice(&[0; 4]);
+			crc32(&bytes)
+		};
+
+		let mut checks: Vec<(String, Result<(), String>)> = Vec::new();
+		if let (Some(header), Some(addr)) = (&self.header, layout.header)
+		{
+			for (i, field) in header.fields.iter().enumerate()
+			{
+				let field_addr = addr + i as u64 * 4;
+				let res = match field
+				{
+					HeaderField::Magic => expect("magic", word(field_addr), header.magic),
+					HeaderField::Version => expect("version", word(field_addr), header.version),
+					HeaderField::Length =>
+						expect("length", word(field_addr), (layout.end - layout.start) as u32),
+					HeaderField::Crc =>
+						expect("crc", word(field_addr), crc_without(field_addr, layout.end)),
+					HeaderField::Reserved => continue,
+				};
+				checks.push((format!("header {:?}", field).to_lowercase(), res));
+			}
+		}
+		match (&self.crc, layout.crc)
+		{
+			(Some(Some(_name)), Some(addr)) =>
+				checks.push((String::from("crc"),
+							 expect("crc", word(addr), crc_without(addr, layout.end)))),
+			(Some(None), Some(addr)) =>
+				checks.push((String::from("crc"),
+							 expect("crc", word(addr), crc32(&image.slice(layout.start, addr, fill))))),
+			_ => (),
+		}
+		if let Some(addr) = layout.signature
+		{
+			let res = match (verifier, image.read(addr, SIGNATURE_LEN as usize))
+			{
+				(None, _) => Err(String::from("no key to verify with")),
+				(_, None) => Err(format!("no signature at 0x{:08x}", addr)),
+				(Some(k), Some(sig)) => k.verify(&image.slice(layout.start, addr, fill), &sig),
+			};
+			checks.push((String::from("signature"), res));
+		}
+		Ok(checks)
+	}
+}
+
+fn align4(addr: u64) -> u64
+{
+	(addr + 3) & !3
+}
+
+/// Writes data at addr, filling the gap from the end of the image
+fn append(image: &mut IglooImage, addr: u64, data: &[u8], fill: u8)
+{
+	let end = image.end().unwrap_or(addr);
+	let mut bytes = vec![fill; (addr - end) as usize];
+	bytes.extend_from_slice(data);
+	// Past the end of the image, this never conflicts
+	let _ = image.write(end, &bytes);
+}
+
+/// Reads and parses the ELF of a target
+pub fn read_elf(fs: &dyn IglooFs, path: &Path) -> Result<IglooElf, IglooErrType>
+{
+	let data = match fs.read(path)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			error!("{}: {}", path.display(), e);
+			return Err(FailedToReadElf)
+		}
+	};
+	IglooElf::parse(data).map_err(|e|
+	{
+		error!("{}: {}", path.display(), e);
+		FailedToReadElf
+	})
+}
@@ -4,11 +4,17 @@ use igloo_manifest::*;
 
 use crate::Igloo;
 use crate::igloo_target::IglooTarget;
-use std::fs::OpenOptions;
-use std::fs::File;
+use crate::igloo_lock::*;
+use crate::igloo_module::*;
+use crate::igloo_template::IglooTemplate;
+use crate::igloo_toolchain::*;
+use semver::Version;
+use std::collections::BTreeMap;
 use std::vec::Vec;
-use std::io::prelude::*;
-use std::path::PathBuf;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use config::Config;
 // New Project
 // --- Verify location
 // --- Populate base folders
@@ -17,12 +23,25 @@ use std::path::PathBuf;
 // --- Read Default Targets manifest toml
 // --- generate projects core manifest toml
 // --- Spawn user manifest config
+/// First line of every igloo generated igloo.h
+const IGLOO_H_BANNER: &str = "/* igloo.h -- Generated by igloo. \
+							  Edit only between the IGLOO_H_USER markers */";
+const IGLOO_H_USER_BEGIN: &str = "/* IGLOO_H_USER_BEGIN */";
+const IGLOO_H_USER_END: &str = "/* IGLOO_H_USER_END */";
+
 pub struct IglooPrj
 {
 	name: String,
 	target_bank: Vec<IglooTarget>,
+	/// target commands run on when no target is given, every target if None
+	default_target: Option<String>,
+	/// modules of the project and their dependencies, resolved from the registries
+	modules: Vec<IglooModule>,
+	/// ESF the project was resolved from, recorded in igloo.lock
+	esfd: PathBuf,
 	pub project_dir: std::path::PathBuf,
 	root: PathBuf,
+	fs: Rc<dyn IglooFs>,
 }
 
 
@@ -34,13 +53,26 @@ impl IglooPrj
 		{
 			name: String::from(""),
 			target_bank: Vec::default(),
+			default_target: None,
+			modules: Vec::default(),
+			esfd: PathBuf::default(),
 			project_dir: std::path::PathBuf::default(),
 			root: PathBuf::default(),
+			fs: Rc::new(RealFs),
 		}
 	}
 
 	pub fn new(inst: &Igloo, name_in: &str, target_in: &str)
 			   -> Result<IglooPrj, IglooErrType>
+	{
+		IglooPrj::new_at(inst, name_in, target_in,
+						 inst.env.cwd.join(name_in))
+	}
+
+	/// Creates a project rooted at root instead of ./<name_in>
+	/// This is used by igloo init to adopt an existing directory
+	pub fn new_at(inst: &Igloo, name_in: &str, target_in: &str, root: PathBuf)
+				  -> Result<IglooPrj, IglooErrType>
 	{
 		let mut res_err = ErrNone;
 		if String::from(name_in).is_empty()
@@ -54,17 +86,136 @@ impl IglooPrj
 			return Err(res_err)
 		}
 
+		let mut temp: Vec<IglooTarget> = Vec::new();
+		let targ = IglooPrj::load_target(inst, root.clone(), target_in)?;
+
+		temp.push(targ);
+		let locked = IglooLock::read(inst.fs.as_ref(), &root).unwrap_or_default();
+		IglooPrj::resolve_toolchains(inst, &locked, &mut temp);
+
+		Ok(IglooPrj
+		{
+			name: String::from(name_in),
+			target_bank: temp,
+			default_target: None,
+			modules: Vec::new(),
+			esfd: inst.env.esfd.clone(),
+			project_dir: root.clone(),
+			root: root,
+			fs: inst.fs.clone(),
+		})
+	}
+
+	/// Opens the igloo project located in the current working directory.
+	/// The project name and its targets are read from .igloo/<PrjName>.toml
+	pub fn open(inst: &Igloo) -> Result<IglooPrj, IglooErrType>
+	{
+		let root = inst.env.cwd.clone();
+		if !inst.fs.exists(&root.join(".igloo"))
+		{
+			return Err(NotInsideProject)
+		}
+
+		let mut prj_man = Config::new();
+		match get_project_manifest(inst.fs.as_ref(), &mut prj_man, &root)
+		{
+			ErrNone => (),
+			e => return Err(e),
+		}
+
+		let name = match prj_man.get_str("project.name")
+		{
+			Ok(v) => v,
+			Err(e) =>
+			{
+				error!("{:?}", e);
+				return Err(FailedToLoadProjectManifest)
+			}
+		};
+
+		let target_names = match prj_man.get_array("project.targets")
+		{
+			Ok(v) => v,
+			Err(e) =>
+			{
+				error!("{:?}", e);
+				return Err(FailedToLoadProjectManifest)
+			}
+		};
+
+		let mut temp: Vec<IglooTarget> = Vec::new();
+		for target_name in target_names
+		{
+			let target_name = match target_name.into_str()
+			{
+				Ok(v) => v,
+				Err(_e) => return Err(FailedToLoadProjectManifest),
+			};
+			let mut target = IglooPrj::load_target(inst, root.clone(), &target_name)?;
+			if let Ok(v) = prj_man.get_table(&format!("target.{}.memory", target_name))
+			{
+				let res_err = target.carve_out(&v.into_iter().collect());
+				if res_err != ErrNone
+				{
+					return Err(res_err)
+				}
+			}
+			// Project image settings (fill, header, crc, sign) override the ESF ones
+			if let Ok(v) = prj_man.get_table(&format!("target.{}.image", target_name))
+			{
+				target.image.extend(v);
+			}
+			temp.push(target);
+		}
+		let locked = IglooLock::read(inst.fs.as_ref(), &root).unwrap_or_default();
+		IglooPrj::resolve_toolchains(inst, &locked, &mut temp);
+
+		let wanted = project_modules(&prj_man)?;
+		let modules = if wanted.is_empty()
+		{
+			Vec::new()
+		}
+		else
+		{
+			let locked: BTreeMap<String, Version> = locked.modules.into_iter()
+				.map(|(k, v)| (k, v.version))
+				.collect();
+			resolve_modules(&available_modules(inst.fs.as_ref(), &inst.env), &wanted, &locked)?
+		};
+		for target in temp.iter_mut()
+		{
+			target.modules = modules_for_target(&modules, target);
+		}
+
+		Ok(IglooPrj
+		{
+			name: name,
+			target_bank: temp,
+			default_target: prj_man.get_str("project.default_target").ok(),
+			modules,
+			esfd: inst.env.esfd.clone(),
+			project_dir: root.clone(),
+			root: root,
+			fs: inst.fs.clone(),
+		})
+	}
+
+	/// Verifies a target exists in the master manifests and loads it
+	/// prj_root -- root directory of the project the target belongs to
+	pub fn load_target(inst: &Igloo, prj_root: PathBuf, target_in: &str)
+				   -> Result<IglooTarget, IglooErrType>
+	{
 		match target_is_valid(&inst.master_make_man, &inst.master_target_man, target_in)
 		{
 			Ok(v) =>
 			{
 				if v
 				{
-					println!("Verified target exists {}", name_in);
+					debug!("Verified target exists {}", target_in);
 				}
 				else
 				{
-					println!("Couldn't verify target exists {}", name_in);
+					error!("Target {} is not in the target manifest", target_in);
 					return Err(InvalidTarget)
 				}
 			}
@@ -74,125 +225,381 @@ impl IglooPrj
 			}
 		}
 
-		let mut _targ_make_table_name = inst.master_target_man.get_str(
+		let _targ_make_table_name = inst.master_target_man.get_str(
 			&("target.make.".to_owned() + &target_in)).unwrap();
-		let mut _targ_manifest_file_name = inst.master_target_man.get_str(
+		let _targ_manifest_file_name = inst.master_target_man.get_str(
 			&("target.manifest.".to_owned() + &target_in)).unwrap();
 
-		let mut temp: Vec<IglooTarget> = Vec::new();
-		let targ = IglooTarget::from(
-			IglooEnvInfo::get_env_info().cwd
-				.join(name_in)
+		IglooTarget::from(
+			prj_root
 				.join(".igloo")
 				.join("target")
 				.join(target_in),
 			inst,
 			String::from(target_in),
 			&_targ_make_table_name,
-			&_targ_manifest_file_name).unwrap();
+			&_targ_manifest_file_name)
+	}
 
-		temp.push(targ);
+	/// Picks the toolchain of every target requiring one: the toolchain locked in
+	/// igloo.lock if it still satisfies the requirement, else the newest installed one
+	fn resolve_toolchains(inst: &Igloo, locked: &IglooLock, targets: &mut Vec<IglooTarget>)
+	{
+		let installed = installed_toolchains(inst.fs.as_ref(), &inst.env.hd);
+		for target in targets.iter_mut()
+		{
+			let req = match &target.toolchain_req
+			{
+				Some(v) => v.clone(),
+				None => continue,
+			};
+			match select_toolchain(&req, &installed, locked.toolchains.get(&target.name))
+			{
+				Some(pin) =>
+				{
+					if pin.dir.is_none()
+					{
+						warn!("{} {} locked for {} is not installed, \
+								  run igloo toolchain install", pin.name, pin.version, target.name);
+					}
+					target.use_toolchain(pin);
+				}
+				None => warn!("{} needs toolchain {}, \
+								  run igloo toolchain install", target.name, req),
+			}
+		}
+	}
 
-		Ok(IglooPrj
+	/// What the project resolved to in this environment
+	pub fn lock(&self) -> IglooLock
+	{
+		let mut manifests: Vec<u8> = Vec::new();
+		for file in &["make-manifest.toml", "target-manifest.toml"]
 		{
-			name: String::from(name_in),
-			target_bank: temp,
-			project_dir: IglooEnvInfo::get_env_info().cwd.join(name_in),
-			root: PathBuf::from(
-				IglooEnvInfo::get_env_info().cwd.join(name_in)),
-		})
+			manifests.extend(self.fs.read(&self.esfd.join("manifest").join(file))
+							 .unwrap_or_default());
+		}
+		IglooLock
+		{
+			esf_revision: git_revision(self.fs.as_ref(), &self.esfd),
+			esf_manifests: sha256_hex(&manifests),
+			targets: self.target_bank.iter()
+				.map(|t| (t.name.clone(), t.manifest_hash.clone()))
+				.collect(),
+			modules: self.modules.iter()
+				.map(|m| (m.name.clone(), LockedModule
+				{
+					version: m.version.clone(),
+					hash: m.hash(self.fs.as_ref()),
+				}))
+				.collect(),
+			toolchains: self.target_bank.iter()
+				.filter_map(|t| t.toolchain.clone().map(|p| (t.name.clone(), p)))
+				.collect(),
+		}
 	}
 
-	pub fn populate(&self) -> IglooErrType
+	/// Writes igloo.lock
+	pub fn gen_lock(&self) -> IglooErrType
 	{
+		match self.fs.write(&self.root.join(LOCK_FILE), self.lock().render().as_bytes())
+		{
+			Ok(_v) => ErrNone,
+			Err(e) =>
+			{
+				error!("{:?}", e);
+				ErrUnknown
+			}
+		}
+	}
 
-		// Create new directory
-		let mut active_dir = self.root.clone();
-		match std::fs::create_dir(&active_dir)
+	/// Modules of the project, with the versions picked for them
+	pub fn modules(&self) -> &[IglooModule]
+	{
+		&self.modules
+	}
+
+	/// Links the modules of the project into modules/. stale names modules
+	/// that are no longer used, their links are removed
+	pub fn link_modules(&self, stale: &[String]) -> IglooErrType
+	{
+		let modules_dir = self.root.join(MODULES_DIR);
+		for name in stale
+		{
+			let link = modules_dir.join(name);
+			if self.fs.symlink_exists(&link)
+			{
+				match self.fs.remove_file(&link)
+				{
+					Err(e) => error!("{}: {:?}", link.display(), e),
+					_ => say!("Removed {}", link.display()),
+				}
+			}
+		}
+		if self.modules.is_empty()
+		{
+			return ErrNone
+		}
+		if !self.fs.exists(&modules_dir)
+		{
+			if let Err(e) = self.fs.create_dir(&modules_dir)
+			{
+				error!("{:?}", e);
+				return ErrUnknown
+			}
+		}
+		for module in &self.modules
 		{
-			Err(e) => println!("{:?}", e),
-			_ => (),
+			// Relink, the version may have changed
+			let link = self.root.join(module.link_path());
+			if self.fs.symlink_exists(&link)
+			{
+				if let Err(e) = self.fs.remove_file(&link)
+				{
+					say!("Skipping {}: {:?}", link.display(), e);
+					continue
+				}
+			}
+			if let Err(e) = self.fs.symlink(&module.root, &link)
+			{
+				error!("{:?}", e);
+				return ErrUnknown
+			}
 		}
+		ErrNone
+	}
+
+	pub fn name(&self) -> &str
+	{
+		&self.name
+	}
+
+	/// Path of the ELF make builds for a target
+	pub fn elf_path(&self, target: &IglooTarget) -> PathBuf
+	{
+		target.root.join(&self.name).with_extension("elf")
+	}
+
+	/// Path of the linker map make writes for a target
+	pub fn map_path(&self, target: &IglooTarget) -> PathBuf
+	{
+		target.root.join(&self.name).with_extension("map")
+	}
 
-		// Create .igloo directory
-		match std::fs::create_dir(
-			std::path::Path::new(&active_dir)
-				.join(".igloo"))
+	/// Returns the targets matching names. If names is empty,
+	/// every target in the project is returned.
+	pub fn get_targets(&self, names: &[&str]) -> Result<Vec<&IglooTarget>, IglooErrType>
+	{
+		if names.is_empty()
 		{
-			Err(e) => println!("{:?}", e),
-			_ => (),
+			return Ok(self.target_bank.iter().collect())
 		}
 
-		// Create target directory
-		match std::fs::create_dir(
-			std::path::Path::new(&active_dir)
-				.join(".igloo/target"))
+		let mut ret: Vec<&IglooTarget> = Vec::new();
+		for name in names
 		{
-			Err(e) => println!("{:?}", e),
-			_ => (),
+			match self.target_bank.iter().find(|t| t.name == *name)
+			{
+				Some(v) => ret.push(v),
+				None =>
+				{
+					say!("Target \"{}\" is not part of this project", name);
+					return Err(InvalidTarget)
+				}
+			}
 		}
+		Ok(ret)
+	}
+
+	/// Target of project.default_target in the project file
+	pub fn default_target(&self) -> Option<&str>
+	{
+		self.default_target.as_deref()
+	}
 
-		// Create src directory
-		match std::fs::create_dir(
-			std::path::Path::new(&active_dir)
-				.join("src"))
+	/// Returns the targets a command runs on: the targets named, every target
+	/// if all is set, else the default target or every target without one
+	pub fn select_targets(&self, names: &[&str], all: bool)
+						  -> Result<Vec<&IglooTarget>, IglooErrType>
+	{
+		if all || !names.is_empty()
+		{
+			return self.get_targets(names)
+		}
+		match &self.default_target
 		{
-			Err(e) => println!("{:?}", e),
-			_ => (),
+			Some(v) => self.get_targets(&[v.as_str()]),
+			None => self.get_targets(&[]),
 		}
+	}
 
-		// Create inc directory
-		match std::fs::create_dir(
-			std::path::Path::new(&active_dir)
-				.join("inc"))
+	/// Sets project.default_target in the project file, None removes it
+	pub fn set_default_target(&self, name: Option<&str>) -> IglooErrType
+	{
+		if let Some(v) = name
 		{
-			Err(e) => println!("{:?}", e),
-			_ => (),
+			if let Err(e) = self.get_targets(&[v])
+			{
+				return e
+			}
 		}
+		let prj_file = self.project_file();
+		let text = match self.fs.read_to_string(&prj_file)
+		{
+			Ok(v) => v,
+			Err(e) =>
+			{
+				error!("{:?}", e);
+				return FailedToLoadProjectManifest
+			}
+		};
+		let mut updated = String::new();
+		let mut table = String::new();
+		for line in text.lines()
+		{
+			let trimmed = line.trim();
+			if trimmed.starts_with('[')
+			{
+				table = String::from(trimmed);
+			}
+			if table == "[project]" && trimmed.starts_with("default_target")
+			{
+				continue
+			}
+			updated.push_str(line);
+			updated.push('\n');
+			if table == "[project]" && trimmed.starts_with("targets")
+			{
+				if let Some(v) = name
+				{
+					writeln!(updated, "default_target = \"{}\"", v).unwrap();
+				}
+			}
+		}
+		match self.fs.write(&prj_file, updated.as_bytes())
+		{
+			Ok(_v) => ErrNone,
+			Err(e) =>
+			{
+				error!("{:?}", e);
+				ErrUnknown
+			}
+		}
+	}
 
-		// Create cfg directory
-		match std::fs::create_dir(
-			std::path::Path::new(&active_dir)
-				.join("cfg"))
+	/// Creates the project folders and generates all project files.
+	/// Anything that already exists (src/, inc/, main.c, etc.) is left untouched
+	/// so populate can be used to adopt an existing firmware directory.
+	pub fn populate(&self) -> IglooErrType
+	{
+		// Create new directory
+		let active_dir = self.root.clone();
+		let prj_dirs = [
+			active_dir.clone(),
+			active_dir.join(".igloo"),
+			active_dir.join(".igloo/target"),
+			active_dir.join("src"),
+			active_dir.join("inc"),
+			active_dir.join("cfg"),
+			active_dir.join("ESF"),
+		];
+		for prj_dir in prj_dirs.iter()
 		{
-			Err(e) => println!("{:?}", e),
-			_ => (),
+			if self.fs.exists(prj_dir)
+			{
+				say!("Skipping {}, already exists", prj_dir.display());
+				continue
+			}
+			match self.fs.create_dir(prj_dir)
+			{
+				Err(e) => error!("{:?}", e),
+				_ => say!("Adding {}", prj_dir.display()),
+			}
 		}
 
-		// Create ESF directory
-		match std::fs::create_dir(
-			std::path::Path::new(&active_dir)
-				.join("ESF"))
+		// Generate project file
+		say!("Adding {}", active_dir
+				 .join(".igloo")
+				 .join(&self.name)
+				 .with_extension("toml")
+				 .display());
+		let res_err = self.gen_project_file();
+		if res_err != ErrNone
 		{
-			Err(e) => println!("{:?}", e),
-			_ => (),
+			return res_err
 		}
 
 		// Generate Targets
-		self.gen_targets();
+		let res_err = self.gen_targets();
+		if res_err != ErrNone
+		{
+			return res_err
+		}
 
 		// Generate igloo.h
-		self.gen_igloo_header();
-
-		// Generate main.c
-		self.gen_igloo_main();
+		say!("Generating {}", active_dir.join("inc/igloo.h").display());
+		let res_err = self.gen_igloo_header();
+		if res_err != ErrNone
+		{
+			return res_err
+		}
 
-		ErrNone
+		say!("Generating {}", active_dir.join(LOCK_FILE).display());
+		self.gen_lock()
 	}
 
 	pub fn debug_manifests(&self)
 	{
 		for target in &self.target_bank
 		{
-			println!("Target manifest:");
+			debug!("Target manifest of {}:", target.name);
 			for (key, val) in &target.links
 			{
-				println!("{} = {:?}", key, val);
+				debug!("{} = {:?}", key, val);
 			}
-			println!("\nMake Manifest:");
+			debug!("Make manifest of {}:", target.name);
 			for (key, val) in &target.make_manifest
 			{
-				println!("{} = {:?}", key, val);
+				debug!("{} = {:?}", key, val);
+			}
+		}
+	}
+
+	/// Path of the project file .igloo/<PrjName>.toml
+	pub fn project_file(&self) -> PathBuf
+	{
+		self.project_dir.join(".igloo").join(&self.name).with_extension("toml")
+	}
+
+	/// Generates the project file .igloo/<PrjName>.toml
+	pub fn gen_project_file(&self) -> IglooErrType
+	{
+		let prj_file_path = self.project_file();
+		let mut prj_file = String::new();
+
+		writeln!(prj_file, "[project]").unwrap();
+		writeln!(prj_file, "name = \"{}\"", self.name).unwrap();
+		write!(prj_file, "targets = [").unwrap();
+		for (i, target) in self.target_bank.iter().enumerate()
+		{
+			if i != 0
+			{
+				write!(prj_file, ", ").unwrap();
+			}
+			write!(prj_file, "\"{}\"", target.name).unwrap();
+		}
+		writeln!(prj_file, "]").unwrap();
+		if let Some(v) = &self.default_target
+		{
+			writeln!(prj_file, "default_target = \"{}\"", v).unwrap();
+		}
+		match self.fs.write(&prj_file_path, prj_file.as_bytes())
+		{
+			Ok(_v) => ErrNone,
+			Err(e) =>
+			{
+				error!("{:?}", e);
+				ErrUnknown
 			}
 		}
 	}
@@ -202,10 +609,25 @@ impl IglooPrj
 	{
 		for target in &self.target_bank
 		{
-			target.generate();
-			target.populate();
-			target.generate_openocd_config();
-			self.gen_makefile(&target);
+			for step in [IglooTarget::generate, IglooTarget::populate,
+						 IglooTarget::generate_openocd_config]
+			{
+				let res_err = step(target);
+				if res_err != ErrNone
+				{
+					return res_err
+				}
+			}
+			let res_err = self.gen_makefile(target);
+			if res_err != ErrNone
+			{
+				return res_err
+			}
+			let res_err = self.gen_linker_script(target);
+			if res_err != ErrNone
+			{
+				return res_err
+			}
 		}
 		ErrNone
 	}
@@ -213,21 +635,92 @@ impl IglooPrj
 	/// Generates a makefile for a target
 	pub fn gen_makefile(&self, target: &IglooTarget) -> IglooErrType
 	{
-		let prj_root = self.project_dir.join(".igloo/target");
-		let target_root = prj_root.join(&target.name);
-		// If the Makefile already exists, trash it
-		if target_root.join("Makefile").exists()
+		match self.fs.write(&target.root.join("Makefile"),
+							self.render_makefile(target).as_bytes())
+		{
+			Ok(_v) => ErrNone,
+			Err(e) =>
+			{
+				error!("{:?}", e);
+				ErrUnknown
+			}
+		}
+	}
+
+	/// Generates the linker script of a target from its memory map
+	/// Targets without a memory map keep using the linker script in ESF
+	pub fn gen_linker_script(&self, target: &IglooTarget) -> IglooErrType
+	{
+		let linker_script = match target.render_linker_script()
+		{
+			Ok(Some(v)) => v,
+			Ok(None) => return ErrNone,
+			Err(e) => return e,
+		};
+		match self.fs.write(&target.root.join(target.linker_script_name()),
+							linker_script.as_bytes())
+		{
+			Ok(_v) => ErrNone,
+			Err(e) =>
+			{
+				error!("{:?}", e);
+				ErrUnknown
+			}
+		}
+	}
+
+	/// Objects of the project sources the make manifest does not list: the
+	/// .c, .s and .S files below src/ and, for projects adopted with igloo init,
+	/// in the project root
+	fn source_objects(&self, target: &IglooTarget) -> Vec<String>
+	{
+		let listed = make_list(target, "OBJS");
+		let mut sources: Vec<PathBuf> = self.fs.read_dir(&self.root).unwrap_or_default();
+		let mut dirs: Vec<PathBuf> = vec![self.root.join("src")];
+		while let Some(dir) = dirs.pop()
 		{
-			std::fs::remove_file(target_root.join("Makefile")).unwrap();
+			for path in self.fs.read_dir(&dir).unwrap_or_default()
+			{
+				if self.fs.is_dir(&path)
+				{
+					dirs.push(path);
+				}
+				else
+				{
+					sources.push(path);
+				}
+			}
 		}
+		let mut ret: Vec<String> = sources.iter()
+			.filter(|p| !self.fs.is_dir(p))
+			.filter(|p| SOURCE_EXTS.iter().any(|e| p.extension() == Some(std::ffi::OsStr::new(e))))
+			.filter_map(|p| p.strip_prefix(&self.root).ok())
+			.map(|p| p.with_extension("o").to_string_lossy().replace('\\', "/"))
+			.filter(|o| !listed.contains(o))
+			.collect();
+		ret.sort();
+		ret.dedup();
+		ret
+	}
+
+	/// Whether an object of the make manifest is built from a project source
+	/// that does not exist, like src/main.o in a project keeping main.c in its root
+	fn source_missing(&self, object: &str) -> bool
+	{
+		let object = Path::new(object.trim_matches('"'));
+		let in_project = object.starts_with("src") || object.parent() == Some(Path::new(""));
+		in_project && object.extension() == Some(std::ffi::OsStr::new("o"))
+			&& !SOURCE_EXTS.iter().any(|e| self.fs.exists(&self.root.join(object.with_extension(e))))
+	}
 
-		// Make our Makefile, set it to append mode
-		std::fs::File::create(target_root.join("Makefile")).unwrap();
-		let mut app_file = OpenOptions::new()
-			.write(true)
-			.append(true)
-			.open(target_root.join("Makefile"))
-			.unwrap();
+	/// Renders the Makefile of a target
+	/// Variables and rules are always emitted in the same order, so rendering
+	/// the same manifests twice gives byte for byte the same Makefile.
+	/// Missing keys the firmware needs to build are warned about, missing optional
+	/// ones (hex, eep and lss outputs, clean, debug and push rules) are debug output.
+	pub fn render_makefile(&self, target: &IglooTarget) -> String
+	{
+		let mut app_file = String::new();
 		//
 		writeln!(app_file, "# ePenguin Generated Variables").unwrap();
 		writeln!(app_file, "PROJECT_NAME={}", self.name).unwrap();
@@ -237,7 +730,7 @@ impl IglooPrj
 		{
 			None =>
 			{
-				println!("TOOLCHAIN Not found");
+				warn!("make manifest of target {} has no TOOLCHAIN", target.name);
 			}
 			Some(v) =>
 			{
@@ -249,7 +742,7 @@ impl IglooPrj
 		{
 			None =>
 			{
-				println!("CC Not found");
+				warn!("make manifest of target {} has no CC", target.name);
 			}
 			Some(v) =>
 			{
@@ -261,7 +754,7 @@ impl IglooPrj
 		{
 			None =>
 			{
-				println!("CXX Not found");
+				debug!("make manifest of target {} has no CXX", target.name);
 			}
 			Some(v) =>
 			{
@@ -273,7 +766,7 @@ impl IglooPrj
 		{
 			None =>
 			{
-				println!("OBJCOPY Not found");
+				warn!("make manifest of target {} has no OBJCOPY", target.name);
 			}
 			Some(v) =>
 			{
@@ -285,7 +778,7 @@ impl IglooPrj
 		{
 			None =>
 			{
-				println!("OBJDUMP Not found");
+				debug!("make manifest of target {} has no OBJDUMP", target.name);
 			}
 			Some(v) =>
 			{
@@ -297,7 +790,7 @@ impl IglooPrj
 		{
 			None =>
 			{
-				println!("GDB Not found");
+				debug!("make manifest of target {} has no GDB", target.name);
 			}
 			Some(v) =>
 			{
@@ -309,7 +802,7 @@ impl IglooPrj
 		{
 			None =>
 			{
-				println!("SIZE Not found");
+				warn!("make manifest of target {} has no SIZE", target.name);
 			}
 			Some(v) =>
 			{
@@ -321,7 +814,7 @@ impl IglooPrj
 		{
 			None =>
 			{
-				println!("AS Not found");
+				warn!("make manifest of target {} has no AS", target.name);
 			}
 			Some(v) =>
 			{
@@ -336,7 +829,7 @@ impl IglooPrj
 		{
 			None =>
 			{
-				println!("MCPU Not found");
+				warn!("make manifest of target {} has no MCPU", target.name);
 			}
 			Some(v) =>
 			{
@@ -348,7 +841,7 @@ impl IglooPrj
 		{
 			None =>
 			{
-				println!("MCU Not found");
+				warn!("make manifest of target {} has no MCU", target.name);
 			}
 			Some(v) =>
 			{
@@ -356,31 +849,39 @@ impl IglooPrj
 				writeln!(app_file, "{}", v.to_string()).unwrap();
 			},
 		}
-		match target.make_manifest.get("LD_PATH")
+		if !target.memory.is_empty()
 		{
-			None =>
-			{
-				println!("LD_PATH Not found");
-			}
-			Some(v) =>
-			{
-				write!(app_file, "LD_PATH=").unwrap();
-				writeln!(app_file, "{}", v.to_string()).unwrap();
-			},
+			// Targets with a memory map link with the script igloo generates
+			writeln!(app_file, "LD_PATH=.").unwrap();
+			writeln!(app_file, "LD_SCRIPT={}", target.linker_script_name()).unwrap();
 		}
-		match target.make_manifest.get("LD_SCRIPT")
+		else
 		{
-			None =>
+			match target.make_manifest.get("LD_PATH")
 			{
-				println!("LD_SCRIPT Not found");
+				None =>
+				{
+					warn!("make manifest of target {} has no LD_PATH", target.name);
+				}
+				Some(v) =>
+				{
+					write!(app_file, "LD_PATH=").unwrap();
+					writeln!(app_file, "{}", v.to_string()).unwrap();
+				},
 			}
-			Some(v) =>
+			match target.make_manifest.get("LD_SCRIPT")
 			{
-				write!(app_file, "LD_SCRIPT=").unwrap();
-				writeln!(app_file, "{}", v.to_string()).unwrap();
-			},
+				None =>
+				{
+					warn!("make manifest of target {} has no LD_SCRIPT", target.name);
+				}
+				Some(v) =>
+				{
+					write!(app_file, "LD_SCRIPT=").unwrap();
+					writeln!(app_file, "{}", v.to_string()).unwrap();
+				},
+			}
 		}
-
 		writeln!(app_file, "\n").unwrap();
 
 		// CFLAGS
@@ -388,7 +889,7 @@ impl IglooPrj
 		{
 			None =>
 			{
-				println!("CFLAGS Not found");
+				warn!("make manifest of target {} has no CFLAGS", target.name);
 			}
 			Some(v) =>
 			{
@@ -400,13 +901,22 @@ impl IglooPrj
 				}
 			},
 		}
+		writeln!(app_file, "\nCFLAGS+=-D{}", target.define_name()).unwrap();
+		for module in &target.modules
+		{
+			writeln!(app_file, "CFLAGS+=-D{}", module.define_name()).unwrap();
+			for define in &module.defines
+			{
+				writeln!(app_file, "CFLAGS+=-D{}", define).unwrap();
+			}
+		}
 		writeln!(app_file, "\n").unwrap();
 		// ELF FLAGS
 		match target.make_manifest.get("ELF_FLAGS")
 		{
 			None =>
 			{
-				println!("ELF_FLAGS Not found");
+				warn!("make manifest of target {} has no ELF_FLAGS", target.name);
 			}
 			Some(v) =>
 			{
@@ -424,7 +934,7 @@ impl IglooPrj
 		{
 			None =>
 			{
-				println!("HEX_FLAGS Not found");
+				debug!("make manifest of target {} has no HEX_FLAGS", target.name);
 			}
 			Some(v) =>
 			{
@@ -442,7 +952,7 @@ impl IglooPrj
 		{
 			None =>
 			{
-				println!("EEP_FLAGS Not found");
+				debug!("make manifest of target {} has no EEP_FLAGS", target.name);
 			}
 			Some(v) =>
 			{
@@ -484,11 +994,12 @@ else
 endif");
 
 		writeln!(app_file, "{}", sysroot).unwrap();
+		let manifest_sub_dirs = make_list(target, "SUB_DIRS");
 		match target.make_manifest.get("SUB_DIRS")
 		{
 			None =>
 			{
-				println!("SUB_DIRS Not found");
+				warn!("make manifest of target {} has no SUB_DIRS", target.name);
 			}
 			Some(v) =>
 			{
@@ -500,49 +1011,69 @@ endif");
 				}
 			},
 		}
+		let source_sub_dirs: Vec<String> = sub_dirs(&self.source_objects(target)).into_iter()
+			.filter(|d| !manifest_sub_dirs.contains(d))
+			.collect();
+		write_make_list(&mut app_file, "SUB_DIRS+=", &source_sub_dirs);
+		write_make_list(&mut app_file, "SUB_DIRS+=", &module_sub_dirs(target));
 
 		writeln!(app_file, "\n").unwrap();
 		match target.make_manifest.get("OBJS")
 		{
 			None =>
 			{
-				println!("OBJS Not found");
+				warn!("make manifest of target {} has no OBJS", target.name);
 			}
 			Some(v) =>
 			{
 				write!(app_file, "OBJS+=").unwrap();
 				for cflag in v.clone().into_array().unwrap()
 				{
+					if self.source_missing(&cflag.to_string())
+					{
+						continue
+					}
 					writeln!(app_file, " \\").unwrap();
 					write!(app_file, "{}", cflag).unwrap();
 				}
 			},
 		}
+		write_make_list(&mut app_file, "OBJS+=", &self.source_objects(target));
+		write_make_list(&mut app_file, "OBJS+=", &module_objects(target));
 
 		writeln!(app_file, "\n").unwrap();
 		match target.make_manifest.get("OBJS_AS_ARGS")
 		{
 			None =>
 			{
-				println!("OBJS_AS_ARGS Not found");
+				warn!("make manifest of target {} has no OBJS_AS_ARGS", target.name);
 			}
 			Some(v) =>
 			{
 				write!(app_file, "OBJS_AS_ARGS+=").unwrap();
 				for cflag in v.clone().into_array().unwrap()
 				{
+					if self.source_missing(&cflag.to_string())
+					{
+						continue
+					}
 					writeln!(app_file, " \\").unwrap();
 					write!(app_file, "{}", cflag).unwrap();
 				}
 			},
 		}
+		let quoted: Vec<String> = self.source_objects(target).iter()
+			.chain(module_objects(target).iter())
+			.map(|o| format!("\"{}\"", o))
+			.collect();
+		write_make_list(&mut app_file, "OBJS_AS_ARGS+=", &quoted);
 
 		writeln!(app_file, "\n").unwrap();
 		match target.make_manifest.get("DIR_INCLUDES")
 		{
 			None =>
 			{
-				println!("DIR_INCLUDES Not found");
+				warn!("make manifest of target {} has no DIR_INCLUDES", target.name);
 			}
 			Some(v) =>
 			{
@@ -554,13 +1085,14 @@ endif");
 				}
 			},
 		}
+		write_make_list(&mut app_file, "DIR_INCLUDES+=", &module_includes(target));
 
 		write!(app_file, "\n\n").unwrap();
 		match target.make_manifest.get("DEPS")
 		{
 			None =>
 			{
-				println!("DEPS Not found");
+				debug!("make manifest of target {} has no DEPS", target.name);
 			}
 			Some(v) =>
 			{
@@ -574,7 +1106,7 @@ endif");
 		{
 			None =>
 			{
-				println!("DEPS_AS_ARGS Not found");
+				debug!("make manifest of target {} has no DEPS_AS_ARGS", target.name);
 			}
 			Some(v) =>
 			{
@@ -593,7 +1125,7 @@ endif");
 		{
 			None =>
 			{
-				println!("ALL_PREREQS Not found");
+				warn!("make manifest of target {} has no ALL_PREREQS", target.name);
 			}
 			Some(v) =>
 			{
@@ -609,7 +1141,7 @@ endif");
 		{
 			None =>
 			{
-				println!("ALL_CMDS Not found");
+				warn!("make manifest of target {} has no ALL_CMDS", target.name);
 			}
 			Some(v) =>
 			{
@@ -626,7 +1158,7 @@ endif");
 		{
 			None =>
 			{
-				println!("ELF_TARGET_PREREQS Not found");
+				warn!("make manifest of target {} has no ELF_TARGET_PREREQS", target.name);
 			}
 			Some(v) =>
 			{
@@ -643,7 +1175,7 @@ endif");
 		{
 			None =>
 			{
-				println!("ELF_TARGET_CMDS Not found");
+				warn!("make manifest of target {} has no ELF_TARGET_CMDS", target.name);
 			}
 			Some(v) =>
 			{
@@ -660,7 +1192,7 @@ endif");
 		{
 			None =>
 			{
-				println!("BIN_TARGET_PREREQS Not found");
+				warn!("make manifest of target {} has no BIN_TARGET_PREREQS", target.name);
 			}
 			Some(v) =>
 			{
@@ -677,7 +1209,7 @@ endif");
 		{
 			None =>
 			{
-				println!("BIN_TARGET_CMDS Not found");
+				warn!("make manifest of target {} has no BIN_TARGET_CMDS", target.name);
 			}
 			Some(v) =>
 			{
@@ -694,7 +1226,7 @@ endif");
 		{
 			None =>
 			{
-				println!("HEX_TARGET_PREREQS Not found");
+				debug!("make manifest of target {} has no HEX_TARGET_PREREQS", target.name);
 			}
 			Some(v) =>
 			{
@@ -711,7 +1243,7 @@ endif");
 		{
 			None =>
 			{
-				println!("HEX_TARGET_CMDS Not found");
+				debug!("make manifest of target {} has no HEX_TARGET_CMDS", target.name);
 			}
 			Some(v) =>
 			{
@@ -727,7 +1259,7 @@ endif");
 		{
 			None =>
 			{
-				println!("EEP_TARGET_PREREQS Not found");
+				debug!("make manifest of target {} has no EEP_TARGET_PREREQS", target.name);
 			}
 			Some(v) =>
 			{
@@ -744,7 +1276,7 @@ endif");
 		{
 			None =>
 			{
-				println!("EEP_TARGET_CMDS Not found");
+				debug!("make manifest of target {} has no EEP_TARGET_CMDS", target.name);
 			}
 			Some(v) =>
 			{
@@ -760,7 +1292,7 @@ endif");
 		{
 			None =>
 			{
-				println!("LSS_TARGET_PREREQS Not found");
+				debug!("make manifest of target {} has no LSS_TARGET_PREREQS", target.name);
 			}
 			Some(v) =>
 			{
@@ -777,7 +1309,7 @@ endif");
 		{
 			None =>
 			{
-				println!("LSS_TARGET_CMDS Not found");
+				debug!("make manifest of target {} has no LSS_TARGET_CMDS", target.name);
 			}
 			Some(v) =>
 			{
@@ -825,7 +1357,7 @@ endif\n").unwrap();
 		{
 			None =>
 			{
-				println!("CLEAN_TARGET_PREREQS Not found");
+				debug!("make manifest of target {} has no CLEAN_PREREQS", target.name);
 			}
 			Some(v) =>
 			{
@@ -845,7 +1377,7 @@ endif\n").unwrap();
 		{
 			None =>
 			{
-				println!("CLEAN_CMDS Not found");
+				debug!("make manifest of target {} has no CLEAN_CMDS", target.name);
 			}
 			Some(v) =>
 			{
@@ -865,7 +1397,7 @@ endif\n").unwrap();
 		{
 			None =>
 			{
-				println!("DEBUG_PREREQS Not found");
+				debug!("make manifest of target {} has no DEBUG_PREREQS", target.name);
 			}
 			Some(v) =>
 			{
@@ -885,7 +1417,7 @@ endif\n").unwrap();
 		{
 			None =>
 			{
-				println!("DEBUG_CMDS Not found");
+				debug!("make manifest of target {} has no DEBUG_CMDS", target.name);
 			}
 			Some(v) =>
 			{
@@ -905,7 +1437,7 @@ endif\n").unwrap();
 		{
 			None =>
 			{
-				println!("PUSH_PREREQS Not found");
+				debug!("make manifest of target {} has no PUSH_PREREQS", target.name);
 			}
 			Some(v) =>
 			{
@@ -925,7 +1457,7 @@ endif\n").unwrap();
 		{
 			None =>
 			{
-				println!("PUSH_CMDS Not found");
+				debug!("make manifest of target {} has no PUSH_CMDS", target.name);
 			}
 			Some(v) =>
 			{
@@ -941,67 +1473,374 @@ endif\n").unwrap();
 		}
 
 		writeln!(app_file, "\n\nQUOTE:=\"").unwrap();
-		ErrNone
+		app_file
 	}
 
+	/// Generates a Doxyfile for a target
+	/// Documentation is generated for the project src and inc folders as well
+	/// as the linked ESF sources. The MCU define and include paths of the target
+	/// are passed to doxygen so the correct #ifdef blocks are documented.
+	pub fn gen_doxyfile(&self, target: &IglooTarget) -> IglooErrType
+	{
+		let doxyfile_path = target.root.join("Doxyfile");
+		let mut doxyfile = String::new();
+
+		writeln!(doxyfile, "# ePenguin Generated Doxyfile").unwrap();
+		writeln!(doxyfile, "PROJECT_NAME = \"{}\"", self.name).unwrap();
+		writeln!(doxyfile, "PROJECT_BRIEF = \"{} ({})\"", self.name, target.name).unwrap();
+		writeln!(doxyfile, "OUTPUT_DIRECTORY = \"{}\"",
+				 self.project_dir.join("doc").join(&target.name).display()).unwrap();
+		writeln!(doxyfile, "INPUT = \"{}\" \\\n\t\"{}\" \\\n\t\"{}\"",
+				 self.project_dir.join("src").display(),
+				 self.project_dir.join("inc").display(),
+				 self.project_dir.join("ESF").display()).unwrap();
+		writeln!(doxyfile, "FILE_PATTERNS = *.c *.h *.s *.S").unwrap();
+		writeln!(doxyfile, "RECURSIVE = YES").unwrap();
+		writeln!(doxyfile, "EXCLUDE_SYMLINKS = NO").unwrap();
+		writeln!(doxyfile, "EXTRACT_ALL = YES").unwrap();
+		writeln!(doxyfile, "EXTRACT_STATIC = YES").unwrap();
+		writeln!(doxyfile, "OPTIMIZE_OUTPUT_FOR_C = YES").unwrap();
+		writeln!(doxyfile, "GENERATE_LATEX = NO").unwrap();
+		writeln!(doxyfile, "ENABLE_PREPROCESSING = YES").unwrap();
+		writeln!(doxyfile, "MACRO_EXPANSION = YES").unwrap();
+		writeln!(doxyfile, "SEARCH_INCLUDES = YES").unwrap();
+
+		match target.make_manifest.get("MCU")
+		{
+			None =>
+			{
+				warn!("make manifest of target {} has no MCU", target.name);
+			}
+			Some(v) =>
+			{
+				writeln!(doxyfile, "PREDEFINED = {}", v.to_string()).unwrap();
+			},
+		}
+
+		write!(doxyfile, "INCLUDE_PATH = \"{}\"",
+			   self.project_dir.join("inc").display()).unwrap();
+		for inc_dir in target.include_dirs()
+		{
+			write!(doxyfile, " \\\n\t\"{}\"", inc_dir.display()).unwrap();
+		}
+		writeln!(doxyfile).unwrap();
+		match self.fs.write(&doxyfile_path, doxyfile.as_bytes())
+		{
+			Ok(_v) => ErrNone,
+			Err(e) =>
+			{
+				error!("{:?}", e);
+				FailedToGenerateDocs
+			}
+		}
+	}
+
+	/// Generates inc/igloo.h
+	/// The header selects the MCU include files for whichever target is being built,
+	/// defines board metadata for the active IGLOO_TARGET_<NAME> and errors out when no
+	/// known MCU is defined. Anything between IGLOO_H_USER_BEGIN and IGLOO_H_USER_END
+	/// is kept when the header is regenerated. An igloo.h that igloo did not generate
+	/// is left alone.
 	pub fn gen_igloo_header(&self) -> IglooErrType
+	{
+		let igloo_h_path = self.project_dir.join("inc/igloo.h");
+		let igloo_h_file = match self.render_igloo_header()
+		{
+			Ok(Some(v)) => v,
+			Ok(None) =>
+			{
+				say!("Skipping {}, not generated by igloo", igloo_h_path.display());
+				return ErrNone
+			}
+			Err(e) => return e,
+		};
+		match self.fs.write(&igloo_h_path, igloo_h_file.as_bytes())
+		{
+			Ok(_v) => ErrNone,
+			Err(e) =>
+			{
+				error!("{:?}", e);
+				ErrUnknown
+			}
+		}
+	}
+
+	/// Renders inc/igloo.h, keeping the user region of the current header.
+	/// Returns None if the current igloo.h was not generated by igloo.
+	pub fn render_igloo_header(&self) -> Result<Option<String>, IglooErrType>
 	{
 		let inc_dir = self.project_dir.join("inc");
-		if inc_dir.join("igloo.h").exists()
+		let mut user_region = String::from("\n");
+		if self.fs.exists(&inc_dir.join("igloo.h"))
 		{
-			std::fs::remove_file(inc_dir.join("igloo.h")).unwrap();
+			let old_header = self.fs.read_to_string(&inc_dir.join("igloo.h"))
+				.unwrap_or_default();
+			if !old_header.contains(IGLOO_H_BANNER)
+			{
+				return Ok(None)
+			}
+			let begin = old_header.find(IGLOO_H_USER_BEGIN);
+			let end = old_header.find(IGLOO_H_USER_END);
+			if let (Some(begin), Some(end)) = (begin, end)
+			{
+				if begin < end
+				{
+					let region = &old_header[begin + IGLOO_H_USER_BEGIN.len()..end];
+					user_region = String::from(
+						region.strip_prefix('\n').unwrap_or(region));
+				}
+			}
 		}
 
-		File::create(inc_dir.join("igloo.h")).unwrap();
-		let mut igloo_h_file = OpenOptions::new()
-			.write(true)
-			.append(true)
-			.open(inc_dir.join("igloo.h"))
-			.unwrap();
+		let mut igloo_h_file = String::new();
+
+		writeln!(igloo_h_file, "{}", IGLOO_H_BANNER).unwrap();
+		writeln!(igloo_h_file, "#ifndef IGLOO_H").unwrap();
+		writeln!(igloo_h_file, "#define IGLOO_H\n").unwrap();
 
+		// Target selection and board metadata
 		for target in &self.target_bank
+		{
+			writeln!(igloo_h_file, "#ifdef {}", target.define_name()).unwrap();
+			writeln!(igloo_h_file, "\t#define IGLOO_TARGET_NAME \"{}\"", target.name).unwrap();
+			let mut board_keys: Vec<&String> = target.board.keys().collect();
+			board_keys.sort();
+			for key in board_keys
+			{
+				let val = target.board.get(key).unwrap().to_string();
+				let macro_name = format!("IGLOO_BOARD_{}", c_ident(key));
+				match val.parse::<i64>()
+				{
+					Ok(_v) => writeln!(igloo_h_file, "\t#define {} {}", macro_name, val).unwrap(),
+					Err(_e) => writeln!(igloo_h_file, "\t#define {} \"{}\"", macro_name, val).unwrap(),
+				}
+			}
+			writeln!(igloo_h_file, "#endif\n").unwrap();
+		}
+
+		// MCU includes
+		for (i, target) in self.target_bank.iter().enumerate()
 		{
 			match &target.make_manifest.get("MCU")
 			{
 				None =>
 				{
-					println!("MCU definition not found in make manifest.\
-							  \nCould not generate igloo.h");
-					return ErrUnknown
+					error!("make manifest of target {} has no MCU, could not generate igloo.h",
+						   target.name);
+					return Err(ErrUnknown)
 				}
 				Some(v) =>
 				{
-					writeln!(igloo_h_file, "#ifdef {}",
-							 v.to_string()).unwrap();
+					if i == 0
+					{
+						writeln!(igloo_h_file, "#if defined({})",
+								 v.to_string()).unwrap();
+					}
+					else
+					{
+						writeln!(igloo_h_file, "#elif defined({})",
+								 v.to_string()).unwrap();
+					}
 				}
 			}
 			for inc_file in &target.includes
 			{
 				writeln!(igloo_h_file, "\t#include \"{}\"", inc_file).unwrap();
 			}
+		}
+		if !self.target_bank.is_empty()
+		{
+			writeln!(igloo_h_file, "#else").unwrap();
+		}
+		writeln!(igloo_h_file, "\t#error \"igloo.h: no known MCU is defined. \
+								Build this project through its igloo generated Makefile.\"").unwrap();
+		if !self.target_bank.is_empty()
+		{
 			writeln!(igloo_h_file, "#endif").unwrap();
 		}
-		ErrNone
+
+		// Module headers, each target defines the modules it builds
+		if !self.modules.is_empty()
+		{
+			writeln!(igloo_h_file).unwrap();
+		}
+		for module in self.modules.iter().filter(|m| !m.headers.is_empty())
+		{
+			writeln!(igloo_h_file, "#ifdef {}", module.define_name()).unwrap();
+			for header in &module.headers
+			{
+				writeln!(igloo_h_file, "\t#include \"{}\"", header).unwrap();
+			}
+			writeln!(igloo_h_file, "#endif").unwrap();
+		}
+
+		writeln!(igloo_h_file, "\n{}", IGLOO_H_USER_BEGIN).unwrap();
+		write!(igloo_h_file, "{}", user_region).unwrap();
+		writeln!(igloo_h_file, "{}", IGLOO_H_USER_END).unwrap();
+		writeln!(igloo_h_file, "\n#endif /* IGLOO_H */").unwrap();
+		Ok(Some(igloo_h_file))
 	}
 
-	pub fn gen_igloo_main(&self) -> IglooErrType
+	/// Renders the generated build files (Makefile, linker script) of the given
+	/// targets along with igloo.h and igloo.lock
+	/// Returns (path, contents) pairs. Used by igloo make regen to rewrite
+	/// the build files or check them for drift.
+	pub fn render_build_files(&self, targets: &[&IglooTarget])
+							  -> Result<Vec<(PathBuf, String)>, IglooErrType>
 	{
-		let src_dir = self.project_dir.join("src");
-		if src_dir.join("main.c").exists()
+		let mut ret: Vec<(PathBuf, String)> = Vec::new();
+		for target in targets
+		{
+			ret.push((target.root.join("Makefile"), self.render_makefile(target)));
+			if let Some(v) = target.render_linker_script()?
+			{
+				ret.push((target.root.join(target.linker_script_name()), v));
+			}
+		}
+		if let Some(v) = self.render_igloo_header()?
 		{
-			std::fs::remove_file(src_dir.join("main.c")).unwrap();
+			ret.push((self.project_dir.join("inc/igloo.h"), v));
 		}
+		ret.push((self.project_dir.join(LOCK_FILE), self.lock().render()));
+		Ok(ret)
+	}
 
-		File::create(src_dir.join("main.c")).unwrap();
-		let mut igloo_main_c_file = OpenOptions::new()
-			.write(true)
-			.append(true)
-			.open(src_dir.join("main.c"))
-			.unwrap();
+	/// Checks that a template has board code for the targets of the project,
+	/// before anything is created
+	pub fn check_template(&self, template: &IglooTemplate) -> IglooErrType
+	{
+		for target in &self.target_bank
+		{
+			if !template.supports(target)
+			{
+				error!("template {} has no board code for {} (it supports {})",
+					   template.name, target.name, template.mcus.join(", "));
+				return TemplateNotCompatible
+			}
+		}
+		ErrNone
+	}
+
+	/// Generates the project sources from a template
+	/// Placeholders are filled in using the first target of the project
+	pub fn gen_from_template(&self, template: &IglooTemplate) -> IglooErrType
+	{
+		let target = match self.target_bank.first()
+		{
+			Some(v) => v,
+			None => return InvalidTarget,
+		};
 
-		writeln!(igloo_main_c_file, "#include \"igloo.h\"").unwrap();
-		writeln!(igloo_main_c_file, "\n\nint main()\n{{\n\treturn 0;\n}}").unwrap();
+		let mcu = match target.make_manifest.get("MCU")
+		{
+			Some(v) => v.to_string(),
+			None => String::new(),
+		};
+		let clock = match target.board.get("clock")
+		{
+			Some(v) => v.to_string(),
+			None => String::new(),
+		};
+
+		let substitutions = [
+			("PROJECT_NAME", self.name.clone()),
+			("TARGET", target.name.clone()),
+			("MCU", mcu),
+			("CLOCK", clock),
+		];
+		let res_err = template.apply(self.fs.as_ref(), &self.root, &substitutions);
+		if res_err != ErrNone
+		{
+			return res_err
+		}
+		// The Makefiles build the sources the template added
+		for target in &self.target_bank
+		{
+			let res_err = self.gen_makefile(target);
+			if res_err != ErrNone
+			{
+				return res_err
+			}
+		}
 		ErrNone
 	}
+}
 
+/// Appends entries to a Makefile list variable, one per line
+fn write_make_list(app_file: &mut String, var: &str, entries: &[String])
+{
+	if entries.is_empty()
+	{
+		return
+	}
+	write!(app_file, "\n{}", var).unwrap();
+	for entry in entries
+	{
+		writeln!(app_file, " \\").unwrap();
+		write!(app_file, "{}", entry).unwrap();
+	}
+}
+
+/// Extensions of the project sources the Makefile compiles
+const SOURCE_EXTS: [&str; 3] = ["c", "s", "S"];
+
+/// Entries of a list of the make manifest of a target, e.g. OBJS
+fn make_list(target: &IglooTarget, key: &str) -> Vec<String>
+{
+	target.make_manifest.get(key)
+		.and_then(|v| v.clone().into_array().ok())
+		.unwrap_or_default()
+		.iter().map(|v| v.to_string())
+		.collect()
+}
+
+/// Objects of the modules built for a target
+fn module_objects(target: &IglooTarget) -> Vec<String>
+{
+	target.modules.iter()
+		.flat_map(|m| m.sources.iter().filter_map(move |s| m.object(s)))
+		.collect()
+}
+
+/// Directories make creates for the module objects of a target
+fn module_sub_dirs(target: &IglooTarget) -> Vec<String>
+{
+	sub_dirs(&module_objects(target))
+}
+
+/// Directories of objects, each once
+fn sub_dirs(objects: &[String]) -> Vec<String>
+{
+	let mut ret: Vec<String> = Vec::new();
+	for object in objects
+	{
+		let dir = match object.rfind('/')
+		{
+			Some(v) => String::from(&object[..v]),
+			None => continue,
+		};
+		if !ret.contains(&dir)
+		{
+			ret.push(dir);
+		}
+	}
+	ret
+}
+
+/// Include directories of the modules built for a target. Make runs in the
+/// target directory, three levels below the project root
+fn module_includes(target: &IglooTarget) -> Vec<String>
+{
+	target.modules.iter()
+		.flat_map(|m| m.includes.iter().map(move |i|
+			format!("-I\"../../../{}\"", m.link_path().join(i).display())))
+		.collect()
+}
+
+/// Converts a name into a valid upper case C identifier
+pub fn c_ident(name: &str) -> String
+{
+	name.chars()
+		.map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+		.collect()
 }
@@ -0,0 +1,433 @@
+// Registry index
+// igloo search and igloo info look through the targets of ESF, the modules of
+// every registry (see igloo_module) and registry index files. An index is either
+// a single file listed in ~/.igloo/registries.toml or an index.toml at the root of
+// a registry directory or ESF, describing targets and modules:
+//
+// [target.nrf52840]
+// description = "nRF52840 DK"
+// vendor = "Nordic Semiconductor"
+// cores = ["cortex-m4"]
+// tags = ["ble", "usb"]
+//
+// [module.ring_buffer]
+// description = "Interrupt safe ring buffer"
+// versions = ["1.0.0", "1.2.0"]
+//
+// Targets describe themselves in [esf.info] of their target manifest (description,
+// vendor, tags). Index entries fill in what ESF and the module manifests leave out.
+// igloo targets lists the targets of the master target manifest along with the
+// tables and files they reference that do not exist.
+use igloo_base::*;
+use igloo_manifest::merge_manifest;
+
+use crate::Igloo;
+use crate::igloo_module::*;
+use crate::igloo_target::IglooTarget;
+use crate::igloo_toolchain::parse_version;
+
+use config::Config;
+use semver::Version;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Index file at the root of a registry directory or ESF
+pub const INDEX_FILE: &str = "index.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EntryKind
+{
+	Target,
+	Module,
+}
+
+impl EntryKind
+{
+	pub fn name(&self) -> &'static str
+	{
+		match self
+		{
+			EntryKind::Target => "target",
+			EntryKind::Module => "module",
+		}
+	}
+}
+
+/// A target or module listed by a registry
+#[derive(Debug, Clone, PartialEq)]
+pub struct IglooIndexEntry
+{
+	pub kind: EntryKind,
+	pub name: String,
+	pub description: String,
+	pub vendor: String,
+	pub cores: Vec<String>,
+	pub tags: Vec<String>,
+	/// module versions, oldest first. Empty for targets
+	pub versions: Vec<Version>,
+	/// where the entry was found (esf, user or a registry path)
+	pub registry: String,
+	/// further details shown by igloo info, in order
+	pub details: Vec<(String, String)>,
+}
+
+impl IglooIndexEntry
+{
+	fn new(kind: EntryKind, name: &str, registry: &str) -> IglooIndexEntry
+	{
+		IglooIndexEntry
+		{
+			kind,
+			name: String::from(name),
+			description: String::new(),
+			vendor: String::new(),
+			cores: Vec::new(),
+			tags: Vec::new(),
+			versions: Vec::new(),
+			registry: String::from(registry),
+			details: Vec::new(),
+		}
+	}
+
+	pub fn to_json(&self) -> serde_json::Value
+	{
+		json!({
+			"kind": self.kind.name(),
+			"name": self.name,
+			"description": self.description,
+			"vendor": self.vendor,
+			"cores": self.cores,
+			"tags": self.tags,
+			"versions": self.versions.iter().map(|v| v.to_string()).collect::<Vec<_>>(),
+			"registry": self.registry,
+			"details": self.details.iter().cloned().collect::<BTreeMap<_, _>>(),
+		})
+	}
+
+	/// Case insensitive match of every word of query against the name,
+	/// description, vendor, cores and tags
+	pub fn matches(&self, query: &str) -> bool
+	{
+		let text = format!("{} {} {} {} {}", self.name, self.description, self.vendor,
+						   self.cores.join(" "), self.tags.join(" ")).to_lowercase();
+		query.split_whitespace().all(|w| text.contains(&w.to_lowercase()))
+	}
+
+	/// Fills in what this entry leaves out from another entry of the same name
+	fn fill_from(&mut self, other: &IglooIndexEntry)
+	{
+		if self.description.is_empty()
+		{
+			self.description = other.description.clone();
+		}
+		if self.vendor.is_empty()
+		{
+			self.vendor = other.vendor.clone();
+		}
+		for (own, theirs) in [(&mut self.cores, &other.cores), (&mut self.tags, &other.tags)]
+		{
+			if own.is_empty()
+			{
+				*own = theirs.clone();
+			}
+		}
+	}
+}
+
+fn strings(table: &BTreeMap<String, config::Value>, key: &str) -> Vec<String>
+{
+	table.get(key)
+		.and_then(|v| v.clone().into_array().ok())
+		.unwrap_or_default()
+		.into_iter()
+		.filter_map(|v| v.into_str().ok())
+		.collect()
+}
+
+fn string(table: &BTreeMap<String, config::Value>, key: &str) -> String
+{
+	table.get(key).and_then(|v| v.clone().into_str().ok()).unwrap_or_default()
+}
+
+/// Reads the entries of an index file
+pub fn read_index(fs: &dyn IglooFs, path: &Path, registry: &str)
+				  -> Result<Vec<IglooIndexEntry>, String>
+{
+	let mut index = Config::new();
+	merge_manifest(fs, &mut index, path)?;
+	let mut ret: Vec<IglooIndexEntry> = Vec::new();
+	for kind in [EntryKind::Target, EntryKind::Module]
+	{
+		for (name, table) in index.get_table(kind.name()).unwrap_or_default()
+		{
+			let table: BTreeMap<String, config::Value> = match table.into_table()
+			{
+				Ok(v) => v.into_iter().collect(),
+				Err(e) => return Err(format!("{}.{}: {}", kind.name(), name, e)),
+			};
+			let mut entry = IglooIndexEntry::new(kind, &name, registry);
+			entry.description = string(&table, "description");
+			entry.vendor = string(&table, "vendor");
+			entry.cores = strings(&table, "cores");
+			entry.tags = strings(&table, "tags");
+			for version in strings(&table, "versions")
+			{
+				entry.versions.push(parse_version(&version)
+									.map_err(|e| format!("{}.{}: {}", kind.name(), name, e))?);
+			}
+			entry.versions.sort();
+			ret.push(entry);
+		}
+	}
+	Ok(ret)
+}
+
+/// A target of the master target manifest and the files it references
+pub struct IglooTargetListing
+{
+	pub name: String,
+	/// make manifest table, empty if target.make has no entry
+	pub make_table: String,
+	/// target manifest relative to ESF, empty if target.manifest has no entry
+	pub manifest: String,
+	pub mcu: String,
+	pub core: String,
+	/// referenced tables and files that do not exist, empty if the target is usable
+	pub problems: Vec<String>,
+	/// [esf.info] of the target manifest
+	info: BTreeMap<String, config::Value>,
+	target: Option<IglooTarget>,
+}
+
+/// Loads a target, listing what it references that does not exist instead of
+/// failing on it
+fn esf_target(inst: &Igloo, name: &str) -> IglooTargetListing
+{
+	let mut ret = IglooTargetListing
+	{
+		name: String::from(name),
+		make_table: inst.master_target_man.get_str(&format!("target.make.{}", name))
+			.unwrap_or_default(),
+		manifest: inst.master_target_man.get_str(&format!("target.manifest.{}", name))
+			.unwrap_or_default(),
+		mcu: String::new(),
+		core: String::new(),
+		problems: Vec::new(),
+		info: BTreeMap::new(),
+		target: None,
+	};
+	if ret.make_table.is_empty()
+	{
+		ret.problems.push(String::from("no target.make entry"));
+	}
+	else if inst.master_make_man.get_table(&ret.make_table).is_err()
+	{
+		ret.problems.push(format!("make table {} does not exist", ret.make_table));
+	}
+	let man_path = inst.env.esfd.join(&ret.manifest);
+	if ret.manifest.is_empty()
+	{
+		ret.problems.push(String::from("no target.manifest entry"));
+	}
+	else if !inst.fs.exists(&man_path)
+	{
+		ret.problems.push(format!("{} does not exist", man_path.display()));
+	}
+	if !ret.problems.is_empty()
+	{
+		return ret
+	}
+
+	let target = match IglooTarget::from(PathBuf::new(), inst, ret.name.clone(),
+										  &ret.make_table, &ret.manifest)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			ret.problems.push(format!("{:?}", e));
+			return ret
+		}
+	};
+	for (key, val) in &target.links
+	{
+		let path = inst.env.esfd.join(val.to_string());
+		if !inst.fs.exists(&path)
+		{
+			ret.problems.push(format!("{} of esf.links.{} does not exist", path.display(), key));
+		}
+	}
+	if let Some(scripts) = target.openocd.get("scripts")
+	{
+		let path = inst.env.esfd.join(scripts.to_string()).join(name).with_extension("cfg");
+		if !inst.fs.exists(&path)
+		{
+			ret.problems.push(format!("{} does not exist", path.display()));
+		}
+	}
+	ret.mcu = target.make_manifest.get("MCU").map(|v| v.to_string()).unwrap_or_default();
+	ret.core = target.make_manifest.get("MCPU").map(|v| v.to_string()).unwrap_or_default();
+
+	let mut target_man = Config::new();
+	let _ = merge_manifest(inst.fs.as_ref(), &mut target_man, &man_path);
+	ret.info = target_man.get_table("esf.info").unwrap_or_default().into_iter().collect();
+	ret.target = Some(target);
+	ret
+}
+
+/// Every target of the master target manifest, sorted by name
+pub fn target_listings(inst: &Igloo) -> Vec<IglooTargetListing>
+{
+	let mut names: Vec<String> = Vec::new();
+	for table in &["target.make", "target.manifest"]
+	{
+		names.extend(inst.master_target_man.get_table(table).unwrap_or_default().into_keys());
+	}
+	names.sort();
+	names.dedup();
+	names.iter().map(|name| esf_target(inst, name)).collect()
+}
+
+/// Describes the usable targets of ESF from the master manifests and [esf.info]
+fn esf_targets(targets: &[IglooTargetListing]) -> Vec<IglooIndexEntry>
+{
+	let mut ret: Vec<IglooIndexEntry> = Vec::new();
+	for listing in targets
+	{
+		let target = match &listing.target
+		{
+			Some(v) => v,
+			None => continue,
+		};
+		let mut entry = IglooIndexEntry::new(EntryKind::Target, &listing.name, "esf");
+		entry.description = string(&listing.info, "description");
+		entry.vendor = string(&listing.info, "vendor");
+		if !listing.core.is_empty()
+		{
+			entry.cores = vec![listing.core.clone()];
+		}
+		entry.tags = strings(&listing.info, "tags");
+		entry.details.push((String::from("make table"), listing.make_table.clone()));
+		if !listing.mcu.is_empty()
+		{
+			entry.details.push((String::from("MCU"), listing.mcu.clone()));
+		}
+		for (key, val) in &target.board
+		{
+			entry.details.push((format!("board {}", key), val.to_string()));
+		}
+		for region in &target.memory
+		{
+			entry.details.push((format!("memory {}", region.name),
+								format!("0x{:08x}, {} bytes", region.origin, region.length)));
+		}
+		if let Some(v) = &target.toolchain_req
+		{
+			entry.details.push((String::from("toolchain"), v.to_string()));
+		}
+		ret.push(entry);
+	}
+	ret
+}
+
+/// Describes the modules of every registry, one entry per module
+fn module_entries(inst: &Igloo) -> Vec<IglooIndexEntry>
+{
+	let mut ret: Vec<IglooIndexEntry> = Vec::new();
+	// sorted by name and version, so the newest version of a module comes last
+	for module in available_modules(inst.fs.as_ref(), &inst.env)
+	{
+		if ret.last().map(|e| e.name != module.name).unwrap_or(true)
+		{
+			ret.push(IglooIndexEntry::new(EntryKind::Module, &module.name, &module.registry));
+		}
+		let entry = ret.last_mut().unwrap();
+		entry.versions.push(module.version.clone());
+		entry.registry = module.registry.clone();
+		entry.description = module.description.clone();
+		entry.vendor = module.vendor.clone();
+		entry.cores = module.cores.clone();
+		entry.tags = module.tags.clone();
+		let mut details: Vec<(String, String)> = Vec::new();
+		let list = |key: &str, values: &[String], details: &mut Vec<(String, String)>|
+		{
+			if !values.is_empty()
+			{
+				details.push((String::from(key), values.join(", ")));
+			}
+		};
+		list("mcus", &module.mcus, &mut details);
+		list("sources", &module.sources, &mut details);
+		list("headers", &module.headers, &mut details);
+		list("defines", &module.defines, &mut details);
+		let deps: Vec<String> = module.dependencies.iter()
+			.map(|(name, req)| format!("{} {}", name, req))
+			.collect();
+		list("dependencies", &deps, &mut details);
+		details.push((String::from("path"), module.root.display().to_string()));
+		entry.details = details;
+	}
+	ret
+}
+
+/// Every target and module igloo knows of, sorted by kind and name
+pub fn index_entries(inst: &Igloo) -> Vec<IglooIndexEntry>
+{
+	index_entries_of(inst, &target_listings(inst))
+}
+
+/// index_entries for the already loaded targets of ESF
+pub fn index_entries_of(inst: &Igloo, targets: &[IglooTargetListing]) -> Vec<IglooIndexEntry>
+{
+	let mut ret = esf_targets(targets);
+	ret.extend(module_entries(inst));
+
+	let mut index_files: Vec<(String, PathBuf)> = Vec::new();
+	for (registry, path) in registries(inst.fs.as_ref(), &inst.env)
+	{
+		if inst.fs.is_dir(&path)
+		{
+			index_files.push((registry, path.join(INDEX_FILE)));
+		}
+		else
+		{
+			index_files.push((registry, path));
+		}
+	}
+	index_files.push((String::from("esf"), inst.env.esfd.join(INDEX_FILE)));
+
+	for (registry, path) in index_files
+	{
+		if !inst.fs.exists(&path) || inst.fs.is_dir(&path)
+		{
+			continue
+		}
+		let listed = match read_index(inst.fs.as_ref(), &path, &registry)
+		{
+			Ok(v) => v,
+			Err(e) =>
+			{
+				warn!("skipping {}: {}", path.display(), e);
+				continue
+			}
+		};
+		for entry in listed
+		{
+			match ret.iter_mut().find(|e| e.kind == entry.kind && e.name == entry.name)
+			{
+				Some(v) => v.fill_from(&entry),
+				None => ret.push(entry),
+			}
+		}
+	}
+	// targets neither describe fall back to their board name
+	for entry in ret.iter_mut().filter(|e| e.description.is_empty())
+	{
+		if let Some((_key, name)) = entry.details.iter().find(|(k, _v)| k == "board name")
+		{
+			entry.description = name.clone();
+		}
+	}
+	ret.sort_by(|a, b| a.kind.cmp(&b.kind).then(a.name.cmp(&b.name)));
+	ret
+}
@@ -0,0 +1,284 @@
+// igloo retarget
+// Replaces a target of a project with another MCU. The project file keeps its
+// settings for the target: [target.<old>.*] tables and the default target are
+// renamed to the new target, except memory carve-outs of regions the new MCU does
+// not have. The migration report lists what the old MCU provided that the new one
+// does not (ESF links, includes, defines, memory regions, section placements,
+// image options, peripherals and modules), the peripherals only the new MCU has,
+// and where the project sources still refer to the old one.
+use igloo_base::*;
+
+use crate::igloo_target::IglooTarget;
+use crate::igloo_project::c_ident;
+
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// What the old target provided that the new one does not
+#[derive(Debug, Default)]
+pub struct RetargetReport
+{
+	/// ESF links, as name (location in ESF)
+	pub links: Vec<String>,
+	/// headers of IGLOO_INCLUDES
+	pub includes: Vec<String>,
+	/// MCU and -D defines of the make manifest, board and target macros of igloo.h
+	pub defines: Vec<String>,
+	pub memory: Vec<String>,
+	/// section placements, as section -> region
+	pub sections: Vec<String>,
+	pub image: Vec<String>,
+	/// peripherals of esf.peripherals
+	pub peripherals: Vec<String>,
+	/// peripherals the new target has and the old one does not
+	pub added_peripherals: Vec<String>,
+	/// modules the new target does not build
+	pub modules: Vec<String>,
+	/// project memory carve-outs dropped from the project file
+	pub carve_outs: Vec<String>,
+	/// project sources still using includes or defines, as path: name
+	pub references: Vec<String>,
+}
+
+/// Entries of old that are not in new, in order
+fn lost(old: Vec<String>, new: Vec<String>) -> Vec<String>
+{
+	old.into_iter().filter(|v| !new.contains(v)).collect()
+}
+
+fn defines(target: &IglooTarget) -> Vec<String>
+{
+	let mut ret: BTreeSet<String> = BTreeSet::new();
+	if let Some(v) = target.make_manifest.get("MCU")
+	{
+		ret.insert(v.to_string());
+	}
+	let cflags = target.make_manifest.get("CFLAGS")
+		.and_then(|v| v.clone().into_array().ok())
+		.unwrap_or_default();
+	for flag in cflags
+	{
+		let flag = flag.to_string();
+		if let Some(define) = flag.trim().strip_prefix("-D")
+		{
+			ret.insert(String::from(define.split('=').next().unwrap_or_default()));
+		}
+	}
+	for key in target.board.keys()
+	{
+		ret.insert(format!("IGLOO_BOARD_{}", c_ident(key)));
+	}
+	ret.insert(target.define_name());
+	ret.into_iter().collect()
+}
+
+/// Compares what old and new provide. Modules and carve-outs are left to the caller
+pub fn compare_targets(old: &IglooTarget, new: &IglooTarget) -> RetargetReport
+{
+	let links = |t: &IglooTarget| -> Vec<String>
+	{
+		t.links.iter().map(|(k, v)| format!("{} ({})", k, v)).collect()
+	};
+	let includes = |t: &IglooTarget| -> Vec<String>
+	{
+		t.includes.iter().map(|v| v.to_string()).collect()
+	};
+	let memory = |t: &IglooTarget| -> Vec<String>
+	{
+		t.memory.iter().map(|r| r.name.clone()).collect()
+	};
+	let sections = |t: &IglooTarget| -> Vec<String>
+	{
+		t.sections.iter().map(|(s, r)| format!("{} -> {}", s, r)).collect()
+	};
+	RetargetReport
+	{
+		links: lost(links(old), links(new)),
+		includes: lost(includes(old), includes(new)),
+		defines: lost(defines(old), defines(new)),
+		memory: lost(memory(old), memory(new)),
+		sections: lost(sections(old), sections(new)),
+		image: lost(old.image.keys().cloned().collect(), new.image.keys().cloned().collect()),
+		peripherals: lost(old.peripherals.clone(), new.peripherals.clone()),
+		added_peripherals: lost(new.peripherals.clone(), old.peripherals.clone()),
+		..RetargetReport::default()
+	}
+}
+
+/// Table header of a project file line, e.g. target.samd21j18a.memory.flash
+fn table_header(line: &str) -> Option<&str>
+{
+	let line = line.trim();
+	if line.starts_with("[[") || !line.starts_with('[')
+	{
+		return None
+	}
+	line.strip_prefix('[')?.split(']').next().map(|v| v.trim())
+}
+
+/// Rewrites the project file for the target old being replaced by new. regions
+/// are the memory regions of new; carve-outs of other regions are dropped and
+/// returned
+pub fn retarget_project_file(text: &str, old: &str, new: &str, regions: &[String])
+							 -> (String, Vec<String>)
+{
+	let old_table = format!("target.{}", old);
+	let old_memory = format!("target.{}.memory", old);
+	let mut ret = String::new();
+	let mut dropped: Vec<String> = Vec::new();
+	let mut table = String::new();
+	let mut skipping = false;
+	for line in text.lines()
+	{
+		if let Some(header) = table_header(line)
+		{
+			table = String::from(header);
+			skipping = false;
+			if let Some(region) = header.strip_prefix(&old_memory).and_then(|r| r.strip_prefix('.'))
+			{
+				if !regions.iter().any(|r| r == region)
+				{
+					dropped.push(String::from(region));
+					skipping = true;
+					continue
+				}
+			}
+			if header == old_table || header.starts_with(&format!("{}.", old_table))
+			{
+				ret.push_str(&line.replacen(&old_table, &format!("target.{}", new), 1));
+				ret.push('\n');
+				continue
+			}
+		}
+		else if table == "project" && (line.trim_start().starts_with("targets")
+										|| line.trim_start().starts_with("default_target"))
+		{
+			ret.push_str(&line.replace(&format!("\"{}\"", old), &format!("\"{}\"", new)));
+			ret.push('\n');
+			continue
+		}
+		else if table == old_memory
+		{
+			// region = { offset = "16K" } form
+			let region = line.split('=').next().unwrap_or_default().trim();
+			if !region.is_empty() && !region.starts_with('#')
+				&& !regions.iter().any(|r| r == region)
+			{
+				dropped.push(String::from(region));
+				continue
+			}
+		}
+		if !skipping
+		{
+			ret.push_str(line);
+			ret.push('\n');
+		}
+	}
+	(ret, dropped)
+}
+
+/// Finds the C and assembly files under dirs using any of names. Generated
+/// igloo.h is skipped, paths are given relative to root
+pub fn find_references(fs: &dyn IglooFs, root: &Path, dirs: &[PathBuf], names: &[String])
+					   -> Vec<String>
+{
+	let exts = ["c", "h", "cpp", "hpp", "cc", "s", "S"];
+	let mut ret: Vec<String> = Vec::new();
+	let mut pending: Vec<PathBuf> = dirs.to_vec();
+	while let Some(dir) = pending.pop()
+	{
+		let mut entries = fs.read_dir(&dir).unwrap_or_default();
+		entries.sort();
+		for path in entries
+		{
+			if fs.is_dir(&path)
+			{
+				pending.push(path);
+				continue
+			}
+			let is_source = path.extension().map(|e| exts.iter().any(|x| e == *x)).unwrap_or(false);
+			if !is_source || path.file_name().map(|n| n == "igloo.h").unwrap_or(false)
+			{
+				continue
+			}
+			let text = match fs.read_to_string(&path)
+			{
+				Ok(v) => v,
+				Err(_e) => continue,
+			};
+			for name in names.iter().filter(|n| text.contains(n.as_str()))
+			{
+				ret.push(format!("{}: {}", path.strip_prefix(root).unwrap_or(&path).display(), name));
+			}
+		}
+	}
+	ret.sort();
+	ret
+}
+
+impl RetargetReport
+{
+	pub fn to_json(&self, old: &str, new: &str) -> serde_json::Value
+	{
+		json!({
+			"old": old,
+			"new": new,
+			"links": self.links,
+			"includes": self.includes,
+			"defines": self.defines,
+			"memory": self.memory,
+			"sections": self.sections,
+			"image": self.image,
+			"peripherals": self.peripherals,
+			"added_peripherals": self.added_peripherals,
+			"modules": self.modules,
+			"carve_outs": self.carve_outs,
+			"references": self.references,
+		})
+	}
+
+	pub fn print(&self, old: &str, new: &str)
+	{
+		let groups = [
+			("ESF link", &self.links),
+			("include", &self.includes),
+			("define", &self.defines),
+			("memory", &self.memory),
+			("section", &self.sections),
+			("image", &self.image),
+			("peripheral", &self.peripherals),
+			("module", &self.modules),
+			("carve-out", &self.carve_outs),
+		];
+		if !self.added_peripherals.is_empty()
+		{
+			say!("New on {}:", new);
+			for peripheral in &self.added_peripherals
+			{
+				say!("  {:<10} {}", "peripheral", peripheral);
+			}
+		}
+		if groups.iter().all(|(_kind, entries)| entries.is_empty())
+		{
+			say!("Everything {} provided is available on {}", old, new);
+			return
+		}
+		say!("Not available on {}:", new);
+		for (kind, entries) in groups.iter()
+		{
+			for entry in entries.iter()
+			{
+				say!("  {:<10} {}", kind, entry);
+			}
+		}
+		if !self.references.is_empty()
+		{
+			say!("Still used by the project:");
+			for reference in &self.references
+			{
+				say!("  {}", reference);
+			}
+		}
+	}
+}
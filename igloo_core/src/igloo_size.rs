@@ -0,0 +1,334 @@
+use igloo_base::*;
+use igloo_base::IglooErrType::*;
+
+use crate::igloo_elf::*;
+use crate::igloo_target::IglooTarget;
+
+use serde_json::json;
+use std::path::{Path, PathBuf};
+
+/// Report of the last size run, kept in the target directory
+pub const SIZE_REPORT: &str = "size.json";
+/// Report of the build before the last one. Deltas are computed against it
+pub const SIZE_REPORT_PREV: &str = "size.prev.json";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegionUsage
+{
+	pub name: String,
+	pub used: u64,
+	/// None if the target has no memory map
+	pub capacity: Option<u64>,
+	/// used minus the used bytes of the previous build
+	pub delta: Option<i64>,
+}
+
+impl RegionUsage
+{
+	pub fn percent(&self) -> Option<f64>
+	{
+		match self.capacity
+		{
+			Some(0) | None => None,
+			Some(v) => Some(self.used as f64 * 100.0 / v as f64),
+		}
+	}
+
+	pub fn overflows(&self) -> bool
+	{
+		match self.capacity
+		{
+			Some(v) => self.used > v,
+			None => false,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SectionSize
+{
+	pub name: String,
+	pub addr: u64,
+	pub size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymbolSize
+{
+	pub name: String,
+	pub size: u64,
+	pub section: String,
+}
+
+/// Memory usage of a built target
+#[derive(Debug, Clone, PartialEq)]
+pub struct IglooSizeReport
+{
+	pub target: String,
+	pub elf: PathBuf,
+	/// CRC-32 of the ELF, identifies the build
+	pub elf_crc: u32,
+	pub regions: Vec<RegionUsage>,
+	pub sections: Vec<SectionSize>,
+	/// largest symbols first
+	pub symbols: Vec<SymbolSize>,
+}
+
+impl IglooSizeReport
+{
+	/// Computes the memory usage of elf for target
+	/// Each allocated section is counted in the memory region containing its address.
+	/// Initialized data (.data) is also counted in the first read only region since
+	/// its initial values are stored there. Targets without a memory map get a flash
+	/// (code + initialized data) and ram (data + bss) total without capacity.
+	pub fn from_elf(target: &IglooTarget, elf_path: &Path, elf: &IglooElf, top: usize)
+					-> IglooSizeReport
+	{
+		let mut regions: Vec<RegionUsage> = Vec::new();
+		if target.memory.is_empty()
+		{
+			for name in &["flash", "ram"]
+			{
+				regions.push(RegionUsage
+				{
+					name: String::from(*name),
+					used: 0,
+					capacity: None,
+					delta: None,
+				});
+			}
+		}
+		else
+		{
+			for region in &target.memory
+			{
+				regions.push(RegionUsage
+				{
+					name: region.name.clone(),
+					used: 0,
+					capacity: Some(region.usable_length()),
+					delta: None,
+				});
+			}
+		}
+		let load_region = target.memory.iter().position(|r| r.is_rom());
+
+		let mut sections: Vec<SectionSize> = Vec::new();
+		for section in &elf.sections
+		{
+			if !section.is_alloc() || section.size == 0
+			{
+				continue
+			}
+			sections.push(SectionSize
+			{
+				name: section.name.clone(),
+				addr: section.addr,
+				size: section.size,
+			});
+
+			if target.memory.is_empty()
+			{
+				if !section.is_nobits()
+				{
+					regions[0].used += section.size;
+				}
+				if section.is_write()
+				{
+					regions[1].used += section.size;
+				}
+				continue
+			}
+
+			let region = target.memory.iter().position(|r|
+				section.addr >= r.origin && section.addr < r.origin + r.length);
+			match region
+			{
+				Some(i) =>
+				{
+					regions[i].used += section.size;
+					if !target.memory[i].is_rom() && !section.is_nobits()
+					{
+						if let Some(l) = load_region
+						{
+							regions[l].used += section.size;
+						}
+					}
+				}
+				None => warn!("section {} at 0x{:08x} is outside of every \
+								  memory region", section.name, section.addr),
+			}
+		}
+
+		let mut symbols: Vec<SymbolSize> = elf.symbols.iter()
+			.filter(|s| s.size > 0 && (s.kind == STT_FUNC || s.kind == STT_OBJECT))
+			.map(|s| SymbolSize
+			{
+				name: s.name.clone(),
+				size: s.size,
+				section: s.section.clone().unwrap_or_default(),
+			})
+			.collect();
+		symbols.sort_by(|a, b| b.size.cmp(&a.size).then(a.name.cmp(&b.name)));
+		symbols.truncate(top);
+
+		IglooSizeReport
+		{
+			target: target.name.clone(),
+			elf: elf_path.to_path_buf(),
+			elf_crc: crc32(&elf.data),
+			regions,
+			sections,
+			symbols,
+		}
+	}
+
+	/// Fills in the region deltas from the report of a previous build
+	pub fn compare(&mut self, prev: &serde_json::Value)
+	{
+		for region in &mut self.regions
+		{
+			let prev_used = prev["regions"].as_array()
+				.and_then(|v| v.iter().find(|r| r["name"] == region.name.as_str()))
+				.and_then(|r| r["used"].as_u64());
+			region.delta = prev_used.map(|v| region.used as i64 - v as i64);
+		}
+	}
+
+	pub fn to_json(&self) -> serde_json::Value
+	{
+		json!({
+			"target": self.target,
+			"elf": self.elf.display().to_string(),
+			"elf_crc": self.elf_crc,
+			"regions": self.regions.iter().map(|r| json!({
+				"name": r.name,
+				"used": r.used,
+				"capacity": r.capacity,
+				"percent": r.percent().map(|v| (v * 100.0).round() / 100.0),
+				"delta": r.delta,
+			})).collect::<Vec<_>>(),
+			"sections": self.sections.iter().map(|s| json!({
+				"name": s.name,
+				"addr": s.addr,
+				"size": s.size,
+			})).collect::<Vec<_>>(),
+			"symbols": self.symbols.iter().map(|s| json!({
+				"name": s.name,
+				"size": s.size,
+				"section": s.section,
+			})).collect::<Vec<_>>(),
+		})
+	}
+
+	/// Prints the report as tables
+	/// One line usage of every region, e.g. flash 1056/253952 (0.42%)
+	pub fn usage(&self) -> String
+	{
+		self.regions.iter().map(|r| match (r.capacity, r.percent())
+		{
+			(Some(c), Some(p)) => format!("{} {}/{} ({:.2}%)", r.name, r.used, c, p),
+			_ => format!("{} {}", r.name, r.used),
+		}).collect::<Vec<_>>().join(", ")
+	}
+
+	pub fn print(&self)
+	{
+		say!("Target {} ({})", self.target, self.elf.display());
+		say!("{:<12} {:>10} {:>10} {:>8} {:>10}", "Region", "Used", "Size", "Use%", "Delta");
+		for region in &self.regions
+		{
+			let capacity = match region.capacity
+			{
+				Some(v) => v.to_string(),
+				None => String::from("-"),
+			};
+			let percent = match region.percent()
+			{
+				Some(v) => format!("{:.2}%", v),
+				None => String::from("-"),
+			};
+			let delta = match region.delta
+			{
+				Some(v) => format!("{:+}", v),
+				None => String::from("-"),
+			};
+			say!("{:<12} {:>10} {:>10} {:>8} {:>10}",
+					 region.name, region.used, capacity, percent, delta);
+		}
+
+		say!("\n{:<20} {:>10} {:>10}", "Section", "Address", "Size");
+		for section in &self.sections
+		{
+			say!("{:<20} 0x{:08x} {:>10}", section.name, section.addr, section.size);
+		}
+
+		if !self.symbols.is_empty()
+		{
+			say!("\n{:>10}  {:<12} {}", "Size", "Section", "Symbol");
+			for symbol in &self.symbols
+			{
+				say!("{:>10}  {:<12} {}", symbol.size, symbol.section, symbol.name);
+			}
+		}
+	}
+}
+
+/// Builds the size report of a target and updates the stored reports
+/// The report of the previous build is rotated to size.prev.json whenever the
+/// ELF changed, so running size twice on the same build keeps the same delta.
+pub fn size_report(fs: &dyn IglooFs, target: &IglooTarget, elf_path: &Path, top: usize)
+				   -> Result<IglooSizeReport, IglooErrType>
+{
+	let elf = match fs.read(elf_path)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			error!("{}: {}\nBuild the target first", elf_path.display(), e);
+			return Err(FailedToReadElf)
+		}
+	};
+	let elf = match IglooElf::parse(elf)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			error!("{}: {}", elf_path.display(), e);
+			return Err(FailedToReadElf)
+		}
+	};
+	let mut report = IglooSizeReport::from_elf(target, elf_path, &elf, top);
+
+	let report_path = target.root.join(SIZE_REPORT);
+	let prev_path = target.root.join(SIZE_REPORT_PREV);
+	let last = read_report(fs, &report_path);
+	if let Some(last) = &last
+	{
+		if last["elf_crc"].as_u64() != Some(report.elf_crc as u64)
+		{
+			match fs.write(&prev_path, last.to_string().as_bytes())
+			{
+				Err(e) => error!("{:?}", e),
+				_ => (),
+			}
+		}
+	}
+	if let Some(prev) = read_report(fs, &prev_path)
+	{
+		report.compare(&prev);
+	}
+
+	match fs.write(&report_path, report.to_json().to_string().as_bytes())
+	{
+		Err(e) => error!("{:?}", e),
+		_ => (),
+	}
+	Ok(report)
+}
+
+fn read_report(fs: &dyn IglooFs, path: &Path) -> Option<serde_json::Value>
+{
+	let contents = fs.read_to_string(path).ok()?;
+	serde_json::from_str(&contents).ok()
+}
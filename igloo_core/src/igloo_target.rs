@@ -2,24 +2,54 @@ use igloo_base::*;
 use igloo_base::IglooErrType::*;
 
 use crate::IglooPrj;
+use crate::igloo_project::c_ident;
 use crate::Igloo;
+use crate::igloo_linker::*;
+use crate::igloo_module::IglooModule;
+use crate::igloo_toolchain::*;
 
 use crate::config::Config;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::path::PathBuf;
-use std::fs::OpenOptions;
-use std::fs::File;
-use std::io::prelude::*;
+use igloo_manifest::merge_manifest;
+use std::rc::Rc;
 
 pub struct IglooTarget
 {
 	// name, links, and includes are extracted from a manifest
 	pub name: String,
-	pub links: HashMap<String, config::Value>,
+	/// table of the target in the master make manifest, e.g. arm.cortex-m0.samd21.samd21j18a
+	pub make_table: String,
+	/// SHA-256 of the make manifest entries and the target manifest of the target,
+	/// recorded in igloo.lock
+	pub manifest_hash: String,
+	// Manifest tables are kept sorted so everything generated from them is stable
+	pub links: BTreeMap<String, config::Value>,
 	pub includes: Vec<config::Value>,
-	pub openocd: HashMap<String, config::Value>,
-	pub make_manifest: HashMap<String, config::Value>,
+	pub openocd: BTreeMap<String, config::Value>,
+	/// optional board information (clock, package, etc.) from esf.board
+	pub board: BTreeMap<String, config::Value>,
+	pub make_manifest: BTreeMap<String, config::Value>,
+	/// memory regions from esf.memory, sorted by origin. Empty if the target
+	/// uses the prebuilt linker script given by LD_PATH/LD_SCRIPT
+	pub memory: Vec<IglooMemRegion>,
+	/// section placement from esf.sections (section -> memory region)
+	pub sections: BTreeMap<String, String>,
+	pub stack_size: Option<u64>,
+	/// image options from esf.image (uf2_family, fill)
+	pub image: BTreeMap<String, config::Value>,
+	/// peripherals of the MCU from esf.peripherals, e.g. ADC, SERCOM0
+	pub peripherals: Vec<String>,
+	/// toolchain required by esf.toolchain, None if the toolchain on PATH is used
+	pub toolchain_req: Option<ToolchainReq>,
+	/// toolchain the target builds with, set by the project from its lockfile
+	/// and the installed toolchains
+	pub toolchain: Option<ToolchainPin>,
+	/// project modules built for this target
+	pub modules: Vec<IglooModule>,
 	pub root: PathBuf,
+	esfd: PathBuf,
+	fs: Rc<dyn IglooFs>,
 }
 
 impl IglooTarget
@@ -29,11 +59,24 @@ impl IglooTarget
 		IglooTarget
 		{
 			name: String::from(""),
+			make_table: String::default(),
+			manifest_hash: String::default(),
 			root: PathBuf::default(),
-			make_manifest: HashMap::default(),
-			links: HashMap::default(),
+			make_manifest: BTreeMap::default(),
+			links: BTreeMap::default(),
 			includes: Vec::default(),
-			openocd: HashMap::default(),
+			openocd: BTreeMap::default(),
+			board: BTreeMap::default(),
+			memory: Vec::default(),
+			sections: BTreeMap::default(),
+			stack_size: None,
+			image: BTreeMap::default(),
+			peripherals: Vec::default(),
+			toolchain_req: None,
+			toolchain: None,
+			modules: Vec::default(),
+			esfd: PathBuf::default(),
+			fs: Rc::new(RealFs),
 		}
 	}
 
@@ -43,14 +86,19 @@ impl IglooTarget
 	{
 		// target man first
 		let mut target_man = Config::new();
-		target_man.merge(
-			config::File::with_name(
-				IglooEnvInfo::get_env_info().esfd.join(target_man_loc)
-					.to_str().unwrap()))
-			.unwrap();
+		match merge_manifest(inst.fs.as_ref(), &mut target_man,
+							 &inst.env.esfd.join(target_man_loc))
+		{
+			Ok(_v) => (),
+			Err(e) =>
+			{
+				error!("{:?}", e);
+				return Err(InvalidTarget)
+			}
+		}
 
 		// now make man
-		let mut makefile: HashMap<String, config::Value> = HashMap::new();
+		let mut makefile: BTreeMap<String, config::Value> = BTreeMap::new();
 		let mut make_table_head = &target_make_loc[0..target_make_loc.len()];
 		let mut b_quit: bool = false;
 		loop
@@ -88,11 +136,65 @@ impl IglooTarget
 			}
 		}
 
+		// memory map is optional
+		let memory = match target_man.get_table("esf.memory")
+		{
+			Ok(v) => memory_map_from(&v.into_iter().collect())?,
+			Err(_e) => Vec::new(),
+		};
+		let mut sections: BTreeMap<String, String> = BTreeMap::new();
+		for (section, region) in target_man.get_table("esf.sections").unwrap_or_default()
+		{
+			sections.insert(section, region.to_string());
+		}
+		let stack_size = match target_man.get("esf.stack_size")
+		{
+			Ok(v) => match parse_size(&v)
+			{
+				Ok(v) => Some(v),
+				Err(e) =>
+				{
+					error!("esf.stack_size: {}", e);
+					return Err(InvalidMemoryMap)
+				}
+			},
+			Err(_e) => None,
+		};
+		let toolchain_req = match target_man.get_table("esf.toolchain")
+		{
+			Ok(v) => match ToolchainReq::from_table(&v.into_iter().collect())
+			{
+				Ok(v) => Some(v),
+				Err(e) =>
+				{
+					error!("esf.toolchain of {}: {}", name_in, e);
+					return Err(InvalidToolchain)
+				}
+			},
+			Err(_e) => None,
+		};
+
+		let manifest_hash = manifest_hash(inst, &makefile, target_man_loc);
 		Ok(IglooTarget
 		{
 			name: String::from(name_in),
+			make_table: String::from(target_make_loc),
+			manifest_hash,
+			memory,
+			sections,
+			stack_size,
+			image: target_man.get_table("esf.image")
+				.unwrap_or_default()
+				.into_iter().collect(),
+			peripherals: target_man.get_array("esf.peripherals")
+				.unwrap_or_default()
+				.into_iter().map(|v| v.to_string()).collect(),
+			toolchain_req,
+			toolchain: None,
+			modules: Vec::new(),
 			make_manifest: makefile,
-			links: target_man.get_table("esf.links").unwrap(),
+			links: target_man.get_table("esf.links").unwrap()
+				.into_iter().collect(),
 			includes: target_man.get_table("esf.includes")
 				.unwrap()
 				.get("IGLOO_INCLUDES")
@@ -101,25 +203,61 @@ impl IglooTarget
 				.into_array()
 				.unwrap(),
 			openocd: target_man.get_table("esf.openocd")
-				.unwrap(),
+				.unwrap()
+				.into_iter().collect(),
+			board: target_man.get_table("esf.board")
+				.unwrap_or_default()
+				.into_iter().collect(),
 			root: root,
+			esfd: inst.env.esfd.clone(),
+			fs: inst.fs.clone(),
 		})
 	}
 
+	/// Builds the target with an installed toolchain: the toolchain programs of
+	/// the make manifest are replaced by the binaries of the toolchain
+	pub fn use_toolchain(&mut self, pin: ToolchainPin)
+	{
+		if let Some(bin) = pin.dir.as_ref().map(|d| d.join("bin"))
+		{
+			for var in &["CC", "CXX", "AS", "OBJCOPY", "OBJDUMP", "SIZE", "GDB"]
+			{
+				let program = match self.make_manifest.get(*var)
+				{
+					Some(v) => v.to_string(),
+					None => continue,
+				};
+				let path = bin.join(program.trim());
+				if self.fs.exists(&path)
+				{
+					self.make_manifest.insert(String::from(*var),
+											  config::Value::from(path.display().to_string()));
+				}
+			}
+			if let Some(prefix) = self.make_manifest.get("TOOLCHAIN").map(|v| v.to_string())
+			{
+				self.make_manifest.insert(
+					String::from("TOOLCHAIN"),
+					config::Value::from(bin.join(prefix.trim()).display().to_string()));
+			}
+		}
+		self.toolchain = Some(pin);
+	}
+
 	/// generate all folders needed for the target
 	pub fn generate(&self) -> IglooErrType
 	{
 		// Create target root directory
-		match std::fs::create_dir(&self.root)
+		match self.fs.create_dir(&self.root)
 		{
-			Err(e) => println!("{:?}", e),
+			Err(e) => error!("{:?}", e),
 			_ => (),
 		}
 
 		// Create target scripts directory
-		match std::fs::create_dir(&self.root.join("scripts"))
+		match self.fs.create_dir(&self.root.join("scripts"))
 		{
-			Err(e) => println!("{:?}", e),
+			Err(e) => error!("{:?}", e),
 			_ => (),
 		}
 
@@ -132,11 +270,7 @@ impl IglooTarget
 		let mut target_scripts_dir: PathBuf = PathBuf::from(
 			self.root.join("scripts"));
 		// Read the gdb scripts directory in ESF
-		let esf_target_scripts_dir = std::fs::read_dir(
-			&(String::from(
-				IglooEnvInfo::get_env_info()
-					.esfd.to_str()
-					.unwrap()) + "/scripts"))
+		let esf_target_scripts_dir = self.fs.read_dir(&self.esfd.join("scripts"))
 			.unwrap();
 
 		// Creating a vector to hold our gdb script file names
@@ -146,19 +280,22 @@ impl IglooTarget
 		// Grab the files only
 		for entry in esf_target_scripts_dir
 		{
-			match &entry
+			if !self.fs.is_dir(&entry)
 			{
-				Ok(v) => if !v.path().is_dir() {
-					gdb_scripts.push(v.path()) },
-				Err(e) => println!("{:?}", e),
+				gdb_scripts.push(entry)
 			}
 		}
 
 		// Generate each GDB script
 		for file in gdb_scripts
 		{
-			std::os::unix::fs::symlink(
-				&file, &target_scripts_dir.join(&file.file_name().unwrap())).unwrap();
+			let script_link = target_scripts_dir.join(&file.file_name().unwrap());
+			if self.fs.symlink_exists(&script_link)
+			{
+				say!("Skipping {}, already exists", script_link.display());
+				continue
+			}
+			self.fs.symlink(&file, &script_link).unwrap();
 		}
 
 		// Populate the project ESF folder with our targets relevant files
@@ -166,19 +303,186 @@ impl IglooTarget
 			.parent().unwrap()
 			.parent().unwrap()
 			.parent().unwrap().join("ESF");
-		println!("PRINTING {:?}", prj_esf_dir);
+		debug!("Linking ESF of {} into {}", self.name, prj_esf_dir.display());
 		for (sym_dir, loc_in_esf) in &self.links
 		{
-			let link_to_dir = IglooEnvInfo::get_env_info()
-				.esfd
+			let link_to_dir = self.esfd
 				.join(&loc_in_esf.clone().into_str().unwrap());
-			std::os::unix::fs::symlink(link_to_dir, prj_esf_dir.join(sym_dir)).unwrap();
+			if self.fs.symlink_exists(&prj_esf_dir.join(sym_dir))
+			{
+				say!("Skipping {}, already exists", prj_esf_dir.join(sym_dir).display());
+				continue
+			}
+			self.fs.symlink(&link_to_dir, &prj_esf_dir.join(sym_dir)).unwrap();
+		}
+
+
+		ErrNone
+	}
+
+	/// Returns the IGLOO_TARGET_<NAME> define passed to the compiler
+	/// when building this target
+	pub fn define_name(&self) -> String
+	{
+		format!("IGLOO_TARGET_{}", c_ident(&self.name))
+	}
+
+	/// Returns the include directories listed in the DIR_INCLUDES
+	/// entry of the make manifest. Relative paths are resolved from the target root
+	/// since that is where make is run from.
+	pub fn include_dirs(&self) -> Vec<PathBuf>
+	{
+		let mut ret: Vec<PathBuf> = Vec::new();
+		let dir_includes = match self.make_manifest.get("DIR_INCLUDES")
+		{
+			None => return ret,
+			Some(v) => v.clone().into_array().unwrap_or_default(),
+		};
+
+		for inc in dir_includes
+		{
+			let inc = inc.to_string();
+			let inc = inc.trim()
+				.trim_start_matches("-I")
+				.trim_matches('"')
+				.trim();
+			if inc.is_empty()
+			{
+				continue
+			}
+			ret.push(self.root.join(inc));
+		}
+		ret
+	}
+
+	/// Removes build outputs from the target directory
+	/// Object files, dependency files and firmware images are removed along with
+	/// the SUB_DIRS make creates to hold them. If all is set, generated build files
+	/// (Makefile, Doxyfile, linker script) are removed as well. They are regenerated by igloo.
+	pub fn clean(&self, all: bool) -> IglooErrType
+	{
+		let build_exts = ["o", "d", "elf", "bin", "hex", "eep", "lss", "map", "srec", "uf2"];
+
+		match self.make_manifest.get("SUB_DIRS")
+		{
+			None => (),
+			Some(v) =>
+			{
+				for sub_dir in v.clone().into_array().unwrap_or_default()
+				{
+					let sub_dir = sub_dir.to_string();
+					if sub_dir.trim().is_empty()
+					{
+						continue
+					}
+					let sub_dir_path = self.root.join(sub_dir.trim());
+					if self.fs.is_dir(&sub_dir_path)
+					{
+						match self.fs.remove_dir_all(&sub_dir_path)
+						{
+							Err(e) => error!("{:?}", e),
+							_ => say!("Removed {}", sub_dir_path.display()),
+						}
+					}
+				}
+			}
 		}
 
+		let target_dir = match self.fs.read_dir(&self.root)
+		{
+			Ok(v) => v,
+			Err(e) =>
+			{
+				error!("{:?}", e);
+				return ErrNone
+			}
+		};
+
+		for path in target_dir
+		{
+			if self.fs.is_dir(&path)
+			{
+				continue
+			}
+
+			let is_build_output = match path.extension()
+			{
+				Some(ext) => build_exts.iter().any(|b| ext == *b),
+				None => false,
+			};
+			let is_build_file = match path.file_name()
+			{
+				Some(name) => name == "Makefile" || name == "Doxyfile"
+					|| *name == *self.linker_script_name(),
+				None => false,
+			};
+
+			if is_build_output || (all && is_build_file)
+			{
+				match self.fs.remove_file(&path)
+				{
+					Err(e) => error!("{:?}", e),
+					_ => say!("Removed {}", path.display()),
+				}
+			}
+		}
 
 		ErrNone
 	}
 
+	/// Name of the linker script igloo generates for the target
+	pub fn linker_script_name(&self) -> String
+	{
+		format!("{}.ld", self.name)
+	}
+
+	/// Renders the linker script of the target from its memory map.
+	/// Returns None if the target has no memory map.
+	pub fn render_linker_script(&self) -> Result<Option<String>, IglooErrType>
+	{
+		if self.memory.is_empty()
+		{
+			return Ok(None)
+		}
+		Ok(Some(render_linker_script(
+			&self.name, &self.memory, &self.sections, self.stack_size)?))
+	}
+
+	/// Applies the memory carve-outs of the project file
+	/// ([target.<name>.memory.<region>]) to the memory map
+	pub fn carve_out(&mut self, carve_outs: &BTreeMap<String, config::Value>)
+					 -> IglooErrType
+	{
+		for (region_name, val) in carve_outs
+		{
+			let table = match val.clone().into_table()
+			{
+				Ok(v) => v.into_iter().collect(),
+				Err(e) =>
+				{
+					error!("target.{}.memory.{}: {}", self.name, region_name, e);
+					return InvalidMemoryMap
+				}
+			};
+			let region = match self.memory.iter_mut().find(|r| &r.name == region_name)
+			{
+				Some(v) => v,
+				None =>
+				{
+					error!("target {} has no memory region \"{}\"",
+							 self.name, region_name);
+					return InvalidMemoryMap
+				}
+			};
+			let res_err = region.carve_out(&table);
+			if res_err != ErrNone
+			{
+				return res_err
+			}
+		}
+		ErrNone
+	}
+
 	/// generates the makefile for a target
 	/// this will be updated as the user edits their project toml
 	pub fn generate_makefile(&self) -> IglooErrType
@@ -190,7 +494,7 @@ impl IglooTarget
 	/// this will be updated as the user edits their project toml
 	pub fn generate_openocd_config(&self) -> IglooErrType
 	{
-		let mut fromPath = IglooEnvInfo::get_env_info()
+		let mut fromPath = self
 			.esfd.join(self.openocd.get("scripts")
 					   .unwrap()
 					   .clone()
@@ -201,7 +505,32 @@ impl IglooTarget
 		let mut toPath = self.root.join("scripts")
 			.join(&self.name).with_extension("cfg");
 
-		std::fs::copy(&fromPath, &toPath).unwrap();
+		self.fs.copy(&fromPath, &toPath).unwrap();
 		ErrNone
 	}
 }
+
+/// Values of manifest entries as text. Unlike the Debug output of config values
+/// this does not hold the path of the file a value came from
+fn value_text(val: &config::Value) -> String
+{
+	match val.clone().into_array()
+	{
+		Ok(v) => format!("[{}]", v.iter().map(value_text).collect::<Vec<_>>().join(", ")),
+		Err(_e) => val.to_string(),
+	}
+}
+
+/// Hashes what a target resolved to: its make manifest entries and the contents
+/// of its target manifest
+fn manifest_hash(inst: &Igloo, makefile: &BTreeMap<String, config::Value>,
+				 target_man_loc: &str) -> String
+{
+	let mut data: Vec<u8> = Vec::new();
+	for (name, val) in makefile
+	{
+		data.extend(format!("{} = {}\n", name, value_text(val)).as_bytes());
+	}
+	data.extend(inst.fs.read(&inst.env.esfd.join(target_man_loc)).unwrap_or_default());
+	sha256_hex(&data)
+}
@@ -0,0 +1,318 @@
+use igloo_base::*;
+use igloo_base::IglooErrType::*;
+
+use crate::Igloo;
+use crate::igloo_module::glob_match;
+use crate::igloo_target::IglooTarget;
+use igloo_manifest::merge_manifest;
+use config::Config;
+use std::path::{Path, PathBuf};
+
+/// Name of the template igloo falls back to when none is given
+pub const DEFAULT_TEMPLATE: &str = "empty";
+
+/// Templates built into igloo (igloo_core/templates), used when neither the user
+/// directory nor ESF has a template with the same name:
+/// (name, description, targets it has board code for, files)
+const BUILTIN_TEMPLATES: &[(&str, &str, &[&str], &[(&str, &str)])] = &[
+	(DEFAULT_TEMPLATE, "Empty main.c", &[], &[
+		("src/main.c", include_str!("../templates/empty/src/main.c")),
+	]),
+	("blinky", "Blinks the board LED with SysTick",
+	 &["samd21j18a", "samd21g18a", "stm32f407vg"], &[
+		("src/main.c", include_str!("../templates/blinky/src/main.c")),
+		("src/board.c", include_str!("../templates/blinky/src/board.c")),
+		("inc/board.h", include_str!("../templates/blinky/inc/board.h")),
+	]),
+	("uart-echo", "Echoes every byte received on the debugger UART",
+	 &["samd21j18a", "stm32f407vg"], &[
+		("src/main.c", include_str!("../templates/uart-echo/src/main.c")),
+		("src/board.c", include_str!("../templates/uart-echo/src/board.c")),
+		("inc/board.h", include_str!("../templates/uart-echo/inc/board.h")),
+	]),
+];
+
+/// Templates are looked up in the user template directory first
+/// (~/.igloo/templates), then in ESF ($ESF_DIR/templates).
+/// A template is a directory whose files are copied into the project root.
+/// An optional template.toml describes it:
+///
+/// [template]
+/// description = "Blinks the board LED"
+/// mcus = ["samd21*"]
+///
+/// mcus restricts the targets the template can be used for, like the mcus
+/// of a module. A template without them works for every target.
+///
+/// The placeholders {{PROJECT_NAME}}, {{TARGET}}, {{MCU}} and {{CLOCK}} are
+/// replaced in file contents and file names.
+pub struct IglooTemplate
+{
+	pub name: String,
+	pub description: String,
+	/// Where the template was found (user, esf or builtin)
+	pub source: String,
+	/// Patterns of the targets the template supports, empty for all
+	pub mcus: Vec<String>,
+	/// Template directory. None for the built in templates
+	pub root: Option<PathBuf>,
+}
+
+impl IglooTemplate
+{
+	/// Template directories in lookup order
+	fn template_dirs(inst: &Igloo) -> Vec<(String, PathBuf)>
+	{
+		let env_info = &inst.env;
+		vec![
+			(String::from("user"), env_info.hd.join(".igloo").join("templates")),
+			(String::from("esf"), env_info.esfd.join("templates")),
+		]
+	}
+
+	fn from_dir(inst: &Igloo, name: &str, source: &str, root: &Path) -> IglooTemplate
+	{
+		let mut description = String::new();
+		let mut mcus: Vec<String> = Vec::new();
+		let template_man_path = root.join("template.toml");
+		if inst.fs.exists(&template_man_path)
+		{
+			let mut template_man = Config::new();
+			match merge_manifest(inst.fs.as_ref(), &mut template_man, &template_man_path)
+			{
+				Ok(_v) =>
+				{
+					description = template_man
+						.get_str("template.description")
+						.unwrap_or_default();
+					mcus = template_man.get_array("template.mcus")
+						.unwrap_or_default()
+						.into_iter().map(|v| v.to_string()).collect();
+				}
+				Err(e) => error!("{:?}", e),
+			}
+		}
+
+		IglooTemplate
+		{
+			name: String::from(name),
+			description: description,
+			source: String::from(source),
+			mcus,
+			root: Some(root.to_path_buf()),
+		}
+	}
+
+	fn builtin(name: &str) -> Option<IglooTemplate>
+	{
+		BUILTIN_TEMPLATES.iter().find(|t| t.0 == name).map(|t| IglooTemplate
+		{
+			name: String::from(t.0),
+			description: String::from(t.1),
+			source: String::from("builtin"),
+			mcus: t.2.iter().map(|m| String::from(*m)).collect(),
+			root: None,
+		})
+	}
+
+	/// Checks the mcus of the template against a target, the same way modules do
+	pub fn supports(&self, target: &IglooTarget) -> bool
+	{
+		let mut names: Vec<String> = target.make_table.split('.').map(String::from).collect();
+		names.push(target.name.clone());
+		self.mcus.is_empty() || self.mcus.iter()
+			.any(|p| names.iter().any(|n| glob_match(&p.to_lowercase(), &n.to_lowercase())))
+	}
+
+	/// Finds a template by name
+	pub fn find(inst: &Igloo, name: &str) -> Result<IglooTemplate, IglooErrType>
+	{
+		for (source, dir) in IglooTemplate::template_dirs(inst)
+		{
+			if inst.fs.is_dir(&dir.join(name))
+			{
+				return Ok(IglooTemplate::from_dir(inst, name, &source, &dir.join(name)))
+			}
+		}
+
+		if let Some(v) = IglooTemplate::builtin(name)
+		{
+			return Ok(v)
+		}
+
+		say!("Template \"{}\" not found", name);
+		Err(InvalidTemplate)
+	}
+
+	/// Lists all available templates. A template in the user directory
+	/// hides an ESF template with the same name.
+	pub fn list(inst: &Igloo) -> Vec<IglooTemplate>
+	{
+		let mut ret: Vec<IglooTemplate> = Vec::new();
+		for (source, dir) in IglooTemplate::template_dirs(inst)
+		{
+			let entries = match inst.fs.read_dir(&dir)
+			{
+				Ok(v) => v,
+				Err(_e) => continue,
+			};
+
+			let mut found: Vec<IglooTemplate> = Vec::new();
+			for path in entries
+			{
+				if !inst.fs.is_dir(&path)
+				{
+					continue
+				}
+				let name = path.file_name().unwrap().to_string_lossy().into_owned();
+				if ret.iter().any(|t| t.name == name)
+				{
+					continue
+				}
+				found.push(IglooTemplate::from_dir(inst, &name, &source, &path));
+			}
+			ret.append(&mut found);
+		}
+
+		for (name, _description, _mcus, _files) in BUILTIN_TEMPLATES
+		{
+			if !ret.iter().any(|t| t.name == *name)
+			{
+				ret.push(IglooTemplate::builtin(name).unwrap());
+			}
+		}
+		ret
+	}
+
+	/// Copies the template into prj_root, replacing placeholders.
+	/// Files that already exist in the project are skipped, so is src/main.c
+	/// when the project keeps its main.c in the root.
+	/// substitutions -- (placeholder name, value) pairs, e.g. ("MCU", "__SAMD21J18A__")
+	pub fn apply(&self, fs: &dyn IglooFs, prj_root: &Path, substitutions: &[(&str, String)])
+				 -> IglooErrType
+	{
+		let mut files: Vec<(PathBuf, Vec<u8>)> = Vec::new();
+		match &self.root
+		{
+			None =>
+			{
+				let builtin = BUILTIN_TEMPLATES.iter().find(|t| t.0 == self.name);
+				for (rel_path, contents) in builtin.map(|t| t.3).unwrap_or_default()
+				{
+					files.push((PathBuf::from(rel_path), contents.as_bytes().to_vec()));
+				}
+			}
+			Some(root) =>
+			{
+				let mut template_files: Vec<PathBuf> = Vec::new();
+				if collect_files(fs, root, &mut template_files) != ErrNone
+				{
+					return FailedToApplyTemplate
+				}
+				for file in template_files
+				{
+					let rel_path = file.strip_prefix(root).unwrap().to_path_buf();
+					if rel_path == Path::new("template.toml")
+					{
+						continue
+					}
+					match fs.read(&file)
+					{
+						Ok(v) => files.push((rel_path, v)),
+						Err(e) =>
+						{
+							error!("{:?}", e);
+							return FailedToApplyTemplate
+						}
+					}
+				}
+			}
+		}
+
+		for (rel_path, contents) in files
+		{
+			let rel_path = PathBuf::from(
+				substitute(&rel_path.to_string_lossy(), substitutions));
+			let dest = prj_root.join(&rel_path);
+			if fs.exists(&dest)
+			{
+				say!("Skipping {}, already exists", dest.display());
+				continue
+			}
+			if rel_path == Path::new("src/main.c") && fs.exists(&prj_root.join("main.c"))
+			{
+				say!("Skipping {}, {} already exists", dest.display(),
+					 prj_root.join("main.c").display());
+				continue
+			}
+
+			// Binary files are copied as is
+			let contents = match String::from_utf8(contents)
+			{
+				Ok(v) => substitute(&v, substitutions).into_bytes(),
+				Err(e) => e.into_bytes(),
+			};
+
+			if let Some(parent) = dest.parent()
+			{
+				match fs.create_dir_all(parent)
+				{
+					Err(e) => error!("{:?}", e),
+					_ => (),
+				}
+			}
+			match fs.write(&dest, &contents)
+			{
+				Ok(_v) => say!("Adding {}", dest.display()),
+				Err(e) =>
+				{
+					error!("{:?}", e);
+					return FailedToApplyTemplate
+				}
+			}
+		}
+		ErrNone
+	}
+}
+
+/// Replaces every {{NAME}} placeholder in text
+fn substitute(text: &str, substitutions: &[(&str, String)]) -> String
+{
+	let mut ret = String::from(text);
+	for (name, value) in substitutions
+	{
+		ret = ret.replace(&format!("{{{{{}}}}}", name), value);
+	}
+	ret
+}
+
+/// Recursively collects all files below dir
+fn collect_files(fs: &dyn IglooFs, dir: &Path, files: &mut Vec<PathBuf>) -> IglooErrType
+{
+	let entries = match fs.read_dir(dir)
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			error!("{:?}", e);
+			return FailedToApplyTemplate
+		}
+	};
+
+	for path in entries
+	{
+		if fs.is_dir(&path)
+		{
+			let res_err = collect_files(fs, &path, files);
+			if res_err != ErrNone
+			{
+				return res_err
+			}
+		}
+		else
+		{
+			files.push(path);
+		}
+	}
+	ErrNone
+}
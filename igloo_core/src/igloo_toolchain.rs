@@ -0,0 +1,409 @@
+// Pinned toolchains
+// A target manifest can require a toolchain in esf.toolchain:
+//
+// [esf.toolchain]
+// name = "arm-none-eabi"
+// version = ">=10.3, <11"
+//
+// igloo installs toolchains from archives listed in toolchains.toml indexes (ESF's,
+// the user's ~/.igloo/toolchains.toml and the mirrors it lists) into a per user
+// cache, points the generated Makefiles at the installed binaries and records the
+// exact toolchain of every target in igloo.lock (see igloo_lock).
+use igloo_base::*;
+use igloo_base::IglooErrType::*;
+use igloo_manifest::merge_manifest;
+
+use crate::igloo_archive::*;
+
+use config::Config;
+use semver::{Version, VersionReq};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Component, Path, PathBuf};
+
+/// Index of toolchain archives, in ESF's toolchains directory and in mirrors
+pub const TOOLCHAIN_INDEX: &str = "toolchains.toml";
+/// Written last into an installed toolchain, so partial installs are not used
+const TOOLCHAIN_INFO: &str = "igloo-toolchain.toml";
+
+/// Toolchain a target needs, from esf.toolchain of its target manifest
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolchainReq
+{
+	pub name: String,
+	pub version: VersionReq,
+}
+
+impl ToolchainReq
+{
+	pub fn from_table(table: &BTreeMap<String, config::Value>) -> Result<ToolchainReq, String>
+	{
+		let get = |key: &str| table.get(key).and_then(|v| v.clone().into_str().ok());
+		let name = get("name").ok_or("name is required")?;
+		let version = match get("version")
+		{
+			Some(v) => VersionReq::parse(&v).map_err(|e| format!("version \"{}\": {}", v, e))?,
+			None => VersionReq::STAR,
+		};
+		Ok(ToolchainReq { name, version })
+	}
+
+	/// Parses name@version, e.g. arm-none-eabi@10.3 or arm-none-eabi@=10.3.1
+	pub fn from_spec(spec: &str) -> Result<ToolchainReq, String>
+	{
+		let (name, version) = match spec.split_once('@')
+		{
+			Some((n, v)) => (n, VersionReq::parse(v).map_err(|e| format!("{}: {}", spec, e))?),
+			None => (spec, VersionReq::STAR),
+		};
+		Ok(ToolchainReq { name: String::from(name), version })
+	}
+
+	pub fn matches(&self, name: &str, version: &Version) -> bool
+	{
+		self.name == name && self.version.matches(version)
+	}
+}
+
+impl std::fmt::Display for ToolchainReq
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result
+	{
+		write!(f, "{} {}", self.name, self.version)
+	}
+}
+
+/// A toolchain archive listed in a toolchains.toml index
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolchainArchive
+{
+	pub name: String,
+	pub version: Version,
+	pub archive: PathBuf,
+	/// expected SHA-256 of the archive, if the index gives one
+	pub sha256: Option<String>,
+	/// directory inside the archive holding bin/. Guessed if not given
+	pub root: Option<String>,
+}
+
+/// The exact toolchain a target builds with
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolchainPin
+{
+	pub name: String,
+	pub version: Version,
+	/// SHA-256 of the archive it was installed from
+	pub sha256: String,
+	/// where it is installed. None if it is locked but not installed
+	pub dir: Option<PathBuf>,
+}
+
+/// Parses a toolchain version. Missing minor and patch numbers are 0
+pub fn parse_version(text: &str) -> Result<Version, String>
+{
+	let parts = text.split('.').count();
+	let padded = match parts
+	{
+		1 => format!("{}.0.0", text),
+		2 => format!("{}.0", text),
+		_ => String::from(text),
+	};
+	Version::parse(&padded).map_err(|e| format!("version \"{}\": {}", text, e))
+}
+
+/// Host toolchains are built for, e.g. x86_64-linux
+pub fn host_triple() -> String
+{
+	format!("{}-{}", std::env::consts::ARCH, std::env::consts::OS)
+}
+
+/// Directory toolchains are installed into
+pub fn toolchain_cache(hd: &Path) -> PathBuf
+{
+	hd.join(".igloo").join("toolchains")
+}
+
+pub fn sha256_hex(data: &[u8]) -> String
+{
+	let mut ret = String::new();
+	for b in Sha256::digest(data)
+	{
+		write!(ret, "{:02x}", b).unwrap();
+	}
+	ret
+}
+
+fn invalid(path: &Path, msg: &str) -> IglooErrType
+{
+	error!("{}: {}", path.display(), msg);
+	InvalidToolchain
+}
+
+/// Reads an index. Returns its archives for this host and the mirrors it lists
+fn read_index(fs: &dyn IglooFs, path: &Path)
+			  -> Result<(Vec<ToolchainArchive>, Vec<PathBuf>), IglooErrType>
+{
+	let mut index = Config::new();
+	merge_manifest(fs, &mut index, path).map_err(|e| invalid(path, &e))?;
+	let dir = path.parent().unwrap_or(Path::new("/"));
+
+	let mirrors: Vec<PathBuf> = index.get_array("mirrors").unwrap_or_default().into_iter()
+		.filter_map(|v| v.into_str().ok())
+		.map(|v| dir.join(v))
+		.collect();
+
+	let mut archives: Vec<ToolchainArchive> = Vec::new();
+	for entry in index.get_array("toolchain").unwrap_or_default()
+	{
+		let table: BTreeMap<String, config::Value> = match entry.into_table()
+		{
+			Ok(v) => v.into_iter().collect(),
+			Err(e) => return Err(invalid(path, &e.to_string())),
+		};
+		let get = |key: &str| table.get(key).and_then(|v| v.clone().into_str().ok());
+		if let Some(host) = get("host")
+		{
+			if host != host_triple()
+			{
+				continue
+			}
+		}
+		let (name, version, archive) = match (get("name"), get("version"), get("archive"))
+		{
+			(Some(n), Some(v), Some(a)) => (n, v, a),
+			_ => return Err(invalid(path, "toolchains need a name, version and archive")),
+		};
+		archives.push(ToolchainArchive
+		{
+			name,
+			version: parse_version(&version).map_err(|e| invalid(path, &e))?,
+			archive: dir.join(archive),
+			sha256: get("sha256").map(|v| v.to_ascii_lowercase()),
+			root: get("root"),
+		});
+	}
+	Ok((archives, mirrors))
+}
+
+/// Every toolchain archive for this host: the user's index and its mirrors first,
+/// then ESF's. Indexes that do not exist are skipped.
+pub fn available_toolchains(fs: &dyn IglooFs, env: &IglooEnvInfo)
+							-> Result<Vec<ToolchainArchive>, IglooErrType>
+{
+	let mut ret: Vec<ToolchainArchive> = Vec::new();
+	let user_index = env.hd.join(".igloo").join(TOOLCHAIN_INDEX);
+	let mut indexes: Vec<PathBuf> = Vec::new();
+	if fs.exists(&user_index)
+	{
+		let (archives, mirrors) = read_index(fs, &user_index)?;
+		ret.extend(archives);
+		indexes.extend(mirrors.into_iter().map(|m| m.join(TOOLCHAIN_INDEX)));
+	}
+	indexes.push(env.esfd.join("toolchains").join(TOOLCHAIN_INDEX));
+	for index in indexes
+	{
+		if !fs.exists(&index)
+		{
+			continue
+		}
+		ret.extend(read_index(fs, &index)?.0);
+	}
+	Ok(ret)
+}
+
+/// The toolchains in the cache, sorted by name and version
+pub fn installed_toolchains(fs: &dyn IglooFs, hd: &Path) -> Vec<ToolchainPin>
+{
+	let mut ret: Vec<ToolchainPin> = Vec::new();
+	for dir in fs.read_dir(&toolchain_cache(hd)).unwrap_or_default()
+	{
+		let mut info = Config::new();
+		if merge_manifest(fs, &mut info, &dir.join(TOOLCHAIN_INFO)).is_err()
+		{
+			continue
+		}
+		let version = info.get_str("version").ok().and_then(|v| parse_version(&v).ok());
+		if let (Ok(name), Some(version), Ok(sha256)) =
+			(info.get_str("name"), version, info.get_str("sha256"))
+		{
+			ret.push(ToolchainPin { name, version, sha256, dir: Some(dir) });
+		}
+	}
+	ret.sort_by(|a, b| a.name.cmp(&b.name).then(a.version.cmp(&b.version)));
+	ret
+}
+
+/// Picks the toolchain for req. A locked toolchain that still satisfies req is
+/// kept even if a newer one is installed, it is returned without a directory if
+/// it is not installed. Otherwise the newest installed match is used.
+pub fn select_toolchain(req: &ToolchainReq, installed: &[ToolchainPin],
+						locked: Option<&ToolchainPin>) -> Option<ToolchainPin>
+{
+	if let Some(locked) = locked.filter(|l| req.matches(&l.name, &l.version))
+	{
+		let found = installed.iter().find(|t| t.name == locked.name
+										  && t.version == locked.version
+										  && t.sha256 == locked.sha256);
+		return Some(found.cloned().unwrap_or_else(|| locked.clone()))
+	}
+	installed.iter().filter(|t| req.matches(&t.name, &t.version)).last().cloned()
+}
+
+/// Normalizes a path inside an archive, following the links created so far.
+/// None if it leaves the archive
+fn resolve(path: &Path, links: &BTreeMap<PathBuf, PathBuf>, depth: usize) -> Option<PathBuf>
+{
+	// as many links as Linux follows before giving up with ELOOP
+	if depth > 40
+	{
+		return None
+	}
+	let mut ret = PathBuf::new();
+	for component in path.components()
+	{
+		match component
+		{
+			Component::Normal(v) =>
+			{
+				ret.push(v);
+				if let Some(target) = links.get(&ret)
+				{
+					let link = ret.parent().unwrap_or(Path::new("")).join(target);
+					ret = resolve(&link, links, depth + 1)?;
+				}
+			}
+			Component::CurDir => (),
+			Component::ParentDir => if !ret.pop() { return None },
+			_ => return None,
+		}
+	}
+	Some(ret)
+}
+
+/// Installs a toolchain archive into the cache, replacing a previous install
+pub fn install_toolchain(fs: &dyn IglooFs, hd: &Path, archive: &ToolchainArchive)
+						 -> Result<ToolchainPin, IglooErrType>
+{
+	let failed = |msg: &str| -> IglooErrType
+	{
+		error!("{}: {}", archive.archive.display(), msg);
+		FailedToInstallToolchain
+	};
+	let data = fs.read(&archive.archive).map_err(|e| failed(&e.to_string()))?;
+	let sha256 = sha256_hex(&data);
+	if let Some(expected) = &archive.sha256
+	{
+		if *expected != sha256
+		{
+			return Err(failed(&format!("SHA-256 is {}, the index expects {}", sha256, expected)))
+		}
+	}
+	let file_name = archive.archive.file_name().unwrap_or_default().to_string_lossy();
+	let entries = unpack(&file_name, &data).map_err(|e| failed(&e))?;
+
+	// Toolchain archives usually hold a single versioned directory
+	let root = match &archive.root
+	{
+		Some(v) => Some(v.trim_end_matches('/').to_string()),
+		None =>
+		{
+			let first = |e: &ArchiveEntry| e.path.split('/').next().unwrap_or("").to_string();
+			match entries.first().map(first)
+			{
+				Some(v) if v != "bin" && entries.iter().all(|e| first(e) == v) => Some(v),
+				_ => None,
+			}
+		}
+	};
+	let strip = |path: &str| -> Option<String>
+	{
+		match &root
+		{
+			None => Some(String::from(path)),
+			Some(r) if path == r => None,
+			Some(r) => path.strip_prefix(&format!("{}/", r)).map(String::from),
+		}
+	};
+
+	let dir = toolchain_cache(hd).join(format!("{}-{}", archive.name, archive.version));
+	if fs.exists(&dir)
+	{
+		fs.remove_dir_all(&dir).map_err(|e| failed(&e.to_string()))?;
+	}
+	fs.create_dir_all(&dir).map_err(|e| failed(&e.to_string()))?;
+	say!("Installing {} {} into {}", archive.name, archive.version, dir.display());
+
+	let outside = |path: &str| failed(&format!("{} is outside of the toolchain", path));
+	let mut links: BTreeMap<PathBuf, PathBuf> = BTreeMap::new();
+	let mut hardlinks: Vec<(PathBuf, PathBuf)> = Vec::new();
+	for entry in &entries
+	{
+		let rel = match strip(&entry.path)
+		{
+			Some(v) => PathBuf::from(v),
+			None => continue,
+		};
+		// Entries below a link are placed where the link points
+		let rel = match (rel.parent().and_then(|v| resolve(v, &links, 0)), rel.file_name())
+		{
+			(Some(parent), Some(name)) => parent.join(name),
+			_ => return Err(outside(&entry.path)),
+		};
+		let dest = dir.join(&rel);
+		if let Some(parent) = dest.parent()
+		{
+			fs.create_dir_all(parent).map_err(|e| failed(&e.to_string()))?;
+		}
+		let res = match &entry.kind
+		{
+			ArchiveKind::Dir => fs.create_dir_all(&dest),
+			ArchiveKind::File { data, executable } =>
+			{
+				fs.write(&dest, data).and_then(|_v|
+					if *executable { fs.set_executable(&dest) } else { Ok(()) })
+			}
+			ArchiveKind::Symlink(target) =>
+			{
+				// Links may point anywhere inside the toolchain, never outside of it
+				let link = rel.parent().unwrap_or(Path::new("")).join(target);
+				if resolve(&link, &links, 0).is_none()
+				{
+					return Err(failed(&format!("{} links outside of the toolchain", entry.path)))
+				}
+				links.insert(rel.clone(), PathBuf::from(target));
+				fs.symlink(Path::new(target), &dest)
+			}
+			ArchiveKind::Hardlink(target) =>
+			{
+				let from = match strip(target)
+				{
+					Some(v) => v,
+					None => return Err(failed(&format!("bad hard link {}", entry.path))),
+				};
+				match resolve(Path::new(&from), &links, 0)
+				{
+					Some(v) => hardlinks.push((dir.join(v), dest.clone())),
+					None => return Err(outside(target)),
+				}
+				Ok(())
+			}
+		};
+		res.map_err(|e| failed(&format!("{}: {}", dest.display(), e)))?;
+	}
+	for (from, to) in hardlinks
+	{
+		fs.copy(&from, &to).map_err(|e| failed(&format!("{}: {}", to.display(), e)))?;
+	}
+
+	let info = format!("name = \"{}\"\nversion = \"{}\"\nsha256 = \"{}\"\narchive = \"{}\"\n",
+					   archive.name, archive.version, sha256, archive.archive.display());
+	fs.write(&dir.join(TOOLCHAIN_INFO), info.as_bytes()).map_err(|e| failed(&e.to_string()))?;
+	Ok(ToolchainPin
+	{
+		name: archive.name.clone(),
+		version: archive.version.clone(),
+		sha256,
+		dir: Some(dir),
+	})
+}
@@ -1,19 +1,36 @@
 #![allow(warnings)]
 extern crate clap;
 extern crate config;
+#[macro_use]
+extern crate log;
 
 mod igloo_action;
+mod igloo_analyze;
+mod igloo_archive;
+mod igloo_doctor;
+mod igloo_elf;
+mod igloo_image;
+mod igloo_linker;
+mod igloo_lock;
+mod igloo_module;
+mod igloo_postbuild;
 mod igloo_project;
+mod igloo_registry;
+mod igloo_retarget;
+mod igloo_size;
 mod igloo_target;
+mod igloo_template;
+mod igloo_toolchain;
 
 use igloo_base::*;
-use igloo_base::IglooInstType::*;
 use igloo_base::IglooErrType::*;
 use igloo_cli::*;
 use igloo_manifest::*;
 use igloo_project::IglooPrj;
 
 use config::Config;
+use serde_json::json;
+use std::rc::Rc;
 #[cfg(test)]
 mod tests {
     #[test]
@@ -22,6 +39,27 @@ mod tests {
     }
 }
 
+/// The --message-format of the command line. It is global, so it may
+/// come before or after the subcommand
+fn message_format_of(cli_conf: &clap::ArgMatches) -> MessageFormat
+{
+	let json = |m: &clap::ArgMatches| m.value_of("message-format") == Some("json");
+	if json(cli_conf) || cli_conf.subcommand().map(|(_name, m)| json(m)).unwrap_or(false)
+	{
+		MessageFormat::Json
+	}
+	else
+	{
+		MessageFormat::Human
+	}
+}
+
+/// Borrows the strings of a parsed argument the way the actions take them
+fn names(values: &[String]) -> Vec<&str>
+{
+	values.iter().map(String::as_str).collect()
+}
+
 /// Igloo should contain a config and any important environment information.
 /// Upon running igloo, an instanc is created, env information is stored, and then
 /// things happen.
@@ -30,6 +68,12 @@ pub struct Igloo
 	cli_conf: clap::ArgMatches,
 	master_make_man: Config,
 	master_target_man: Config,
+	env: IglooEnvInfo,
+	/// Everything igloo reads from ESF or writes to a project goes through fs.
+	/// With --dry-run, this is a DryRunFs recording the changes.
+	fs: Rc<dyn IglooFs>,
+	/// Programs igloo runs (toolchain version checks) go through host
+	host: Rc<dyn IglooHost>,
 }
 
 impl Igloo
@@ -42,14 +86,48 @@ impl Igloo
 	/// start function.
 	pub fn new() -> Igloo
 	{
+		Igloo::from_parts(igloo_app(), IglooEnvInfo::get_env_info(), Rc::new(RealFs))
+	}
+
+	/// Creates an instance of igloo from already parsed cli input, environment
+	/// information and a filesystem. This lets igloo run against a MemFs.
+	pub fn from_parts(cli_conf: clap::ArgMatches, env: IglooEnvInfo,
+					  fs: Rc<dyn IglooFs>) -> Igloo
+	{
+		set_message_format(message_format_of(&cli_conf));
+		let level = log_level(cli_conf.is_present("quiet"), cli_conf.occurrences_of("verbose"),
+							  std::env::var("IGLOO_LOG").ok().as_deref());
+		let _ = init_logging(level, None);
+		let fs: Rc<dyn IglooFs> = if cli_conf.is_present("dry-run")
+		{
+			Rc::new(DryRunFs::new(fs))
+		}
+		else if message_format() == MessageFormat::Json
+		{
+			Rc::new(ReportingFs::new(fs))
+		}
+		else
+		{
+			fs
+		};
 		Igloo
 		{
 			master_make_man: Config::new(),
 			master_target_man: Config::new(),
-			cli_conf: igloo_app(),
+			cli_conf: cli_conf,
+			env: env,
+			fs: fs,
+			host: Rc::new(RealHost),
 		}
 	}
 
+	/// Replaces the host igloo runs programs on, e.g. with a MemHost
+	pub fn with_host(mut self, host: Rc<dyn IglooHost>) -> Igloo
+	{
+		self.host = host;
+		self
+	}
+
 	/// The start function processes the command you want igloo to run
 	///  It theoretically should never return an error. It should just exit.
 	///  If an error was returned, It was my fault and not the users.
@@ -58,37 +136,56 @@ impl Igloo
 	///  The Inst Type is only returned for usage outside of this struct.
 	pub fn start(&mut self) -> Result<IglooInstType, IglooErrType>
 	{
-		let mut res: IglooInstType = Null;
-		// Load manifests first
-		match get_master_make_manifest(&mut self.master_make_man)
+		let res = self.load();
+		if let Err(e) = res
+		{
+			self.finish(Err(e));
+		}
+		res
+	}
+
+	fn load(&mut self) -> Result<IglooInstType, IglooErrType>
+	{
+		// Parse the command (new, run, flash, etc..) and its arguments
+		let res = igloo_subcommand(&self.cli_conf)?;
+
+		// Completion scripts only need the clap app
+		if matches!(res, IglooInstType::Completions(_))
+		{
+			return Ok(res)
+		}
+
+		// igloo doctor reports a broken environment instead of failing on it
+		let doctor = matches!(res, IglooInstType::Doctor(_));
+		if self.env.esfd.as_os_str().is_empty() && !doctor
+		{
+			error!("$ESF_DIR not defined as an environment variable\n\
+					  Run igloo doctor to check your setup");
+			return Err(InvalidEnvInfo)
+		}
+
+		// Load manifests
+		match get_master_make_manifest(
+			self.fs.as_ref(), &self.env.esfd, &mut self.master_make_man)
 		{
 			ErrNone => (),
-			v =>
+			v if !doctor =>
 			{
-				println!("{:?}", v);
+				error!("{:?}", v);
 				return Err(v)
 			}
+			_ => (),
 		}
-		match get_master_target_manifest(&mut self.master_target_man)
+		match get_master_target_manifest(
+			self.fs.as_ref(), &self.env.esfd, &mut self.master_target_man)
 		{
 			ErrNone => (),
-			v =>
+			v if !doctor =>
 			{
-				println!("{:?}", v);
+				error!("{:?}", v);
 				return Err(v)
 			},
-		}
-
-		// Assign our instance type (new, run, flash, etc..)
-		match igloo_subcommand(&self.cli_conf)
-		{
-			Ok(v) => res = v,
-			Err(e) => return Err(e),
-		}
-
-		if res == Null
-		{
-			return Err(ErrUnknown)
+			_ => (),
 		}
 
 		Ok(res)
@@ -99,50 +196,103 @@ impl Igloo
 	/// On failure, it will return some error type.
 	pub fn run(&self, inst_type: IglooInstType) -> Result<String, IglooErrType>
 	{
-		let mut res_err = ErrNone;
-		let mut prj: IglooPrj;
-		loop { match inst_type
-		{
-			Null => res_err = ErrNone,
-			New =>
-			{
-				let prj_name: &str = self
-					.cli_conf
-					.subcommand()
-					.unwrap().1
-					.value_of("project_name")
-					.unwrap();
-
-				let target: &str = self
-					.cli_conf
-					.subcommand()
-					.unwrap().1
-					.value_of("target")
-					.unwrap();
-				let res_err = igloo_action::new(
-					self, prj_name, target);
-				if res_err != ErrNone
-				{
-					return Err(res_err)
-				}
-			}
-			Flash =>
-			{
+		self.finish(self.run_inst(inst_type))
+	}
 
-			}
-			Run =>
-			{
+	/// Emits the finished or error message ending every command
+	fn finish(&self, res: Result<String, IglooErrType>) -> Result<String, IglooErrType>
+	{
+		let command = self.cli_conf.subcommand_name().unwrap_or_default();
+		match res
+		{
+			Ok(ref v) => emit(json!({
+				"reason": "finished",
+				"command": command,
+				"success": true,
+				"message": v,
+			})),
+			Err(e) => emit(json!({
+				"reason": "error",
+				"command": command,
+				"code": e as i32,
+				"error": format!("{:?}", e),
+			})),
+		}
+		res
+	}
 
-			}
-			_ => println!("Unhandled case: {:?}", inst_type),
-		} break; }
-		if res_err == ErrNone
+	fn run_inst(&self, inst_type: IglooInstType) -> Result<String, IglooErrType>
+	{
+		use IglooInstType::*;
+		let res_err = match &inst_type
 		{
-			Ok(String::from("We won!"))
+			New(args) => igloo_action::new(self, &args.name, &args.target, &args.template),
+			Init(args) => igloo_action::init(
+				self, args.name.as_deref(), &args.target, &args.template),
+			Run(args) => igloo_action::run(self, &args.names(), args.all_targets),
+			Flash(args) => igloo_action::flash(self, &args.names(), args.all_targets),
+			Erase(args) => igloo_action::erase(self, &args.names(), args.all_targets),
+			Debug(args) => igloo_action::debug(self, &args.names(), args.all_targets),
+			Target(TargetCmd::Default(args)) => igloo_action::target_default(
+				self, args.name.as_deref(), args.unset),
+			Build(args) => igloo_action::build(
+				self, &args.targets.names(), args.targets.all_targets, args.locked, args.jobs),
+			Clean(args) => igloo_action::clean(
+				self, &args.targets.names(), args.targets.all_targets, args.all),
+			GenDoc(args) => igloo_action::gendoc(
+				self, &args.targets.names(), args.targets.all_targets),
+			Template(TemplateCmd::List) => igloo_action::template_list(self),
+			Make(MakeCmd::Regen(args)) => igloo_action::make_regen(
+				self, &args.targets.names(), args.targets.all_targets, args.check),
+			Size(args) => igloo_action::size(
+				self, &args.targets.names(), args.targets.all_targets, args.top, args.json),
+			Analyze(args) => igloo_action::analyze(
+				self,
+				&args.targets.names(),
+				args.targets.all_targets,
+				&args.by,
+				args.tree,
+				args.diff,
+				args.base.as_deref(),
+				args.json),
+			Image(ImageCmd::Convert(args)) => igloo_action::image_convert(
+				self, &args.targets.names(), args.targets.all_targets, &names(&args.formats),
+				args.options.fill.as_deref(),
+				args.options.pad_to.as_deref(),
+				args.options.uf2_family.as_deref(),
+				args.output.as_deref(),
+				args.key.as_deref()),
+			Image(ImageCmd::Verify(args)) => igloo_action::image_verify(
+				self, &args.targets.names(), args.targets.all_targets,
+				args.image.as_deref(), args.key.as_deref()),
+			Image(ImageCmd::Merge(args)) => igloo_action::image_merge(
+				self, &names(&args.inputs), &args.output,
+				args.options.fill.as_deref(),
+				args.options.pad_to.as_deref(),
+				args.options.uf2_family.as_deref()),
+			Completions(args) => igloo_action::completions(&args.shell),
+			Complete(_args) => igloo_action::complete_targets(self),
+			Doctor(args) => igloo_action::doctor(self, args.json),
+			Toolchain(ToolchainCmd::List) => igloo_action::toolchain_list(self),
+			Toolchain(ToolchainCmd::Install(specs)) =>
+				igloo_action::toolchain_install(self, &names(specs)),
+			Retarget(args) => igloo_action::retarget(self, &args.old, &args.new),
+			Targets(args) => igloo_action::targets(
+				self, args.vendor.as_deref(), args.core.as_deref(), args.json),
+			Search(args) => igloo_action::search(self, &args.query.join(" ")),
+			Info(args) => igloo_action::info(self, &args.name),
+			Add(args) => igloo_action::add(self, &names(&args.modules)),
+			Remove(args) => igloo_action::remove(self, &names(&args.modules)),
+		};
+		if res_err != ErrNone
+		{
+			return Err(res_err)
 		}
-		else
+
+		if let Some(plan) = self.fs.plan()
 		{
-			Err(res_err)
+			print_plan(&plan);
 		}
+		Ok(format!("{} succeeded", inst_type.name()))
 	}
 }
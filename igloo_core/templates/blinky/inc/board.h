@@ -0,0 +1,12 @@
+#ifndef BOARD_H
+#define BOARD_H
+
+#include <stdint.h>
+
+/* core clock of {{TARGET}} ({{MCU}}) */
+#define BOARD_CLOCK_HZ {{CLOCK}}UL
+
+void board_led_init(void);
+void board_led_toggle(void);
+
+#endif
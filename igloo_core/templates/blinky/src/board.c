@@ -0,0 +1,41 @@
+/* LED of the {{TARGET}} board */
+#include "igloo.h"
+#include "board.h"
+
+#if defined(__SAMD21J18A__)
+/* LED0 of the SAM D21 Xplained Pro, PB30 */
+#define LED_GROUP 1
+#define LED_PIN 30
+#elif defined(__SAMD21G18A__)
+/* L LED of the Arduino Zero, PA17 */
+#define LED_GROUP 0
+#define LED_PIN 17
+#elif defined(STM32F407xx)
+/* LD4 (green) of the STM32F4DISCOVERY, PD12 */
+#define LED_PIN 12
+#else
+#error "blinky has no LED for {{TARGET}}"
+#endif
+
+#if defined(__SAMD21J18A__) || defined(__SAMD21G18A__)
+void board_led_init(void)
+{
+	PORT->Group[LED_GROUP].DIRSET.reg = 1u << LED_PIN;
+}
+
+void board_led_toggle(void)
+{
+	PORT->Group[LED_GROUP].OUTTGL.reg = 1u << LED_PIN;
+}
+#else
+void board_led_init(void)
+{
+	RCC->AHB1ENR |= RCC_AHB1ENR_GPIODEN;
+	GPIOD->MODER = (GPIOD->MODER & ~(3u << (LED_PIN * 2))) | (1u << (LED_PIN * 2));
+}
+
+void board_led_toggle(void)
+{
+	GPIOD->ODR ^= 1u << LED_PIN;
+}
+#endif
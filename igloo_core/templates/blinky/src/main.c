@@ -0,0 +1,30 @@
+/* {{PROJECT_NAME}} -- blinks the board LED of {{TARGET}} */
+#include "igloo.h"
+#include "board.h"
+
+/* incremented every millisecond by SysTick */
+static volatile uint32_t ticks;
+
+void SysTick_Handler(void)
+{
+	ticks++;
+}
+
+static void delay_ms(uint32_t ms)
+{
+	uint32_t start = ticks;
+	while ((ticks - start) < ms)
+	{
+	}
+}
+
+int main(void)
+{
+	SysTick_Config(BOARD_CLOCK_HZ / 1000);
+	board_led_init();
+	for (;;)
+	{
+		board_led_toggle();
+		delay_ms(500);
+	}
+}
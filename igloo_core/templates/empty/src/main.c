@@ -0,0 +1,7 @@
+#include "igloo.h"
+
+
+int main()
+{
+	return 0;
+}
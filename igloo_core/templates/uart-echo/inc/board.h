@@ -0,0 +1,15 @@
+#ifndef BOARD_H
+#define BOARD_H
+
+#include <stdint.h>
+
+/* core clock of {{TARGET}} ({{MCU}}) */
+#define BOARD_CLOCK_HZ {{CLOCK}}UL
+
+void board_uart_init(uint32_t baud);
+/* blocks until a byte is received */
+uint8_t board_uart_getc(void);
+/* blocks until the byte can be sent */
+void board_uart_putc(uint8_t c);
+
+#endif
@@ -0,0 +1,85 @@
+/* UART of the {{TARGET}} board wired to the on-board debugger */
+#include "igloo.h"
+#include "board.h"
+
+#if defined(__SAMD21J18A__)
+/* SERCOM3 on PA22 (TX, PAD0) and PA23 (RX, PAD1) of the SAM D21 Xplained Pro */
+void board_uart_init(uint32_t baud)
+{
+	PM->APBCMASK.reg |= PM_APBCMASK_SERCOM3;
+	GCLK->CLKCTRL.reg = GCLK_CLKCTRL_ID(SERCOM3_GCLK_ID_CORE) | GCLK_CLKCTRL_GEN_GCLK0
+		| GCLK_CLKCTRL_CLKEN;
+	while (GCLK->STATUS.bit.SYNCBUSY)
+	{
+	}
+
+	/* peripheral function C of PA22 and PA23 */
+	PORT->Group[0].PINCFG[22].reg = PORT_PINCFG_PMUXEN;
+	PORT->Group[0].PINCFG[23].reg = PORT_PINCFG_PMUXEN;
+	PORT->Group[0].PMUX[11].reg = PORT_PMUX_PMUXE(2) | PORT_PMUX_PMUXO(2);
+
+	SERCOM3->USART.CTRLA.reg = SERCOM_USART_CTRLA_DORD | SERCOM_USART_CTRLA_MODE_USART_INT_CLK
+		| SERCOM_USART_CTRLA_RXPO(1) | SERCOM_USART_CTRLA_TXPO(0);
+	SERCOM3->USART.CTRLB.reg = SERCOM_USART_CTRLB_RXEN | SERCOM_USART_CTRLB_TXEN;
+	while (SERCOM3->USART.SYNCBUSY.bit.CTRLB)
+	{
+	}
+	/* arithmetic baud rate generation with 16x oversampling */
+	SERCOM3->USART.BAUD.reg = (uint16_t)(65536ull - (65536ull * 16 * baud) / BOARD_CLOCK_HZ);
+	SERCOM3->USART.CTRLA.reg |= SERCOM_USART_CTRLA_ENABLE;
+	while (SERCOM3->USART.SYNCBUSY.bit.ENABLE)
+	{
+	}
+}
+
+uint8_t board_uart_getc(void)
+{
+	while (!SERCOM3->USART.INTFLAG.bit.RXC)
+	{
+	}
+	return (uint8_t)SERCOM3->USART.DATA.reg;
+}
+
+void board_uart_putc(uint8_t c)
+{
+	while (!SERCOM3->USART.INTFLAG.bit.DRE)
+	{
+	}
+	SERCOM3->USART.DATA.reg = c;
+}
+#elif defined(STM32F407xx)
+/* USART2 on PA2 (TX) and PA3 (RX) of the STM32F4DISCOVERY */
+void board_uart_init(uint32_t baud)
+{
+	/* USART2 is clocked by APB1, PPRE1 divides the core clock by 2 to 16 */
+	uint32_t ppre1 = (RCC->CFGR & RCC_CFGR_PPRE1) >> 10;
+	uint32_t apb1_hz = ppre1 < 4 ? BOARD_CLOCK_HZ : BOARD_CLOCK_HZ >> (ppre1 - 3);
+
+	RCC->AHB1ENR |= RCC_AHB1ENR_GPIOAEN;
+	RCC->APB1ENR |= RCC_APB1ENR_USART2EN;
+	/* alternate function 7 of PA2 and PA3 */
+	GPIOA->MODER = (GPIOA->MODER & ~(0xfu << 4)) | (0xau << 4);
+	GPIOA->AFR[0] = (GPIOA->AFR[0] & ~(0xffu << 8)) | (0x77u << 8);
+
+	USART2->BRR = (apb1_hz + baud / 2) / baud;
+	USART2->CR1 = USART_CR1_UE | USART_CR1_TE | USART_CR1_RE;
+}
+
+uint8_t board_uart_getc(void)
+{
+	while (!(USART2->SR & USART_SR_RXNE))
+	{
+	}
+	return (uint8_t)USART2->DR;
+}
+
+void board_uart_putc(uint8_t c)
+{
+	while (!(USART2->SR & USART_SR_TXE))
+	{
+	}
+	USART2->DR = c;
+}
+#else
+#error "uart-echo has no UART for {{TARGET}}"
+#endif
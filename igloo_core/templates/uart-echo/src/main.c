@@ -0,0 +1,12 @@
+/* {{PROJECT_NAME}} -- echoes every byte received on the UART of {{TARGET}} */
+#include "igloo.h"
+#include "board.h"
+
+int main(void)
+{
+	board_uart_init(115200);
+	for (;;)
+	{
+		board_uart_putc(board_uart_getc());
+	}
+}
@@ -0,0 +1,133 @@
+mod common;
+
+use common::*;
+use common::elf::*;
+use igloo_base::*;
+use igloo_base::IglooErrType::*;
+
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+const TARGET_DIR: &str = "/work/blinky/.igloo/target/samd21j18a";
+
+fn fixture_map() -> String
+{
+	std::fs::read_to_string(PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+							.join("tests/fixtures/build/blinky.map")).unwrap()
+}
+
+/// ELF matching tests/fixtures/build/blinky.map
+fn fixture_elf() -> Vec<u8>
+{
+	ElfBuilder::new()
+		.section(".text", 0x2000, SHF_ALLOC | SHF_EXECINSTR, &[0; 0x1c0])
+		.section(".relocate", 0x2000_0000, SHF_ALLOC | SHF_WRITE, &[0; 0x10])
+		.nobits(".bss", 0x2000_0010, SHF_ALLOC | SHF_WRITE, 0x104)
+		.symbol("main", 0x20b4, 0x40, STT_FUNC, ".text")
+		.symbol("Reset_Handler", 0x20f4, 0x88, STT_FUNC, ".text")
+		.symbol("memset", 0x217c, 0x30, STT_FUNC, ".text")
+		.symbol("counter", 0x2000_0000, 4, STT_OBJECT, ".relocate")
+		.symbol("SystemCoreClock", 0x2000_0004, 4, STT_OBJECT, ".relocate")
+		.symbol("rx_buffer", 0x2000_0010, 0x100, STT_OBJECT, ".bss")
+		.build()
+}
+
+fn built_project() -> Rc<MemFs>
+{
+	let fs = fake_esf();
+	run_igloo(&fs, Path::new(WORK_DIR), &["new", "blinky", "-t", "samd21j18a"]).unwrap();
+	fs.add_file(&Path::new(TARGET_DIR).join("blinky.map"), &fixture_map());
+	fs.write(&Path::new(TARGET_DIR).join("blinky.elf"), &fixture_elf()).unwrap();
+	fs
+}
+
+fn analysis(fs: &Rc<MemFs>, name: &str) -> serde_json::Value
+{
+	serde_json::from_str(&read(fs, &format!("{}/{}", TARGET_DIR, name))).unwrap()
+}
+
+fn file<'a>(analysis: &'a serde_json::Value, name: &str) -> &'a serde_json::Value
+{
+	analysis["files"].as_array().unwrap().iter().find(|f| f["file"] == name).unwrap()
+}
+
+#[test]
+fn attributes_sizes_to_files_and_groups()
+{
+	let fs = built_project();
+	run_igloo(&fs, Path::new("/work/blinky"), &["analyze"]).unwrap();
+	let analysis = analysis(&fs, "analyze.json");
+
+	let main = file(&analysis, "src/main.o");
+	assert_eq!(main["group"], "user");
+	assert_eq!(main["sizes"], serde_json::json!({ "code": 0x50, "data": 4, "bss": 0x100 }));
+	assert_eq!(main["sections"][".text"]["code"], 0x50);
+	assert_eq!(main["symbols"][0]["name"], "rx_buffer");
+	assert_eq!(main["symbols"][1]["name"], "main");
+
+	let startup = file(&analysis, "../../../ESF/samd21/src/startup_samd21.o");
+	assert_eq!(startup["group"], "ESF");
+	assert_eq!(startup["sizes"]["code"], 0xb4 + 0x88);
+
+	let memset = file(&analysis,
+		"/opt/arm-none-eabi/lib/thumb/v6-m/nofp/libc_nano.a(lib_a-memset.o)");
+	assert_eq!(memset["group"], "libc_nano");
+	assert_eq!(memset["symbols"][0]["name"], "memset");
+
+	assert_eq!(file(&analysis, "*fill*")["group"], "(linker)");
+	// Sections that are not loaded on the target are left out
+	assert_eq!(analysis["total"], serde_json::json!({ "code": 0x1c0, "data": 0x10, "bss": 0x104 }));
+}
+
+#[test]
+fn views_and_tree_print()
+{
+	let fs = built_project();
+	for args in &[vec!["analyze", "--by", "file"], vec!["analyze", "--by", "section"],
+				  vec!["analyze", "--tree"], vec!["analyze", "--json"]]
+	{
+		run_igloo(&fs, Path::new("/work/blinky"), args).unwrap();
+	}
+}
+
+#[test]
+fn diff_keeps_previous_build()
+{
+	let fs = built_project();
+	run_igloo(&fs, Path::new("/work/blinky"), &["analyze"]).unwrap();
+
+	// Rebuild with a larger main
+	let map = fixture_map()
+		.replace(" .text.main     0x000020b4       0x40 src/main.o",
+				 " .text.main     0x000020b4       0x60 src/main.o");
+	fs.write(&Path::new(TARGET_DIR).join("blinky.map"), map.as_bytes()).unwrap();
+	run_igloo(&fs, Path::new("/work/blinky"), &["analyze", "--diff"]).unwrap();
+
+	assert_eq!(file(&analysis(&fs, "analyze.prev.json"), "src/main.o")["sizes"]["code"], 0x50);
+	assert_eq!(file(&analysis(&fs, "analyze.json"), "src/main.o")["sizes"]["code"], 0x70);
+
+	// Analyzing the same build again keeps the previous build
+	run_igloo(&fs, Path::new("/work/blinky"), &["analyze", "--diff"]).unwrap();
+	assert_eq!(file(&analysis(&fs, "analyze.prev.json"), "src/main.o")["sizes"]["code"], 0x50);
+}
+
+#[test]
+fn diff_against_other_build()
+{
+	let fs = built_project();
+	fs.add_file(Path::new("/work/blinky/old/blinky.map"), &fixture_map());
+	fs.write(Path::new("/work/blinky/old/blinky.elf"), &fixture_elf()).unwrap();
+	run_igloo(&fs, Path::new("/work/blinky"),
+			  &["analyze", "--base", "old/blinky.map"]).unwrap();
+	assert_eq!(run_igloo(&fs, Path::new("/work/blinky"),
+						 &["analyze", "--base", "missing/blinky.map"]),
+			   Err(FailedToReadMap));
+}
+
+#[test]
+fn missing_map_fails()
+{
+	let fs = fake_esf();
+	run_igloo(&fs, Path::new(WORK_DIR), &["new", "blinky", "-t", "samd21j18a"]).unwrap();
+	assert_eq!(run_igloo(&fs, Path::new("/work/blinky"), &["analyze"]), Err(FailedToReadMap));
+}
@@ -0,0 +1,122 @@
+use igloo_base::IglooErrType::*;
+use igloo_cli::*;
+use igloo_cli::IglooInstType::*;
+
+fn parse(args: &[&str]) -> Result<IglooInstType, igloo_base::IglooErrType>
+{
+	let mut full_args = vec!["igloo"];
+	full_args.extend_from_slice(args);
+	igloo_subcommand(&igloo_app_from(&full_args))
+}
+
+fn strings(values: &[&str]) -> Vec<String>
+{
+	values.iter().map(|v| String::from(*v)).collect()
+}
+
+#[test]
+fn new_and_init_parse_into_their_args()
+{
+	assert_eq!(parse(&["new", "blinky", "-t", "samd21j18a"]), Ok(New(NewArgs
+	{
+		name: String::from("blinky"),
+		target: String::from("samd21j18a"),
+		template: String::from("empty"),
+	})));
+	assert_eq!(parse(&["init", "-t", "samd21j18a", "--template", "blinky"]), Ok(Init(InitArgs
+	{
+		name: None,
+		target: String::from("samd21j18a"),
+		template: String::from("blinky"),
+	})));
+}
+
+#[test]
+fn target_selection_parses_for_every_target_command()
+{
+	let selection = TargetArgs
+	{
+		targets: strings(&["a", "b"]),
+		all_targets: false,
+	};
+	assert_eq!(parse(&["build", "-t", "a", "-t", "b", "--locked", "-j", "4"]), Ok(Build(BuildArgs
+	{
+		targets: selection.clone(),
+		locked: true,
+		jobs: 4,
+	})));
+	assert_eq!(parse(&["erase", "-t", "a", "-t", "b"]), Ok(Erase(selection)));
+	assert_eq!(parse(&["clean", "--all-targets", "--all"]), Ok(Clean(CleanArgs
+	{
+		targets: TargetArgs { targets: Vec::new(), all_targets: true },
+		all: true,
+	})));
+	assert_eq!(parse(&["size", "--top", "3"]), Ok(Size(SizeArgs
+	{
+		targets: TargetArgs::default(),
+		top: 3,
+		json: false,
+	})));
+}
+
+#[test]
+fn nested_commands_parse_into_their_variant()
+{
+	assert_eq!(parse(&["template", "list"]), Ok(Template(TemplateCmd::List)));
+	assert_eq!(parse(&["toolchain", "install", "arm-none-eabi-gcc@10"]),
+			   Ok(Toolchain(ToolchainCmd::Install(strings(&["arm-none-eabi-gcc@10"])))));
+	assert_eq!(parse(&["target", "default", "--unset"]),
+			   Ok(Target(TargetCmd::Default(TargetDefaultArgs { name: None, unset: true }))));
+	assert_eq!(parse(&["image", "merge", "a.bin", "b.bin", "-o", "all.bin", "--fill", "0x00"]),
+			   Ok(Image(ImageCmd::Merge(ImageMergeArgs
+			   {
+				   inputs: strings(&["a.bin", "b.bin"]),
+				   output: String::from("all.bin"),
+				   options: ImageOptionArgs
+				   {
+					   fill: Some(String::from("0x00")),
+					   ..ImageOptionArgs::default()
+				   },
+			   }))));
+}
+
+#[test]
+fn invalid_numbers_are_rejected_while_parsing()
+{
+	assert_eq!(parse(&["build", "-j", "0"]), Err(InvalidArgument));
+	assert_eq!(parse(&["build", "-j", "many"]), Err(InvalidArgument));
+	assert_eq!(parse(&["size", "--top", "ten"]), Err(InvalidArgument));
+}
+
+#[test]
+fn names_match_the_command_line()
+{
+	assert_eq!(parse(&["gendoc"]).unwrap().name(), "gendoc");
+	assert_eq!(parse(&["__complete", "targets"]).unwrap().name(), "__complete");
+}
+
+#[test]
+fn positional_targets_are_kept_next_to_the_target_option()
+{
+	let selection = TargetArgs
+	{
+		targets: strings(&["a", "b"]),
+		all_targets: false,
+	};
+	assert_eq!(parse(&["size", "a"]).unwrap(), parse(&["size", "-t", "a"]).unwrap());
+	match parse(&["analyze", "a", "b", "--tree"])
+	{
+		Ok(Analyze(args)) => assert_eq!(args.targets, selection),
+		v => panic!("{:?}", v),
+	}
+	match parse(&["image", "verify", "-t", "a", "-t", "b"])
+	{
+		Ok(Image(ImageCmd::Verify(args))) => assert_eq!(args.targets, selection),
+		v => panic!("{:?}", v),
+	}
+	match parse(&["gendoc", "--all-targets"])
+	{
+		Ok(GenDoc(args)) => assert!(args.targets.all_targets),
+		v => panic!("{:?}", v),
+	}
+}
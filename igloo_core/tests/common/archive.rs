@@ -0,0 +1,70 @@
+//! Builds small tar and tar.gz archives for tests
+
+pub enum Entry<'a>
+{
+	File(&'a str, &'a [u8]),
+	Exec(&'a str, &'a [u8]),
+	Dir(&'a str),
+	Symlink(&'a str, &'a str),
+	Hardlink(&'a str, &'a str),
+}
+
+fn header(path: &str, mode: u32, size: usize, kind: u8, link: &str) -> [u8; 512]
+{
+	let mut h = [0u8; 512];
+	h[..path.len()].copy_from_slice(path.as_bytes());
+	h[100..108].copy_from_slice(format!("{:07o}\0", mode).as_bytes());
+	h[108..116].copy_from_slice(b"0000000\0");
+	h[116..124].copy_from_slice(b"0000000\0");
+	h[124..136].copy_from_slice(format!("{:011o}\0", size).as_bytes());
+	h[136..148].copy_from_slice(b"00000000000\0");
+	h[156] = kind;
+	h[157..157 + link.len()].copy_from_slice(link.as_bytes());
+	h[257..263].copy_from_slice(b"ustar\0");
+	h[263..265].copy_from_slice(b"00");
+	checksum(&mut h);
+	h
+}
+
+/// Fills in the checksum of a tar header
+pub fn checksum(h: &mut [u8])
+{
+	h[148..156].copy_from_slice(b"        ");
+	let sum: u32 = h[..512].iter().map(|b| *b as u32).sum();
+	h[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+}
+
+pub fn tar(entries: &[Entry]) -> Vec<u8>
+{
+	let mut ret: Vec<u8> = Vec::new();
+	for entry in entries
+	{
+		let (path, mode, data, kind, link): (&str, u32, &[u8], u8, &str) = match entry
+		{
+			Entry::File(p, d) => (p, 0o644, d, b'0', ""),
+			Entry::Exec(p, d) => (p, 0o755, d, b'0', ""),
+			Entry::Dir(p) => (p, 0o755, &[], b'5', ""),
+			Entry::Symlink(p, l) => (p, 0o777, &[], b'2', l),
+			Entry::Hardlink(p, l) => (p, 0o644, &[], b'1', l),
+		};
+		ret.extend_from_slice(&header(path, mode, data.len(), kind, link));
+		ret.extend_from_slice(data);
+		ret.resize(ret.len().div_ceil(512) * 512, 0);
+	}
+	ret.resize(ret.len() + 1024, 0);
+	ret
+}
+
+pub fn gzip(data: &[u8]) -> Vec<u8>
+{
+	let mut ret = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+	ret.extend(miniz_oxide::deflate::compress_to_vec(data, 6));
+	ret.extend_from_slice(&igloo_base::crc32(data).to_le_bytes());
+	ret.extend_from_slice(&(data.len() as u32).to_le_bytes());
+	ret
+}
+
+pub fn tar_gz(entries: &[Entry]) -> Vec<u8>
+{
+	gzip(&tar(entries))
+}
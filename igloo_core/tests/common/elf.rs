@@ -0,0 +1,202 @@
+//! Builds small 32 bit little endian ARM ELF files for tests
+
+pub const SHF_WRITE: u32 = 0x1;
+pub const SHF_ALLOC: u32 = 0x2;
+pub const SHF_EXECINSTR: u32 = 0x4;
+pub const STT_OBJECT: u8 = 1;
+pub const STT_FUNC: u8 = 2;
+
+struct Section
+{
+	name: String,
+	kind: u32,
+	flags: u32,
+	addr: u32,
+	data: Vec<u8>,
+	size: u32,
+	link: u32,
+	entsize: u32,
+}
+
+#[derive(Default)]
+pub struct ElfBuilder
+{
+	sections: Vec<Section>,
+	symbols: Vec<(String, u32, u32, u8, String)>,
+	/// (section, load address)
+	loads: Vec<(String, u32)>,
+}
+
+fn push_u16(buf: &mut Vec<u8>, v: u16) { buf.extend_from_slice(&v.to_le_bytes()); }
+fn push_u32(buf: &mut Vec<u8>, v: u32) { buf.extend_from_slice(&v.to_le_bytes()); }
+
+impl ElfBuilder
+{
+	pub fn new() -> ElfBuilder
+	{
+		ElfBuilder::default()
+	}
+
+	/// Adds a section with contents
+	pub fn section(mut self, name: &str, addr: u32, flags: u32, data: &[u8]) -> ElfBuilder
+	{
+		self.sections.push(Section
+		{
+			name: name.to_string(), kind: 1, flags, addr,
+			data: data.to_vec(), size: data.len() as u32, link: 0, entsize: 0,
+		});
+		self
+	}
+
+	/// Adds a section without contents (.bss)
+	pub fn nobits(mut self, name: &str, addr: u32, flags: u32, size: u32) -> ElfBuilder
+	{
+		self.sections.push(Section
+		{
+			name: name.to_string(), kind: 8, flags, addr,
+			data: Vec::new(), size, link: 0, entsize: 0,
+		});
+		self
+	}
+
+	pub fn symbol(mut self, name: &str, value: u32, size: u32, kind: u8, section: &str)
+				  -> ElfBuilder
+	{
+		self.symbols.push((name.to_string(), value, size, kind, section.to_string()));
+		self
+	}
+
+	/// Adds a PT_LOAD segment for section, stored at paddr
+	pub fn load(mut self, section: &str, paddr: u32) -> ElfBuilder
+	{
+		self.loads.push((section.to_string(), paddr));
+		self
+	}
+
+	/// Adds a PT_LOAD segment for every allocated section with contents,
+	/// stored at its own address
+	pub fn load_all(mut self) -> ElfBuilder
+	{
+		let loads: Vec<(String, u32)> = self.sections.iter()
+			.filter(|s| s.flags & SHF_ALLOC != 0 && s.kind != 8)
+			.map(|s| (s.name.clone(), s.addr))
+			.collect();
+		self.loads.extend(loads);
+		self
+	}
+
+	pub fn build(mut self) -> Vec<u8>
+	{
+		// index 0 is the null section, user sections start at 1
+		let section_index = |name: &str, sections: &Vec<Section>| -> u16
+		{
+			sections.iter().position(|s| s.name == name).unwrap() as u16 + 1
+		};
+
+		let mut strtab: Vec<u8> = vec![0];
+		let mut symtab: Vec<u8> = vec![0; 16];
+		for (name, value, size, kind, section) in &self.symbols
+		{
+			push_u32(&mut symtab, strtab.len() as u32);
+			strtab.extend_from_slice(name.as_bytes());
+			strtab.push(0);
+			let value = if *kind == STT_FUNC { value | 1 } else { *value };
+			push_u32(&mut symtab, value);
+			push_u32(&mut symtab, *size);
+			symtab.push(0x10 | kind);
+			symtab.push(0);
+			push_u16(&mut symtab, section_index(section, &self.sections));
+		}
+		let user_sections = self.sections.len() as u32;
+		self.sections.push(Section
+		{
+			name: String::from(".symtab"), kind: 2, flags: 0, addr: 0,
+			size: symtab.len() as u32, data: symtab, link: user_sections + 2, entsize: 16,
+		});
+		self.sections.push(Section
+		{
+			name: String::from(".strtab"), kind: 3, flags: 0, addr: 0,
+			size: strtab.len() as u32, data: strtab, link: 0, entsize: 0,
+		});
+		let mut shstrtab: Vec<u8> = vec![0];
+		let mut name_offs: Vec<u32> = Vec::new();
+		for section in &self.sections
+		{
+			name_offs.push(shstrtab.len() as u32);
+			shstrtab.extend_from_slice(section.name.as_bytes());
+			shstrtab.push(0);
+		}
+		name_offs.push(shstrtab.len() as u32);
+		shstrtab.extend_from_slice(b".shstrtab\0");
+		self.sections.push(Section
+		{
+			name: String::from(".shstrtab"), kind: 3, flags: 0, addr: 0,
+			size: shstrtab.len() as u32, data: shstrtab, link: 0, entsize: 0,
+		});
+
+		let phoff = 52u32;
+		let mut offset = phoff + 32 * self.loads.len() as u32;
+		let mut offsets: Vec<u32> = Vec::new();
+		for section in &self.sections
+		{
+			offset = (offset + 3) & !3;
+			offsets.push(offset);
+			offset += section.data.len() as u32;
+		}
+		let shoff = (offset + 3) & !3;
+		let shnum = self.sections.len() as u16 + 1;
+
+		let mut elf: Vec<u8> = Vec::new();
+		elf.extend_from_slice(b"\x7fELF\x01\x01\x01\0\0\0\0\0\0\0\0\0");
+		push_u16(&mut elf, 2);
+		push_u16(&mut elf, 40);
+		push_u32(&mut elf, 1);
+		push_u32(&mut elf, 0);
+		push_u32(&mut elf, phoff);
+		push_u32(&mut elf, shoff);
+		push_u32(&mut elf, 0x05000000);
+		push_u16(&mut elf, 52);
+		push_u16(&mut elf, 32);
+		push_u16(&mut elf, self.loads.len() as u16);
+		push_u16(&mut elf, 40);
+		push_u16(&mut elf, shnum);
+		push_u16(&mut elf, shnum - 1);
+
+		for (name, paddr) in &self.loads
+		{
+			let i = self.sections.iter().position(|s| &s.name == name).unwrap();
+			let section = &self.sections[i];
+			push_u32(&mut elf, 1);
+			push_u32(&mut elf, offsets[i]);
+			push_u32(&mut elf, section.addr);
+			push_u32(&mut elf, *paddr);
+			push_u32(&mut elf, section.data.len() as u32);
+			push_u32(&mut elf, section.size);
+			push_u32(&mut elf, 5);
+			push_u32(&mut elf, 4);
+		}
+
+		for (section, off) in self.sections.iter().zip(&offsets)
+		{
+			elf.resize(*off as usize, 0);
+			elf.extend_from_slice(&section.data);
+		}
+		elf.resize(shoff as usize, 0);
+
+		elf.extend_from_slice(&[0; 40]);
+		for (i, section) in self.sections.iter().enumerate()
+		{
+			push_u32(&mut elf, name_offs[i]);
+			push_u32(&mut elf, section.kind);
+			push_u32(&mut elf, section.flags);
+			push_u32(&mut elf, section.addr);
+			push_u32(&mut elf, offsets[i]);
+			push_u32(&mut elf, section.size);
+			push_u32(&mut elf, section.link);
+			push_u32(&mut elf, 0);
+			push_u32(&mut elf, 4);
+			push_u32(&mut elf, section.entsize);
+		}
+		elf
+	}
+}
@@ -0,0 +1,100 @@
+#![allow(dead_code)]
+
+pub mod archive;
+pub mod elf;
+
+use igloo_base::*;
+use igloo_cli::igloo_app_from;
+use igloo_core::Igloo;
+
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+pub const ESF_DIR: &str = "/esf";
+pub const WORK_DIR: &str = "/work";
+pub const HOME_DIR: &str = "/home/penguin";
+
+/// ESF fixture on disk with two SAMD21 targets and a blinky template
+pub fn fixture_dir() -> PathBuf
+{
+	PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/esf")
+}
+
+/// Loads the fixture ESF into a MemFs at ESF_DIR
+pub fn fake_esf() -> Rc<MemFs>
+{
+	let fs = Rc::new(MemFs::new());
+	load_dir(&fs, &fixture_dir(), Path::new(ESF_DIR));
+	fs.create_dir_all(Path::new(WORK_DIR)).unwrap();
+	fs
+}
+
+fn load_dir(fs: &MemFs, from: &Path, to: &Path)
+{
+	for entry in std::fs::read_dir(from).unwrap()
+	{
+		let path = entry.unwrap().path();
+		let dest = to.join(path.file_name().unwrap());
+		if path.is_dir()
+		{
+			load_dir(fs, &path, &dest);
+		}
+		else
+		{
+			fs.add_file(&dest, &std::fs::read_to_string(&path).unwrap());
+		}
+	}
+}
+
+pub fn env_info(cwd: &Path) -> IglooEnvInfo
+{
+	IglooEnvInfo
+	{
+		cwd: cwd.to_path_buf(),
+		hd: PathBuf::from(HOME_DIR),
+		esfd: PathBuf::from(ESF_DIR),
+	}
+}
+
+/// Runs igloo with args from cwd against fs
+pub fn run_igloo(fs: &Rc<MemFs>, cwd: &Path, args: &[&str])
+				 -> Result<String, IglooErrType>
+{
+	run_igloo_with(fs, Rc::new(MemHost::new()), env_info(cwd), args)
+}
+
+/// Creates the project blinky for target in WORK_DIR on the fixture ESF
+pub fn new_project(target: &str) -> Rc<MemFs>
+{
+	let fs = fake_esf();
+	run_igloo(&fs, Path::new(WORK_DIR), &["new", "blinky", "-t", target]).unwrap();
+	fs
+}
+
+/// Runs igloo with args against fs and host in the environment env
+pub fn run_igloo_with(fs: &Rc<MemFs>, host: Rc<MemHost>, env: IglooEnvInfo, args: &[&str])
+					  -> Result<String, IglooErrType>
+{
+	let mut full_args = vec!["igloo"];
+	full_args.extend_from_slice(args);
+	let mut ig = Igloo::from_parts(igloo_app_from(&full_args), env, fs.clone())
+		.with_host(host);
+	let inst_type = ig.start()?;
+	ig.run(inst_type)
+}
+
+pub fn read(fs: &Rc<MemFs>, path: &str) -> String
+{
+	fs.read_to_string(Path::new(path)).unwrap()
+}
+
+/// Runs igloo with --message-format json, returning the JSON messages it emitted
+pub fn run_igloo_json(fs: &Rc<MemFs>, host: Rc<MemHost>, env: IglooEnvInfo, args: &[&str])
+					  -> (Result<String, IglooErrType>, Vec<serde_json::Value>)
+{
+	let mut full_args = vec!["--message-format", "json"];
+	full_args.extend_from_slice(args);
+	take_messages();
+	let res = run_igloo_with(fs, host, env, &full_args);
+	(res, take_messages())
+}
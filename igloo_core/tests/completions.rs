@@ -0,0 +1,67 @@
+mod common;
+
+use common::*;
+use igloo_base::*;
+use igloo_cli::completion_script;
+
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+const HOOK: &str = "igloo __complete targets 2>/dev/null";
+
+#[test]
+fn bash_completes_targets_with_the_hook()
+{
+	let script = completion_script("bash").unwrap();
+	assert!(script.contains("complete -F _igloo"));
+	assert!(script.contains("retarget)"));
+	let hook = format!("COMPREPLY=($(compgen -W \"$({})\" -- \"${{cur}}\"))", HOOK);
+	assert!(script.contains(&format!("--target)\n                    {}", hook)));
+	assert!(script.contains(&format!("-t)\n                    {}", hook)));
+	// other options still complete file names
+	assert!(script.contains("--jobs)\n                    COMPREPLY=($(compgen -f \"${cur}\"))"));
+	// the hook itself is hidden
+	assert!(!script.contains("__complete)"));
+}
+
+#[test]
+fn zsh_and_fish_complete_targets_with_the_hook()
+{
+	let zsh = completion_script("zsh").unwrap();
+	assert!(zsh.starts_with("#compdef igloo"));
+	assert!(zsh.contains("'-t+[MCU Target]:target:_igloo_targets' \\\n"));
+	assert!(zsh.contains("'--target=[MCU Target]:target:_igloo_targets' \\\n"));
+	assert!(zsh.contains(&format!(
+		"_igloo_targets() {{\n    local -a targets\n    targets=(${{(f)\"$({})\"}})\n", HOOK)));
+	assert!(zsh.trim_end().ends_with("_igloo \"$@\""));
+
+	let fish = completion_script("fish").unwrap();
+	for sub in &["new", "init", "regen", "build", "flash", "size", "clean"]
+	{
+		assert!(fish.contains(&format!(
+			"complete -c igloo -n \"__fish_seen_subcommand_from {}\" -s t -l target -f -a \"({})\"\n",
+			sub, HOOK)));
+	}
+	assert!(!fish.contains("__fish_seen_subcommand_from targets\" -s t"));
+	assert_eq!(completion_script("tcsh"), None);
+}
+
+#[test]
+fn completions_need_no_esf()
+{
+	let fs = Rc::new(MemFs::new());
+	let env = IglooEnvInfo
+	{
+		cwd: PathBuf::from(WORK_DIR),
+		hd: PathBuf::from(HOME_DIR),
+		esfd: PathBuf::new(),
+	};
+	run_igloo_with(&fs, Rc::new(MemHost::new()), env.clone(), &["completions", "fish"]).unwrap();
+	assert_eq!(run_igloo_with(&fs, Rc::new(MemHost::new()), env, &["__complete", "targets"]),
+			   Err(IglooErrType::InvalidEnvInfo));
+
+	let fs = fake_esf();
+	run_igloo(&fs, Path::new(WORK_DIR), &["__complete", "targets"]).unwrap();
+	run_igloo(&fs, Path::new(WORK_DIR), &["new", "blinky", "-t", "samd21j18a"]).unwrap();
+	run_igloo(&fs, Path::new("/work/blinky"), &["__complete", "targets"]).unwrap();
+}
@@ -0,0 +1,117 @@
+mod common;
+
+use common::*;
+use igloo_base::*;
+use igloo_base::IglooErrType::*;
+
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// A machine with the arm toolchain, openocd and make but without ninja
+fn healthy_host() -> Rc<MemHost>
+{
+	let host = Rc::new(MemHost::new());
+	for tool in &["gcc", "g++", "as", "objcopy", "objdump", "size"]
+	{
+		host.add_program(&format!("arm-none-eabi-{}", tool),
+						 &format!("arm-none-eabi-{} (GNU Arm Embedded Toolchain 10.3-2021.10) \
+								   10.3.1 20210824 (release)\n", tool));
+	}
+	host.add_program("arm-none-eabi-gdb", "GNU gdb (GNU Arm Embedded Toolchain 10.3-2021.10) \
+										   10.2.90.20210621-git\n");
+	host.add_program("openocd", "Open On-Chip Debugger 0.11.0\n");
+	host.add_program("make", "GNU Make 4.3\n");
+	host
+}
+
+fn doctor(fs: &Rc<MemFs>, host: &Rc<MemHost>) -> Result<String, IglooErrType>
+{
+	run_igloo_with(fs, host.clone(), env_info(Path::new(WORK_DIR)), &["doctor"])
+}
+
+#[test]
+fn healthy_environment_passes()
+{
+	let fs = fake_esf();
+	let host = healthy_host();
+	assert!(doctor(&fs, &host).is_ok());
+	assert!(run_igloo_with(&fs, host, env_info(Path::new(WORK_DIR)),
+						   &["doctor", "--json"]).is_ok());
+}
+
+#[test]
+fn missing_compiler_fails()
+{
+	let fs = fake_esf();
+	let host = healthy_host();
+	host.remove_program("arm-none-eabi-gcc");
+	assert_eq!(doctor(&fs, &host), Err(DoctorFoundProblems));
+}
+
+#[test]
+fn missing_debug_tools_only_warn()
+{
+	let fs = fake_esf();
+	let host = healthy_host();
+	host.remove_program("arm-none-eabi-gdb");
+	host.remove_program("openocd");
+	assert!(doctor(&fs, &host).is_ok());
+}
+
+#[test]
+fn missing_make_and_symlinks_fail()
+{
+	let fs = fake_esf();
+	let host = healthy_host();
+	host.remove_program("make");
+	assert_eq!(doctor(&fs, &host), Err(DoctorFoundProblems));
+
+	let host = healthy_host();
+	host.set_symlinks_supported(false);
+	assert_eq!(doctor(&fs, &host), Err(DoctorFoundProblems));
+}
+
+#[test]
+fn reports_missing_esf_instead_of_exiting()
+{
+	let fs = fake_esf();
+	let mut env = env_info(Path::new(WORK_DIR));
+	env.esfd = PathBuf::new();
+	assert_eq!(run_igloo_with(&fs, healthy_host(), env.clone(), &["doctor"]),
+			   Err(DoctorFoundProblems));
+	// every other command refuses to run
+	assert_eq!(run_igloo_with(&fs, healthy_host(), env, &["new", "blinky", "-t", "samd21j18a"]),
+			   Err(InvalidEnvInfo));
+
+	let mut env = env_info(Path::new(WORK_DIR));
+	env.esfd = PathBuf::from("/nowhere");
+	assert_eq!(run_igloo_with(&fs, healthy_host(), env, &["doctor"]), Err(DoctorFoundProblems));
+}
+
+#[test]
+fn reports_broken_manifests()
+{
+	let fs = fake_esf();
+	fs.add_file(Path::new("/esf/manifest/make-manifest.toml"), "[arm\nCC = ");
+	assert_eq!(doctor(&fs, &healthy_host()), Err(DoctorFoundProblems));
+}
+
+#[test]
+fn checks_only_project_targets_inside_a_project()
+{
+	let fs = fake_esf();
+	run_igloo(&fs, Path::new(WORK_DIR), &["new", "blinky", "-t", "samd21j18a"]).unwrap();
+	// a target of another architecture whose toolchain is not installed
+	let make_manifest = read(&fs, "/esf/manifest/make-manifest.toml")
+		+ "\n[riscv]\nTOOLCHAIN = \"riscv64-unknown-elf-\"\nCC = \"riscv64-unknown-elf-gcc\"\n\
+		   \n[riscv.fe310]\nMCU = \"FE310\"\n";
+	fs.add_file(Path::new("/esf/manifest/make-manifest.toml"), &make_manifest);
+	let target_manifest = read(&fs, "/esf/manifest/target-manifest.toml")
+		.replace("[target.manifest]\n", "fe310 = \"riscv.fe310\"\n\n[target.manifest]\n\
+										  fe310 = \"manifest/samd21g18a.toml\"\n");
+	fs.add_file(Path::new("/esf/manifest/target-manifest.toml"), &target_manifest);
+	let host = healthy_host();
+
+	assert_eq!(doctor(&fs, &host), Err(DoctorFoundProblems));
+	assert!(run_igloo_with(&fs, host, env_info(Path::new("/work/blinky")), &["doctor"]).is_ok());
+}
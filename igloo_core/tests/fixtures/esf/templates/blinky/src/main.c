@@ -0,0 +1 @@
+/* {{PROJECT_NAME}} {{TARGET}} {{MCU}} {{CLOCK}} */
@@ -0,0 +1,121 @@
+//! Renders every target of the fixture ESF and compares the generated
+//! build files with the golden files in tests/golden/<target>.
+//! Run with IGLOO_BLESS=1 to rewrite the golden files after an intended change.
+mod common;
+
+use common::*;
+use igloo_base::*;
+
+use config::Config;
+use std::path::{Path, PathBuf};
+
+fn golden_dir() -> PathBuf
+{
+	PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn fixture_targets() -> Vec<String>
+{
+	let mut master_tm = Config::new();
+	master_tm.merge(config::File::from(
+		fixture_dir().join("manifest/target-manifest.toml"))).unwrap();
+	let mut ret: Vec<String> = master_tm.get_table("target.make").unwrap()
+		.into_keys()
+		.collect();
+	ret.sort();
+	ret
+}
+
+/// true if the target manifest of target has an esf.memory table, which is
+/// what gets a target a linker script
+fn has_memory_map(target: &str) -> bool
+{
+	let mut master_tm = Config::new();
+	master_tm.merge(config::File::from(
+		fixture_dir().join("manifest/target-manifest.toml"))).unwrap();
+	let manifest: String = master_tm.get(&format!("target.manifest.{}", target)).unwrap();
+	let mut target_man = Config::new();
+	target_man.merge(config::File::from(fixture_dir().join(manifest))).unwrap();
+	target_man.get_table("esf.memory").is_ok()
+}
+
+#[test]
+fn generated_build_files_match_golden_files()
+{
+	let bless = std::env::var("IGLOO_BLESS").is_ok();
+	let mut mismatches: Vec<String> = Vec::new();
+	let targets = fixture_targets();
+	assert!(!targets.is_empty());
+
+	for target in &targets
+	{
+		let fs = fake_esf();
+		run_igloo(&fs, Path::new(WORK_DIR), &["new", "golden", "-t", target]).unwrap();
+
+		let target_root = format!("/work/golden/.igloo/target/{}", target);
+		let linker_script = format!("{}.ld", target);
+		let linker_path = format!("{}/{}", target_root, linker_script);
+		let mut generated = vec![
+			(format!("{}/Makefile", target_root), "Makefile"),
+			(String::from("/work/golden/inc/igloo.h"), "igloo.h"),
+		];
+		// Only targets with a memory map get a linker script
+		if has_memory_map(target)
+		{
+			generated.push((linker_path, linker_script.as_str()));
+		}
+		else
+		{
+			assert!(!fs.exists(Path::new(&linker_path)), "{} has no memory map", target);
+		}
+		for (path, golden_name) in &generated
+		{
+			let contents = match fs.read_to_string(Path::new(path))
+			{
+				Ok(v) => v,
+				Err(e) => panic!("{} was not generated: {:?}", path, e),
+			};
+			let golden_path = golden_dir().join(target).join(golden_name);
+			if bless
+			{
+				std::fs::create_dir_all(golden_path.parent().unwrap()).unwrap();
+				std::fs::write(&golden_path, &contents).unwrap();
+				continue
+			}
+			let golden = match std::fs::read_to_string(&golden_path)
+			{
+				Ok(v) => v,
+				Err(_e) =>
+				{
+					mismatches.push(format!("{}: missing golden file", golden_path.display()));
+					continue
+				}
+			};
+			if golden != contents
+			{
+				mismatches.push(format!("{}:\n{}", golden_path.display(),
+										diff_lines(&golden, &contents)));
+			}
+		}
+	}
+
+	assert!(mismatches.is_empty(),
+			"generated files differ from golden files \
+			 (rerun with IGLOO_BLESS=1 if intended):\n{}", mismatches.join("\n"));
+}
+
+#[test]
+fn generation_is_deterministic()
+{
+	for target in &fixture_targets()
+	{
+		let mut renders: Vec<String> = Vec::new();
+		for _i in 0..4
+		{
+			let fs = fake_esf();
+			run_igloo(&fs, Path::new(WORK_DIR), &["new", "golden", "-t", target]).unwrap();
+			renders.push(read(&fs, &format!("/work/golden/.igloo/target/{}/Makefile", target)));
+		}
+		assert!(renders.windows(2).all(|w| w[0] == w[1]));
+	}
+}
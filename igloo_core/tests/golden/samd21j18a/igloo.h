@@ -0,0 +1,22 @@
+/* igloo.h -- Generated by igloo. Edit only between the IGLOO_H_USER markers */
+#ifndef IGLOO_H
+#define IGLOO_H
+
+#ifdef IGLOO_TARGET_SAMD21J18A
+	#define IGLOO_TARGET_NAME "samd21j18a"
+	#define IGLOO_BOARD_CLOCK 48000000
+	#define IGLOO_BOARD_NAME "samd21j18a test board"
+	#define IGLOO_BOARD_PACKAGE "TQFP64"
+#endif
+
+#if defined(__SAMD21J18A__)
+	#include "samd21j18a.h"
+#else
+	#error "igloo.h: no known MCU is defined. Build this project through its igloo generated Makefile."
+#endif
+
+/* IGLOO_H_USER_BEGIN */
+
+/* IGLOO_H_USER_END */
+
+#endif /* IGLOO_H */
@@ -0,0 +1,21 @@
+/* igloo.h -- Generated by igloo. Edit only between the IGLOO_H_USER markers */
+#ifndef IGLOO_H
+#define IGLOO_H
+
+#ifdef IGLOO_TARGET_STM32F407VG
+	#define IGLOO_TARGET_NAME "stm32f407vg"
+	#define IGLOO_BOARD_CLOCK 168000000
+	#define IGLOO_BOARD_NAME "STM32F4DISCOVERY"
+#endif
+
+#if defined(STM32F407xx)
+	#include "stm32f4xx.h"
+#else
+	#error "igloo.h: no known MCU is defined. Build this project through its igloo generated Makefile."
+#endif
+
+/* IGLOO_H_USER_BEGIN */
+
+/* IGLOO_H_USER_END */
+
+#endif /* IGLOO_H */
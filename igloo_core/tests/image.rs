@@ -0,0 +1,193 @@
+mod common;
+
+use common::*;
+use common::elf::*;
+use igloo_base::*;
+use igloo_base::IglooErrType::*;
+
+use std::path::Path;
+use std::rc::Rc;
+
+const TARGET_DIR: &str = "/work/blinky/.igloo/target/samd21j18a";
+
+/// 0x30 bytes of code at 0x2000 followed by 0x10 bytes of initialized
+/// data stored in flash at 0x2030 and copied to RAM at startup
+fn firmware() -> Vec<u8>
+{
+	let text: Vec<u8> = (0..0x30).collect();
+	ElfBuilder::new()
+		.section(".text", 0x2000, SHF_ALLOC | SHF_EXECINSTR, &text)
+		.section(".relocate", 0x2000_0000, SHF_ALLOC | SHF_WRITE, &[0xd0; 0x10])
+		.nobits(".bss", 0x2000_0010, SHF_ALLOC | SHF_WRITE, 0x100)
+		.load(".text", 0x2000)
+		.load(".relocate", 0x2030)
+		.build()
+}
+
+fn firmware_bin() -> Vec<u8>
+{
+	let mut ret: Vec<u8> = (0..0x30).collect();
+	ret.extend_from_slice(&[0xd0; 0x10]);
+	ret
+}
+
+fn built_project() -> Rc<MemFs>
+{
+	let fs = fake_esf();
+	run_igloo(&fs, Path::new(WORK_DIR), &["new", "blinky", "-t", "samd21j18a"]).unwrap();
+	fs.write(&Path::new(TARGET_DIR).join("blinky.elf"), &firmware()).unwrap();
+	fs
+}
+
+fn read_bytes(fs: &Rc<MemFs>, path: &str) -> Vec<u8>
+{
+	fs.read(Path::new(path)).unwrap()
+}
+
+#[test]
+fn converts_elf_to_every_format()
+{
+	let fs = built_project();
+	run_igloo(&fs, Path::new("/work/blinky"),
+			  &["image", "convert", "-f", "hex", "-f", "srec", "-f", "bin", "-f", "uf2"]).unwrap();
+
+	let hex = read(&fs, &format!("{}/blinky.hex", TARGET_DIR));
+	let lines: Vec<&str> = hex.lines().collect();
+	assert_eq!(lines[0], ":020000040000FA");
+	assert_eq!(lines[1], ":10200000000102030405060708090A0B0C0D0E0F58");
+	assert_eq!(lines[4], ":10203000D0D0D0D0D0D0D0D0D0D0D0D0D0D0D0D0A0");
+	assert_eq!(lines[5], ":00000001FF");
+
+	let srec = read(&fs, &format!("{}/blinky.srec", TARGET_DIR));
+	let lines: Vec<&str> = srec.lines().collect();
+	assert!(lines[0].starts_with("S0"));
+	assert_eq!(lines[1], "S1132000000102030405060708090A0B0C0D0E0F54");
+	assert_eq!(lines[5], "S5030004F8");
+	assert_eq!(lines[6], "S9030000FC");
+
+	assert_eq!(read_bytes(&fs, &format!("{}/blinky.bin", TARGET_DIR)), firmware_bin());
+
+	let uf2 = read_bytes(&fs, &format!("{}/blinky.uf2", TARGET_DIR));
+	assert_eq!(uf2.len(), 512);
+	assert_eq!(&uf2[0..8], &[0x55, 0x46, 0x32, 0x0a, 0x57, 0x51, 0x5d, 0x9e]);
+	// family id flag, target address and the SAMD21 family from the target manifest
+	assert_eq!(&uf2[8..16], &[0x00, 0x20, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00]);
+	assert_eq!(&uf2[28..32], &0x68ed2b88u32.to_le_bytes());
+	assert_eq!(&uf2[32..32 + 0x40], firmware_bin().as_slice());
+	assert_eq!(&uf2[508..512], &[0x30, 0x6f, 0xb1, 0x0a]);
+}
+
+#[test]
+fn bin_fill_and_padding()
+{
+	let fs = built_project();
+	run_igloo(&fs, Path::new("/work/blinky"),
+			  &["image", "convert", "-f", "bin", "--fill", "0", "--pad-to", "0x100",
+				"-o", "padded.bin"]).unwrap();
+	let bin = read_bytes(&fs, "/work/blinky/padded.bin");
+	assert_eq!(bin.len(), 0x100);
+	assert_eq!(&bin[..0x40], firmware_bin().as_slice());
+	assert!(bin[0x40..].iter().all(|b| *b == 0));
+
+	// the fill is a single byte
+	assert_eq!(run_igloo(&fs, Path::new("/work/blinky"),
+						 &["image", "convert", "-f", "bin", "--fill", "0x100"]),
+			   Err(InvalidImage));
+}
+
+#[test]
+fn every_format_round_trips()
+{
+	let fs = built_project();
+	run_igloo(&fs, Path::new("/work/blinky"),
+			  &["image", "convert", "-f", "hex", "-f", "srec", "-f", "bin", "-f", "uf2"]).unwrap();
+	let hex = read(&fs, &format!("{}/blinky.hex", TARGET_DIR));
+
+	for input in &["blinky.srec", "blinky.bin@0x2000", "blinky.elf"]
+	{
+		run_igloo(&fs, Path::new(TARGET_DIR),
+				  &["image", "merge", input, "-o", "copy.hex"]).unwrap();
+		assert_eq!(read(&fs, &format!("{}/copy.hex", TARGET_DIR)), hex, "{}", input);
+	}
+
+	// UF2 blocks are whole pages, the rest of the last page reads back as fill
+	run_igloo(&fs, Path::new(TARGET_DIR),
+			  &["image", "merge", "blinky.uf2", "-o", "copy.bin"]).unwrap();
+	let bin = read_bytes(&fs, &format!("{}/copy.bin", TARGET_DIR));
+	assert_eq!(bin.len(), 0x100);
+	assert_eq!(&bin[..0x40], firmware_bin().as_slice());
+	assert!(bin[0x40..].iter().all(|b| *b == 0xff));
+}
+
+#[test]
+fn merges_bootloader_and_application()
+{
+	let fs = built_project();
+	fs.write(Path::new("/work/blinky/boot.bin"), &[0x11; 0x100]).unwrap();
+	run_igloo(&fs, Path::new("/work/blinky"), &["image", "convert", "-f", "hex"]).unwrap();
+	run_igloo(&fs, Path::new("/work/blinky"),
+			  &["image", "merge", "boot.bin@0", ".igloo/target/samd21j18a/blinky.hex",
+				"-o", "full.bin"]).unwrap();
+
+	let full = read_bytes(&fs, "/work/blinky/full.bin");
+	assert_eq!(full.len(), 0x2040);
+	assert!(full[..0x100].iter().all(|b| *b == 0x11));
+	assert!(full[0x100..0x2000].iter().all(|b| *b == 0xff));
+	assert_eq!(&full[0x2000..], firmware_bin().as_slice());
+}
+
+#[test]
+fn merge_rejects_overlapping_images()
+{
+	let fs = built_project();
+	fs.write(Path::new("/work/blinky/other.bin"), &[0x22; 0x10]).unwrap();
+	assert_eq!(run_igloo(&fs, Path::new("/work/blinky"),
+						 &["image", "merge", ".igloo/target/samd21j18a/blinky.elf",
+						   "other.bin@0x2008", "-o", "full.hex"]),
+			   Err(ImageOverlap));
+	assert!(!fs.exists(Path::new("/work/blinky/full.hex")));
+}
+
+#[test]
+fn rejects_corrupt_hex()
+{
+	let fs = built_project();
+	fs.add_file(Path::new("/work/blinky/bad.hex"), ":10200000000102030405060708090A0B0C0D0E0F00\n");
+	assert_eq!(run_igloo(&fs, Path::new("/work/blinky"),
+						 &["image", "merge", "bad.hex", "-o", "out.bin"]),
+			   Err(InvalidImage));
+}
+
+#[test]
+fn rejects_corrupt_srec_and_elf()
+{
+	let fs = built_project();
+	fs.add_file(Path::new("/work/blinky/bad.srec"), "S\u{e9}00\n");
+	assert_eq!(run_igloo(&fs, Path::new("/work/blinky"),
+						 &["image", "merge", "bad.srec", "-o", "out.bin"]),
+			   Err(InvalidImage));
+
+	// 64-bit ELF with a loadable segment at the end of the file offsets
+	let mut elf = vec![0u8; 120];
+	elf[0..8].copy_from_slice(b"\x7fELF\x02\x01\x01\x00");
+	elf[18] = 40;
+	elf[32] = 64;
+	elf[54] = 56;
+	elf[56] = 1;
+	elf[64] = 1;
+	elf[72..80].copy_from_slice(&u64::MAX.to_le_bytes());
+	elf[96] = 1;
+	fs.write(Path::new("/work/blinky/bad.elf"), &elf).unwrap();
+	assert_eq!(run_igloo(&fs, Path::new("/work/blinky"),
+						 &["image", "merge", "bad.elf", "-o", "out.bin"]),
+			   Err(InvalidImage));
+}
+
+#[test]
+fn convert_needs_a_build()
+{
+	let fs = fake_esf();
+	run_igloo(&fs, Path::new(WORK_DIR), &["new", "blinky", "-t", "samd21j18a"]).unwrap();
+	assert_eq!(run_igloo(&fs, Path::new("/work/blinky"), &["image", "convert", "-f", "hex"]),
+			   Err(InvalidImage));
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use common::*;
+use igloo_base::*;
+use igloo_base::IglooErrType::*;
+
+use std::path::Path;
+use std::rc::Rc;
+
+const PRJ_FILE: &str = "/work/blinky/.igloo/blinky.toml";
+const LINKER_SCRIPT: &str = "/work/blinky/.igloo/target/samd21j18a/samd21j18a.ld";
+
+fn add_carve_out(fs: &Rc<MemFs>, carve_out: &str)
+{
+	let prj_file = read(fs, PRJ_FILE) + carve_out;
+	fs.write(Path::new(PRJ_FILE), prj_file.as_bytes()).unwrap();
+}
+
+#[test]
+fn bootloader_reservation_moves_flash_origin()
+{
+	let fs = new_project("samd21j18a");
+	let linker_script = read(&fs, LINKER_SCRIPT);
+	assert!(linker_script.contains("\tFLASH (rx) : ORIGIN = 0x00002000, LENGTH = 0x0003E000\n"));
+	assert!(linker_script.contains("\tRAM (rwx) : ORIGIN = 0x20000000, LENGTH = 0x00008000\n"));
+}
+
+#[test]
+fn project_carve_outs_shrink_regions()
+{
+	let fs = new_project("samd21j18a");
+	add_carve_out(&fs, "\n[target.samd21j18a.memory.flash]\noffset = \"16K\"\nreserve = 0x1000\n\
+						\n[target.samd21j18a.memory.ram]\nreserve = \"1K\"\n");
+
+	assert_eq!(run_igloo(&fs, Path::new("/work/blinky"), &["make", "regen", "--check"]),
+			   Err(BuildFilesOutOfDate));
+	run_igloo(&fs, Path::new("/work/blinky"), &["make", "regen"]).unwrap();
+
+	let linker_script = read(&fs, LINKER_SCRIPT);
+	assert!(linker_script.contains("\tFLASH (rx) : ORIGIN = 0x00004000, LENGTH = 0x0003B000\n"));
+	assert!(linker_script.contains("\tRAM (rwx) : ORIGIN = 0x20000000, LENGTH = 0x00007C00\n"));
+}
+
+#[test]
+fn carve_out_larger_than_region_is_rejected()
+{
+	let fs = new_project("samd21j18a");
+	add_carve_out(&fs, "\n[target.samd21j18a.memory.ram]\noffset = \"32K\"\nreserve = \"1K\"\n");
+	assert_eq!(run_igloo(&fs, Path::new("/work/blinky"), &["make", "regen"]),
+			   Err(InvalidMemoryMap));
+}
+
+#[test]
+fn carve_out_of_unknown_region_is_rejected()
+{
+	let fs = new_project("samd21j18a");
+	add_carve_out(&fs, "\n[target.samd21j18a.memory.ccm]\noffset = \"1K\"\n");
+	assert_eq!(run_igloo(&fs, Path::new("/work/blinky"), &["make", "regen"]),
+			   Err(InvalidMemoryMap));
+}
+
+#[test]
+fn overlapping_regions_are_rejected()
+{
+	let fs = fake_esf();
+	let manifest = read(&fs, "/esf/manifest/samd21j18a.toml")
+		+ "\n[esf.memory.sram2]\norigin = 0x20004000\nlength = \"16K\"\n";
+	fs.write(Path::new("/esf/manifest/samd21j18a.toml"), manifest.as_bytes()).unwrap();
+	assert_eq!(run_igloo(&fs, Path::new(WORK_DIR), &["new", "blinky", "-t", "samd21j18a"]),
+			   Err(InvalidMemoryMap));
+}
+
+#[test]
+fn clean_all_removes_linker_script()
+{
+	let fs = new_project("samd21j18a");
+	run_igloo(&fs, Path::new("/work/blinky"), &["clean", "--all"]).unwrap();
+	assert!(!fs.exists(Path::new(LINKER_SCRIPT)));
+}
+
+#[test]
+fn sizes_past_the_address_space_are_rejected()
+{
+	let fs = new_project("samd21j18a");
+	add_carve_out(&fs, "\n[target.samd21j18a.memory.flash]\nreserve = \"0xFFFFFFFFFFFFM\"\n");
+	assert_eq!(run_igloo(&fs, Path::new("/work/blinky"), &["make", "regen"]),
+			   Err(InvalidMemoryMap));
+
+	let fs = new_project("samd21j18a");
+	add_carve_out(&fs, "\n[target.samd21j18a.memory.flash]\noffset = 0x2000\n\
+						reserve = \"0xFFFFFFFFFFFFFFFF\"\n");
+	assert_eq!(run_igloo(&fs, Path::new("/work/blinky"), &["make", "regen"]),
+			   Err(InvalidMemoryMap));
+
+	let fs = fake_esf();
+	let manifest = read(&fs, "/esf/manifest/samd21j18a.toml")
+		+ "\n[esf.memory.top]\norigin = \"0xFFFFFFFFFFFFF000\"\nlength = \"8K\"\n";
+	fs.write(Path::new("/esf/manifest/samd21j18a.toml"), manifest.as_bytes()).unwrap();
+	assert_eq!(run_igloo(&fs, Path::new(WORK_DIR), &["new", "blinky", "-t", "samd21j18a"]),
+			   Err(InvalidMemoryMap));
+}
@@ -0,0 +1,135 @@
+mod common;
+
+use common::*;
+use igloo_base::*;
+use igloo_base::IglooErrType::*;
+
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+const PRJ_DIR: &str = "/work/blinky";
+const LOCK: &str = "/work/blinky/igloo.lock";
+const MAKEFILE: &str = "/work/blinky/.igloo/target/samd21j18a/Makefile";
+
+fn host_with_make() -> Rc<MemHost>
+{
+	let host = Rc::new(MemHost::new());
+	host.add_program("make", "GNU Make 4.3\n");
+	host
+}
+
+fn build(fs: &Rc<MemFs>, host: &Rc<MemHost>, args: &[&str]) -> Result<String, IglooErrType>
+{
+	let mut full_args = vec!["build"];
+	full_args.extend_from_slice(args);
+	run_igloo_with(fs, host.clone(), env_info(Path::new(PRJ_DIR)), &full_args)
+}
+
+fn add_module(fs: &Rc<MemFs>, version: &str)
+{
+	fs.add_file(Path::new(&format!("/esf/modules/crc/{}/module.toml", version)), &format!(
+		"[module]\nname = \"crc\"\nversion = \"{}\"\nsources = [\"crc.c\"]\n", version));
+	fs.add_file(Path::new(&format!("/esf/modules/crc/{}/crc.c", version)), "");
+}
+
+#[test]
+fn new_project_is_locked()
+{
+	let fs = new_project("samd21j18a");
+	let lock = read(&fs, LOCK);
+	assert!(lock.starts_with("# igloo.lock -- Generated by igloo. Do not edit\n\n[esf]\nmanifests = \""));
+	assert!(lock.contains("\n[target.samd21j18a]\nmanifest = \""));
+	assert!(!lock.contains("revision"));
+	run_igloo(&fs, Path::new(PRJ_DIR), &["make", "regen", "--check"]).unwrap();
+}
+
+#[test]
+fn records_the_esf_git_revision()
+{
+	let fs = fake_esf();
+	let commit = "0123456789abcdef0123456789abcdef01234567";
+	fs.add_file(Path::new("/esf/.git/HEAD"), "ref: refs/heads/main\n");
+	fs.add_file(Path::new("/esf/.git/packed-refs"),
+				&format!("# pack-refs with: peeled fully-peeled sorted\n{} refs/heads/main\n", commit));
+	run_igloo(&fs, Path::new(WORK_DIR), &["new", "blinky", "-t", "samd21j18a"]).unwrap();
+	assert!(read(&fs, LOCK).contains(&format!("[esf]\nrevision = \"{}\"\n", commit)));
+
+	// a newer commit
+	fs.add_file(Path::new("/esf/.git/refs/heads/main"), "fedcba9876543210fedcba9876543210fedcba98\n");
+	assert_eq!(build(&fs, &host_with_make(), &["--locked"]), Err(LockfileMismatch));
+}
+
+#[test]
+fn build_runs_make_in_each_target()
+{
+	let fs = new_project("samd21j18a");
+	let host = host_with_make();
+	build(&fs, &host, &[]).unwrap();
+	assert_eq!(host.runs(), vec![(String::from("make"), vec![String::from("all")],
+								  PathBuf::from("/work/blinky/.igloo/target/samd21j18a"))]);
+
+	assert_eq!(build(&fs, &host, &["-t", "stm32f407vg"]), Err(InvalidTarget));
+	host.add_failing_program("make", "make: *** [src/main.o] Error 1\n");
+	assert_eq!(build(&fs, &host, &[]), Err(BuildFailed));
+	host.remove_program("make");
+	assert_eq!(build(&fs, &host, &[]), Err(BuildFailed));
+}
+
+#[test]
+fn locked_build_fails_when_esf_changes()
+{
+	let fs = new_project("samd21j18a");
+	let host = host_with_make();
+	build(&fs, &host, &["--locked"]).unwrap();
+	let lock = read(&fs, LOCK);
+	let makefile = read(&fs, MAKEFILE);
+
+	let manifest = read(&fs, "/esf/manifest/samd21j18a.toml").replace("\"32K\"", "\"16K\"");
+	fs.add_file(Path::new("/esf/manifest/samd21j18a.toml"), &manifest);
+	assert_eq!(build(&fs, &host, &["--locked"]), Err(LockfileMismatch));
+	assert_eq!(read(&fs, LOCK), lock);
+	assert_eq!(read(&fs, MAKEFILE), makefile);
+	assert_eq!(host.runs().len(), 1);
+
+	// building without --locked takes the change and locks it
+	build(&fs, &host, &[]).unwrap();
+	assert_ne!(read(&fs, LOCK), lock);
+	build(&fs, &host, &["--locked"]).unwrap();
+
+	let make_manifest = read(&fs, "/esf/manifest/make-manifest.toml").replace("-O1", "-O2");
+	fs.add_file(Path::new("/esf/manifest/make-manifest.toml"), &make_manifest);
+	assert_eq!(build(&fs, &host, &["--locked"]), Err(LockfileMismatch));
+}
+
+#[test]
+fn locked_build_needs_a_lock()
+{
+	let fs = new_project("samd21j18a");
+	fs.remove_file(Path::new(LOCK)).unwrap();
+	assert_eq!(build(&fs, &host_with_make(), &["--locked"]), Err(LockfileMismatch));
+}
+
+#[test]
+fn lock_keeps_module_versions()
+{
+	let fs = new_project("samd21j18a");
+	let host = host_with_make();
+	add_module(&fs, "1.0.0");
+	run_igloo(&fs, Path::new(PRJ_DIR), &["add", "crc"]).unwrap();
+	assert!(read(&fs, LOCK).contains("[module.crc]\nversion = \"1.0.0\"\nhash = \""));
+
+	// a newer version in the registry does not change the build
+	add_module(&fs, "1.1.0");
+	build(&fs, &host, &["--locked"]).unwrap();
+	assert!(read(&fs, "/work/blinky/modules/crc/module.toml").contains("1.0.0"));
+
+	// edited module files do
+	fs.add_file(Path::new("/esf/modules/crc/1.0.0/crc.c"), "int crc;\n");
+	assert_eq!(build(&fs, &host, &["--locked"]), Err(LockfileMismatch));
+
+	// without a lock the newest version is picked
+	fs.remove_file(Path::new(LOCK)).unwrap();
+	run_igloo(&fs, Path::new(PRJ_DIR), &["make", "regen"]).unwrap();
+	assert!(read(&fs, LOCK).contains("[module.crc]\nversion = \"1.1.0\""));
+	assert!(read(&fs, "/work/blinky/modules/crc/module.toml").contains("1.1.0"));
+}
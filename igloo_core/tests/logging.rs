@@ -0,0 +1,47 @@
+mod common;
+
+use common::*;
+use igloo_base::*;
+use log::LevelFilter;
+
+use std::path::Path;
+
+#[test]
+fn level_comes_from_flags_then_igloo_log()
+{
+	assert_eq!(log_level(false, 0, None), LevelFilter::Warn);
+	assert_eq!(log_level(true, 0, None), LevelFilter::Error);
+	assert_eq!(log_level(false, 1, None), LevelFilter::Info);
+	assert_eq!(log_level(false, 2, None), LevelFilter::Debug);
+	assert_eq!(log_level(false, 5, None), LevelFilter::Trace);
+	assert_eq!(log_level(false, 0, Some("debug")), LevelFilter::Debug);
+	assert_eq!(log_level(false, 0, Some("OFF")), LevelFilter::Off);
+	assert_eq!(log_level(false, 0, Some("chatty")), LevelFilter::Warn);
+	// flags win over $IGLOO_LOG
+	assert_eq!(log_level(true, 0, Some("trace")), LevelFilter::Error);
+	assert_eq!(log_level(false, 1, Some("error")), LevelFilter::Info);
+}
+
+// The logger is global, so everything touching it is one test
+#[test]
+fn logs_go_to_the_log_file()
+{
+	let fs = fake_esf();
+	run_igloo(&fs, Path::new(WORK_DIR), &["-vv", "new", "blinky", "-t", "samd21j18a"]).unwrap();
+	assert_eq!(log::max_level(), LevelFilter::Debug);
+	run_igloo(&fs, Path::new("/work/blinky"), &["make", "regen", "-q"]).unwrap();
+	assert_eq!(log::max_level(), LevelFilter::Error);
+
+	let path = std::env::temp_dir().join(format!("igloo-log-{}.log", std::process::id()));
+	let _ = std::fs::remove_file(&path);
+	init_logging(LevelFilter::Info, Some(&path)).unwrap();
+	log::info!("agent started");
+	log::debug!("not logged at info");
+	log::warn!("manifest has no CC");
+	log::logger().flush();
+	let text = std::fs::read_to_string(&path).unwrap();
+	let _ = std::fs::remove_file(&path);
+	assert!(text.contains("info [logging] agent started\n"));
+	assert!(text.contains("warning [logging] manifest has no CC\n"));
+	assert!(!text.contains("not logged"));
+}
@@ -0,0 +1,125 @@
+mod common;
+
+use common::*;
+use common::elf::*;
+use igloo_base::*;
+use igloo_base::IglooErrType::*;
+
+use serde_json::Value;
+use std::path::Path;
+use std::rc::Rc;
+
+const PRJ_DIR: &str = "/work/blinky";
+
+fn with_reason<'a>(messages: &'a [Value], reason: &str) -> Vec<&'a Value>
+{
+	messages.iter().filter(|m| m["reason"] == reason).collect()
+}
+
+#[test]
+fn new_reports_created_paths()
+{
+	let fs = fake_esf();
+	let (res, messages) = run_igloo_json(&fs, Rc::new(MemHost::new()),
+										 env_info(Path::new(WORK_DIR)),
+										 &["new", "blinky", "-t", "samd21j18a"]);
+	res.unwrap();
+	let created = with_reason(&messages, "path-created");
+	let has = |kind: &str, path: &str|
+	{
+		created.iter().any(|m| m["kind"] == kind && m["path"] == path)
+	};
+	assert!(has("dir", "/work/blinky"));
+	assert!(has("file", "/work/blinky/.igloo/blinky.toml"));
+	assert!(has("file", "/work/blinky/inc/igloo.h"));
+	assert!(has("symlink", "/work/blinky/ESF/samd21"));
+	assert_eq!(messages.last().unwrap()["reason"], "finished");
+	assert_eq!(messages.last().unwrap()["command"], "new");
+	assert_eq!(messages.last().unwrap()["success"], true);
+}
+
+#[test]
+fn errors_carry_their_code()
+{
+	let fs = new_project("samd21j18a");
+	let (res, messages) = run_igloo_json(&fs, Rc::new(MemHost::new()),
+										 env_info(Path::new(WORK_DIR)),
+										 &["new", "blinky", "-t", "samd21j18a"]);
+	assert_eq!(res, Err(FolderAlreadyExists));
+	assert_eq!(messages.len(), 1);
+	assert_eq!(messages[0]["reason"], "error");
+	assert_eq!(messages[0]["command"], "new");
+	assert_eq!(messages[0]["code"], FolderAlreadyExists as i32);
+	assert_eq!(messages[0]["error"], "FolderAlreadyExists");
+
+	// the format may also be given after the subcommand
+	let (res, messages) = run_igloo_json(&fs, Rc::new(MemHost::new()),
+										 env_info(Path::new(PRJ_DIR)),
+										 &["size", "-t", "esp32"]);
+	assert_eq!(res, Err(InvalidTarget));
+	assert_eq!(messages.last().unwrap()["code"], InvalidTarget as i32);
+	take_messages();
+	assert_eq!(run_igloo(&fs, Path::new(PRJ_DIR),
+						 &["init", "-t", "samd21j18a", "--message-format", "json"]),
+			   Err(ProjectAlreadyInitialized));
+	assert_eq!(take_messages().last().unwrap()["code"], ProjectAlreadyInitialized as i32);
+}
+
+#[test]
+fn build_reports_targets_and_artifacts()
+{
+	let fs = new_project("samd21j18a");
+	let elf = ElfBuilder::new()
+		.section(".text", 0x2000, SHF_ALLOC | SHF_EXECINSTR, &[0xaa; 0x400])
+		.build();
+	fs.write(Path::new("/work/blinky/.igloo/target/samd21j18a/blinky.elf"), &elf).unwrap();
+	let host = Rc::new(MemHost::new());
+	host.add_program("make", "");
+	let (res, messages) = run_igloo_json(&fs, host, env_info(Path::new(PRJ_DIR)), &["build"]);
+	res.unwrap();
+
+	let builds = with_reason(&messages, "build-finished");
+	assert_eq!(builds.len(), 1);
+	assert_eq!(builds[0]["target"], "samd21j18a");
+	assert_eq!(builds[0]["success"], true);
+	assert_eq!(builds[0]["artifacts"][0], "/work/blinky/.igloo/target/samd21j18a/blinky.elf");
+	assert_eq!(builds[0]["regions"][0]["name"], "flash");
+	assert_eq!(builds[0]["regions"][0]["used"], 0x400);
+	// make regen ran first and found everything up to date
+	assert!(with_reason(&messages, "build-file").iter().all(|m| m["status"] == "up-to-date"));
+	assert!(with_reason(&messages, "path-updated").is_empty());
+}
+
+#[test]
+fn listings_are_one_message_per_entry()
+{
+	let fs = fake_esf();
+	let (res, messages) = run_igloo_json(&fs, Rc::new(MemHost::new()),
+										 env_info(Path::new(WORK_DIR)), &["targets"]);
+	res.unwrap();
+	let targets = with_reason(&messages, "target");
+	assert!(targets.iter().any(|m| m["name"] == "samd21j18a" && m["ok"] == true));
+	assert!(targets.iter().any(|m| m["name"] == "stm32f407vg"));
+
+	let (res, messages) = run_igloo_json(&fs, Rc::new(MemHost::new()),
+										 env_info(Path::new(WORK_DIR)), &["info", "samd21j18a"]);
+	res.unwrap();
+	let entries = with_reason(&messages, "registry-entry");
+	assert_eq!(entries.len(), 1);
+	assert_eq!(entries[0]["kind"], "target");
+	assert_eq!(entries[0]["vendor"], "Microchip");
+}
+
+#[test]
+fn dry_run_reports_the_plan()
+{
+	let fs = fake_esf();
+	let (res, messages) = run_igloo_json(&fs, Rc::new(MemHost::new()),
+										 env_info(Path::new(WORK_DIR)),
+										 &["--dry-run", "new", "blinky", "-t", "samd21j18a"]);
+	res.unwrap();
+	assert!(with_reason(&messages, "path-created").is_empty());
+	assert!(with_reason(&messages, "planned").iter()
+			.any(|m| m["op"] == "write" && m["path"] == "/work/blinky/.igloo/blinky.toml"));
+	assert!(!fs.exists(Path::new(PRJ_DIR)));
+}
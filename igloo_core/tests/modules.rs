@@ -0,0 +1,182 @@
+mod common;
+
+use common::*;
+use igloo_base::*;
+use igloo_base::IglooErrType::*;
+
+use std::path::Path;
+use std::rc::Rc;
+
+const PRJ_DIR: &str = "/work/blinky";
+const PRJ_FILE: &str = "/work/blinky/.igloo/blinky.toml";
+const MAKEFILE: &str = "/work/blinky/.igloo/target/samd21j18a/Makefile";
+const IGLOO_H: &str = "/work/blinky/inc/igloo.h";
+
+/// Adds a module to the ESF registry. extra is appended to its [module] table
+fn add_module(fs: &Rc<MemFs>, name: &str, version: &str, extra: &str, deps: &str)
+{
+	let dir = format!("/esf/modules/{}/{}", name, version);
+	fs.add_file(Path::new(&format!("{}/module.toml", dir)), &format!(
+		"[module]\nname = \"{}\"\nversion = \"{}\"\nsources = [\"src/{}.c\"]\n\
+		 includes = [\"inc\"]\nheaders = [\"{}.h\"]\n{}\n[dependencies]\n{}",
+		name, version, name, name, extra, deps));
+	fs.add_file(Path::new(&format!("{}/src/{}.c", dir, name)), "");
+	fs.add_file(Path::new(&format!("{}/inc/{}.h", dir, name)), "");
+}
+
+fn new_project() -> Rc<MemFs>
+{
+	let fs = common::new_project("samd21j18a");
+	add_module(&fs, "crc", "1.0.0", "", "");
+	add_module(&fs, "crc", "2.0.0", "", "");
+	add_module(&fs, "ring_buffer", "1.0.0", "", "");
+	add_module(&fs, "ring_buffer", "1.2.0", "defines = [\"RING_BUFFER_SIZE=64\"]\n\
+											 cores = [\"cortex-m*\"]", "crc = \"^1.0\"\n");
+	fs
+}
+
+fn igloo(fs: &Rc<MemFs>, args: &[&str]) -> Result<String, IglooErrType>
+{
+	run_igloo(fs, Path::new(PRJ_DIR), args)
+}
+
+#[test]
+fn add_wires_module_and_dependencies_into_the_build()
+{
+	let fs = new_project();
+	igloo(&fs, &["add", "ring_buffer"]).unwrap();
+
+	assert!(read(&fs, PRJ_FILE).ends_with("\n\n[modules]\nring_buffer = \"^1.2.0\"\n"));
+	let makefile = read(&fs, MAKEFILE);
+	assert!(makefile.contains("CFLAGS+=-DIGLOO_MODULE_CRC\n"));
+	assert!(makefile.contains("CFLAGS+=-DIGLOO_MODULE_RING_BUFFER\nCFLAGS+=-DRING_BUFFER_SIZE=64\n"));
+	assert!(makefile.contains("SUB_DIRS+= \\\nmodules/crc/src \\\nmodules/ring_buffer/src\n"));
+	assert!(makefile.contains("OBJS+= \\\nmodules/crc/src/crc.o \\\nmodules/ring_buffer/src/ring_buffer.o\n"));
+	assert!(makefile.contains("\"modules/ring_buffer/src/ring_buffer.o\""));
+	assert!(makefile.contains("-I\"../../../modules/ring_buffer/inc\""));
+
+	let igloo_h = read(&fs, IGLOO_H);
+	assert!(igloo_h.contains("#ifdef IGLOO_MODULE_RING_BUFFER\n\t#include \"ring_buffer.h\"\n#endif\n"));
+	assert!(igloo_h.contains("#ifdef IGLOO_MODULE_CRC\n\t#include \"crc.h\"\n#endif\n"));
+
+	// crc 2.0.0 is newer but ring_buffer needs 1.x
+	assert!(read(&fs, "/work/blinky/modules/crc/module.toml").contains("version = \"1.0.0\""));
+	assert!(fs.exists(Path::new("/work/blinky/modules/ring_buffer/src/ring_buffer.c")));
+	igloo(&fs, &["make", "regen", "--check"]).unwrap();
+}
+
+#[test]
+fn remove_restores_the_build()
+{
+	let fs = new_project();
+	let prj_file = read(&fs, PRJ_FILE);
+	let makefile = read(&fs, MAKEFILE);
+	let igloo_h = read(&fs, IGLOO_H);
+
+	igloo(&fs, &["add", "ring_buffer@=1.0.0", "crc"]).unwrap();
+	assert!(read(&fs, PRJ_FILE).contains("crc = \"^2.0.0\"\nring_buffer = \"=1.0.0\"\n"));
+	assert!(read(&fs, "/work/blinky/modules/crc/module.toml").contains("version = \"2.0.0\""));
+
+	igloo(&fs, &["remove", "ring_buffer", "crc"]).unwrap();
+	assert_eq!(read(&fs, PRJ_FILE), prj_file);
+	assert_eq!(read(&fs, MAKEFILE), makefile);
+	assert_eq!(read(&fs, IGLOO_H), igloo_h);
+	assert!(!fs.symlink_exists(Path::new("/work/blinky/modules/crc")));
+	assert!(!fs.symlink_exists(Path::new("/work/blinky/modules/ring_buffer")));
+}
+
+#[test]
+fn conflicting_requirements_leave_the_project_alone()
+{
+	let fs = new_project();
+	add_module(&fs, "logger", "0.3.0", "", "crc = \">=2\"\n");
+	igloo(&fs, &["add", "ring_buffer"]).unwrap();
+	let prj_file = read(&fs, PRJ_FILE);
+
+	assert_eq!(igloo(&fs, &["add", "logger"]), Err(ModuleVersionConflict));
+	assert_eq!(igloo(&fs, &["add", "crc@^3"]), Err(ModuleVersionConflict));
+	assert_eq!(read(&fs, PRJ_FILE), prj_file);
+}
+
+#[test]
+fn reports_unknown_modules()
+{
+	let fs = new_project();
+	assert_eq!(igloo(&fs, &["add", "usb_stack"]), Err(ModuleNotFound));
+	assert_eq!(igloo(&fs, &["remove", "crc"]), Err(ModuleNotFound));
+	assert_eq!(igloo(&fs, &["add", "crc@one"]), Err(InvalidModule));
+	assert_eq!(run_igloo(&fs, Path::new(WORK_DIR), &["add", "crc"]), Err(NotInsideProject));
+}
+
+#[test]
+fn refuses_modules_for_other_mcus()
+{
+	let fs = new_project();
+	add_module(&fs, "stm32_hal", "1.0.0", "mcus = [\"stm32f4*\"]", "");
+	add_module(&fs, "samd_usb", "1.0.0", "mcus = [\"samd21\"]\ncores = [\"cortex-m0\"]", "");
+	assert_eq!(igloo(&fs, &["add", "stm32_hal"]), Err(ModuleNotCompatible));
+	igloo(&fs, &["add", "samd_usb"]).unwrap();
+	assert!(read(&fs, MAKEFILE).contains("modules/samd_usb/src/samd_usb.o"));
+}
+
+#[test]
+fn user_registry_comes_first()
+{
+	let fs = new_project();
+	fs.add_file(Path::new("/home/penguin/.igloo/modules/crc/1.0.0/module.toml"),
+				"[module]\nname = \"crc\"\nversion = \"1.0.0\"\nsources = [\"crc_fast.c\"]\n");
+	fs.add_file(Path::new("/home/penguin/.igloo/vendor/crc/1.5.0/module.toml"),
+				"[module]\nname = \"crc\"\nversion = \"1.5.0\"\nsources = [\"crc.c\"]\n");
+	igloo(&fs, &["add", "crc@=1.0.0"]).unwrap();
+	assert!(read(&fs, MAKEFILE).contains("modules/crc/crc_fast.o"));
+
+	fs.add_file(Path::new("/home/penguin/.igloo/registries.toml"), "registries = [\"vendor\"]\n");
+	igloo(&fs, &["add", "crc@~1"]).unwrap();
+	assert!(read(&fs, PRJ_FILE).contains("crc = \"~1\"\n"));
+	assert!(read(&fs, "/work/blinky/modules/crc/module.toml").contains("1.5.0"));
+}
+
+#[test]
+fn add_keeps_locked_versions_of_other_modules()
+{
+	let fs = new_project();
+	igloo(&fs, &["add", "crc@^1"]).unwrap();
+	add_module(&fs, "crc", "1.1.0", "", "");
+
+	igloo(&fs, &["add", "ring_buffer"]).unwrap();
+	assert!(read(&fs, "/work/blinky/modules/crc/module.toml").contains("version = \"1.0.0\""));
+	assert!(read(&fs, "/work/blinky/igloo.lock").contains("[module.crc]\nversion = \"1.0.0\"\n"));
+}
+
+#[test]
+fn add_resolves_the_named_module_again()
+{
+	let fs = new_project();
+	igloo(&fs, &["add", "crc@^1"]).unwrap();
+	add_module(&fs, "crc", "1.5.0", "", "");
+
+	// the locked 1.0.0 satisfies the requirement, add picks the newest anyway
+	igloo(&fs, &["add", "crc@~1"]).unwrap();
+	assert!(read(&fs, "/work/blinky/modules/crc/module.toml").contains("version = \"1.5.0\""));
+	assert!(read(&fs, "/work/blinky/igloo.lock").contains("[module.crc]\nversion = \"1.5.0\"\n"));
+
+	// a failed add keeps the lock
+	let lock = read(&fs, "/work/blinky/igloo.lock");
+	assert_eq!(igloo(&fs, &["add", "crc@^3"]), Err(ModuleVersionConflict));
+	assert_eq!(read(&fs, "/work/blinky/igloo.lock"), lock);
+}
+
+#[test]
+fn dry_run_add_plans_from_the_updated_project()
+{
+	let fs = new_project();
+	let makefile = read(&fs, MAKEFILE);
+	let (res, messages) = run_igloo_json(&fs, Rc::new(MemHost::new()), env_info(Path::new(PRJ_DIR)),
+										 &["--dry-run", "add", "ring_buffer"]);
+	res.unwrap();
+	assert_eq!(read(&fs, MAKEFILE), makefile);
+	// the Makefile is planned to change because the planned project file has the module
+	assert!(messages.iter().any(|m| m["op"] == "modify" && m["path"] == MAKEFILE));
+	assert!(messages.iter().any(|m| m["op"] == "symlink"
+								&& m["path"] == "/work/blinky/modules/ring_buffer"));
+}
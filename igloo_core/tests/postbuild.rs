@@ -0,0 +1,202 @@
+mod common;
+
+use common::*;
+use common::elf::*;
+use igloo_base::*;
+use igloo_base::IglooErrType::*;
+
+use ed25519_dalek::pkcs8::{EncodePrivateKey, EncodePublicKey};
+use ed25519_dalek::pkcs8::spki::der::pem::LineEnding;
+use std::path::Path;
+use std::rc::Rc;
+
+const PRJ_DIR: &str = "/work/blinky";
+const PRJ_FILE: &str = "/work/blinky/.igloo/blinky.toml";
+const TARGET_DIR: &str = "/work/blinky/.igloo/target/samd21j18a";
+
+/// 0x40 bytes of code at 0x2000 with a 4 word header at 0x2010
+/// and a CRC slot at 0x2030
+fn built_project() -> Rc<MemFs>
+{
+	let fs = fake_esf();
+	run_igloo(&fs, Path::new(WORK_DIR), &["new", "blinky", "-t", "samd21j18a"]).unwrap();
+	let text: Vec<u8> = (0..0x40).collect();
+	let elf = ElfBuilder::new()
+		.section(".text", 0x2000, SHF_ALLOC | SHF_EXECINSTR, &text)
+		.symbol("image_header", 0x2010, 16, STT_OBJECT, ".text")
+		.symbol("image_crc", 0x2030, 4, STT_OBJECT, ".text")
+		.load(".text", 0x2000)
+		.build();
+	fs.write(&Path::new(TARGET_DIR).join("blinky.elf"), &elf).unwrap();
+	fs
+}
+
+fn configure(fs: &Rc<MemFs>, config: &str)
+{
+	let prj_file = read(fs, PRJ_FILE) + config;
+	fs.write(Path::new(PRJ_FILE), prj_file.as_bytes()).unwrap();
+}
+
+fn write_keys(fs: &Rc<MemFs>, algorithm: &str)
+{
+	let (private, public) = match algorithm
+	{
+		"ed25519" =>
+		{
+			let key = ed25519_dalek::SigningKey::from_bytes(&[7; 32]);
+			(key.to_pkcs8_pem(LineEnding::LF).unwrap().to_string(),
+			 key.verifying_key().to_public_key_pem(LineEnding::LF).unwrap())
+		}
+		_ =>
+		{
+			let key = p256::ecdsa::SigningKey::from_slice(&[7; 32]).unwrap();
+			(key.to_pkcs8_pem(LineEnding::LF).unwrap().to_string(),
+			 key.verifying_key().to_public_key_pem(LineEnding::LF).unwrap())
+		}
+	};
+	fs.create_dir_all(Path::new("/keys")).unwrap();
+	fs.create_dir_all(Path::new("/work/blinky/keys")).unwrap();
+	fs.write(Path::new("/keys/release.pem"), private.as_bytes()).unwrap();
+	fs.write(Path::new("/work/blinky/keys/release.pub.pem"), public.as_bytes()).unwrap();
+}
+
+fn convert_bin(fs: &Rc<MemFs>) -> Vec<u8>
+{
+	run_igloo(fs, Path::new(PRJ_DIR), &["image", "convert", "-f", "bin"]).unwrap();
+	fs.read(&Path::new(TARGET_DIR).join("blinky.bin")).unwrap()
+}
+
+fn word(bin: &[u8], off: usize) -> u32
+{
+	u32::from_le_bytes([bin[off], bin[off + 1], bin[off + 2], bin[off + 3]])
+}
+
+#[test]
+fn patches_header_and_appends_crc()
+{
+	let fs = built_project();
+	configure(&fs, "\n[target.samd21j18a.image.header]\nsymbol = \"image_header\"\n\
+					magic = 0x49474c4f\nversion = \"1.2.3\"\n\
+					\n[target.samd21j18a.image.crc]\n");
+	let bin = convert_bin(&fs);
+
+	assert_eq!(bin.len(), 0x44);
+	assert_eq!(word(&bin, 0x10), 0x49474c4f);
+	assert_eq!(word(&bin, 0x14), 0x0102_0003);
+	assert_eq!(word(&bin, 0x18), 0x40);
+	let mut zeroed = bin[..0x40].to_vec();
+	zeroed[0x1c..0x20].copy_from_slice(&[0; 4]);
+	assert_eq!(word(&bin, 0x1c), crc32(&zeroed));
+	assert_eq!(word(&bin, 0x40), crc32(&bin[..0x40]));
+	// the rest of the firmware is untouched
+	assert_eq!(&bin[..0x10], &(0..0x10).collect::<Vec<u8>>()[..]);
+
+	run_igloo(&fs, Path::new(PRJ_DIR), &["image", "verify"]).unwrap();
+}
+
+#[test]
+fn inserts_crc_at_symbol()
+{
+	let fs = built_project();
+	configure(&fs, "\n[target.samd21j18a.image.crc]\nsymbol = \"image_crc\"\n");
+	let bin = convert_bin(&fs);
+
+	assert_eq!(bin.len(), 0x40);
+	let mut zeroed = bin.clone();
+	zeroed[0x30..0x34].copy_from_slice(&[0; 4]);
+	assert_eq!(word(&bin, 0x30), crc32(&zeroed));
+	run_igloo(&fs, Path::new(PRJ_DIR), &["image", "verify"]).unwrap();
+}
+
+#[test]
+fn signs_with_ed25519_and_verifies_with_public_key()
+{
+	let fs = built_project();
+	write_keys(&fs, "ed25519");
+	configure(&fs, "\n[target.samd21j18a.image.crc]\n\
+					\n[target.samd21j18a.image.sign]\nalgorithm = \"ed25519\"\n\
+					public_key = \"keys/release.pub.pem\"\n");
+	// the private key lives outside of the project and is passed on the command line
+	run_igloo(&fs, Path::new(PRJ_DIR),
+			  &["image", "convert", "-f", "bin", "-f", "hex", "--key", "/keys/release.pem"]).unwrap();
+
+	let bin = fs.read(&Path::new(TARGET_DIR).join("blinky.bin")).unwrap();
+	assert_eq!(bin.len(), 0x40 + 4 + 64);
+	let key = ed25519_dalek::SigningKey::from_bytes(&[7; 32]);
+	let sig = ed25519_dalek::Signer::sign(&key, &bin[..0x44]);
+	assert_eq!(&bin[0x44..], &sig.to_bytes()[..]);
+
+	run_igloo(&fs, Path::new(PRJ_DIR), &["image", "verify"]).unwrap();
+}
+
+#[test]
+fn signs_with_ecdsa_p256()
+{
+	let fs = built_project();
+	write_keys(&fs, "ecdsa-p256");
+	configure(&fs, "\n[target.samd21j18a.image.sign]\nalgorithm = \"ecdsa-p256\"\n\
+					key = \"/keys/release.pem\"\n");
+	let bin = convert_bin(&fs);
+	assert_eq!(bin.len(), 0x40 + 64);
+
+	run_igloo(&fs, Path::new(PRJ_DIR), &["image", "verify"]).unwrap();
+	run_igloo(&fs, Path::new(PRJ_DIR),
+			  &["image", "verify", "--key", "keys/release.pub.pem"]).unwrap();
+}
+
+#[test]
+fn verify_detects_tampering()
+{
+	let fs = built_project();
+	write_keys(&fs, "ed25519");
+	configure(&fs, "\n[target.samd21j18a.image.header]\nsymbol = \"image_header\"\n\
+					\n[target.samd21j18a.image.crc]\n\
+					\n[target.samd21j18a.image.sign]\nkey = \"/keys/release.pem\"\n");
+	let mut bin = convert_bin(&fs);
+	bin[0x04] ^= 0xff;
+	fs.write(&Path::new(TARGET_DIR).join("blinky.bin"), &bin).unwrap();
+
+	assert_eq!(run_igloo(&fs, Path::new(PRJ_DIR), &["image", "verify"]), Err(ImageCheckFailed));
+}
+
+#[test]
+fn verify_rejects_other_keys()
+{
+	let fs = built_project();
+	write_keys(&fs, "ed25519");
+	configure(&fs, "\n[target.samd21j18a.image.sign]\nkey = \"/keys/release.pem\"\n");
+	convert_bin(&fs);
+
+	let other = ed25519_dalek::SigningKey::from_bytes(&[8; 32]);
+	let other = other.verifying_key().to_public_key_pem(LineEnding::LF).unwrap();
+	fs.write(Path::new("/work/blinky/other.pem"), other.as_bytes()).unwrap();
+	assert_eq!(run_igloo(&fs, Path::new(PRJ_DIR), &["image", "verify", "--key", "other.pem"]),
+			   Err(ImageCheckFailed));
+}
+
+#[test]
+fn invalid_configurations_are_rejected()
+{
+	let fs = built_project();
+	configure(&fs, "\n[target.samd21j18a.image.header]\nsymbol = \"missing_header\"\n");
+	assert_eq!(run_igloo(&fs, Path::new(PRJ_DIR), &["image", "convert", "-f", "bin"]),
+			   Err(InvalidPostBuild));
+
+	let fs = built_project();
+	configure(&fs, "\n[target.samd21j18a.image.header]\nsymbol = \"image_header\"\n\
+					\n[target.samd21j18a.image.crc]\nsymbol = \"image_crc\"\n");
+	assert_eq!(run_igloo(&fs, Path::new(PRJ_DIR), &["image", "convert", "-f", "bin"]),
+			   Err(InvalidPostBuild));
+
+	// magics and versions are words, a larger value would be cut short
+	let fs = built_project();
+	configure(&fs, "\n[target.samd21j18a.image.header]\nsymbol = \"image_header\"\n\
+					magic = 0x149474c4f\n");
+	assert_eq!(run_igloo(&fs, Path::new(PRJ_DIR), &["image", "convert", "-f", "bin"]),
+			   Err(InvalidPostBuild));
+
+	let fs = built_project();
+	configure(&fs, "\n[target.samd21j18a.image.sign]\nalgorithm = \"ed25519\"\n");
+	assert_eq!(run_igloo(&fs, Path::new(PRJ_DIR), &["image", "convert", "-f", "bin"]),
+			   Err(FailedToReadKey));
+}
@@ -0,0 +1,223 @@
+mod common;
+
+use common::*;
+use igloo_base::*;
+use igloo_base::IglooErrType::*;
+
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+#[test]
+fn new_generates_project_tree()
+{
+	let fs = fake_esf();
+	run_igloo(&fs, Path::new(WORK_DIR), &["new", "blinky", "-t", "samd21j18a"]).unwrap();
+
+	for dir in &["src", "inc", "cfg", "ESF", ".igloo/target/samd21j18a/scripts"]
+	{
+		assert!(fs.is_dir(&Path::new("/work/blinky").join(dir)), "missing {}", dir);
+	}
+	assert!(fs.exists(Path::new("/work/blinky/.igloo/target/samd21j18a/scripts/debug.gdb")));
+	assert!(fs.exists(Path::new("/work/blinky/.igloo/target/samd21j18a/scripts/samd21j18a.cfg")));
+	assert!(fs.is_dir(Path::new("/work/blinky/ESF/samd21/inc")));
+
+	assert_eq!(read(&fs, "/work/blinky/.igloo/blinky.toml"),
+			   "[project]\nname = \"blinky\"\ntargets = [\"samd21j18a\"]\n");
+	assert_eq!(read(&fs, "/work/blinky/src/main.c"),
+			   "#include \"igloo.h\"\n\n\nint main()\n{\n\treturn 0;\n}\n");
+}
+
+#[test]
+fn new_generates_makefile_from_merged_manifest()
+{
+	let fs = fake_esf();
+	run_igloo(&fs, Path::new(WORK_DIR), &["new", "blinky", "-t", "samd21j18a"]).unwrap();
+
+	let makefile = read(&fs, "/work/blinky/.igloo/target/samd21j18a/Makefile");
+	assert!(makefile.starts_with("# ePenguin Generated Variables\n\
+								  PROJECT_NAME=blinky\n\
+								  TARGET_NAME=samd21j18a\n\
+								  TOOLCHAIN=arm-none-eabi-\n"));
+	assert!(makefile.contains("MCPU=cortex-m0plus\nMCU=__SAMD21J18A__\n"));
+	// Leaf tables are merged before their parents
+	assert!(makefile.contains("CFLAGS= \\\n-D__SAMD21J18A__ \\\n-mcpu=cortex-m0plus \\\n-mthumb \\\n-O1\n"));
+	assert!(makefile.contains("CFLAGS+=-DIGLOO_TARGET_SAMD21J18A\n"));
+	assert!(makefile.ends_with("QUOTE:=\"\n"));
+}
+
+#[test]
+fn new_generates_igloo_header()
+{
+	let fs = fake_esf();
+	run_igloo(&fs, Path::new(WORK_DIR), &["new", "blinky", "-t", "samd21j18a"]).unwrap();
+
+	let header = read(&fs, "/work/blinky/inc/igloo.h");
+	assert!(header.contains("#ifndef IGLOO_H\n#define IGLOO_H\n"));
+	assert!(header.contains("#ifdef IGLOO_TARGET_SAMD21J18A\n\
+							 \t#define IGLOO_TARGET_NAME \"samd21j18a\"\n\
+							 \t#define IGLOO_BOARD_CLOCK 48000000\n\
+							 \t#define IGLOO_BOARD_NAME \"samd21j18a test board\"\n\
+							 \t#define IGLOO_BOARD_PACKAGE \"TQFP64\"\n\
+							 #endif\n"));
+	assert!(header.contains("#if defined(__SAMD21J18A__)\n\t#include \"samd21j18a.h\"\n#else\n\t#error"));
+	assert!(header.contains("/* IGLOO_H_USER_BEGIN */\n\n/* IGLOO_H_USER_END */\n"));
+	assert!(header.ends_with("#endif /* IGLOO_H */\n"));
+}
+
+#[test]
+fn new_refuses_existing_folder()
+{
+	let fs = fake_esf();
+	fs.create_dir_all(Path::new("/work/blinky")).unwrap();
+	assert_eq!(run_igloo(&fs, Path::new(WORK_DIR), &["new", "blinky", "-t", "samd21j18a"]),
+			   Err(FolderAlreadyExists));
+}
+
+#[test]
+fn new_rejects_unknown_target()
+{
+	let fs = fake_esf();
+	assert_eq!(run_igloo(&fs, Path::new(WORK_DIR), &["new", "blinky", "-t", "atmega328p"]),
+			   Err(InvalidTarget));
+}
+
+#[test]
+fn new_applies_template()
+{
+	let fs = fake_esf();
+	run_igloo(&fs, Path::new(WORK_DIR),
+			  &["new", "blinky", "-t", "samd21j18a", "--template", "blinky"]).unwrap();
+	assert_eq!(read(&fs, "/work/blinky/src/main.c"),
+			   "/* blinky samd21j18a __SAMD21J18A__ 48000000 */\n");
+}
+
+#[test]
+fn init_keeps_existing_sources()
+{
+	let fs = fake_esf();
+	fs.add_file(Path::new("/work/legacy/src/main.c"), "int main(){for(;;);}\n");
+	run_igloo(&fs, Path::new("/work/legacy"), &["init", "-t", "samd21j18a"]).unwrap();
+
+	assert_eq!(read(&fs, "/work/legacy/src/main.c"), "int main(){for(;;);}\n");
+	assert!(fs.exists(Path::new("/work/legacy/.igloo/legacy.toml")));
+	assert!(fs.exists(Path::new("/work/legacy/.igloo/target/samd21j18a/Makefile")));
+	assert!(fs.exists(Path::new("/work/legacy/inc/igloo.h")));
+
+	assert_eq!(run_igloo(&fs, Path::new("/work/legacy"), &["init", "-t", "samd21j18a"]),
+			   Err(ProjectAlreadyInitialized));
+}
+
+#[test]
+fn init_keeps_a_root_main_c()
+{
+	let fs = fake_esf();
+	fs.add_file(Path::new("/work/legacy/main.c"), "int main(){for(;;);}\n");
+	run_igloo(&fs, Path::new("/work/legacy"), &["init", "-t", "samd21j18a"]).unwrap();
+
+	assert_eq!(read(&fs, "/work/legacy/main.c"), "int main(){for(;;);}\n");
+	assert!(!fs.exists(Path::new("/work/legacy/src/main.c")));
+	// the Makefile builds the root main.c instead of the missing src/main.c
+	let makefile = read(&fs, "/work/legacy/.igloo/target/samd21j18a/Makefile");
+	assert!(makefile.contains("OBJS+= \\\nmain.o\n"));
+	assert!(makefile.contains("OBJS_AS_ARGS+= \\\n\"main.o\"\n"));
+	assert!(!makefile.contains("src/main.o"));
+	run_igloo(&fs, Path::new("/work/legacy"), &["make", "regen", "--check"]).unwrap();
+}
+
+#[test]
+fn builtin_templates_fill_in_the_target()
+{
+	let fs = fake_esf();
+	run_igloo(&fs, Path::new(WORK_DIR),
+			  &["new", "echo", "-t", "samd21j18a", "--template", "uart-echo"]).unwrap();
+	assert!(read(&fs, "/work/echo/src/main.c").starts_with(
+		"/* echo -- echoes every byte received on the UART of samd21j18a */\n"));
+	assert!(read(&fs, "/work/echo/src/board.c").contains("board_uart_getc"));
+	assert!(read(&fs, "/work/echo/inc/board.h").contains("#define BOARD_CLOCK_HZ 48000000UL\n"));
+	// board.c is built next to main.c
+	let makefile = read(&fs, "/work/echo/.igloo/target/samd21j18a/Makefile");
+	assert!(makefile.contains("OBJS+= \\\nsrc/main.o\nOBJS+= \\\nsrc/board.o\n"));
+	assert!(makefile.contains("OBJS_AS_ARGS+= \\\n\"src/main.o\"\nOBJS_AS_ARGS+= \\\n\"src/board.o\"\n"));
+	run_igloo(&fs, Path::new("/work/echo"), &["make", "regen", "--check"]).unwrap();
+
+	let (res, messages) = run_igloo_json(&fs, Rc::new(MemHost::new()), env_info(Path::new(WORK_DIR)),
+										 &["template", "list"]);
+	res.unwrap();
+	let listed: Vec<(String, String)> = messages.iter()
+		.filter(|m| m["reason"] == "template")
+		.map(|m| (m["name"].as_str().unwrap().to_string(), m["source"].as_str().unwrap().to_string()))
+		.collect();
+	// the ESF blinky hides the built in one
+	for (name, source) in &[("blinky", "esf"), ("empty", "builtin"), ("uart-echo", "builtin")]
+	{
+		assert!(listed.contains(&(name.to_string(), source.to_string())), "{} {:?}", name, listed);
+	}
+	assert_eq!(listed.len(), 3);
+}
+
+#[test]
+fn templates_refuse_targets_without_board_code()
+{
+	let fs = fake_esf();
+	assert_eq!(run_igloo(&fs, Path::new(WORK_DIR),
+						 &["new", "echo", "-t", "samd21g18a", "--template", "uart-echo"]),
+			   Err(TemplateNotCompatible));
+	assert!(!fs.exists(Path::new("/work/echo")));
+}
+
+#[test]
+fn dry_run_changes_nothing()
+{
+	let fs = fake_esf();
+	let before = fs.paths();
+	run_igloo(&fs, Path::new(WORK_DIR),
+			  &["--dry-run", "new", "blinky", "-t", "samd21j18a"]).unwrap();
+	assert_eq!(fs.paths(), before);
+}
+
+#[test]
+fn clean_removes_build_outputs()
+{
+	let fs = fake_esf();
+	run_igloo(&fs, Path::new(WORK_DIR), &["new", "blinky", "-t", "samd21j18a"]).unwrap();
+	fs.add_file(Path::new("/work/blinky/.igloo/target/samd21j18a/src/main.o"), "");
+	fs.add_file(Path::new("/work/blinky/.igloo/target/samd21j18a/blinky.elf"), "");
+
+	run_igloo(&fs, Path::new("/work/blinky"), &["clean"]).unwrap();
+	assert!(!fs.exists(Path::new("/work/blinky/.igloo/target/samd21j18a/src")));
+	assert!(!fs.exists(Path::new("/work/blinky/.igloo/target/samd21j18a/blinky.elf")));
+	assert!(fs.exists(Path::new("/work/blinky/.igloo/target/samd21j18a/Makefile")));
+
+	run_igloo(&fs, Path::new("/work/blinky"), &["clean", "--all"]).unwrap();
+	assert!(!fs.exists(Path::new("/work/blinky/.igloo/target/samd21j18a/Makefile")));
+}
+
+#[test]
+fn gendoc_runs_doxygen_with_the_target_defines()
+{
+	let fs = fake_esf();
+	run_igloo(&fs, Path::new(WORK_DIR), &["new", "blinky", "-t", "samd21j18a"]).unwrap();
+	let host = Rc::new(MemHost::new());
+	host.add_program("doxygen", "1.9.1\n");
+	run_igloo_with(&fs, host.clone(), env_info(Path::new("/work/blinky")), &["gendoc"]).unwrap();
+
+	let doxyfile = read(&fs, "/work/blinky/.igloo/target/samd21j18a/Doxyfile");
+	assert!(doxyfile.contains("OUTPUT_DIRECTORY = \"/work/blinky/doc/samd21j18a\"\n"));
+	assert!(doxyfile.contains("PREDEFINED = __SAMD21J18A__\n"));
+	assert!(doxyfile.contains("INCLUDE_PATH = \"/work/blinky/inc\""));
+	assert!(fs.is_dir(Path::new("/work/blinky/doc/samd21j18a")));
+	assert_eq!(host.runs(), vec![(String::from("doxygen"),
+								  vec![String::from("/work/blinky/.igloo/target/samd21j18a/Doxyfile")],
+								  PathBuf::from("/work/blinky"))]);
+
+	host.add_failing_program("doxygen", "error: Doxyfile not found\n");
+	assert_eq!(run_igloo_with(&fs, host, env_info(Path::new("/work/blinky")), &["gendoc"]),
+			   Err(FailedToGenerateDocs));
+}
+
+#[test]
+fn project_commands_need_a_project()
+{
+	let fs = fake_esf();
+	assert_eq!(run_igloo(&fs, Path::new(WORK_DIR), &["clean"]), Err(NotInsideProject));
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use common::*;
+use igloo_base::*;
+use igloo_base::IglooErrType::*;
+
+use std::path::Path;
+
+const MAKEFILE: &str = "/work/blinky/.igloo/target/samd21j18a/Makefile";
+const IGLOO_H: &str = "/work/blinky/inc/igloo.h";
+
+#[test]
+fn check_passes_on_fresh_project()
+{
+	let fs = new_project("samd21j18a");
+	run_igloo(&fs, Path::new("/work/blinky"), &["make", "regen", "--check"]).unwrap();
+}
+
+#[test]
+fn check_detects_drift_without_writing()
+{
+	let fs = new_project("samd21j18a");
+	let edited = read(&fs, MAKEFILE).replace("MCPU=cortex-m0plus", "MCPU=cortex-m4");
+	fs.write(Path::new(MAKEFILE), edited.as_bytes()).unwrap();
+
+	assert_eq!(run_igloo(&fs, Path::new("/work/blinky"), &["make", "regen", "--check"]),
+			   Err(BuildFilesOutOfDate));
+	assert_eq!(read(&fs, MAKEFILE), edited);
+}
+
+#[test]
+fn check_detects_missing_build_file()
+{
+	let fs = new_project("samd21j18a");
+	fs.remove_file(Path::new(MAKEFILE)).unwrap();
+	assert_eq!(run_igloo(&fs, Path::new("/work/blinky"), &["make", "regen", "--check"]),
+			   Err(BuildFilesOutOfDate));
+}
+
+#[test]
+fn regen_rewrites_drifted_files()
+{
+	let fs = new_project("samd21j18a");
+	let original = read(&fs, MAKEFILE);
+	fs.write(Path::new(MAKEFILE), b"all:\n").unwrap();
+
+	run_igloo(&fs, Path::new("/work/blinky"), &["make", "regen"]).unwrap();
+	assert_eq!(read(&fs, MAKEFILE), original);
+	run_igloo(&fs, Path::new("/work/blinky"), &["make", "regen", "--check"]).unwrap();
+}
+
+#[test]
+fn regen_keeps_igloo_h_user_region()
+{
+	let fs = new_project("samd21j18a");
+	let edited = read(&fs, IGLOO_H).replace(
+		"/* IGLOO_H_USER_BEGIN */\n",
+		"/* IGLOO_H_USER_BEGIN */\n#define LED_PIN 17\n");
+	fs.write(Path::new(IGLOO_H), edited.as_bytes()).unwrap();
+
+	run_igloo(&fs, Path::new("/work/blinky"), &["make", "regen"]).unwrap();
+	assert_eq!(read(&fs, IGLOO_H), edited);
+	run_igloo(&fs, Path::new("/work/blinky"), &["make", "regen", "--check"]).unwrap();
+}
+
+#[test]
+fn regen_leaves_foreign_igloo_h_alone()
+{
+	let fs = new_project("samd21j18a");
+	fs.write(Path::new(IGLOO_H), b"/* hand written */\n").unwrap();
+
+	run_igloo(&fs, Path::new("/work/blinky"), &["make", "regen", "--check"]).unwrap();
+	run_igloo(&fs, Path::new("/work/blinky"), &["make", "regen"]).unwrap();
+	assert_eq!(read(&fs, IGLOO_H), "/* hand written */\n");
+}
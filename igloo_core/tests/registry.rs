@@ -0,0 +1,120 @@
+mod common;
+
+use common::*;
+use igloo_base::*;
+use igloo_base::IglooErrType::*;
+
+use std::path::Path;
+use std::rc::Rc;
+
+/// ESF with a ring_buffer module in its registry
+fn esf() -> Rc<MemFs>
+{
+	let fs = fake_esf();
+	fs.add_file(Path::new("/esf/modules/ring_buffer/1.2.0/module.toml"),
+				"[module]\nname = \"ring_buffer\"\nversion = \"1.2.0\"\n\
+				 description = \"Interrupt safe ring buffer\"\nvendor = \"igloo\"\n\
+				 tags = [\"queue\"]\ncores = [\"cortex-m*\"]\nsources = [\"src/ring_buffer.c\"]\n");
+	fs
+}
+
+fn igloo(fs: &Rc<MemFs>, args: &[&str]) -> Result<String, IglooErrType>
+{
+	run_igloo(fs, Path::new(WORK_DIR), args)
+}
+
+#[test]
+fn search_matches_names_vendors_cores_and_tags()
+{
+	let fs = esf();
+	assert!(igloo(&fs, &["search"]).is_ok());
+	assert!(igloo(&fs, &["search", "samd21"]).is_ok());
+	assert!(igloo(&fs, &["search", "microchip", "usb"]).is_ok());
+	assert!(igloo(&fs, &["search", "cortex-m4"]).is_ok());
+	assert!(igloo(&fs, &["search", "QUEUE"]).is_ok());
+	// every word has to match
+	assert_eq!(igloo(&fs, &["search", "microchip", "discovery"]), Err(NotFoundInRegistry));
+	assert_eq!(igloo(&fs, &["search", "esp32"]), Err(NotFoundInRegistry));
+}
+
+#[test]
+fn info_describes_targets_and_modules()
+{
+	let fs = esf();
+	assert!(igloo(&fs, &["info", "stm32f407vg"]).is_ok());
+	assert!(igloo(&fs, &["info", "ring_buffer"]).is_ok());
+	assert_eq!(igloo(&fs, &["info", "ring"]), Err(NotFoundInRegistry));
+}
+
+#[test]
+fn esf_index_fills_in_descriptions()
+{
+	let fs = esf();
+	assert_eq!(igloo(&fs, &["search", "zero"]), Err(NotFoundInRegistry));
+	fs.add_file(Path::new("/esf/index.toml"),
+				"[target.samd21g18a]\ndescription = \"Arduino Zero\"\n\n\
+				 [target.samd21j18a]\ndescription = \"ignored, the manifest has one\"\n");
+	assert!(igloo(&fs, &["search", "arduino", "zero"]).is_ok());
+	assert_eq!(igloo(&fs, &["search", "ignored"]), Err(NotFoundInRegistry));
+}
+
+#[test]
+fn registries_list_index_files()
+{
+	let fs = esf();
+	fs.add_file(Path::new("/home/penguin/.igloo/registries.toml"),
+				"registries = [\"vendor\", \"nordic.toml\"]\n");
+	fs.add_file(Path::new("/home/penguin/.igloo/vendor/index.toml"),
+				"[module.fatfs]\ndescription = \"FAT file system\"\nversions = [\"0.14\", \"0.15\"]\n");
+	fs.add_file(Path::new("/home/penguin/.igloo/nordic.toml"),
+				"[target.nrf52840]\ndescription = \"nRF52840 DK\"\nvendor = \"Nordic Semiconductor\"\n\
+				 cores = [\"cortex-m4\"]\ntags = [\"ble\"]\n");
+
+	assert!(igloo(&fs, &["info", "fatfs"]).is_ok());
+	assert!(igloo(&fs, &["info", "nrf52840"]).is_ok());
+	assert!(igloo(&fs, &["search", "nordic", "ble"]).is_ok());
+	assert!(igloo(&fs, &["search", "file", "system"]).is_ok());
+}
+
+#[test]
+fn broken_index_is_skipped()
+{
+	let fs = esf();
+	fs.add_file(Path::new("/esf/index.toml"), "[module.fatfs]\nversions = [\"latest\"]\n");
+	assert!(igloo(&fs, &["search", "samd21"]).is_ok());
+	assert_eq!(igloo(&fs, &["info", "fatfs"]), Err(NotFoundInRegistry));
+}
+
+#[test]
+fn targets_filter_by_vendor_and_core()
+{
+	let fs = esf();
+	assert!(igloo(&fs, &["targets"]).is_ok());
+	assert!(igloo(&fs, &["targets", "--json"]).is_ok());
+	assert!(igloo(&fs, &["targets", "--vendor", "microchip"]).is_ok());
+	assert!(igloo(&fs, &["targets", "--core", "Cortex-M4", "--vendor", "stmicro"]).is_ok());
+	assert_eq!(igloo(&fs, &["targets", "--core", "cortex-m4", "--vendor", "microchip"]),
+			   Err(NotFoundInRegistry));
+	assert_eq!(igloo(&fs, &["targets", "--core", "riscv"]), Err(NotFoundInRegistry));
+
+	// the vendor of an index entry counts as well
+	fs.add_file(Path::new("/esf/index.toml"), "[target.samd21g18a]\nvendor = \"Arduino\"\n");
+	assert!(igloo(&fs, &["targets", "--vendor", "arduino", "--json"]).is_ok());
+}
+
+#[test]
+fn targets_list_broken_entries()
+{
+	let fs = esf();
+	let master = read(&fs, "/esf/manifest/target-manifest.toml")
+		.replace("[target.manifest]", "nrf52840 = \"arm.cortex-m4.nrf52\"\n\n[target.manifest]")
+		+ "nrf52840 = \"manifest/nrf52840.toml\"\nesp32 = \"manifest/esp32.toml\"\n";
+	fs.add_file(Path::new("/esf/manifest/target-manifest.toml"), &master);
+	fs.remove_file(Path::new("/esf/openocd/stm32f407vg.cfg")).unwrap();
+
+	assert!(igloo(&fs, &["targets"]).is_ok());
+	assert!(igloo(&fs, &["targets", "--json"]).is_ok());
+	// a missing openocd config is only listed, targets that do not load are not searchable
+	assert!(igloo(&fs, &["info", "stm32f407vg"]).is_ok());
+	assert_eq!(igloo(&fs, &["info", "nrf52840"]), Err(NotFoundInRegistry));
+}
@@ -0,0 +1,121 @@
+mod common;
+
+use common::*;
+use igloo_base::*;
+use igloo_base::IglooErrType::*;
+
+use std::path::Path;
+use std::rc::Rc;
+
+const PRJ_DIR: &str = "/work/blinky";
+const PRJ_FILE: &str = "/work/blinky/.igloo/blinky.toml";
+const IGLOO_H: &str = "/work/blinky/inc/igloo.h";
+
+fn new_project(carve_out: &str) -> Rc<MemFs>
+{
+	let fs = common::new_project("samd21j18a");
+	let prj_file = read(&fs, PRJ_FILE) + carve_out;
+	fs.write(Path::new(PRJ_FILE), prj_file.as_bytes()).unwrap();
+	fs
+}
+
+fn igloo(fs: &Rc<MemFs>, args: &[&str]) -> Result<String, IglooErrType>
+{
+	run_igloo(fs, Path::new(PRJ_DIR), args)
+}
+
+#[test]
+fn retarget_replaces_the_target()
+{
+	let fs = new_project("");
+	let main_c = "#include \"samd21j18a.h\"\n#ifdef __SAMD21J18A__\n#endif\n";
+	fs.write(Path::new("/work/blinky/src/main.c"), main_c.as_bytes()).unwrap();
+	igloo(&fs, &["retarget", "samd21j18a", "stm32f407vg"]).unwrap();
+
+	assert!(read(&fs, PRJ_FILE).contains("targets = [\"stm32f407vg\"]\n"));
+	assert!(!fs.exists(Path::new("/work/blinky/.igloo/target/samd21j18a")));
+	assert!(fs.exists(Path::new("/work/blinky/.igloo/target/stm32f407vg/Makefile")));
+	assert!(fs.exists(Path::new("/work/blinky/.igloo/target/stm32f407vg/stm32f407vg.ld")));
+	assert!(fs.exists(Path::new("/work/blinky/.igloo/target/stm32f407vg/scripts/stm32f407vg.cfg")));
+	assert!(!fs.symlink_exists(Path::new("/work/blinky/ESF/samd21")));
+	assert!(fs.symlink_exists(Path::new("/work/blinky/ESF/stm32f4")));
+
+	let igloo_h = read(&fs, IGLOO_H);
+	assert!(igloo_h.contains("#ifdef IGLOO_TARGET_STM32F407VG\n"));
+	assert!(igloo_h.contains("#if defined(STM32F407xx)\n\t#include \"stm32f4xx.h\"\n"));
+	assert!(!igloo_h.contains("SAMD21"));
+	let lock = read(&fs, "/work/blinky/igloo.lock");
+	assert!(lock.contains("[target.stm32f407vg]") && !lock.contains("[target.samd21j18a]"));
+	// user code is only reported, not changed
+	assert_eq!(read(&fs, "/work/blinky/src/main.c"), main_c);
+	igloo(&fs, &["make", "regen", "--check"]).unwrap();
+}
+
+#[test]
+fn retarget_keeps_project_settings()
+{
+	let fs = new_project("\n[target.samd21j18a.memory.flash]\noffset = \"16K\"\n\
+						  \n[target.samd21j18a.image]\nfill = 0x00\n");
+	igloo(&fs, &["retarget", "samd21j18a", "stm32f407vg"]).unwrap();
+
+	let prj_file = read(&fs, PRJ_FILE);
+	assert!(prj_file.contains("\n[target.stm32f407vg.memory.flash]\noffset = \"16K\"\n"));
+	assert!(prj_file.contains("\n[target.stm32f407vg.image]\nfill = 0x00\n"));
+	assert!(!prj_file.contains("samd21j18a"));
+	let linker_script = read(&fs, "/work/blinky/.igloo/target/stm32f407vg/stm32f407vg.ld");
+	assert!(linker_script.contains("ORIGIN = 0x08004000"));
+}
+
+#[test]
+fn retarget_drops_carve_outs_of_missing_regions()
+{
+	// the samd21g18a has no memory map
+	let fs = new_project("\n[target.samd21j18a.memory.ram]\nreserve = \"1K\"\n\
+						  \n[target.samd21j18a.image]\nfill = 0x00\n");
+	igloo(&fs, &["retarget", "samd21j18a", "samd21g18a"]).unwrap();
+
+	let prj_file = read(&fs, PRJ_FILE);
+	assert!(!prj_file.contains("memory"));
+	assert!(prj_file.contains("\n[target.samd21g18a.image]\nfill = 0x00\n"));
+	// the ESF link is shared by both MCUs
+	assert!(fs.symlink_exists(Path::new("/work/blinky/ESF/samd21")));
+	igloo(&fs, &["make", "regen", "--check"]).unwrap();
+}
+
+#[test]
+fn retarget_checks_both_targets()
+{
+	let fs = new_project("");
+	let prj_file = read(&fs, PRJ_FILE);
+	assert_eq!(igloo(&fs, &["retarget", "stm32f407vg", "samd21g18a"]), Err(InvalidTarget));
+	assert_eq!(igloo(&fs, &["retarget", "samd21j18a", "esp32"]), Err(InvalidTarget));
+	assert_eq!(igloo(&fs, &["retarget", "samd21j18a", "samd21j18a"]), Err(TargetAlreadyInProject));
+	assert_eq!(read(&fs, PRJ_FILE), prj_file);
+	assert!(fs.exists(Path::new("/work/blinky/.igloo/target/samd21j18a/Makefile")));
+}
+
+#[test]
+fn retarget_reports_added_and_removed_peripherals()
+{
+	let fs = new_project("");
+	let (res, messages) = run_igloo_json(&fs, Rc::new(MemHost::new()), env_info(Path::new(PRJ_DIR)),
+										 &["retarget", "samd21j18a", "stm32f407vg"]);
+	res.unwrap();
+	let report = messages.iter().find(|m| m["reason"] == "retarget-report").unwrap();
+	assert_eq!(report["peripherals"], serde_json::json!(["ADC", "SERCOM0", "SERCOM1", "TCC0", "USB"]));
+	assert_eq!(report["added_peripherals"],
+			   serde_json::json!(["ADC1", "ETH", "SPI1", "TIM1", "USART1", "USB_OTG_FS"]));
+}
+
+#[test]
+fn dry_run_retarget_plans_the_new_target()
+{
+	let fs = new_project("");
+	let prj_file = read(&fs, PRJ_FILE);
+	let (res, messages) = run_igloo_json(&fs, Rc::new(MemHost::new()), env_info(Path::new(PRJ_DIR)),
+										 &["--dry-run", "retarget", "samd21j18a", "stm32f407vg"]);
+	res.unwrap();
+	assert_eq!(read(&fs, PRJ_FILE), prj_file);
+	assert!(!fs.exists(Path::new("/work/blinky/.igloo/target/stm32f407vg")));
+	assert!(messages.iter().any(|m| m["path"] == "/work/blinky/.igloo/target/stm32f407vg/Makefile"));
+}
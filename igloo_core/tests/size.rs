@@ -0,0 +1,138 @@
+mod common;
+
+use common::*;
+use common::elf::*;
+use igloo_base::*;
+use igloo_base::IglooErrType::*;
+
+use std::path::Path;
+use std::rc::Rc;
+
+const TARGET_DIR: &str = "/work/blinky/.igloo/target/samd21j18a";
+const ELF: &str = "/work/blinky/.igloo/target/samd21j18a/blinky.elf";
+
+/// Firmware for the samd21j18a fixture, flash starts at 0x2000 after the bootloader
+fn firmware(text_size: u32) -> Vec<u8>
+{
+	ElfBuilder::new()
+		.section(".text", 0x2000, SHF_ALLOC | SHF_EXECINSTR, &vec![0xaa; text_size as usize])
+		.section(".relocate", 0x2000_0000, SHF_ALLOC | SHF_WRITE, &[1; 0x20])
+		.nobits(".bss", 0x2000_0020, SHF_ALLOC | SHF_WRITE, 0x100)
+		.nobits(".stack", 0x2000_0120, SHF_ALLOC | SHF_WRITE, 0x2000)
+		.symbol("main", 0x2000, 0x40, STT_FUNC, ".text")
+		.symbol("SystemInit", 0x2040, 0x80, STT_FUNC, ".text")
+		.symbol("rx_buffer", 0x2000_0020, 0x100, STT_OBJECT, ".bss")
+		.symbol("counter", 0x2000_0000, 4, STT_OBJECT, ".relocate")
+		.build()
+}
+
+fn size_json(fs: &Rc<MemFs>) -> serde_json::Value
+{
+	serde_json::from_str(&read(fs, &format!("{}/size.json", TARGET_DIR))).unwrap()
+}
+
+#[test]
+fn reports_usage_per_region()
+{
+	let fs = new_project("samd21j18a");
+	fs.write(Path::new(ELF), &firmware(0x400)).unwrap();
+	run_igloo(&fs, Path::new("/work/blinky"), &["size", "--json"]).unwrap();
+
+	let report = size_json(&fs);
+	assert_eq!(report["target"], "samd21j18a");
+	let flash = &report["regions"][0];
+	assert_eq!(flash["name"], "flash");
+	// .text plus the initial values of .relocate
+	assert_eq!(flash["used"], 0x420);
+	assert_eq!(flash["capacity"], 0x3E000);
+	let ram = &report["regions"][1];
+	assert_eq!(ram["name"], "ram");
+	assert_eq!(ram["used"], 0x20 + 0x100 + 0x2000);
+	assert_eq!(ram["capacity"], 0x8000);
+	assert_eq!(ram["percent"], 25.88);
+}
+
+#[test]
+fn lists_largest_symbols_first()
+{
+	let fs = new_project("samd21j18a");
+	fs.write(Path::new(ELF), &firmware(0x400)).unwrap();
+	run_igloo(&fs, Path::new("/work/blinky"), &["size", "--top", "2"]).unwrap();
+
+	let symbols = size_json(&fs)["symbols"].clone();
+	assert_eq!(symbols.as_array().unwrap().len(), 2);
+	assert_eq!(symbols[0]["name"], "rx_buffer");
+	assert_eq!(symbols[1]["name"], "SystemInit");
+	assert_eq!(symbols[1]["section"], ".text");
+}
+
+#[test]
+fn delta_is_against_previous_build()
+{
+	let fs = new_project("samd21j18a");
+	fs.write(Path::new(ELF), &firmware(0x400)).unwrap();
+	run_igloo(&fs, Path::new("/work/blinky"), &["size"]).unwrap();
+	assert!(size_json(&fs)["regions"][0]["delta"].is_null());
+
+	fs.write(Path::new(ELF), &firmware(0x480)).unwrap();
+	run_igloo(&fs, Path::new("/work/blinky"), &["size"]).unwrap();
+	assert_eq!(size_json(&fs)["regions"][0]["delta"], 0x80);
+	assert_eq!(size_json(&fs)["regions"][1]["delta"], 0);
+
+	// Same build again, still compared against the build before it
+	run_igloo(&fs, Path::new("/work/blinky"), &["size"]).unwrap();
+	assert_eq!(size_json(&fs)["regions"][0]["delta"], 0x80);
+}
+
+#[test]
+fn overflow_fails()
+{
+	let fs = new_project("samd21j18a");
+	fs.write(Path::new(ELF), &firmware(0x3E000)).unwrap();
+	assert_eq!(run_igloo(&fs, Path::new("/work/blinky"), &["size"]),
+			   Err(MemoryRegionOverflow));
+}
+
+#[test]
+fn targets_without_memory_map_report_totals()
+{
+	let fs = new_project("samd21g18a");
+	fs.write(Path::new("/work/blinky/.igloo/target/samd21g18a/blinky.elf"),
+			 &firmware(0x400)).unwrap();
+	run_igloo(&fs, Path::new("/work/blinky"), &["size", "samd21g18a"]).unwrap();
+
+	let report: serde_json::Value = serde_json::from_str(
+		&read(&fs, "/work/blinky/.igloo/target/samd21g18a/size.json")).unwrap();
+	assert_eq!(report["regions"][0]["used"], 0x420);
+	assert!(report["regions"][0]["capacity"].is_null());
+	assert_eq!(report["regions"][1]["used"], 0x2120);
+}
+
+#[test]
+fn missing_elf_fails()
+{
+	let fs = new_project("samd21j18a");
+	assert_eq!(run_igloo(&fs, Path::new("/work/blinky"), &["size"]), Err(FailedToReadElf));
+}
+
+#[test]
+fn truncated_or_overflowing_elf_fails()
+{
+	let fs = new_project("samd21j18a");
+	let elf = firmware(0x400);
+	fs.write(Path::new(ELF), &elf[..elf.len() / 2]).unwrap();
+	assert_eq!(run_igloo(&fs, Path::new("/work/blinky"), &["size"]), Err(FailedToReadElf));
+
+	// 64-bit ELF whose section name string table lies at the end of the address space
+	let mut elf = vec![0u8; 128];
+	elf[0..8].copy_from_slice(b"\x7fELF\x02\x01\x01\x00");
+	elf[18] = 40;
+	elf[40] = 64;
+	elf[58] = 64;
+	elf[60] = 1;
+	elf[64] = 1;
+	elf[68] = 3;
+	elf[88..96].copy_from_slice(&u64::MAX.to_le_bytes());
+	fs.write(Path::new(ELF), &elf).unwrap();
+	assert_eq!(run_igloo(&fs, Path::new("/work/blinky"), &["size"]), Err(FailedToReadElf));
+}
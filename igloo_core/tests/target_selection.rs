@@ -0,0 +1,258 @@
+mod common;
+
+use common::*;
+use igloo_base::*;
+use igloo_base::IglooErrType::*;
+
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+const PRJ_DIR: &str = "/work/blinky";
+const PRJ_FILE: &str = "/work/blinky/.igloo/blinky.toml";
+const SAMD21_DIR: &str = "/work/blinky/.igloo/target/samd21j18a";
+const STM32_DIR: &str = "/work/blinky/.igloo/target/stm32f407vg";
+
+/// Project building for a SAMD21 and an STM32F4
+fn new_project() -> Rc<MemFs>
+{
+	let fs = common::new_project("samd21j18a");
+	let prj_file = read(&fs, PRJ_FILE)
+		.replace("[\"samd21j18a\"]", "[\"samd21j18a\", \"stm32f407vg\"]");
+	fs.write(Path::new(PRJ_FILE), prj_file.as_bytes()).unwrap();
+	fs.create_dir_all(Path::new(STM32_DIR)).unwrap();
+	fs
+}
+
+fn host() -> Rc<MemHost>
+{
+	let host = Rc::new(MemHost::new());
+	host.add_program("make", "GNU Make 4.3\n");
+	host.add_program("openocd", "Open On-Chip Debugger 0.11.0\n");
+	host
+}
+
+fn igloo(fs: &Rc<MemFs>, host: &Rc<MemHost>, args: &[&str]) -> Result<String, IglooErrType>
+{
+	run_igloo_with(fs, host.clone(), env_info(Path::new(PRJ_DIR)), args)
+}
+
+fn run(program: &str, args: &[&str], dir: &str) -> (String, Vec<String>, PathBuf)
+{
+	(String::from(program), args.iter().map(|a| String::from(*a)).collect(), PathBuf::from(dir))
+}
+
+#[test]
+fn commands_use_every_target_without_a_default()
+{
+	let fs = new_project();
+	let host = host();
+	igloo(&fs, &host, &["flash"]).unwrap();
+	assert_eq!(host.runs(), vec![run("make", &["push"], SAMD21_DIR),
+								 run("make", &["push"], STM32_DIR)]);
+	assert_eq!(igloo(&fs, &host, &["debug"]), Err(MultipleTargetsSelected));
+}
+
+#[test]
+fn default_target_is_stored_in_the_project_file()
+{
+	let fs = new_project();
+	let host = host();
+	igloo(&fs, &host, &["target", "default", "stm32f407vg"]).unwrap();
+	assert!(read(&fs, PRJ_FILE).contains(
+		"targets = [\"samd21j18a\", \"stm32f407vg\"]\ndefault_target = \"stm32f407vg\"\n"));
+
+	igloo(&fs, &host, &["build"]).unwrap();
+	igloo(&fs, &host, &["debug"]).unwrap();
+	igloo(&fs, &host, &["erase"]).unwrap();
+	assert_eq!(host.runs(), vec![
+		run("make", &["all"], STM32_DIR),
+		run("make", &["debug"], STM32_DIR),
+		run("openocd", &["-f", "scripts/stm32f407vg.cfg", "-c",
+						 "init; reset halt; flash erase_sector 0 0 last; shutdown"], STM32_DIR),
+	]);
+
+	// changing the default replaces it
+	igloo(&fs, &host, &["target", "default", "samd21j18a"]).unwrap();
+	let prj_file = read(&fs, PRJ_FILE);
+	assert_eq!(prj_file.matches("default_target").count(), 1);
+	assert!(prj_file.contains("default_target = \"samd21j18a\"\n"));
+
+	igloo(&fs, &host, &["target", "default", "--unset"]).unwrap();
+	assert!(!read(&fs, PRJ_FILE).contains("default_target"));
+	assert_eq!(igloo(&fs, &host, &["target", "default", "esp32"]), Err(InvalidTarget));
+	assert!(igloo(&fs, &host, &["target", "default"]).is_ok());
+}
+
+#[test]
+fn target_options_override_the_default()
+{
+	let fs = new_project();
+	let host = host();
+	igloo(&fs, &host, &["target", "default", "stm32f407vg"]).unwrap();
+
+	igloo(&fs, &host, &["build", "-t", "samd21j18a"]).unwrap();
+	igloo(&fs, &host, &["flash", "--all-targets"]).unwrap();
+	assert_eq!(host.runs(), vec![run("make", &["all"], SAMD21_DIR),
+								 run("make", &["push"], SAMD21_DIR),
+								 run("make", &["push"], STM32_DIR)]);
+	assert_eq!(igloo(&fs, &host, &["debug", "--all-targets"]), Err(MultipleTargetsSelected));
+	assert_eq!(igloo(&fs, &host, &["erase", "-t", "esp32"]), Err(InvalidTarget));
+}
+
+#[test]
+fn run_builds_then_flashes()
+{
+	let fs = new_project();
+	let host = host();
+	igloo(&fs, &host, &["run", "-t", "samd21j18a"]).unwrap();
+	assert_eq!(host.runs(), vec![run("make", &["all"], SAMD21_DIR),
+								 run("make", &["push"], SAMD21_DIR)]);
+
+	host.add_failing_program("make", "make: *** [push] Error 1\n");
+	assert_eq!(igloo(&fs, &host, &["flash"]), Err(FlashFailed));
+	host.add_failing_program("openocd", "Error: unable to open CMSIS-DAP device\n");
+	assert_eq!(igloo(&fs, &host, &["erase", "-t", "samd21j18a"]), Err(EraseFailed));
+}
+
+#[test]
+fn retarget_moves_the_default_target()
+{
+	let fs = common::new_project("samd21j18a");
+	let host = host();
+	igloo(&fs, &host, &["target", "default", "samd21j18a"]).unwrap();
+	igloo(&fs, &host, &["retarget", "samd21j18a", "stm32f407vg"]).unwrap();
+	assert!(read(&fs, PRJ_FILE).contains("default_target = \"stm32f407vg\"\n"));
+}
+
+#[test]
+fn parallel_build_builds_every_target()
+{
+	let fs = new_project();
+	let host = host();
+	igloo(&fs, &host, &["build", "--all-targets", "-j", "2"]).unwrap();
+	assert_eq!(host.runs(), vec![run("make", &["all"], SAMD21_DIR),
+								 run("make", &["all"], STM32_DIR)]);
+
+	// a failing target does not stop the others
+	host.add_failing_program("make", "make: *** [src/main.o] Error 1\n");
+	assert_eq!(igloo(&fs, &host, &["build", "--all-targets", "-j", "2"]), Err(BuildFailed));
+	assert_eq!(host.runs().len(), 4);
+	assert_eq!(igloo(&fs, &host, &["build", "-j", "0"]), Err(InvalidArgument));
+}
+
+#[cfg(unix)]
+#[test]
+fn real_host_runs_jobs_in_parallel()
+{
+	let job = |name: &str, script: &str| IglooJob
+	{
+		name: String::from(name),
+		program: String::from("sh"),
+		args: vec![String::from("-c"), String::from(script)],
+		dir: std::env::temp_dir(),
+	};
+	let mut jobs = vec![job("a", "sleep 0.3; echo a"), job("b", "sleep 0.3; exit 3"),
+						job("c", "sleep 0.3; echo c >&2")];
+	jobs.push(IglooJob { program: String::from("igloo-no-such-program"), ..job("d", "") });
+
+	let start = std::time::Instant::now();
+	let results = RealHost.run_jobs(&jobs, 4);
+	assert!(start.elapsed() < std::time::Duration::from_millis(850));
+	assert!(matches!(results[0].result, Ok(true)));
+	assert!(matches!(results[1].result, Ok(false)));
+	assert!(matches!(results[2].result, Ok(true)));
+	assert!(results[3].result.is_err());
+}
+
+/// Shell job appending + when it starts and - when it ends to log
+#[cfg(unix)]
+fn logged_job(name: &str, log: &Path, script: &str) -> IglooJob
+{
+	IglooJob
+	{
+		name: String::from(name),
+		program: String::from("sh"),
+		args: vec![String::from("-c"),
+				   format!("echo + >> {0}; ({1}); status=$?; echo - >> {0}; exit $status",
+						   log.display(), script)],
+		dir: std::env::temp_dir(),
+	}
+}
+
+/// Most jobs of log that ran at the same time
+#[cfg(unix)]
+fn most_at_once(log: &Path) -> usize
+{
+	let mut running = 0;
+	let mut most = 0;
+	for line in std::fs::read_to_string(log).unwrap().lines()
+	{
+		match line
+		{
+			"+" => running += 1,
+			_ => running -= 1,
+		}
+		most = most.max(running);
+	}
+	most
+}
+
+#[cfg(unix)]
+#[test]
+fn real_host_keeps_job_order_with_fewer_workers()
+{
+	let log = std::env::temp_dir().join(format!("igloo-jobs-{}.log", std::process::id()));
+	let _ = std::fs::remove_file(&log);
+	let mut jobs = vec![logged_job("slow", &log, "sleep 0.4")];
+	for i in 1..6
+	{
+		let script = if i == 3 { "sleep 0.1; exit 1" } else { "sleep 0.1" };
+		jobs.push(logged_job(&format!("job{}", i), &log, script));
+	}
+
+	let results = RealHost.run_jobs(&jobs, 2);
+	assert_eq!(most_at_once(&log), 2);
+	let _ = std::fs::remove_file(&log);
+	assert_eq!(results.len(), 6);
+	// the slow job finishes last but its result stays first
+	assert!(results[0].duration >= std::time::Duration::from_millis(400));
+	for (i, job) in results.iter().enumerate().skip(1)
+	{
+		assert!(job.duration < std::time::Duration::from_millis(400));
+		assert_eq!(job.result.as_ref().ok(), Some(&(i != 3)));
+	}
+	assert!(matches!(results[0].result, Ok(true)));
+}
+
+#[cfg(unix)]
+#[test]
+fn real_host_runs_one_job_at_a_time_with_j1()
+{
+	let log = std::env::temp_dir().join(format!("igloo-jobs-j1-{}.log", std::process::id()));
+	let _ = std::fs::remove_file(&log);
+	let jobs: Vec<IglooJob> = (0..3)
+		.map(|i| logged_job(&format!("job{}", i), &log, "sleep 0.1"))
+		.collect();
+
+	let results = RealHost.run_jobs(&jobs, 1);
+	assert_eq!(std::fs::read_to_string(&log).unwrap(), "+\n-\n+\n-\n+\n-\n");
+	let _ = std::fs::remove_file(&log);
+	assert!(results.iter().all(|r| matches!(r.result, Ok(true))));
+}
+
+#[test]
+fn regen_follows_the_default_target()
+{
+	let fs = new_project();
+	let host = host();
+	let samd21_makefile = format!("{}/Makefile", SAMD21_DIR);
+	let stm32_makefile = format!("{}/Makefile", STM32_DIR);
+	fs.remove_file(Path::new(&samd21_makefile)).unwrap();
+	igloo(&fs, &host, &["target", "default", "stm32f407vg"]).unwrap();
+
+	igloo(&fs, &host, &["make", "regen"]).unwrap();
+	assert!(fs.exists(Path::new(&stm32_makefile)));
+	assert!(!fs.exists(Path::new(&samd21_makefile)));
+	igloo(&fs, &host, &["make", "regen", "--all-targets"]).unwrap();
+	assert!(fs.exists(Path::new(&samd21_makefile)));
+}
@@ -0,0 +1,251 @@
+mod common;
+
+use common::*;
+use common::archive::*;
+use igloo_base::*;
+use igloo_base::IglooErrType::*;
+
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::rc::Rc;
+
+const PRJ_DIR: &str = "/work/blinky";
+const MAKEFILE: &str = "/work/blinky/.igloo/target/samd21j18a/Makefile";
+const LOCK: &str = "/work/blinky/igloo.lock";
+const CACHE: &str = "/home/penguin/.igloo/toolchains";
+
+fn sha256(data: &[u8]) -> String
+{
+	Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// An Arm GNU Toolchain style archive with everything under a versioned directory
+fn toolchain_archive(version: &str) -> Vec<u8>
+{
+	let root = format!("arm-gnu-toolchain-{}", version);
+	let gcc = format!("{}/bin/arm-none-eabi-gcc", root);
+	let gxx = format!("{}/bin/arm-none-eabi-g++", root);
+	let cxx = format!("{}/bin/arm-none-eabi-c++", root);
+	let readme = format!("{}/share/README", root);
+	tar_gz(&[
+		Entry::Dir(&root),
+		Entry::Exec(&gcc, b"gcc"),
+		Entry::Exec(&gxx, b"g++"),
+		Entry::Symlink(&cxx, "arm-none-eabi-g++"),
+		Entry::File(&readme, version.as_bytes()),
+	])
+}
+
+/// Lists archive in ESF's toolchain index
+fn add_archive(fs: &Rc<MemFs>, version: &str, data: &[u8], sha256: &str)
+{
+	let file = format!("arm-gnu-toolchain-{}.tar.gz", version);
+	fs.create_dir_all(Path::new("/esf/toolchains")).unwrap();
+	fs.write(&Path::new("/esf/toolchains").join(&file), data).unwrap();
+	let index = fs.read_to_string(Path::new("/esf/toolchains/toolchains.toml"))
+		.unwrap_or_default()
+		+ &format!("\n[[toolchain]]\nname = \"arm-none-eabi\"\nversion = \"{}\"\n\
+					archive = \"{}\"\nsha256 = \"{}\"\n", version, file, sha256);
+	fs.add_file(Path::new("/esf/toolchains/toolchains.toml"), &index);
+}
+
+/// ESF whose samd21j18a requires arm-none-eabi 10.3.x, with a 10.3.1 archive
+fn esf_with_toolchain(req: &str) -> Rc<MemFs>
+{
+	let fs = fake_esf();
+	let manifest = read(&fs, "/esf/manifest/samd21j18a.toml")
+		+ &format!("\n[esf.toolchain]\nname = \"arm-none-eabi\"\nversion = \"{}\"\n", req);
+	fs.add_file(Path::new("/esf/manifest/samd21j18a.toml"), &manifest);
+	let data = toolchain_archive("10.3.1");
+	add_archive(&fs, "10.3.1", &data, &sha256(&data));
+	fs
+}
+
+fn new_project(fs: &Rc<MemFs>)
+{
+	run_igloo(fs, Path::new(WORK_DIR), &["new", "blinky", "-t", "samd21j18a"]).unwrap();
+}
+
+#[test]
+fn installs_required_toolchain_into_build_files_and_lock()
+{
+	let fs = esf_with_toolchain("~10.3");
+	new_project(&fs);
+	// nothing installed yet, the toolchain on PATH is used
+	assert!(read(&fs, MAKEFILE).contains("CC=arm-none-eabi-gcc\n"));
+	assert!(!read(&fs, LOCK).contains("[toolchain."));
+
+	run_igloo(&fs, Path::new(PRJ_DIR), &["toolchain", "install"]).unwrap();
+	let bin = format!("{}/arm-none-eabi-10.3.1/bin", CACHE);
+	assert_eq!(read(&fs, &format!("{}/arm-none-eabi-gcc", bin)), "gcc");
+	assert_eq!(read(&fs, &format!("{}/arm-none-eabi-10.3.1/share/README", CACHE)), "10.3.1");
+	assert!(fs.symlink_exists(Path::new(&format!("{}/arm-none-eabi-c++", bin))));
+
+	let makefile = read(&fs, MAKEFILE);
+	assert!(makefile.contains(&format!("CC={}/arm-none-eabi-gcc\n", bin)));
+	assert!(makefile.contains(&format!("CXX={}/arm-none-eabi-g++\n", bin)));
+	assert!(makefile.contains(&format!("TOOLCHAIN={}/arm-none-eabi-\n", bin)));
+	// programs the archive does not have still come from PATH
+	assert!(makefile.contains("OBJCOPY=arm-none-eabi-objcopy\n"));
+
+	let lock = read(&fs, LOCK);
+	assert!(lock.contains("[toolchain.samd21j18a]\nname = \"arm-none-eabi\"\nversion = \"10.3.1\"\n"));
+	assert!(lock.contains(&sha256(&toolchain_archive("10.3.1"))));
+	run_igloo(&fs, Path::new(PRJ_DIR), &["make", "regen", "--check"]).unwrap();
+}
+
+#[test]
+fn lock_keeps_toolchain_when_newer_one_is_installed()
+{
+	let fs = esf_with_toolchain("~10.3");
+	new_project(&fs);
+	run_igloo(&fs, Path::new(PRJ_DIR), &["toolchain", "install"]).unwrap();
+	let lock = read(&fs, LOCK);
+
+	let data = toolchain_archive("10.3.2");
+	add_archive(&fs, "10.3.2", &data, &sha256(&data));
+	run_igloo(&fs, Path::new(WORK_DIR), &["toolchain", "install", "arm-none-eabi@10.3.2"]).unwrap();
+	assert!(fs.exists(Path::new(&format!("{}/arm-none-eabi-10.3.2/bin/arm-none-eabi-gcc", CACHE))));
+
+	run_igloo(&fs, Path::new(PRJ_DIR), &["make", "regen"]).unwrap();
+	assert_eq!(read(&fs, LOCK), lock);
+	assert!(read(&fs, MAKEFILE).contains("arm-none-eabi-10.3.1/bin/arm-none-eabi-gcc"));
+
+	// without a lock the newest matching toolchain is picked
+	fs.remove_file(Path::new(LOCK)).unwrap();
+	run_igloo(&fs, Path::new(PRJ_DIR), &["make", "regen"]).unwrap();
+	assert!(read(&fs, LOCK).contains("version = \"10.3.2\""));
+	assert!(read(&fs, MAKEFILE).contains("arm-none-eabi-10.3.2/bin/arm-none-eabi-gcc"));
+}
+
+#[test]
+fn installs_locked_version_on_a_fresh_machine()
+{
+	let fs = esf_with_toolchain("~10.3");
+	let data = toolchain_archive("10.3.2");
+	add_archive(&fs, "10.3.2", &data, &sha256(&data));
+	new_project(&fs);
+	fs.add_file(Path::new(LOCK), &format!(
+		"[toolchain.samd21j18a]\nname = \"arm-none-eabi\"\nversion = \"10.3.1\"\nsha256 = \"{}\"\n",
+		sha256(&toolchain_archive("10.3.1"))));
+
+	run_igloo(&fs, Path::new(PRJ_DIR), &["toolchain", "install"]).unwrap();
+	assert!(fs.exists(Path::new(&format!("{}/arm-none-eabi-10.3.1", CACHE))));
+	assert!(!fs.exists(Path::new(&format!("{}/arm-none-eabi-10.3.2", CACHE))));
+}
+
+#[test]
+fn rejects_archive_with_wrong_checksum()
+{
+	let fs = esf_with_toolchain("~10.3");
+	let data = toolchain_archive("10.3.2");
+	add_archive(&fs, "10.3.2", &data, &sha256(b"something else"));
+	assert_eq!(run_igloo(&fs, Path::new(WORK_DIR), &["toolchain", "install", "arm-none-eabi@10.3.2"]),
+			   Err(FailedToInstallToolchain));
+	assert!(!fs.exists(Path::new(&format!("{}/arm-none-eabi-10.3.2", CACHE))));
+}
+
+#[test]
+fn rejects_links_out_of_the_toolchain()
+{
+	let fs = fake_esf();
+	let data = tar_gz(&[
+		Entry::Exec("bin/arm-none-eabi-gcc", b"gcc"),
+		Entry::Symlink("bin/passwd", "../../../../etc/passwd"),
+	]);
+	add_archive(&fs, "10.3.1", &data, &sha256(&data));
+	assert_eq!(run_igloo(&fs, Path::new(WORK_DIR), &["toolchain", "install", "arm-none-eabi"]),
+			   Err(FailedToInstallToolchain));
+}
+
+#[test]
+fn rejects_links_through_earlier_links()
+{
+	// d/l points at the toolchain root, so d/l/m -> .. would leave it
+	let fs = fake_esf();
+	let data = tar_gz(&[
+		Entry::Exec("bin/arm-none-eabi-gcc", b"gcc"),
+		Entry::Symlink("d/l", ".."),
+		Entry::Symlink("d/l/m", ".."),
+	]);
+	add_archive(&fs, "10.3.1", &data, &sha256(&data));
+	assert_eq!(run_igloo(&fs, Path::new(WORK_DIR), &["toolchain", "install", "arm-none-eabi"]),
+			   Err(FailedToInstallToolchain));
+
+	let fs = fake_esf();
+	let data = tar_gz(&[
+		Entry::Exec("bin/arm-none-eabi-gcc", b"gcc"),
+		Entry::Symlink("d/l", ".."),
+		Entry::File("d/l/m/passwd", b"root"),
+		Entry::Symlink("d/l/m/up", "../.."),
+	]);
+	add_archive(&fs, "10.3.1", &data, &sha256(&data));
+	assert_eq!(run_igloo(&fs, Path::new(WORK_DIR), &["toolchain", "install", "arm-none-eabi"]),
+			   Err(FailedToInstallToolchain));
+}
+
+#[test]
+fn rejects_hard_links_out_of_the_toolchain()
+{
+	for target in &["../../etc/passwd", "/etc/passwd"]
+	{
+		let fs = fake_esf();
+		fs.add_file(Path::new("/etc/passwd"), "root");
+		let data = tar_gz(&[
+			Entry::Exec("bin/arm-none-eabi-gcc", b"gcc"),
+			Entry::Hardlink("bin/passwd", target),
+		]);
+		add_archive(&fs, "10.3.1", &data, &sha256(&data));
+		assert_eq!(run_igloo(&fs, Path::new(WORK_DIR), &["toolchain", "install", "arm-none-eabi"]),
+				   Err(FailedToInstallToolchain));
+	}
+}
+
+#[test]
+fn rejects_entries_larger_than_the_archive()
+{
+	// a base-256 size of 2^64 - 1 bytes
+	let mut data = tar(&[Entry::Exec("bin/arm-none-eabi-gcc", b"gcc")]);
+	data[124] = 0x80;
+	data[125..136].copy_from_slice(&[0xff; 11]);
+	checksum(&mut data);
+	let data = gzip(&data);
+	let fs = fake_esf();
+	add_archive(&fs, "10.3.1", &data, &sha256(&data));
+	assert_eq!(run_igloo(&fs, Path::new(WORK_DIR), &["toolchain", "install", "arm-none-eabi"]),
+			   Err(FailedToInstallToolchain));
+}
+
+#[test]
+fn reports_missing_toolchain()
+{
+	let fs = esf_with_toolchain(">=11");
+	new_project(&fs);
+	assert_eq!(run_igloo(&fs, Path::new(PRJ_DIR), &["toolchain", "install"]),
+			   Err(ToolchainNotFound));
+	assert_eq!(run_igloo(&fs, Path::new(WORK_DIR), &["toolchain", "install", "riscv-none-elf"]),
+			   Err(ToolchainNotFound));
+	// no project to read the requirements from
+	assert_eq!(run_igloo(&fs, Path::new(WORK_DIR), &["toolchain", "install"]),
+			   Err(NotInsideProject));
+}
+
+#[test]
+fn reads_user_index_and_mirrors()
+{
+	let fs = fake_esf();
+	let data = toolchain_archive("12.2.1");
+	fs.create_dir_all(Path::new("/mirror")).unwrap();
+	fs.write(Path::new("/mirror/arm-12.2.1.tar.gz"), &data).unwrap();
+	fs.add_file(Path::new("/mirror/toolchains.toml"),
+				"[[toolchain]]\nname = \"arm-none-eabi\"\nversion = \"12.2.1\"\n\
+				 archive = \"arm-12.2.1.tar.gz\"\n\
+				 \n[[toolchain]]\nname = \"arm-none-eabi\"\nversion = \"13.1\"\n\
+				 archive = \"arm-13.1.tar.gz\"\nhost = \"sparc-plan9\"\n");
+	fs.add_file(Path::new("/home/penguin/.igloo/toolchains.toml"), "mirrors = [\"/mirror\"]\n");
+
+	run_igloo(&fs, Path::new(WORK_DIR), &["toolchain", "list"]).unwrap();
+	// 13.1 is for another host
+	run_igloo(&fs, Path::new(WORK_DIR), &["toolchain", "install", "arm-none-eabi"]).unwrap();
+	assert!(fs.exists(Path::new(&format!("{}/arm-none-eabi-12.2.1/bin/arm-none-eabi-gcc", CACHE))));
+}
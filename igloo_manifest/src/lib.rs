@@ -1,4 +1,6 @@
 extern crate config;
+#[macro_use]
+extern crate log;
 
 use igloo_base::*;
 use igloo_base::IglooErrType::*;
@@ -11,21 +13,40 @@ mod tests {
         assert_eq!(2 + 2, 4);
     }
 }
+use std::ffi::OsStr;
+use std::path::Path;
+
+/// Merges a toml manifest read through fs into man
+pub fn merge_manifest(fs: &dyn IglooFs, man: &mut Config, path: &Path)
+					  -> Result<(), String>
+{
+	let contents = match fs.read_to_string(path)
+	{
+		Ok(v) => v,
+		Err(e) => return Err(format!("{}: {}", path.display(), e)),
+	};
+	match man.merge(config::File::from_str(&contents, config::FileFormat::Toml))
+	{
+		Ok(_v) =>
+		{
+			debug!("Merged manifest {}", path.display());
+			Ok(())
+		}
+		Err(e) => Err(format!("{}: {}", path.display(), e)),
+	}
+}
 
 /// Igloo Manifest -- Responsible for all lookups in manifest files
-pub fn get_master_make_manifest(man: &mut Config) -> IglooErrType
+pub fn get_master_make_manifest(fs: &dyn IglooFs, esfd: &Path, man: &mut Config)
+								-> IglooErrType
 {
 	let mut ret: IglooErrType = ErrNone;
-	match man.merge(
-		config::File::with_name(
-			IglooEnvInfo::get_env_info().esfd.join("manifest/make-manifest.toml")
-				.to_str()
-				.unwrap()))
+	match merge_manifest(fs, man, &esfd.join("manifest/make-manifest.toml"))
 	{
 		Ok(_v) => (),
 		Err(e) =>
 		{
-			println!("Error: {:?}", e);
+			error!("{:?}", e);
 			ret = FailedToLoadMasterMakeManifest;
 		}
 	}
@@ -33,28 +54,61 @@ pub fn get_master_make_manifest(man: &mut Config) -> IglooErrType
 	ret
 }
 
-pub fn get_master_target_manifest(man: &mut Config) -> IglooErrType
+pub fn get_master_target_manifest(fs: &dyn IglooFs, esfd: &Path, man: &mut Config)
+								  -> IglooErrType
 {
 	let mut ret: IglooErrType = ErrNone;
-	match man.merge(
-		config::File::with_name(
-			IglooEnvInfo::get_env_info().esfd.join("manifest/target-manifest.toml")
-				.to_str()
-				.unwrap()))
+	match merge_manifest(fs, man, &esfd.join("manifest/target-manifest.toml"))
 	{
 		Ok(_v) => (),
 		Err(e) =>
 		{
-			println!("Error: {:?}", e);
+			error!("{:?}", e);
 			ret = FailedToLoadMasterTargetManifest;
 		}
 	}
 
 	ret
 }
+
+/// Loads the project file (.igloo/<PrjName>.toml) of the project at prj_root
+pub fn get_project_manifest(fs: &dyn IglooFs, man: &mut Config, prj_root: &Path)
+							-> IglooErrType
+{
+	let igloo_dir = match fs.read_dir(&prj_root.join(".igloo"))
+	{
+		Ok(v) => v,
+		Err(e) =>
+		{
+			error!("{:?}", e);
+			return NotInsideProject
+		}
+	};
+
+	for path in igloo_dir
+	{
+		if fs.is_dir(&path) || path.extension() != Some(OsStr::new("toml"))
+		{
+			continue
+		}
+		match merge_manifest(fs, man, &path)
+		{
+			Ok(_v) => return ErrNone,
+			Err(e) =>
+			{
+				error!("{:?}", e);
+				return FailedToLoadProjectManifest
+			}
+		}
+	}
+
+	FailedToLoadProjectManifest
+}
+
 /// master_mm -- Master Make Manifest
 /// master_tm -- Master Target Manifest
 /// name -- name of target
+#[allow(unused_variables)]
 pub fn target_is_valid(master_mm: &Config, master_tm: &Config, name: &str)
 					 -> Result<bool, IglooErrType>
 {
@@ -72,11 +126,11 @@ pub fn target_is_valid(master_mm: &Config, master_tm: &Config, name: &str)
 			{
 				Some(v) =>
 				{
-					println!("target.make entry for \"{}\" exists!", v);
+					debug!("target.make entry for \"{}\" is {}", name, v);
 				}
 				None =>
 				{
-					println!("target.make entry for \"{}\" does not exist", name);
+					debug!("target.make has no entry for \"{}\"", name);
 					ret = false;
 				}
 			}
@@ -84,7 +138,7 @@ pub fn target_is_valid(master_mm: &Config, master_tm: &Config, name: &str)
 		}
 		Err(e) =>
 		{
-			println!("{:?}", e);
+			error!("{:?}", e);
 			return Err(FailedToLoadMasterMakeManifest)
 		}
 	}
@@ -103,7 +157,7 @@ pub fn target_is_valid(master_mm: &Config, master_tm: &Config, name: &str)
 			{
 				Some(v) =>
 				{
-					println!("target.manifest entry for \"{}\" exists!", v);
+					debug!("target.manifest entry for \"{}\" is {}", name, v);
 				}
 				None =>
 				{
@@ -113,7 +167,7 @@ pub fn target_is_valid(master_mm: &Config, master_tm: &Config, name: &str)
 		}
 		Err(e) =>
 		{
-			println!("{:?}", e);
+			error!("{:?}", e);
 			return Err(FailedToLoadMasterTargetManifest)
 		}
 	}
@@ -1,4 +1,6 @@
 #![allow(warnings)]
+#[macro_use]
+extern crate log;
 
 use igloo_core::Igloo;
 
@@ -11,11 +13,21 @@ fn main()
 		{
 			match ig.run(it)
 			{
-				Ok(rt) => println!("{:?}", rt),
-				Err(e) => println!("Run Error: {:?}", e),
+				// stdout may be a completion script or JSON messages
+				Ok(rt) => info!("{}", rt),
+				// errors go to stderr, completion scripts read stdout
+				Err(e) =>
+				{
+					eprintln!("Run Error: {:?}", e);
+					std::process::exit(1);
+				}
 			}
 		}
-		Err(e) => println!("Error: {:?}", e),
+		Err(e) =>
+		{
+			eprintln!("Error: {:?}", e);
+			std::process::exit(1);
+		}
 	};
 
 }
@@ -0,0 +1,31 @@
+// Runs the igloo binary the way the completion scripts do, whatever it prints
+// on stdout is offered as a completion
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn complete_targets(esf_dir: Option<&str>) -> Output
+{
+	let cwd: PathBuf = std::env::temp_dir();
+	let mut cmd = Command::new(env!("CARGO_BIN_EXE_igloo"));
+	cmd.args(["__complete", "targets"]).current_dir(&cwd).env_remove("IGLOO_LOG");
+	match esf_dir
+	{
+		Some(v) => cmd.env("ESF_DIR", v),
+		None => cmd.env_remove("ESF_DIR"),
+	};
+	cmd.output().unwrap()
+}
+
+#[test]
+fn failing_hook_prints_nothing_on_stdout()
+{
+	let unset = complete_targets(None);
+	assert!(!unset.status.success());
+	assert_eq!(String::from_utf8_lossy(&unset.stdout), "");
+	assert!(String::from_utf8_lossy(&unset.stderr).contains("InvalidEnvInfo"));
+
+	let missing = complete_targets(Some("/nonexistent/esf"));
+	assert!(!missing.status.success());
+	assert_eq!(String::from_utf8_lossy(&missing.stdout), "");
+	assert!(String::from_utf8_lossy(&missing.stderr).contains("FailedToLoadMasterMakeManifest"));
+}
@@ -0,0 +1,34 @@
+// The log level filters log records only, command output is always printed
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn igloo(args: &[&str], igloo_log: Option<&str>) -> Output
+{
+	let esf = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("igloo_core/tests/fixtures/esf");
+	let mut cmd = Command::new(env!("CARGO_BIN_EXE_igloo"));
+	cmd.args(args).current_dir(std::env::temp_dir()).env("ESF_DIR", esf);
+	match igloo_log
+	{
+		Some(v) => cmd.env("IGLOO_LOG", v),
+		None => cmd.env_remove("IGLOO_LOG"),
+	};
+	cmd.output().unwrap()
+}
+
+#[test]
+fn quiet_keeps_requested_json()
+{
+	let out = igloo(&["-q", "targets", "--json"], None);
+	assert!(out.status.success());
+	let stdout = String::from_utf8_lossy(&out.stdout);
+	assert!(stdout.trim_start().starts_with('{'));
+	assert!(stdout.contains("samd21j18a"));
+}
+
+#[test]
+fn igloo_log_does_not_silence_output()
+{
+	let out = igloo(&["targets"], Some("error"));
+	assert!(out.status.success());
+	assert!(String::from_utf8_lossy(&out.stdout).contains("samd21j18a"));
+}